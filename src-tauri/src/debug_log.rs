@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::reload;
+use tracing_subscriber::Registry;
+
+pub const DEBUG_ENV: &str = "NOTERM_DEBUG";
+
+// 调试日志：写入 app_data_dir/logs，按天滚动。订阅者只在首次开启时安装，
+// 之后通过 reload 句柄切换级别。
+static FILTER: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+static GUARD: Mutex<Option<tracing_appender::non_blocking::WorkerGuard>> = Mutex::new(None);
+
+pub fn env_enabled() -> bool {
+    crate::env_flag_enabled(DEBUG_ENV)
+}
+
+pub fn set_enabled(log_dir: PathBuf, enabled: bool) -> anyhow::Result<()> {
+    if let Some(handle) = FILTER.get() {
+        let level = if enabled { LevelFilter::DEBUG } else { LevelFilter::OFF };
+        handle.reload(level)?;
+        return Ok(());
+    }
+    if !enabled {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&log_dir)?;
+    let appender = tracing_appender::rolling::Builder::new()
+        .rotation(tracing_appender::rolling::Rotation::DAILY)
+        .filename_prefix("noterm")
+        .filename_suffix("log")
+        .max_log_files(7)
+        .build(&log_dir)?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    let (filter, handle) = reload::Layer::new(LevelFilter::DEBUG);
+    let subscriber = Registry::default().with(filter).with(
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_target(false)
+            .with_writer(writer),
+    );
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    let _ = FILTER.set(handle);
+    *GUARD.lock().unwrap() = Some(guard);
+    tracing::info!(dir = %log_dir.display(), "debug logging enabled");
+    Ok(())
+}
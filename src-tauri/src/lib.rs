@@ -1,9 +1,24 @@
+mod debug_log;
 mod local_pty;
+mod session_state;
+mod ssh_agent;
+mod ssh_config;
 mod ssh_manager;
+mod thread_stats;
+mod ws_bridge;
 
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use local_pty::LocalPtyManager;
-use ssh_manager::{ControlledCommandResult, ForwardConfig, SftpEntry, SshConnection, SshManager};
+use local_pty::{LocalPtyManager, LocalSessionInfo};
+use session_state::SessionSnapshot;
+use ssh_config::ImportedHost;
+use ws_bridge::{WsBridgeInfo, WsBridgeOptions};
+use thread_stats::{ThreadKind, ThreadStats};
+use ssh_manager::{
+    ActiveTransfer, BindCheck, ConnectResult, ForwardKeepalive, ConnectTiming, DirTransferOptions, DisconnectSummary, PathDiagnostics, SftpDownloadDirSummary, SymlinkPolicy, MacroStep, SessionGcReport, SftpDirListing, SftpFeatures, InterruptedTransfer, TransferDirection, ScriptInterpreter, ScriptRunResult, ConnectedSession, ConnectionCount, ConnectionIssue, ControlledCommandResult, HostCommandResult, HostKeyInfo, HostProbe, HostStats, InterfaceList, MergedExecResult, MultiplexerAttach, MultiplexerKind, RemoteUserInfo, ShellBufferStats, TmuxSession, ForwardConfig, ForwardKind, ServerAlgorithms, ForwardInfo, ForwardState, SftpEntry, SftpPreview, SftpSessionInfo,
+    SftpEditInfo, SftpSyncSummary, SftpUploadDirSummary, ShellOptions, ShellSizeReport, SshConnection, SshManager, SshPermsReport, TransferRecord, TransferVerify,
+};
+use std::collections::HashMap;
 use std::fs;
 use std::sync::Mutex;
 use std::net::{TcpStream, ToSocketAddrs};
@@ -20,6 +35,15 @@ struct AppState {
     local_pty_manager: Mutex<LocalPtyManager>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppResourceStats {
+    connections: ConnectionCount,
+    shells: usize,
+    local_sessions: usize,
+    threads: ThreadStats,
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct EndpointCheck {
     ip: String,
@@ -31,10 +55,38 @@ struct EndpointCheck {
 struct GeneratedKeypair {
     key_path: String,
     public_key: String,
+    public_key_export: Option<String>,
+    algorithm: String,
+    format: String,
+    comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MemoryKeypair {
+    private_key: String, // OpenSSH 格式，设置了口令时已加密
+    public_key: String,
+    fingerprint: String,
     algorithm: String,
     comment: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClipboardSupport {
+    read: bool,
+    write: bool,
+    read_tool: Option<String>,
+    write_tool: Option<String>,
+    hint: Option<String>, // 不可用时提示安装的工具
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LocalToolStatus {
+    name: String,
+    category: String,
+    available: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct SftpTransferProgress {
     session_id: String,
@@ -45,6 +97,25 @@ struct SftpTransferProgress {
     percent: f64,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct SftpChunk {
+    session_id: String,
+    stream_id: String,
+    seq: u64,
+    data_b64: String,
+    done: bool,
+    cancelled: bool, // done 且被 stop 中止，数据不完整
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ExecChunk {
+    session_id: String,
+    stream_id: String,
+    seq: u64,
+    data: String,
+    pty_merged: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[allow(dead_code)]
@@ -66,9 +137,23 @@ struct RdpConnection {
     redirect_clipboard: Option<bool>,
     redirect_audio: Option<bool>,
     redirect_drives: Option<bool>,
+    ephemeral_file: Option<bool>,
+}
+
+// 设置后跳过更新插件（CI、嵌入式等没有更新配置的环境）
+const NO_UPDATER_ENV: &str = "NOTERM_NO_UPDATER";
+
+// 环境变量开关：非空且不为 "0"/"false" 即视为开启
+pub(crate) fn env_flag_enabled(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| {
+            let value = value.trim();
+            !value.is_empty() && value != "0" && !value.eq_ignore_ascii_case("false")
+        })
+        .unwrap_or(false)
 }
 
-fn command_exists(cmd: &str) -> bool {
+pub(crate) fn command_exists(cmd: &str) -> bool {
     let checker = if cfg!(target_os = "windows") { "where" } else { "which" };
     Command::new(checker)
         .arg(cmd)
@@ -77,6 +162,44 @@ fn command_exists(cmd: &str) -> bool {
         .unwrap_or(false)
 }
 
+// 各功能依赖的本地工具（按平台）
+fn local_tool_candidates() -> Vec<(&'static str, &'static str)> {
+    let mut tools = vec![("ssh-keygen", "keys")];
+    if cfg!(target_os = "macos") {
+        tools.extend([("pbcopy", "clipboard"), ("pbpaste", "clipboard")]);
+    } else if cfg!(target_os = "windows") {
+        tools.extend([("clip", "clipboard"), ("powershell", "clipboard"), ("mstsc", "rdp")]);
+    } else {
+        tools.extend([
+            ("wl-copy", "clipboard"),
+            ("wl-paste", "clipboard"),
+            ("xclip", "clipboard"),
+            ("xsel", "clipboard"),
+        ]);
+    }
+    if !cfg!(target_os = "windows") {
+        tools.push(("xfreerdp", "rdp"));
+    }
+    tools.push(("vncviewer", "vnc"));
+    tools
+}
+
+#[tauri::command]
+async fn check_local_tools() -> Result<Vec<LocalToolStatus>, String> {
+    tokio::task::spawn_blocking(|| {
+        local_tool_candidates()
+            .into_iter()
+            .map(|(name, category)| LocalToolStatus {
+                name: name.to_string(),
+                category: category.to_string(),
+                available: command_exists(name),
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
 fn build_rdp_content(conn: &RdpConnection) -> String {
     let mut lines: Vec<String> = Vec::new();
     lines.push(format!("full address:s:{}:{}", conn.host, conn.port));
@@ -136,17 +259,131 @@ fn build_rdp_content(conn: &RdpConnection) -> String {
     lines.join("\n")
 }
 
+// rdp_validate 的结果：content 为 rdp_open 将写入的 .rdp 内容（不含密码）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RdpValidation {
+    warnings: Vec<ConnectionIssue>,
+    content: String,
+}
+
+// 字段名与前端 RdpConnectionConfig 一致
+fn validate_rdp_connection(conn: &RdpConnection) -> Vec<ConnectionIssue> {
+    let mut warnings: Vec<ConnectionIssue> = Vec::new();
+    let mut warn = |field: &str, message: String| {
+        warnings.push(ConnectionIssue {
+            field: field.to_string(),
+            message,
+        });
+    };
+
+    let host = conn.host.trim();
+    if host.is_empty() {
+        warn("host", "Host is empty".to_string());
+    } else if host.chars().any(char::is_whitespace) {
+        warn("host", "Host must not contain whitespace".to_string());
+    }
+    if conn.port == 0 {
+        warn("port", "Port must be between 1 and 65535".to_string());
+    }
+    if conn.username.trim().is_empty() && conn.password.as_deref().is_some_and(|pass| !pass.is_empty()) {
+        warn("username", "Password is set but username is empty".to_string());
+    }
+
+    // 只给出宽或高时 build_rdp_content 会退回全屏
+    match (conn.resolution_width, conn.resolution_height) {
+        (Some(_), None) => warn("resolutionHeight", "Height is missing; the session will open full screen".to_string()),
+        (None, Some(_)) => warn("resolutionWidth", "Width is missing; the session will open full screen".to_string()),
+        (Some(width), Some(height)) => {
+            if !(200..=8192).contains(&width) {
+                warn("resolutionWidth", format!("Width {} is outside the supported range 200-8192", width));
+            }
+            if !(200..=8192).contains(&height) {
+                warn("resolutionHeight", format!("Height {} is outside the supported range 200-8192", height));
+            }
+        }
+        (None, None) => {}
+    }
+    if let Some(depth) = conn.color_depth {
+        if ![8, 15, 16, 24, 32].contains(&depth) {
+            warn("colorDepth", format!("Color depth {} is not supported; use 16, 24 or 32", depth));
+        } else if depth < 16 && conn.resolution_width.zip(conn.resolution_height).is_some_and(|(w, h)| u64::from(w) * u64::from(h) > 1920 * 1080) {
+            warn(
+                "colorDepth",
+                format!("{}-bit color is rejected by most servers at resolutions above 1920x1080", depth),
+            );
+        }
+    }
+
+    // build_rdp_content 只认小写 "ignore"，其他值都按 default 处理
+    if let Some(policy) = conn.cert_policy.as_deref() {
+        match policy {
+            "" | "default" | "ignore" => {}
+            other if other.trim().eq_ignore_ascii_case("ignore") => warn(
+                "certPolicy",
+                format!("Certificate policy '{}' must be lowercase 'ignore'; it is treated as 'default'", other),
+            ),
+            other => warn(
+                "certPolicy",
+                format!("Unknown certificate policy '{}'; expected 'default' or 'ignore'", other),
+            ),
+        }
+    }
+
+    let gateway = conn.gateway_host.as_deref().map(str::trim).unwrap_or("");
+    let gateway_user = conn.gateway_username.as_deref().map(str::trim).unwrap_or("");
+    if !gateway.is_empty() {
+        if gateway.chars().any(char::is_whitespace) {
+            warn("gatewayHost", "Gateway host must not contain whitespace".to_string());
+        }
+        if gateway_user.is_empty() {
+            warn(
+                "gatewayUsername",
+                "Gateway is set without a username; the client will prompt for gateway credentials or fail".to_string(),
+            );
+        }
+    } else {
+        let orphaned = [
+            ("gatewayUsername", conn.gateway_username.as_deref()),
+            ("gatewayPassword", conn.gateway_password.as_deref()),
+            ("gatewayDomain", conn.gateway_domain.as_deref()),
+        ];
+        for (field, value) in orphaned {
+            if value.is_some_and(|value| !value.trim().is_empty()) {
+                warn(field, "Ignored because no gateway host is set".to_string());
+            }
+        }
+    }
+    warnings
+}
+
+// 启动前校验 RDP 设置并返回将写入的 .rdp 内容，不启动客户端
+#[tauri::command]
+fn rdp_validate(connection: RdpConnection) -> RdpValidation {
+    RdpValidation {
+        warnings: validate_rdp_connection(&connection),
+        content: build_rdp_content(&connection),
+    }
+}
+
 #[tauri::command]
 async fn rdp_open(app_handle: AppHandle, connection: RdpConnection) -> Result<(), String> {
-    let base = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| e.to_string())?;
-    let rdp_dir = base.join("rdp");
-    fs::create_dir_all(&rdp_dir).map_err(|e| e.to_string())?;
+    // ephemeral：.rdp 写到仅当前用户可访问的临时目录，客户端退出后删除
+    let ephemeral = connection.ephemeral_file.unwrap_or(false);
+    let rdp_dir = if ephemeral {
+        create_private_temp_dir("noterm-rdp").map_err(|e| e.to_string())?
+    } else {
+        let base = app_handle
+            .path()
+            .app_data_dir()
+            .map_err(|e| e.to_string())?;
+        let rdp_dir = base.join("rdp");
+        fs::create_dir_all(&rdp_dir).map_err(|e| e.to_string())?;
+        rdp_dir
+    };
     let rdp_path = rdp_dir.join(format!("{}.rdp", connection.id));
     let content = build_rdp_content(&connection);
-    fs::write(&rdp_path, content).map_err(|e| e.to_string())?;
+    write_private_file(&rdp_path, &content).map_err(|e| e.to_string())?;
 
     if command_exists("xfreerdp") {
         let mut cmd = Command::new("xfreerdp");
@@ -194,6 +431,10 @@ async fn rdp_open(app_handle: AppHandle, connection: RdpConnection) -> Result<()
             }
         }
         cmd.spawn().map_err(|e| e.to_string())?;
+        // xfreerdp 直接使用命令行参数，不需要 .rdp 文件
+        if ephemeral {
+            let _ = fs::remove_dir_all(&rdp_dir);
+        }
         return Ok(());
     }
 
@@ -210,25 +451,143 @@ async fn rdp_open(app_handle: AppHandle, connection: RdpConnection) -> Result<()
                     .status();
             }
         }
-        Command::new("mstsc")
+        let child = Command::new("mstsc")
             .arg(&rdp_path)
             .spawn()
             .map_err(|e| e.to_string())?;
+        if ephemeral {
+            remove_dir_after_exit(child, rdp_dir, Duration::ZERO);
+        }
         return Ok(());
     }
 
+    if !ephemeral {
+        return open_local_path(&rdp_path, None);
+    }
+
     if cfg!(target_os = "macos") {
-        Command::new("open")
+        // open -W 等待打开的应用退出
+        let child = Command::new("open")
+            .arg("-W")
             .arg(&rdp_path)
             .spawn()
             .map_err(|e| e.to_string())?;
+        remove_dir_after_exit(child, rdp_dir, Duration::ZERO);
         return Ok(());
     }
 
-    Command::new("xdg-open")
+    // xdg-open 启动客户端后立即返回，无法跟踪客户端进程：留出读取文件的时间再删除
+    let child = Command::new("xdg-open")
         .arg(&rdp_path)
         .spawn()
         .map_err(|e| e.to_string())?;
+    remove_dir_after_exit(child, rdp_dir, Duration::from_secs(60));
+    Ok(())
+}
+
+fn create_private_temp_dir(prefix: &str) -> std::io::Result<std::path::PathBuf> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("{}-{}-{}", prefix, std::process::id(), nanos));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+fn write_private_file(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content.as_bytes())
+}
+
+fn remove_dir_after_exit(mut child: std::process::Child, dir: std::path::PathBuf, grace: Duration) {
+    thread_stats::spawn(ThreadKind::Other, move || {
+        let _ = child.wait();
+        std::thread::sleep(grace);
+        let _ = fs::remove_dir_all(&dir);
+    });
+}
+
+// 把编辑器命令行拆成程序与参数（支持单/双引号，不处理反斜杠转义以保留 Windows 路径），不经过 shell
+fn split_command_line(command: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_part = false;
+    let mut quote: Option<char> = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_part = true;
+            }
+            None if c.is_whitespace() => {
+                if in_part {
+                    parts.push(std::mem::take(&mut current));
+                    in_part = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_part = true;
+            }
+        }
+    }
+    if in_part {
+        parts.push(current);
+    }
+    parts
+}
+
+// 用指定程序打开本地文件；未指定时交给系统默认程序。路径来自远端文件名，任何分支都不能经过 shell
+fn open_local_path(path: &std::path::Path, program: Option<&str>) -> Result<(), String> {
+    if let Some(command) = program.map(str::trim).filter(|program| !program.is_empty()) {
+        let parts = split_command_line(command);
+        let (program, args) = parts
+            .split_first()
+            .ok_or_else(|| format!("Invalid editor command '{}'", command))?;
+        Command::new(program)
+            .args(args)
+            .arg(path)
+            .spawn()
+            .map_err(|e| format!("Failed to launch '{}': {}", program, e))?;
+        return Ok(());
+    }
+
+    if cfg!(target_os = "windows") {
+        // explorer 按文件关联打开；它的退出码不可靠，只检查能否启动
+        Command::new("explorer.exe")
+            .arg(path)
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    if cfg!(target_os = "macos") {
+        Command::new("open")
+            .arg(path)
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    Command::new("xdg-open")
+        .arg(path)
+        .spawn()
+        .map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -365,6 +724,37 @@ fn clipboard_write_text(text: String) -> Result<(), String> {
     Err("Clipboard write is not supported on this platform".to_string())
 }
 
+// 与 clipboard_read_text / clipboard_write_text 使用相同的工具顺序做预检
+#[tauri::command]
+async fn clipboard_available() -> Result<ClipboardSupport, String> {
+    tokio::task::spawn_blocking(|| {
+        let (read_tools, write_tools, hint): (&[&str], &[&str], &str) = if cfg!(target_os = "macos") {
+            (&["pbpaste"], &["pbcopy"], "pbcopy/pbpaste not found")
+        } else if cfg!(target_os = "windows") {
+            (&["powershell"], &["clip"], "PowerShell or clip.exe not found")
+        } else if cfg!(target_os = "linux") {
+            (
+                &["wl-paste", "xclip", "xsel"],
+                &["wl-copy", "xclip", "xsel"],
+                "Install wl-clipboard (Wayland) or xclip/xsel (X11)",
+            )
+        } else {
+            (&[], &[], "Clipboard is not supported on this platform")
+        };
+        let read_tool = read_tools.iter().find(|tool| command_exists(tool));
+        let write_tool = write_tools.iter().find(|tool| command_exists(tool));
+        ClipboardSupport {
+            read: read_tool.is_some(),
+            write: write_tool.is_some(),
+            read_tool: read_tool.map(|tool| tool.to_string()),
+            write_tool: write_tool.map(|tool| tool.to_string()),
+            hint: (read_tool.is_none() || write_tool.is_none()).then(|| hint.to_string()),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn ssh_check_endpoint(host: String, port: u16) -> Result<EndpointCheck, String> {
     tokio::task::spawn_blocking(move || {
@@ -426,6 +816,33 @@ fn sanitize_filename(input: &str) -> String {
     }
 }
 
+// 映射到 ssh-keygen -m 的格式名；ed25519 只支持 OpenSSH 格式
+fn key_format_flag(algorithm: &str, format: &str) -> Result<Option<&'static str>, String> {
+    let flag = match format.trim().to_ascii_lowercase().as_str() {
+        "" | "openssh" => return Ok(None),
+        "pem" => "PEM",
+        "pkcs8" => "PKCS8",
+        "rfc4716" => "RFC4716",
+        other => return Err(format!("Unsupported key format: {}", other)),
+    };
+    if algorithm == "ed25519" {
+        return Err(format!("{} format is not supported for ed25519 keys", flag));
+    }
+    Ok(Some(flag))
+}
+
+fn public_export_flag(algorithm: &str, format: &str) -> Result<&'static str, String> {
+    match format.trim().to_ascii_lowercase().as_str() {
+        "rfc4716" | "ssh2" => Ok("RFC4716"),
+        "pem" | "pkcs8" if algorithm == "ed25519" => {
+            Err("PEM public key export is not supported for ed25519 keys".to_string())
+        }
+        "pem" => Ok("PEM"),
+        "pkcs8" => Ok("PKCS8"),
+        other => Err(format!("Unsupported public key export format: {}", other)),
+    }
+}
+
 #[tauri::command]
 async fn ssh_generate_keypair(
     app_handle: AppHandle,
@@ -433,8 +850,20 @@ async fn ssh_generate_keypair(
     name: String,
     passphrase: Option<String>,
     comment: Option<String>,
+    format: Option<String>,
+    public_export: Option<String>,
 ) -> Result<GeneratedKeypair, String> {
     tokio::task::spawn_blocking(move || {
+        let format = format.unwrap_or_else(|| "openssh".to_string());
+        let format_flag = key_format_flag(&algorithm, &format)?;
+        let export_flag = public_export
+            .as_deref()
+            .map(|export| public_export_flag(&algorithm, export))
+            .transpose()?;
+        if !command_exists("ssh-keygen") {
+            return Err("ssh-keygen not found; install OpenSSH client".to_string());
+        }
+
         let base = app_handle
             .path()
             .app_data_dir()
@@ -467,6 +896,9 @@ async fn ssh_generate_keypair(
             }
         }
 
+        if let Some(flag) = format_flag {
+            cmd.args(["-m", flag]);
+        }
         cmd.arg("-f").arg(&key_path);
         cmd.arg("-N").arg(passphrase.clone().unwrap_or_default());
         cmd.arg("-C").arg(comment.clone().unwrap_or_else(|| name.clone()));
@@ -489,12 +921,41 @@ async fn ssh_generate_keypair(
         }
 
         let pub_path = std::path::PathBuf::from(format!("{}.pub", key_path.display()));
-        let public_key = fs::read_to_string(&pub_path).map_err(|e| e.to_string())?;
+        // 密钥对已写入磁盘：之后任一步失败都删除这对文件，不留下调用方拿不到路径的私钥
+        let exported = (|| {
+            let public_key = fs::read_to_string(&pub_path).map_err(|e| e.to_string())?;
+            let public_key_export = match export_flag {
+                Some(flag) => {
+                    let output = Command::new("ssh-keygen")
+                        .args(["-e", "-m", flag, "-f"])
+                        .arg(&pub_path)
+                        .output()
+                        .map_err(|e| format!("Failed to export public key: {}", e))?;
+                    if !output.status.success() {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        return Err(format!("Public key export failed: {}", stderr.trim()));
+                    }
+                    Some(String::from_utf8_lossy(&output.stdout).to_string())
+                }
+                None => None,
+            };
+            Ok((public_key, public_key_export))
+        })();
+        let (public_key, public_key_export) = match exported {
+            Ok(exported) => exported,
+            Err(e) => {
+                let _ = fs::remove_file(&key_path);
+                let _ = fs::remove_file(&pub_path);
+                return Err(e);
+            }
+        };
 
         Ok(GeneratedKeypair {
             key_path: key_path.display().to_string(),
             public_key,
+            public_key_export,
             algorithm,
+            format: format_flag.unwrap_or("OpenSSH").to_string(),
             comment,
         })
     })
@@ -502,14 +963,102 @@ async fn ssh_generate_keypair(
     .map_err(|e| e.to_string())?
 }
 
+// 仅在内存中生成密钥对，不写入 app_data_dir/keys，也不调用 ssh-keygen
 #[tauri::command]
-async fn ssh_connect(
+async fn ssh_generate_keypair_memory(
+    algorithm: String,
+    passphrase: Option<String>,
+    comment: Option<String>,
+) -> Result<MemoryKeypair, String> {
+    use ssh_key::private::{Ed25519Keypair, KeypairData, RsaKeypair};
+    use ssh_key::rand_core::OsRng;
+
+    tokio::task::spawn_blocking(move || {
+        let keypair = match algorithm.as_str() {
+            "ed25519" => KeypairData::from(Ed25519Keypair::random(&mut OsRng)),
+            "rsa4096" => KeypairData::from(RsaKeypair::random(&mut OsRng, 4096).map_err(|e| e.to_string())?),
+            other => return Err(format!("Unsupported key algorithm: {}", other)),
+        };
+        let comment = comment.filter(|value| !value.trim().is_empty());
+        let mut private_key = ssh_key::PrivateKey::new(keypair, comment.clone().unwrap_or_default())
+            .map_err(|e| e.to_string())?;
+        let public_key = private_key.public_key().to_openssh().map_err(|e| e.to_string())?;
+        let fingerprint = private_key
+            .public_key()
+            .fingerprint(ssh_key::HashAlg::Sha256)
+            .to_string();
+        if let Some(passphrase) = passphrase.filter(|value| !value.is_empty()) {
+            private_key = private_key
+                .encrypt(&mut OsRng, passphrase.as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        let private_key = private_key
+            .to_openssh(ssh_key::LineEnding::LF)
+            .map_err(|e| e.to_string())?
+            .to_string();
+
+        Ok(MemoryKeypair {
+            private_key,
+            public_key,
+            fingerprint,
+            algorithm,
+            comment,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn ssh_validate_connection(connection: SshConnection) -> Vec<ConnectionIssue> {
+    ssh_manager::validate_connection(&connection)
+}
+
+#[tauri::command]
+async fn ssh_server_algorithms(host: String, port: u16) -> Result<ServerAlgorithms, String> {
+    tokio::task::spawn_blocking(move || ssh_manager::server_algorithms(&host, port))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_connect_nested(
     state: State<'_, AppState>,
+    outer_session_id: String,
     connection: SshConnection,
 ) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.connect_nested(&outer_session_id, &connection))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_connect_many(
+    state: State<'_, AppState>,
+    connections: Vec<SshConnection>,
+) -> Result<Vec<ConnectResult>, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.connect_many(connections))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_connect(
+    state: State<'_, AppState>,
+    connection: SshConnection,
+) -> Result<ConnectedSession, String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
     tokio::task::spawn_blocking(move || {
-        manager.connect(&connection)
+        let session_id = manager.connect(&connection)?;
+        Ok::<_, anyhow::Error>(ConnectedSession {
+            session_id,
+            username: manager.effective_username(&connection)?,
+            username_fallback: connection.username.trim().is_empty(),
+        })
     })
     .await
     .map_err(|e| e.to_string())?
@@ -517,14 +1066,14 @@ async fn ssh_connect(
 }
 
 #[tauri::command]
-async fn ssh_open_shell(
+async fn ssh_retry_with_passphrase(
     state: State<'_, AppState>,
-    app_handle: AppHandle,
-    session_id: String,
-) -> Result<(), String> {
+    connection: SshConnection,
+    passphrase: String,
+) -> Result<String, String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
     tokio::task::spawn_blocking(move || {
-        manager.open_shell(&session_id, app_handle)
+        manager.retry_with_passphrase(&connection, &passphrase)
     })
     .await
     .map_err(|e| e.to_string())?
@@ -532,66 +1081,470 @@ async fn ssh_open_shell(
 }
 
 #[tauri::command]
-fn ssh_write_to_shell(
-    state: State<AppState>,
+async fn ssh_clone_session(
+    state: State<'_, AppState>,
     session_id: String,
-    data: String,
-) -> Result<(), String> {
-    let manager = state.ssh_manager.lock().unwrap();
-    manager
-        .write_to_shell(&session_id, &data)
+    new_id: String,
+) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.clone_session(&session_id, &new_id))
+        .await
+        .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())
 }
 
-#[tauri::command]
-fn ssh_resize_pty(
-    state: State<AppState>,
-    session_id: String,
-    cols: u32,
-    rows: u32,
-) -> Result<(), String> {
-    let manager = state.ssh_manager.lock().unwrap();
-    manager
-        .resize_pty(&session_id, cols, rows)
-        .map_err(|e| e.to_string())
+// multiplexer 参数早于 options 出现，保留它以兼容旧调用；两者都给出时以 multiplexer 为准
+fn shell_options(multiplexer: Option<MultiplexerAttach>, options: Option<ShellOptions>) -> ShellOptions {
+    let mut options = options.unwrap_or_default();
+    if multiplexer.is_some() {
+        options.multiplexer = multiplexer;
+    }
+    options
 }
 
 #[tauri::command]
-async fn local_open_shell(
+async fn ssh_open_shell(
     state: State<'_, AppState>,
     app_handle: AppHandle,
     session_id: String,
-    shell: Option<String>,
+    multiplexer: Option<MultiplexerAttach>,
+    options: Option<ShellOptions>,
 ) -> Result<(), String> {
-    let manager = state.local_pty_manager.lock().unwrap().clone();
-    tokio::task::spawn_blocking(move || manager.open_shell(&session_id, app_handle, shell))
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let options = shell_options(multiplexer, options);
+    tokio::task::spawn_blocking(move || manager.open_shell(&session_id, app_handle, options))
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_host_probe(state: State<'_, AppState>, session_id: String) -> Result<HostProbe, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.host_probe(&session_id))
         .await
         .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn local_write_to_shell(
-    state: State<AppState>,
-    session_id: String,
-    data: String,
-) -> Result<(), String> {
+async fn ssh_user_info(state: State<'_, AppState>, session_id: String) -> Result<RemoteUserInfo, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.user_info(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_tmux_list(state: State<'_, AppState>, session_id: String) -> Result<Vec<TmuxSession>, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.tmux_list(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+// 以 `tmux attach-session -t <target>` 打开 shell，target 来自 ssh_tmux_list
+#[tauri::command]
+async fn ssh_tmux_attach(
+    state: State<'_, AppState>,
+    app_handle: AppHandle,
+    session_id: String,
+    target: String,
+    options: Option<ShellOptions>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let mut options = options.unwrap_or_default();
+    options.multiplexer = Some(MultiplexerAttach {
+        kind: MultiplexerKind::Tmux,
+        name: target,
+        existing: true,
+    });
+    tokio::task::spawn_blocking(move || manager.open_shell(&session_id, app_handle, options))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_write_to_shell(
+    state: State<AppState>,
+    session_id: String,
+    data: String,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .write_to_shell(&session_id, &data)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_open_subsystem(
+    state: State<'_, AppState>,
+    session_id: String,
+    name: String,
+) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.open_subsystem(&session_id, &name))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_subsystem_write(
+    state: State<AppState>,
+    channel_id: String,
+    data_b64: String,
+) -> Result<(), String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data_b64.as_bytes())
+        .map_err(|e| format!("Invalid base64 data: {}", e))?;
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .write_subsystem(&channel_id, &data)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_subsystem_close(state: State<AppState>, channel_id: String) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .close_subsystem(&channel_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_write_to_shell_bytes(
+    state: State<AppState>,
+    session_id: String,
+    data_b64: String,
+) -> Result<(), String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data_b64.as_bytes())
+        .map_err(|e| format!("Invalid base64 data: {}", e))?;
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .write_bytes_to_shell(&session_id, &data)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_signal_shell(
+    state: State<AppState>,
+    session_id: String,
+    signal: String,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .signal_shell(&session_id, &signal)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_set_latency_monitor(
+    state: State<AppState>,
+    session_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .set_latency_monitor(&session_id, enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_set_shell_title(
+    state: State<AppState>,
+    session_id: String,
+    title: String,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .set_shell_title(&session_id, &title)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_rotate_log(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.rotate_log(&session_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_get_hostkey(state: State<AppState>, session_id: String) -> Result<HostKeyInfo, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.host_key(&session_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_shell_title(state: State<AppState>, session_id: String) -> Option<String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.shell_title(&session_id)
+}
+
+// 运行中切换会话编码，返回规范化后的编码名（如 "GBK"）
+#[tauri::command]
+fn ssh_set_session_encoding(
+    state: State<AppState>,
+    session_id: String,
+    encoding: String,
+) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .set_session_encoding(&session_id, &encoding)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_bracketed_paste(state: State<AppState>, session_id: String) -> bool {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.bracketed_paste(&session_id)
+}
+
+#[tauri::command]
+fn ssh_get_screen(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.get_screen(&session_id).map_err(|e| e.to_string())
+}
+
+// 超过上限时读取线程暂停读取并发出 terminal-throttled；bytes_per_sec 为空或 0 时取消
+#[tauri::command]
+fn ssh_set_output_rate_limit(
+    state: State<AppState>,
+    session_id: String,
+    bytes_per_sec: Option<u64>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .set_output_rate_limit(&session_id, bytes_per_sec)
+        .map_err(|e| e.to_string())
+}
+
+// 分离后远端 shell 继续运行，输出缓存到重新附加；与 ssh_disconnect 不同，不关闭通道
+#[tauri::command]
+fn ssh_detach_shell(state: State<AppState>, session_id: String) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.detach_shell(&session_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_attach_shell(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.attach_shell(&session_id).map_err(|e| e.to_string())
+}
+
+// 无头/PTY 执行场景下远端程序发出 DA、DSR 查询时由后端代答，默认关闭
+#[tauri::command]
+fn ssh_set_terminal_auto_answer(state: State<AppState>, session_id: String, enabled: bool) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.set_terminal_auto_answer(&session_id, enabled).map_err(|e| e.to_string())
+}
+
+// 宏在后台播放，结束时发出 macro-result；macro_id 由前端生成，用于 ssh_abort_macro
+#[tauri::command]
+fn ssh_play_macro(
+    state: State<AppState>,
+    session_id: String,
+    macro_id: String,
+    steps: Vec<MacroStep>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .play_macro(&session_id, &macro_id, steps)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_abort_macro(state: State<AppState>, macro_id: String) -> bool {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.cancel(&macro_id)
+}
+
+// WebSocket 桥默认关闭；关闭时同时停掉所有已启动的桥
+#[tauri::command]
+fn ssh_set_ws_bridge_enabled(state: State<AppState>, enabled: bool) -> usize {
+    ws_bridge::set_enabled(enabled);
+    if enabled {
+        return 0;
+    }
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.stop_all_ws_bridges()
+}
+
+#[tauri::command]
+fn ssh_start_ws_bridge(
+    state: State<AppState>,
+    session_id: String,
+    options: Option<WsBridgeOptions>,
+) -> Result<WsBridgeInfo, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .start_ws_bridge(&session_id, &options.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_stop_ws_bridge(state: State<AppState>, session_id: String) -> bool {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.stop_ws_bridge(&session_id)
+}
+
+#[tauri::command]
+fn ssh_list_ws_bridges(state: State<AppState>) -> Vec<WsBridgeInfo> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.list_ws_bridges()
+}
+
+#[tauri::command]
+fn ssh_shell_buffer_stats(state: State<AppState>, session_id: String) -> Result<ShellBufferStats, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.shell_buffer_stats(&session_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_update_keepalive(
+    state: State<AppState>,
+    session_id: String,
+    secs: u32,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .update_keepalive(&session_id, secs)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_shell_size(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<ShellSizeReport, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.shell_size(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_fix_ssh_perms(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<SshPermsReport, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.fix_ssh_perms(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_resize_pty(
+    state: State<AppState>,
+    session_id: String,
+    cols: u32,
+    rows: u32,
+    pixel_width: Option<u32>,
+    pixel_height: Option<u32>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .resize_pty(&session_id, cols, rows, pixel_width, pixel_height)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn local_open_shell(
+    state: State<'_, AppState>,
+    app_handle: AppHandle,
+    session_id: String,
+    shell: Option<String>,
+    multiplexer: Option<MultiplexerAttach>,
+    options: Option<ShellOptions>,
+    cols: Option<u32>,
+    rows: Option<u32>,
+) -> Result<(), String> {
+    let manager = state.local_pty_manager.lock().unwrap().clone();
+    let options = shell_options(multiplexer, options);
+    let size = cols.zip(rows);
+    tokio::task::spawn_blocking(move || manager.open_shell(&session_id, app_handle, shell, options, size))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn local_restart_shell(
+    state: State<'_, AppState>,
+    app_handle: AppHandle,
+    session_id: String,
+    shell: Option<String>,
+) -> Result<(), String> {
+    let manager = state.local_pty_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.restart_shell(&session_id, app_handle, shell))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn local_write_to_shell(
+    state: State<AppState>,
+    session_id: String,
+    data: String,
+) -> Result<(), String> {
     let manager = state.local_pty_manager.lock().unwrap();
     manager
         .write_to_shell(&session_id, &data)
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn local_write_to_shell_bytes(
+    state: State<AppState>,
+    session_id: String,
+    data_b64: String,
+) -> Result<(), String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data_b64.as_bytes())
+        .map_err(|e| format!("Invalid base64 data: {}", e))?;
+    let manager = state.local_pty_manager.lock().unwrap();
+    manager
+        .write_bytes_to_shell(&session_id, &data)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn local_session_info(
+    state: State<AppState>,
+    session_id: String,
+) -> Result<LocalSessionInfo, String> {
+    let manager = state.local_pty_manager.lock().unwrap();
+    manager
+        .session_info(&session_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn local_bracketed_paste(state: State<AppState>, session_id: String) -> bool {
+    let manager = state.local_pty_manager.lock().unwrap();
+    manager.bracketed_paste(&session_id)
+}
+
 #[tauri::command]
 fn local_resize_pty(
     state: State<AppState>,
     session_id: String,
     cols: u32,
     rows: u32,
+    pixel_width: Option<u32>,
+    pixel_height: Option<u32>,
 ) -> Result<(), String> {
     let manager = state.local_pty_manager.lock().unwrap();
     manager
-        .resize_pty(&session_id, cols, rows)
+        .resize_pty(&session_id, cols, rows, pixel_width, pixel_height)
         .map_err(|e| e.to_string())
 }
 
@@ -603,23 +1556,102 @@ fn local_disconnect(state: State<AppState>, session_id: String) -> Result<(), St
         .map_err(|e| e.to_string())
 }
 
+// stop_forwards 默认为 true：同一连接的端口转发随之停止
 #[tauri::command]
-fn ssh_disconnect(state: State<AppState>, session_id: String) -> Result<(), String> {
+fn ssh_disconnect(
+    state: State<AppState>,
+    session_id: String,
+    stop_forwards: Option<bool>,
+) -> Result<DisconnectSummary, String> {
     let manager = state.ssh_manager.lock().unwrap();
     manager
-        .disconnect(&session_id)
+        .disconnect(&session_id, stop_forwards.unwrap_or(true))
         .map_err(|e| e.to_string())
 }
 
+// 上次运行（含崩溃）结束时仍在使用的连接与转发，密码等机密已去除，由前端提示用户重新连接
+#[tauri::command]
+fn ssh_recover_sessions() -> Option<SessionSnapshot> {
+    session_state::previous()
+}
+
+// 回收已失效会话遗留的读取线程、保活条目等；会等待读取线程退出，放在阻塞线程池中执行
+#[tauri::command]
+async fn ssh_gc_sessions(state: State<'_, AppState>) -> Result<SessionGcReport, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.gc_sessions())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// SSH 会话、转发、SFTP 会话、本地终端以及各类后台线程的当前数量
+#[tauri::command]
+fn app_resource_stats(state: State<AppState>) -> AppResourceStats {
+    let (connections, shells) = {
+        let manager = state.ssh_manager.lock().unwrap();
+        (manager.connection_count(), manager.shell_count())
+    };
+    let local_sessions = state.local_pty_manager.lock().unwrap().session_count();
+    AppResourceStats {
+        connections,
+        shells,
+        local_sessions,
+        threads: thread_stats::snapshot(),
+    }
+}
+
+#[tauri::command]
+fn ssh_set_debug_logging(app_handle: AppHandle, enabled: bool) -> Result<(), String> {
+    let log_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("logs");
+    debug_log::set_enabled(log_dir, enabled).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn ssh_execute_command(
     state: State<AppState>,
     session_id: String,
     command: String,
-) -> Result<String, String> {
-    let manager = state.ssh_manager.lock().unwrap();
-    manager
-        .execute_command(&session_id, &command)
+) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .execute_command(&session_id, &command)
+        .map_err(|e| e.to_string())
+}
+
+// interpreter 缺省为 bash
+#[tauri::command]
+async fn ssh_run_script(
+    state: State<'_, AppState>,
+    session_id: String,
+    script: String,
+    interpreter: Option<ScriptInterpreter>,
+) -> Result<ScriptRunResult, String> {
+    if script.trim().is_empty() {
+        return Err("Script is empty".to_string());
+    }
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.run_script(&session_id, &script, interpreter.unwrap_or_default()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_execute_many(
+    state: State<'_, AppState>,
+    session_ids: Vec<String>,
+    command: String,
+) -> Result<HashMap<String, HostCommandResult>, String> {
+    if command.trim().is_empty() {
+        return Err("Command is empty".to_string());
+    }
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.execute_many(session_ids, &command))
+        .await
         .map_err(|e| e.to_string())
 }
 
@@ -639,6 +1671,36 @@ async fn ssh_execute_command_controlled(
     .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn ssh_exec_merged(
+    state: State<'_, AppState>,
+    app: AppHandle,
+    session_id: String,
+    command: String,
+    stream_id: String,
+    use_pty: Option<bool>,
+) -> Result<MergedExecResult, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let use_pty = use_pty.unwrap_or(false);
+    tokio::task::spawn_blocking(move || {
+        manager.exec_merged(&session_id, &command, &stream_id, use_pty, |seq, data| {
+            let _ = app.emit(
+                "exec-chunk",
+                ExecChunk {
+                    session_id: session_id.clone(),
+                    stream_id: stream_id.clone(),
+                    seq,
+                    data: data.to_string(),
+                    pty_merged: use_pty,
+                },
+            );
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn local_execute_command_controlled(
     session_id: String,
@@ -689,73 +1751,449 @@ async fn local_execute_command_controlled(
 }
 
 #[tauri::command]
-fn ssh_is_connected(state: State<AppState>, session_id: String) -> bool {
-    let manager = state.ssh_manager.lock().unwrap();
-    manager.is_connected(&session_id)
+fn ssh_is_connected(state: State<AppState>, session_id: String, deep: Option<bool>) -> bool {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    manager.is_connected(&session_id, deep.unwrap_or(false))
+}
+
+#[tauri::command]
+fn ssh_list_sessions(state: State<AppState>) -> Vec<String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.list_sessions()
+}
+
+#[tauri::command]
+fn ssh_connection_count(state: State<AppState>) -> ConnectionCount {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.connection_count()
+}
+
+// 最近一次为该连接建立会话（含 SFTP 等辅助会话）的各阶段耗时，失败的连接也会记录
+#[tauri::command]
+fn ssh_last_connect_timing(state: State<AppState>, connection_id: String) -> Option<ConnectTiming> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.last_connect_timing(&connection_id)
+}
+
+// required 为 true 时用户名留空的连接直接失败，而不是退回本机用户名
+#[tauri::command]
+fn ssh_set_require_username(state: State<AppState>, required: bool) {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.set_require_username(required);
+}
+
+#[tauri::command]
+fn ssh_set_max_sessions(state: State<AppState>, limit: Option<usize>) {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.set_max_sessions(limit.unwrap_or(0));
+}
+
+// 从 OpenSSH 配置导入主机；path 为空时读取 ~/.ssh/config
+#[tauri::command]
+async fn ssh_import_config(path: Option<String>) -> Result<Vec<ImportedHost>, String> {
+    tokio::task::spawn_blocking(move || ssh_config::import(path.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_forward_start(
+    state: State<'_, AppState>,
+    config: ForwardConfig,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.start_forward(config))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_forward_stop(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.stop_forward(&id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_forward_list(state: State<AppState>) -> Vec<String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.list_forwards()
+}
+
+#[tauri::command]
+fn ssh_forward_find(
+    state: State<AppState>,
+    target_host: String,
+    target_port: Option<u16>,
+) -> Vec<ForwardInfo> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.find_forwards(&target_host, target_port)
+}
+
+#[tauri::command]
+fn ssh_forward_status(state: State<AppState>, id: String) -> Result<ForwardState, String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.forward_status(&id).map_err(|e| e.to_string())
+}
+
+// keep_existing 默认 true：只拒绝新连接，进行中的连接继续
+#[tauri::command]
+fn ssh_forward_pause(state: State<AppState>, id: String, keep_existing: Option<bool>) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager
+        .pause_forward(&id, keep_existing.unwrap_or(true))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_forward_resume(state: State<AppState>, id: String) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.resume_forward(&id).map_err(|e| e.to_string())
+}
+
+// keepalive 为空时新转发连接不再设置 SO_KEEPALIVE
+#[tauri::command]
+fn ssh_set_forward_keepalive(state: State<AppState>, keepalive: Option<ForwardKeepalive>) {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.set_forward_keepalive(keepalive);
+}
+
+#[tauri::command]
+fn ssh_forward_check_bind(kind: Option<ForwardKind>, bind_host: Option<String>, bind_port: u16) -> BindCheck {
+    ssh_manager::check_bind(&kind.unwrap_or(ForwardKind::Local), bind_host.as_deref(), bind_port)
+}
+
+#[tauri::command]
+async fn ssh_sftp_list_dir(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+    with_inodes: Option<bool>,
+) -> Result<Vec<SftpEntry>, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_list_dir(&session_id, &path, with_inodes.unwrap_or(false)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_features(state: State<'_, AppState>, session_id: String) -> Result<SftpFeatures, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_features(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_change_dir(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<SftpDirListing, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_change_dir(&session_id, &path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_host_stats(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<HostStats, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.host_stats(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_list_interfaces(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<InterfaceList, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.list_interfaces(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_has_sftp(state: State<'_, AppState>, session_id: String) -> Result<bool, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.has_sftp(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_list_dir_via_exec(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<Vec<SftpEntry>, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.list_dir_via_exec(&session_id, &path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_sftp_session_info(state: State<AppState>) -> Vec<SftpSessionInfo> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    manager.sftp_session_info()
+}
+
+#[tauri::command]
+fn ssh_close_idle_sftp(state: State<AppState>, session_id: String) -> bool {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    manager.close_sftp_session(&session_id)
+}
+
+#[tauri::command]
+async fn ssh_sftp_download_file(
+    state: State<'_, AppState>,
+    app: AppHandle,
+    session_id: String,
+    remote_path: String,
+    local_path: String,
+    transfer_id: Option<String>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let transfer_id = transfer_id.unwrap_or_else(|| format!("download:{}", remote_path));
+    let progress = manager.track_transfer(&transfer_id, &session_id, TransferDirection::Download, &remote_path);
+    tokio::task::spawn_blocking(move || {
+        manager.sftp_download_file(&session_id, &remote_path, &local_path, |transferred, total| {
+            progress.update(transferred, total);
+            let percent = if total > 0 {
+                (transferred as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
+            } else {
+                0.0
+            };
+            let _ = app.emit(
+                "sftp-transfer-progress",
+                SftpTransferProgress {
+                    session_id: session_id.clone(),
+                    transfer_id: transfer_id.clone(),
+                    direction: "download".to_string(),
+                    transferred,
+                    total,
+                    percent,
+                },
+            );
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_upload_file(
+    state: State<'_, AppState>,
+    app: AppHandle,
+    session_id: String,
+    local_path: String,
+    remote_path: String,
+    transfer_id: Option<String>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let transfer_id = transfer_id.unwrap_or_else(|| format!("upload:{}", local_path));
+    let progress = manager.track_transfer(&transfer_id, &session_id, TransferDirection::Upload, &remote_path);
+    tokio::task::spawn_blocking(move || {
+        manager.sftp_upload_file(&session_id, &local_path, &remote_path, |transferred, total| {
+            progress.update(transferred, total);
+            let percent = if total > 0 {
+                (transferred as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
+            } else {
+                0.0
+            };
+            let _ = app.emit(
+                "sftp-transfer-progress",
+                SftpTransferProgress {
+                    session_id: session_id.clone(),
+                    transfer_id: transfer_id.clone(),
+                    direction: "upload".to_string(),
+                    transferred,
+                    total,
+                    percent,
+                },
+            );
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_upload_dir(
+    state: State<'_, AppState>,
+    app: AppHandle,
+    session_id: String,
+    local_dir: String,
+    remote_dir: String,
+    transfer_id: Option<String>,
+    options: Option<DirTransferOptions>,
+) -> Result<SftpUploadDirSummary, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let transfer_id = transfer_id.unwrap_or_else(|| format!("upload-dir:{}", local_dir));
+    let options = options.unwrap_or_default();
+    let progress = manager.track_transfer(&transfer_id, &session_id, TransferDirection::Upload, &remote_dir);
+    tokio::task::spawn_blocking(move || {
+        manager.sftp_upload_dir(&session_id, &local_dir, &remote_dir, &transfer_id, &options, |transferred, total| {
+            progress.update(transferred, total);
+            let percent = if total > 0 {
+                (transferred as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
+            } else {
+                0.0
+            };
+            let _ = app.emit(
+                "sftp-transfer-progress",
+                SftpTransferProgress {
+                    session_id: session_id.clone(),
+                    transfer_id: transfer_id.clone(),
+                    direction: "upload".to_string(),
+                    transferred,
+                    total,
+                    percent,
+                },
+            );
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
 }
 
+// 依次传输不同大小的数据并读取套接字参数，给出 MTU/窗口相关的提示；最长约 90 秒
 #[tauri::command]
-fn ssh_list_sessions(state: State<AppState>) -> Vec<String> {
-    let manager = state.ssh_manager.lock().unwrap();
-    manager.list_sessions()
+async fn ssh_path_diagnostics(state: State<'_, AppState>, session_id: String) -> Result<PathDiagnostics, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.path_diagnostics(&session_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
 }
 
+// symlink_policy 默认为 skip；以 cancelTransfer(transfer_id) 中止
 #[tauri::command]
-async fn ssh_forward_start(
+async fn ssh_sftp_download_dir(
     state: State<'_, AppState>,
-    config: ForwardConfig,
-) -> Result<(), String> {
+    app: AppHandle,
+    session_id: String,
+    remote_dir: String,
+    local_dir: String,
+    transfer_id: Option<String>,
+    symlink_policy: Option<SymlinkPolicy>,
+) -> Result<SftpDownloadDirSummary, String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
-    tokio::task::spawn_blocking(move || manager.start_forward(config))
-        .await
-        .map_err(|e| e.to_string())?
-        .map_err(|e| e.to_string())
+    let transfer_id = transfer_id.unwrap_or_else(|| format!("download-dir:{}", remote_dir));
+    let symlink_policy = symlink_policy.unwrap_or_default();
+    let progress = manager.track_transfer(&transfer_id, &session_id, TransferDirection::Download, &remote_dir);
+    tokio::task::spawn_blocking(move || {
+        manager.sftp_download_dir(&session_id, &remote_dir, &local_dir, &transfer_id, symlink_policy, |transferred, total| {
+            progress.update(transferred, total);
+            let percent = if total > 0 {
+                (transferred as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
+            } else {
+                0.0
+            };
+            let _ = app.emit(
+                "sftp-transfer-progress",
+                SftpTransferProgress {
+                    session_id: session_id.clone(),
+                    transfer_id: transfer_id.clone(),
+                    direction: "download".to_string(),
+                    transferred,
+                    total,
+                    percent,
+                },
+            );
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
 }
 
+// 单个文件传输后的校验；返回 None 表示一致，否则为不一致的原因
 #[tauri::command]
-async fn ssh_forward_stop(
+async fn ssh_sftp_verify_file(
     state: State<'_, AppState>,
-    id: String,
-) -> Result<(), String> {
+    session_id: String,
+    local_path: String,
+    remote_path: String,
+    verify_hash: Option<bool>,
+) -> Result<Option<String>, String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
-    tokio::task::spawn_blocking(move || manager.stop_forward(&id))
+    let verify = TransferVerify {
+        size: true,
+        hash: verify_hash.unwrap_or(false),
+    };
+    tokio::task::spawn_blocking(move || manager.sftp_verify_file(&session_id, &local_path, &remote_path, verify))
         .await
         .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())
 }
 
+// 最近完成的上传/下载/复制记录，旧的在前；断开连接后清空
+// 所有会话上进行中的传输
 #[tauri::command]
-fn ssh_forward_list(state: State<AppState>) -> Vec<String> {
+fn ssh_active_transfers(state: State<AppState>) -> Vec<ActiveTransfer> {
     let manager = state.ssh_manager.lock().unwrap();
-    manager.list_forwards()
+    manager.active_transfers()
 }
 
 #[tauri::command]
-async fn ssh_sftp_list_dir(
+fn ssh_transfer_history(state: State<AppState>, session_id: String) -> Vec<TransferRecord> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.transfer_history(&session_id)
+}
+
+// 未完成的上传/下载，断开后仍保留，供重连后续传
+#[tauri::command]
+async fn ssh_list_interrupted_transfers(
     state: State<'_, AppState>,
     session_id: String,
-    path: String,
-) -> Result<Vec<SftpEntry>, String> {
+) -> Result<Vec<InterruptedTransfer>, String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
-    tokio::task::spawn_blocking(move || manager.sftp_list_dir(&session_id, &path))
+    tokio::task::spawn_blocking(move || manager.interrupted_transfers(&session_id))
         .await
-        .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())
 }
 
+// 进度通过 sftp-transfer-progress 发出，transfer_id 即中断记录的 id
 #[tauri::command]
-async fn ssh_sftp_download_file(
+async fn ssh_resume_transfer(
     state: State<'_, AppState>,
     app: AppHandle,
-    session_id: String,
-    remote_path: String,
-    local_path: String,
-    transfer_id: Option<String>,
+    transfer_id: String,
 ) -> Result<(), String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
-    let transfer_id = transfer_id.unwrap_or_else(|| format!("download:{}", remote_path));
+    let entry = manager.interrupted_transfer(&transfer_id).map_err(|e| e.to_string())?;
+    let direction = match entry.direction {
+        TransferDirection::Download => "download",
+        _ => "upload",
+    };
+    let progress = manager.track_transfer(&transfer_id, &entry.session_id, entry.direction, &entry.remote_path);
     tokio::task::spawn_blocking(move || {
-        manager.sftp_download_file(&session_id, &remote_path, &local_path, |transferred, total| {
+        manager.resume_transfer(&transfer_id, |transferred, total| {
+            progress.update(transferred, total);
             let percent = if total > 0 {
                 (transferred as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
             } else {
@@ -764,9 +2202,9 @@ async fn ssh_sftp_download_file(
             let _ = app.emit(
                 "sftp-transfer-progress",
                 SftpTransferProgress {
-                    session_id: session_id.clone(),
+                    session_id: entry.session_id.clone(),
                     transfer_id: transfer_id.clone(),
-                    direction: "download".to_string(),
+                    direction: direction.to_string(),
                     transferred,
                     total,
                     percent,
@@ -780,18 +2218,21 @@ async fn ssh_sftp_download_file(
 }
 
 #[tauri::command]
-async fn ssh_sftp_upload_file(
+async fn ssh_sftp_remote_to_remote(
     state: State<'_, AppState>,
     app: AppHandle,
-    session_id: String,
-    local_path: String,
-    remote_path: String,
+    src_session: String,
+    src_path: String,
+    dst_session: String,
+    dst_path: String,
     transfer_id: Option<String>,
-) -> Result<(), String> {
+) -> Result<u64, String> {
     let manager = state.ssh_manager.lock().unwrap().clone();
-    let transfer_id = transfer_id.unwrap_or_else(|| format!("upload:{}", local_path));
+    let transfer_id = transfer_id.unwrap_or_else(|| format!("remote:{}:{}", src_session, src_path));
+    let progress = manager.track_transfer(&transfer_id, &dst_session, TransferDirection::Copy, &dst_path);
     tokio::task::spawn_blocking(move || {
-        manager.sftp_upload_file(&session_id, &local_path, &remote_path, |transferred, total| {
+        manager.sftp_remote_to_remote(&src_session, &src_path, &dst_session, &dst_path, &transfer_id, |transferred, total| {
+            progress.update(transferred, total);
             let percent = if total > 0 {
                 (transferred as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
             } else {
@@ -800,9 +2241,9 @@ async fn ssh_sftp_upload_file(
             let _ = app.emit(
                 "sftp-transfer-progress",
                 SftpTransferProgress {
-                    session_id: session_id.clone(),
+                    session_id: dst_session.clone(),
                     transfer_id: transfer_id.clone(),
-                    direction: "upload".to_string(),
+                    direction: "remote".to_string(),
                     transferred,
                     total,
                     percent,
@@ -815,6 +2256,189 @@ async fn ssh_sftp_upload_file(
     .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn ssh_sftp_sync(
+    state: State<'_, AppState>,
+    session_id: String,
+    local_dir: String,
+    remote_dir: String,
+    delete_extraneous: Option<bool>,
+    exclude: Option<Vec<String>>,
+) -> Result<SftpSyncSummary, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let exclude = exclude.unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        manager.sftp_sync(&session_id, &local_dir, &remote_dir, delete_extraneous.unwrap_or(false), &exclude)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_edit_file(
+    state: State<'_, AppState>,
+    session_id: String,
+    remote_path: String,
+    editor: Option<String>,
+    watch: Option<bool>,
+) -> Result<SftpEditInfo, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || {
+        let info = manager
+            .sftp_edit_begin(&session_id, &remote_path)
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = open_local_path(std::path::Path::new(&info.local_path), editor.as_deref()) {
+            let _ = manager.sftp_edit_close(&info.edit_id);
+            return Err(e);
+        }
+        if watch.unwrap_or(false) {
+            manager.watch_edit(&info.edit_id);
+        }
+        Ok(info)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn ssh_sftp_edit_commit(
+    state: State<'_, AppState>,
+    edit_id: String,
+) -> Result<bool, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_edit_commit(&edit_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_sftp_edit_close(state: State<AppState>, edit_id: String) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap();
+    manager.sftp_edit_close(&edit_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_stream_read(
+    state: State<'_, AppState>,
+    app: AppHandle,
+    session_id: String,
+    path: String,
+    stream_id: String,
+    chunk_size: Option<usize>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || {
+        let chunk_size = chunk_size.unwrap_or(64 * 1024);
+        manager.sftp_stream_read(&session_id, &path, &stream_id, chunk_size, |seq, data, done, cancelled| {
+            let _ = app.emit(
+                "sftp-chunk",
+                SftpChunk {
+                    session_id: session_id.clone(),
+                    stream_id: stream_id.clone(),
+                    seq,
+                    data_b64: base64::engine::general_purpose::STANDARD.encode(data),
+                    done,
+                    cancelled,
+                },
+            );
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ssh_sftp_stream_stop(state: State<AppState>, stream_id: String) -> bool {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    manager.cancel(&stream_id)
+}
+
+#[tauri::command]
+async fn ssh_sftp_stream_write_begin(
+    state: State<'_, AppState>,
+    session_id: String,
+    stream_id: String,
+    path: String,
+    atomic: Option<bool>,
+) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || {
+        manager.sftp_stream_write_begin(&session_id, &stream_id, &path, atomic.unwrap_or(false))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_stream_write_chunk(
+    state: State<'_, AppState>,
+    stream_id: String,
+    data_b64: String,
+) -> Result<u64, String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data_b64.as_bytes())
+        .map_err(|e| format!("Invalid base64 chunk: {}", e))?;
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_stream_write_chunk(&stream_id, &data))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_stream_write_abort(state: State<'_, AppState>, stream_id: String) -> Result<(), String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_stream_write_abort(&stream_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_stream_write_end(
+    state: State<'_, AppState>,
+    stream_id: String,
+) -> Result<u64, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_stream_write_end(&stream_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_tail_lines(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+    n: usize,
+) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_tail_lines(&session_id, &path, n))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_sftp_preview(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+    max_bytes: Option<usize>,
+) -> Result<SftpPreview, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    let max_bytes = max_bytes.unwrap_or(64 * 1024);
+    tokio::task::spawn_blocking(move || manager.sftp_preview(&session_id, &path, max_bytes))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn ssh_sftp_rename(
     state: State<'_, AppState>,
@@ -872,6 +2496,32 @@ async fn ssh_sftp_mkdir(
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn ssh_sftp_realpath(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<String, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.sftp_realpath(&session_id, &path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ssh_get_env(
+    state: State<'_, AppState>,
+    session_id: String,
+    name: String,
+) -> Result<Option<String>, String> {
+    let manager = state.ssh_manager.lock().unwrap().clone();
+    tokio::task::spawn_blocking(move || manager.get_env(&session_id, &name))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -882,9 +2532,37 @@ pub fn run() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_http::init())
         .setup(|app| {
+            // 取不到数据目录时跳过调试日志和会话状态保存，不中断启动。
+            // 调试日志未开启时没有 tracing 订阅者，启动阶段的错误直接写到 stderr
+            let data_dir = app.path().app_data_dir();
+            if let Err(e) = &data_dir {
+                eprintln!("app data directory unavailable: {}", e);
+            }
+            // 先初始化调试日志，后续启动过程的记录才能写入
+            if let (true, Ok(data_dir)) = (debug_log::env_enabled(), &data_dir) {
+                if let Err(e) = debug_log::set_enabled(data_dir.join("logs"), true) {
+                    eprintln!("failed to enable debug logging: {}", e);
+                }
+            }
+            // 更新插件初始化失败只记录，不中断启动
             #[cfg(desktop)]
-            app.handle()
-                .plugin(tauri_plugin_updater::Builder::new().build())?;
+            if env_flag_enabled(NO_UPDATER_ENV) {
+                tracing::info!(env = NO_UPDATER_ENV, "updater disabled");
+            } else if let Err(e) = app
+                .handle()
+                .plugin(tauri_plugin_updater::Builder::new().build())
+            {
+                eprintln!("failed to initialize updater: {}", e);
+            }
+            app.state::<AppState>()
+                .ssh_manager
+                .lock()
+                .unwrap()
+                .attach_app_handle(app.handle().clone());
+            let manager = app.state::<AppState>().ssh_manager.lock().unwrap().clone();
+            if let Ok(data_dir) = data_dir {
+                session_state::start(manager, data_dir);
+            }
             Ok(())
         })
         .manage(AppState {
@@ -895,34 +2573,150 @@ pub fn run() {
             greet,
             clipboard_read_text,
             clipboard_write_text,
+            clipboard_available,
             ssh_check_endpoint,
+            check_local_tools,
             ssh_generate_keypair,
+            ssh_generate_keypair_memory,
             rdp_open,
+            rdp_validate,
+            ssh_validate_connection,
+            ssh_get_hostkey,
+            ssh_server_algorithms,
             ssh_connect,
+            ssh_connect_many,
+            ssh_connect_nested,
+            ssh_retry_with_passphrase,
+            ssh_clone_session,
             ssh_open_shell,
+            ssh_host_probe,
+            ssh_user_info,
+            ssh_tmux_list,
+            ssh_tmux_attach,
             ssh_write_to_shell,
+            ssh_write_to_shell_bytes,
+            ssh_open_subsystem,
+            ssh_subsystem_write,
+            ssh_subsystem_close,
+            ssh_signal_shell,
+            ssh_update_keepalive,
+            ssh_set_latency_monitor,
+            ssh_set_shell_title,
+            ssh_shell_title,
+            ssh_bracketed_paste,
+            ssh_set_session_encoding,
+            ssh_get_screen,
+            ssh_shell_buffer_stats,
+            ssh_set_output_rate_limit,
+            ssh_rotate_log,
             ssh_resize_pty,
+            ssh_shell_size,
+            ssh_fix_ssh_perms,
             ssh_disconnect,
+            ssh_set_debug_logging,
             local_open_shell,
+            local_restart_shell,
             local_write_to_shell,
+            local_write_to_shell_bytes,
             local_resize_pty,
+            local_session_info,
+            local_bracketed_paste,
             local_disconnect,
             ssh_execute_command,
             ssh_execute_command_controlled,
+            ssh_exec_merged,
+            ssh_execute_many,
             local_execute_command_controlled,
             ssh_is_connected,
             ssh_list_sessions,
+            ssh_connection_count,
+            ssh_set_max_sessions,
+            ssh_set_require_username,
+            ssh_import_config,
             ssh_forward_start,
             ssh_forward_stop,
             ssh_forward_list,
+            ssh_forward_find,
+            ssh_forward_status,
+            ssh_forward_pause,
+            ssh_forward_resume,
+            ssh_set_forward_keepalive,
+            app_resource_stats,
+            ssh_run_script,
+            ssh_list_interrupted_transfers,
+            ssh_resume_transfer,
+            ssh_sftp_change_dir,
+            ssh_active_transfers,
+            ssh_sftp_features,
+            ssh_last_connect_timing,
+            ssh_detach_shell,
+            ssh_attach_shell,
+            ssh_set_terminal_auto_answer,
+            ssh_gc_sessions,
+            ssh_play_macro,
+            ssh_abort_macro,
+            ssh_set_ws_bridge_enabled,
+            ssh_start_ws_bridge,
+            ssh_stop_ws_bridge,
+            ssh_list_ws_bridges,
+            ssh_recover_sessions,
+            ssh_path_diagnostics,
+            ssh_forward_check_bind,
             ssh_sftp_list_dir,
+            ssh_has_sftp,
+            ssh_list_dir_via_exec,
+            ssh_host_stats,
+            ssh_list_interfaces,
+            ssh_sftp_session_info,
+            ssh_close_idle_sftp,
             ssh_sftp_download_file,
             ssh_sftp_upload_file,
+            ssh_sftp_upload_dir,
+            ssh_sftp_download_dir,
+            ssh_sftp_remote_to_remote,
+            ssh_transfer_history,
+            ssh_sftp_verify_file,
+            ssh_sftp_sync,
+            ssh_sftp_edit_file,
+            ssh_sftp_edit_commit,
+            ssh_sftp_edit_close,
+            ssh_sftp_stream_read,
+            ssh_sftp_stream_stop,
+            ssh_sftp_stream_write_begin,
+            ssh_sftp_stream_write_abort,
+            ssh_sftp_stream_write_chunk,
+            ssh_sftp_stream_write_end,
+            ssh_sftp_tail_lines,
+            ssh_sftp_preview,
             ssh_sftp_rename,
             ssh_sftp_chmod,
             ssh_sftp_delete,
-            ssh_sftp_mkdir
+            ssh_sftp_mkdir,
+            ssh_sftp_realpath,
+            ssh_get_env
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                session_state::mark_clean_exit();
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_command_line_handles_quotes() {
+        assert_eq!(split_command_line("code --wait"), vec!["code", "--wait"]);
+        assert_eq!(
+            split_command_line(r#""C:\Program Files\Notepad++\notepad++.exe" -multiInst"#),
+            vec![r"C:\Program Files\Notepad++\notepad++.exe", "-multiInst"]
+        );
+        assert_eq!(split_command_line("  subl   -n  'my dir/x'"), vec!["subl", "-n", "my dir/x"]);
+        assert_eq!(split_command_line(r#"vim """#), vec!["vim", ""]);
+        assert!(split_command_line("   ").is_empty());
+    }
 }
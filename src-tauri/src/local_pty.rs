@@ -2,9 +2,13 @@ use portable_pty::{native_pty_system, CommandBuilder, PtySize};
 use serde::Serialize;
 use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use tauri::Emitter;
 use std::path::Path;
+use crate::command_exists;
+use crate::thread_stats::{self, ThreadKind};
+use crate::ssh_manager::{BracketedPasteChanged, BracketedPasteTracker, DisconnectReason, ShellOptions};
 
 #[derive(Clone, Serialize)]
 struct TerminalOutput {
@@ -12,21 +16,83 @@ struct TerminalOutput {
     data: String,
 }
 
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalSessionInfo {
+    pub shell: String,
+    pub pid: Option<u32>,
+    pub cwd: Option<String>, // 平台不支持或无权限时为空
+}
+
+// 与 SSH 会话的 terminal-disconnected 负载一致
+#[derive(Clone, Serialize)]
+struct TerminalDisconnected {
+    session_id: String,
+    reason: String,
+    detail: DisconnectReason,
+}
+
+// 子进程退出后仍有输入写入时返回，代替含糊的 Broken pipe。
+// 命令层只传字符串，前端按 PROCESS_EXITED_ERROR 前缀识别（见 isProcessExitedError）
+pub const PROCESS_EXITED_ERROR: &str = "Local process has exited";
+
+#[derive(Debug)]
+pub struct ProcessExited {
+    pub exit_code: u32,
+}
+
+impl std::fmt::Display for ProcessExited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} with code {}", PROCESS_EXITED_ERROR, self.exit_code)
+    }
+}
+
+impl std::error::Error for ProcessExited {}
+
+#[derive(Clone, Serialize)]
+struct TerminalRestarted {
+    session_id: String,
+    shell: String,
+}
+
 struct LocalPtySession {
     master: Mutex<Box<dyn portable_pty::MasterPty + Send>>,
     writer: Mutex<Box<dyn Write + Send>>,
     child: Mutex<Box<dyn portable_pty::Child + Send>>,
+    shell: String,
+    options: ShellOptions, // 打开时的参数与最近的尺寸，重启时沿用
+    size: Mutex<(u32, u32)>,
+    disconnected: Arc<AtomicBool>, // terminal-disconnected 已发出（或会话被主动关闭）
+    on_disconnect: DisconnectNotifier,
+}
+
+// 参数为子进程退出码；open_shell 中用它发出 terminal-disconnected
+type DisconnectNotifier = Arc<dyn Fn(Option<u32>) + Send + Sync>;
+
+impl LocalPtySession {
+    // 子进程已退出时返回退出码
+    fn exit_code(&self) -> Option<u32> {
+        let status = self.child.lock().ok()?.try_wait().ok()??;
+        Some(status.exit_code())
+    }
+
+    fn process_exited(&self, exit_code: u32) -> anyhow::Error {
+        notify_disconnected(&self.disconnected, &self.on_disconnect, Some(exit_code));
+        ProcessExited { exit_code }.into()
+    }
 }
 
 #[derive(Clone)]
 pub struct LocalPtyManager {
     sessions: Arc<Mutex<HashMap<String, LocalPtySession>>>,
+    bracketed_paste: Arc<Mutex<HashMap<String, bool>>>,
 }
 
 impl LocalPtyManager {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            bracketed_paste: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -35,20 +101,33 @@ impl LocalPtyManager {
         session_id: &str,
         app_handle: tauri::AppHandle,
         shell: Option<String>,
+        options: ShellOptions,
+        size: Option<(u32, u32)>,
     ) -> anyhow::Result<()> {
-        let _ = self.disconnect(session_id);
+        let (mut cmd, shell_path) = match &options.multiplexer {
+            Some(attach) => {
+                let args = attach.args()?;
+                let program = attach.program();
+                if !command_exists(program) {
+                    return Err(anyhow::anyhow!("{} not found in PATH", program));
+                }
+                let mut cmd = CommandBuilder::new(program);
+                cmd.args(args);
+                (cmd, program.to_string())
+            }
+            None => {
+                let shell_path = resolve_shell_path(shell);
+                (CommandBuilder::new(&shell_path), shell_path)
+            }
+        };
 
-        let shell_path = resolve_shell_path(shell);
+        let _ = self.disconnect(session_id);
 
         let pty_system = native_pty_system();
-        let pair = pty_system.openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })?;
-
-        let mut cmd = CommandBuilder::new(shell_path);
+        // 直接以前端给出的尺寸创建 PTY，避免全屏程序启动后再被 resize 重排
+        let (cols, rows) = size.unwrap_or((80, 24));
+        let pair = pty_system.openpty(pty_size(cols, rows, 0, 0))?;
+
         cmd.env("TERM", "xterm-256color");
         if let Some(home) = resolve_home_dir() {
             cmd.cwd(home);
@@ -61,6 +140,12 @@ impl LocalPtyManager {
         let writer = pair.master.take_writer()?;
         let master = pair.master;
 
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let on_disconnect: DisconnectNotifier = {
+            let app_handle = app_handle.clone();
+            let session_id = session_id.to_string();
+            Arc::new(move |exit_code| emit_disconnected(&app_handle, &session_id, exit_code))
+        };
         let mut sessions = self.sessions.lock().unwrap();
         sessions.insert(
             session_id.to_string(),
@@ -68,17 +153,42 @@ impl LocalPtyManager {
                 master: Mutex::new(master),
                 writer: Mutex::new(writer),
                 child: Mutex::new(child),
+                shell: shell_path,
+                options: options.clone(),
+                size: Mutex::new((cols, rows)),
+                disconnected: disconnected.clone(),
+                on_disconnect: on_disconnect.clone(),
             },
         );
         drop(sessions);
 
         let session_id = session_id.to_string();
-        std::thread::spawn(move || {
-            let mut buffer = [0u8; 8192];
+        let buffer_size = options.read_buffer_size();
+        let paste_map = self.bracketed_paste.clone();
+        let sessions_map = self.sessions.clone();
+        thread_stats::spawn(ThreadKind::Reader, move || {
+            let mut buffer = vec![0u8; buffer_size];
+            let mut paste_tracker = BracketedPasteTracker::default();
             loop {
                 match reader.read(&mut buffer) {
                     Ok(0) => break,
                     Ok(n) => {
+                        if let Some(enabled) = paste_tracker.feed(&buffer[..n]) {
+                            let changed = paste_map
+                                .lock()
+                                .ok()
+                                .map(|mut modes| modes.insert(session_id.clone(), enabled) != Some(enabled))
+                                .unwrap_or(false);
+                            if changed {
+                                let _ = app_handle.emit(
+                                    "bracketed-paste",
+                                    BracketedPasteChanged {
+                                        session_id: session_id.clone(),
+                                        enabled,
+                                    },
+                                );
+                            }
+                        }
                         let output = String::from_utf8_lossy(&buffer[..n]).to_string();
                         let _ = app_handle.emit(
                             "terminal-output",
@@ -94,46 +204,114 @@ impl LocalPtyManager {
                     Err(_) => break,
                 }
             }
+            // 主动断开或重启时 disconnected 已置位，不发事件
+            let exit_code = sessions_map
+                .lock()
+                .ok()
+                .and_then(|sessions| sessions.get(&session_id)?.exit_code());
+            notify_disconnected(&disconnected, &on_disconnect, exit_code);
         });
 
         Ok(())
     }
 
+    // 以指定 shell 重启会话，保持 session id 不变；先发出标记事件让前端清屏
+    pub fn restart_shell(
+        &self,
+        session_id: &str,
+        app_handle: tauri::AppHandle,
+        shell: Option<String>,
+    ) -> anyhow::Result<()> {
+        let shell_path = resolve_shell_path(shell);
+        let previous = self.sessions.lock().unwrap().get(session_id).map(|session| {
+            let size = *session.size.lock().unwrap();
+            (session.options.clone(), size)
+        });
+        let (options, size) = match previous {
+            Some((options, size)) => (options, Some(size)),
+            None => (ShellOptions::default(), None),
+        };
+        self.disconnect(session_id)?;
+        let _ = app_handle.emit(
+            "terminal-restarted",
+            TerminalRestarted {
+                session_id: session_id.to_string(),
+                shell: shell_path.clone(),
+            },
+        );
+        self.open_shell(session_id, app_handle, Some(shell_path), options, size)
+    }
+
     pub fn write_to_shell(&self, session_id: &str, data: &str) -> anyhow::Result<()> {
+        self.write_bytes_to_shell(session_id, data.as_bytes())
+    }
+
+    pub fn write_bytes_to_shell(&self, session_id: &str, data: &[u8]) -> anyhow::Result<()> {
         let sessions = self.sessions.lock().unwrap();
         let session = sessions
             .get(session_id)
             .ok_or_else(|| anyhow::anyhow!("Local session not found"))?;
 
-        let mut writer = session.writer.lock().unwrap();
-        writer.write_all(data.as_bytes())?;
-        writer.flush()?;
-
-        Ok(())
+        write_session(session, data)
     }
 
-    pub fn resize_pty(&self, session_id: &str, cols: u32, rows: u32) -> anyhow::Result<()> {
+    pub fn resize_pty(
+        &self,
+        session_id: &str,
+        cols: u32,
+        rows: u32,
+        pixel_width: Option<u32>,
+        pixel_height: Option<u32>,
+    ) -> anyhow::Result<()> {
         let sessions = self.sessions.lock().unwrap();
         let session = sessions
             .get(session_id)
             .ok_or_else(|| anyhow::anyhow!("Local session not found"))?;
 
         let master = session.master.lock().unwrap();
-        let safe_cols = std::cmp::min(cols, u16::MAX as u32) as u16;
-        let safe_rows = std::cmp::min(rows, u16::MAX as u32) as u16;
-        master.resize(PtySize {
-            rows: safe_rows,
-            cols: safe_cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })?;
+        master.resize(pty_size(
+            cols,
+            rows,
+            pixel_width.unwrap_or(0),
+            pixel_height.unwrap_or(0),
+        ))?;
+        *session.size.lock().unwrap() = (cols, rows);
 
         Ok(())
     }
 
+    pub fn bracketed_paste(&self, session_id: &str) -> bool {
+        self.bracketed_paste
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn session_info(&self, session_id: &str) -> anyhow::Result<LocalSessionInfo> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Local session not found"))?;
+
+        let pid = session.child.lock().unwrap().process_id();
+        Ok(LocalSessionInfo {
+            shell: session.shell.clone(),
+            pid,
+            cwd: pid.and_then(process_cwd),
+        })
+    }
+
     pub fn disconnect(&self, session_id: &str) -> anyhow::Result<()> {
+        self.bracketed_paste.lock().unwrap().remove(session_id);
         let mut sessions = self.sessions.lock().unwrap();
         if let Some(session) = sessions.remove(session_id) {
+            session.disconnected.store(true, Ordering::SeqCst);
             if let Ok(mut child) = session.child.lock() {
                 let _ = child.kill();
                 let _ = child.wait();
@@ -144,6 +322,57 @@ impl LocalPtyManager {
     }
 }
 
+fn write_session(session: &LocalPtySession, data: &[u8]) -> anyhow::Result<()> {
+    if let Some(code) = session.exit_code() {
+        return Err(session.process_exited(code));
+    }
+    let mut writer = session.writer.lock().unwrap();
+    if let Err(e) = writer.write_all(data).and_then(|_| writer.flush()) {
+        // 写入与子进程退出同时发生时也按已退出处理
+        if let Some(code) = session.exit_code() {
+            return Err(session.process_exited(code));
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+// 每个会话只通知一次
+fn notify_disconnected(disconnected: &AtomicBool, on_disconnect: &DisconnectNotifier, exit_code: Option<u32>) {
+    if !disconnected.swap(true, Ordering::SeqCst) {
+        on_disconnect(exit_code);
+    }
+}
+
+fn emit_disconnected(app_handle: &tauri::AppHandle, session_id: &str, exit_code: Option<u32>) {
+    let detail = match exit_code {
+        Some(code) => DisconnectReason::Exited {
+            exit_status: code as i32,
+        },
+        None => DisconnectReason::Closed,
+    };
+    let _ = app_handle.emit(
+        "terminal-disconnected",
+        TerminalDisconnected {
+            session_id: session_id.to_string(),
+            reason: detail.label(),
+            detail,
+        },
+    );
+}
+
+// 尺寸超出 u16 时截断；0 行/列没有意义，退回默认的 80x24
+fn pty_size(cols: u32, rows: u32, pixel_width: u32, pixel_height: u32) -> PtySize {
+    let safe_cols = std::cmp::min(cols, u16::MAX as u32) as u16;
+    let safe_rows = std::cmp::min(rows, u16::MAX as u32) as u16;
+    PtySize {
+        rows: if safe_rows == 0 { 24 } else { safe_rows },
+        cols: if safe_cols == 0 { 80 } else { safe_cols },
+        pixel_width: std::cmp::min(pixel_width, u16::MAX as u32) as u16,
+        pixel_height: std::cmp::min(pixel_height, u16::MAX as u32) as u16,
+    }
+}
+
 fn resolve_shell_path(shell: Option<String>) -> String {
     let from_input = shell
         .and_then(|value| {
@@ -189,7 +418,40 @@ fn resolve_shell_path(shell: Option<String>) -> String {
         .unwrap_or_else(|| "/bin/bash".to_string())
 }
 
-fn resolve_home_dir() -> Option<String> {
+#[cfg(target_os = "linux")]
+fn process_cwd(pid: u32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn process_cwd(pid: u32) -> Option<String> {
+    let mut info: libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as libc::c_int;
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDVNODEPATHINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+    let path = unsafe { std::ffi::CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr() as *const libc::c_char) };
+    Some(path.to_string_lossy().to_string()).filter(|path| !path.is_empty())
+}
+
+// Windows 读取其他进程的当前目录需要读取其 PEB，暂不支持
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn process_cwd(_pid: u32) -> Option<String> {
+    None
+}
+
+pub(crate) fn resolve_home_dir() -> Option<String> {
     if cfg!(target_os = "windows") {
         std::env::var("USERPROFILE")
             .ok()
@@ -200,3 +462,44 @@ fn resolve_home_dir() -> Option<String> {
             .filter(|value| !value.trim().is_empty())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[cfg(unix)]
+    #[test]
+    fn write_after_exit_returns_process_exited() {
+        let pair = native_pty_system().openpty(pty_size(80, 24, 0, 0)).unwrap();
+        let mut cmd = CommandBuilder::new("sh");
+        cmd.args(["-c", "exit 3"]);
+        let mut child = pair.slave.spawn_command(cmd).unwrap();
+        drop(pair.slave);
+        child.wait().unwrap();
+
+        let notified = Arc::new(AtomicUsize::new(0));
+        let counter = notified.clone();
+        let session = LocalPtySession {
+            writer: Mutex::new(pair.master.take_writer().unwrap()),
+            master: Mutex::new(pair.master),
+            child: Mutex::new(child),
+            shell: "sh".to_string(),
+            options: ShellOptions::default(),
+            size: Mutex::new((80, 24)),
+            disconnected: Arc::new(AtomicBool::new(false)),
+            on_disconnect: Arc::new(move |exit_code| {
+                assert_eq!(exit_code, Some(3));
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        };
+
+        for _ in 0..2 {
+            let err = write_session(&session, b"ls\n").unwrap_err();
+            assert_eq!(err.downcast_ref::<ProcessExited>().map(|e| e.exit_code), Some(3));
+            assert!(err.to_string().starts_with(PROCESS_EXITED_ERROR));
+        }
+        // 断开事件只发一次
+        assert_eq!(notified.load(Ordering::SeqCst), 1);
+    }
+}
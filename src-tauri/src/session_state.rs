@@ -0,0 +1,135 @@
+use crate::ssh_manager::{AuthType, ForwardConfig, SshConnection, SshManager};
+use crate::thread_stats::{self, ThreadKind};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 崩溃恢复：定期把当前连接与转发配置写入 app_data_dir/session_state.json，密码、私钥内容、口令
+// 与远端环境变量（常含 token）不落盘，keychain 引用保留（连接时自行读取）。
+// 正常退出时写入 session_state.clean 标记；启动时只有缺少该标记（上次是崩溃或被杀）才保留上次的内容，
+// 前端通过 ssh_recover_sessions 取得并提示重新连接，缺少的密码需用户重新输入
+const STATE_FILE: &str = "session_state.json";
+const CLEAN_EXIT_FILE: &str = "session_state.clean";
+const SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+static PREVIOUS: Mutex<Option<SessionSnapshot>> = Mutex::new(None);
+static STATE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSnapshot {
+    pub saved_at: u64, // Unix 毫秒
+    pub connections: Vec<SshConnection>,
+    pub forwards: Vec<ForwardConfig>,
+}
+
+// 上次运行留下的状态；没有活动连接与转发时为 None
+pub fn previous() -> Option<SessionSnapshot> {
+    PREVIOUS.lock().unwrap().clone()
+}
+
+pub fn start(manager: SshManager, dir: PathBuf) {
+    let path = dir.join(STATE_FILE);
+    let marker = dir.join(CLEAN_EXIT_FILE);
+    let clean_exit = marker.exists();
+    if clean_exit {
+        if let Err(e) = std::fs::remove_file(&marker) {
+            tracing::warn!(path = %marker.display(), error = %e, "failed to remove clean exit marker");
+        }
+    }
+    *STATE_DIR.lock().unwrap() = Some(dir.clone());
+    match load(&path) {
+        Ok(Some(_)) if clean_exit => {}
+        Ok(Some(snapshot)) if !snapshot.connections.is_empty() || !snapshot.forwards.is_empty() => {
+            tracing::info!(
+                connections = snapshot.connections.len(),
+                forwards = snapshot.forwards.len(),
+                "previous session state found"
+            );
+            *PREVIOUS.lock().unwrap() = Some(snapshot);
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to read session state"),
+    }
+
+    thread_stats::spawn(ThreadKind::Other, move || {
+        // 内容不变时不重写文件
+        let mut last_saved: Option<String> = None;
+        loop {
+            let (connections, forwards) = manager.recovery_state();
+            let snapshot = SessionSnapshot {
+                saved_at: 0,
+                connections: connections.iter().map(without_secrets).collect(),
+                forwards: forwards
+                    .into_iter()
+                    .map(|forward| ForwardConfig {
+                        connection: without_secrets(&forward.connection),
+                        ..forward
+                    })
+                    .collect(),
+            };
+            let key = serde_json::to_string(&snapshot).unwrap_or_default();
+            if last_saved.as_deref() != Some(key.as_str()) {
+                match save(&dir, &path, snapshot) {
+                    Ok(()) => last_saved = Some(key),
+                    Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to save session state"),
+                }
+            }
+            std::thread::sleep(SAVE_INTERVAL);
+        }
+    });
+}
+
+// 由 RunEvent::Exit 调用；之后自动保存线程即使再写一次也不影响判断
+pub fn mark_clean_exit() {
+    let Some(dir) = STATE_DIR.lock().unwrap().clone() else {
+        return;
+    };
+    let marker = dir.join(CLEAN_EXIT_FILE);
+    if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| crate::write_private_file(&marker, "")) {
+        tracing::warn!(path = %marker.display(), error = %e, "failed to write clean exit marker");
+    }
+}
+
+fn load(path: &Path) -> anyhow::Result<Option<SessionSnapshot>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(serde_json::from_str(&content)?))
+}
+
+// 先写临时文件再改名，崩溃时不会留下半个文件
+fn save(dir: &Path, path: &Path, mut snapshot: SessionSnapshot) -> anyhow::Result<()> {
+    snapshot.saved_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+    std::fs::create_dir_all(dir)?;
+    let tmp = path.with_extension("json.tmp");
+    crate::write_private_file(&tmp, &serde_json::to_string_pretty(&snapshot)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn without_secrets(connection: &SshConnection) -> SshConnection {
+    let auth_type = match &connection.auth_type {
+        AuthType::Password { keychain, .. } => AuthType::Password {
+            password: String::new(),
+            keychain: keychain.clone(),
+        },
+        AuthType::PrivateKey { key_path, .. } => AuthType::PrivateKey {
+            key_path: key_path.clone(),
+            key_content: None,
+            passphrase: None,
+        },
+        agent @ AuthType::Agent { .. } => agent.clone(),
+    };
+    SshConnection {
+        auth_type,
+        env: None,
+        ..connection.clone()
+    }
+}
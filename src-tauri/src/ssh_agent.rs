@@ -0,0 +1,127 @@
+use libssh2_sys as raw;
+use ssh2::{Error, ErrorCode, Session};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+
+// ssh2::Agent 只能通过进程环境变量 SSH_AUTH_SOCK 选择 agent，而多线程进程里修改环境变量
+// 与其他线程的 getenv 构成数据竞争。这里直接调用 libssh2 的 agent API，
+// 用 libssh2_agent_set_identity_path 把 socket 路径交给这一个 agent 实例
+extern "C" {
+    fn libssh2_agent_set_identity_path(agent: *mut raw::LIBSSH2_AGENT, path: *const c_char);
+}
+
+pub struct Agent<'a> {
+    sess: &'a Session,
+    raw: *mut raw::LIBSSH2_AGENT,
+    _path: Option<CString>, // libssh2 只保存指针，需与 agent 同生命周期
+}
+
+pub struct AgentIdentity {
+    raw: *mut raw::libssh2_agent_publickey,
+    comment: String,
+    blob: Vec<u8>,
+}
+
+impl AgentIdentity {
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    pub fn blob(&self) -> &[u8] {
+        &self.blob
+    }
+}
+
+impl<'a> Agent<'a> {
+    // path 为 None 时由 libssh2 按默认方式查找（SSH_AUTH_SOCK / Pageant / OpenSSH 命名管道）
+    pub fn connect(sess: &'a Session, path: Option<&str>) -> Result<Agent<'a>, Error> {
+        let path = path
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| Error::new(ErrorCode::Session(raw::LIBSSH2_ERROR_BAD_USE), "Agent socket path contains NUL"))?;
+        let agent = {
+            let session = sess.raw();
+            let session_ptr = &*session as *const raw::LIBSSH2_SESSION as *mut raw::LIBSSH2_SESSION;
+            let agent = unsafe { raw::libssh2_agent_init(session_ptr) };
+            if agent.is_null() {
+                return Err(last_error(session_ptr, raw::LIBSSH2_ERROR_ALLOC));
+            }
+            if let Some(path) = &path {
+                unsafe { libssh2_agent_set_identity_path(agent, path.as_ptr()) };
+            }
+            agent
+        };
+        let agent = Agent { sess, raw: agent, _path: path };
+        agent.call(|raw| unsafe { raw::libssh2_agent_connect(raw) })?;
+        Ok(agent)
+    }
+
+    // 返回的身份在 agent 释放或再次列出前有效
+    pub fn identities(&self) -> Result<Vec<AgentIdentity>, Error> {
+        self.call(|raw| unsafe { raw::libssh2_agent_list_identities(raw) })?;
+        let mut identities = Vec::new();
+        let mut prev: *mut raw::libssh2_agent_publickey = std::ptr::null_mut();
+        loop {
+            let mut next: *mut raw::libssh2_agent_publickey = std::ptr::null_mut();
+            let rc = unsafe { raw::libssh2_agent_get_identity(self.raw, &mut next, prev) };
+            if rc == 1 {
+                break;
+            }
+            if rc < 0 {
+                let session = self.sess.raw();
+                return Err(last_error(&*session as *const _ as *mut _, rc));
+            }
+            let identity = unsafe { &*next };
+            let blob = unsafe { std::slice::from_raw_parts(identity.blob, identity.blob_len as usize) }.to_vec();
+            let comment = if identity.comment.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(identity.comment) }.to_string_lossy().into_owned()
+            };
+            identities.push(AgentIdentity { raw: next, comment, blob });
+            prev = next;
+        }
+        Ok(identities)
+    }
+
+    pub fn userauth(&self, username: &str, identity: &AgentIdentity) -> Result<(), Error> {
+        let username = CString::new(username)?;
+        self.call(|raw| unsafe { raw::libssh2_agent_userauth(raw, username.as_ptr(), identity.raw) })
+    }
+
+    // 持有会话锁调用，失败时取会话的最后错误
+    fn call(&self, f: impl FnOnce(*mut raw::LIBSSH2_AGENT) -> c_int) -> Result<(), Error> {
+        let session = self.sess.raw();
+        let rc = f(self.raw);
+        if rc < 0 {
+            return Err(last_error(&*session as *const _ as *mut _, rc));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Agent<'_> {
+    fn drop(&mut self) {
+        let _session = self.sess.raw();
+        unsafe {
+            raw::libssh2_agent_disconnect(self.raw);
+            raw::libssh2_agent_free(self.raw);
+        }
+    }
+}
+
+fn last_error(session: *mut raw::LIBSSH2_SESSION, rc: c_int) -> Error {
+    Error::last_session_error_raw(session).unwrap_or_else(|| Error::from_errno(ErrorCode::Session(rc)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_uses_given_socket_path() {
+        let sess = Session::new().unwrap();
+        let missing = std::env::temp_dir().join("noterm-missing-agent.sock");
+        assert!(Agent::connect(&sess, Some(&missing.to_string_lossy())).is_err());
+    }
+}
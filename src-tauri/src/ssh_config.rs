@@ -0,0 +1,446 @@
+use crate::local_pty::resolve_home_dir;
+use crate::ssh_manager::{AuthType, SshConnection};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// 从 OpenSSH 客户端配置（默认 ~/.ssh/config）导入连接。支持的指令：
+//   Host、Match（all / host / originalhost / user / localuser，条件前可加 ! 取反）、
+//   HostName、Port、User、IdentityFile、IdentityAgent、SetEnv、RequestTTY。
+// 与 ssh 相同，同一选项以最先匹配到的值为准；路径只展开开头的 ~。
+// 其余指令（Include、ProxyJump 等）以及含 exec / canonical 等条件的 Match 块会被忽略，
+// 并在 ignored 中列出，方便前端提示。
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedHost {
+    pub connection: SshConnection,
+    pub request_tty: Option<bool>, // RequestTTY no 为 false，yes/force/auto 为 true
+    pub ignored: Vec<String>,
+}
+
+enum Selector {
+    Host(Vec<String>),
+    Match(Vec<String>),
+}
+
+struct Section {
+    selector: Selector,
+    options: Vec<(String, Vec<String>)>, // 原始关键字 + 参数
+}
+
+#[derive(Default)]
+struct Resolved {
+    host_name: Option<String>,
+    port: Option<u16>,
+    user: Option<String>,
+    identity_file: Option<String>,
+    identity_agent: Option<String>,
+    env: Vec<(String, String)>,
+    request_tty: Option<bool>,
+    ignored: Vec<String>,
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    resolve_home_dir().map(|home| Path::new(&home).join(".ssh").join("config"))
+}
+
+pub fn import(path: Option<&str>) -> anyhow::Result<Vec<ImportedHost>> {
+    let path = match path.map(str::trim).filter(|path| !path.is_empty()) {
+        Some(path) => PathBuf::from(expand_tilde(path)),
+        None => default_config_path().ok_or_else(|| anyhow::anyhow!("Home directory not found"))?,
+    };
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", path.display(), e))?;
+    Ok(parse(&content))
+}
+
+pub fn parse(content: &str) -> Vec<ImportedHost> {
+    let sections = parse_sections(content);
+    let mut aliases: Vec<String> = Vec::new();
+    for section in &sections {
+        if let Selector::Host(patterns) = &section.selector {
+            for pattern in patterns {
+                let concrete = !pattern.starts_with('!') && !pattern.contains(['*', '?']);
+                if concrete && !aliases.contains(pattern) {
+                    aliases.push(pattern.clone());
+                }
+            }
+        }
+    }
+
+    let local_user = local_user();
+    aliases
+        .into_iter()
+        .map(|alias| {
+            let resolved = resolve(&sections, &alias, &local_user);
+            let username = resolved.user.clone().unwrap_or_else(|| local_user.clone());
+            let env: HashMap<String, String> = resolved.env.iter().cloned().collect();
+            ImportedHost {
+                connection: SshConnection {
+                    id: format!("ssh-config:{}", alias),
+                    name: alias.clone(),
+                    host: resolved.host_name.clone().unwrap_or_else(|| alias.clone()),
+                    port: resolved.port.unwrap_or(22),
+                    username,
+                    auth_type: auth_type(&resolved),
+                    encoding: None,
+                    env: (!env.is_empty()).then_some(env),
+                    host_key_algorithms: None,
+                },
+                request_tty: resolved.request_tty,
+                ignored: resolved.ignored,
+            }
+        })
+        .collect()
+}
+
+fn parse_sections(content: &str) -> Vec<Section> {
+    // Host 之前的选项对所有主机生效
+    let mut sections = vec![Section {
+        selector: Selector::Host(vec!["*".to_string()]),
+        options: Vec::new(),
+    }];
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let split = line
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(line.len());
+        let keyword = &line[..split];
+        let rest = line[split..].trim_start().trim_start_matches('=').trim_start();
+        let args = split_args(rest);
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => sections.push(Section {
+                selector: Selector::Host(args),
+                options: Vec::new(),
+            }),
+            "match" => sections.push(Section {
+                selector: Selector::Match(args),
+                options: Vec::new(),
+            }),
+            _ => {
+                if let Some(section) = sections.last_mut() {
+                    section.options.push((keyword.to_string(), args));
+                }
+            }
+        }
+    }
+    sections
+}
+
+// 按空白切分参数，支持双引号包裹含空格的值
+fn split_args(rest: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut has_token = false;
+    for c in rest.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                has_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if has_token {
+                    args.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        args.push(current);
+    }
+    args
+}
+
+fn resolve(sections: &[Section], alias: &str, local_user: &str) -> Resolved {
+    let mut resolved = Resolved::default();
+    for section in sections {
+        let applies = match &section.selector {
+            Selector::Host(patterns) => match_pattern_list(patterns, alias),
+            Selector::Match(criteria) => match_criteria(criteria, alias, &resolved, local_user)
+                .unwrap_or_else(|unsupported| {
+                    note_ignored(&mut resolved.ignored, format!("Match {}", unsupported));
+                    false
+                }),
+        };
+        if !applies {
+            continue;
+        }
+        for (keyword, args) in &section.options {
+            apply_option(&mut resolved, keyword, args);
+        }
+    }
+    resolved
+}
+
+fn apply_option(resolved: &mut Resolved, keyword: &str, args: &[String]) {
+    let Some(value) = args.first().cloned() else {
+        return;
+    };
+    match keyword.to_ascii_lowercase().as_str() {
+        "hostname" => {
+            resolved.host_name.get_or_insert(value);
+        }
+        "port" => {
+            if resolved.port.is_none() {
+                resolved.port = value.parse::<u16>().ok().filter(|port| *port > 0);
+            }
+        }
+        "user" => {
+            resolved.user.get_or_insert(value);
+        }
+        "identityfile" => {
+            resolved.identity_file.get_or_insert_with(|| expand_tilde(&value));
+        }
+        "identityagent" => {
+            resolved.identity_agent.get_or_insert(value);
+        }
+        "setenv" => {
+            for assignment in args {
+                if let Some((name, value)) = assignment.split_once('=') {
+                    if !name.is_empty() && !resolved.env.iter().any(|(existing, _)| existing == name) {
+                        resolved.env.push((name.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+        "requesttty" => {
+            resolved
+                .request_tty
+                .get_or_insert(!value.eq_ignore_ascii_case("no"));
+        }
+        _ => note_ignored(&mut resolved.ignored, keyword.to_string()),
+    }
+}
+
+fn note_ignored(ignored: &mut Vec<String>, entry: String) {
+    if !ignored.iter().any(|existing| existing.eq_ignore_ascii_case(&entry)) {
+        ignored.push(entry);
+    }
+}
+
+// 返回 Err(条件名) 表示遇到不支持的条件，此时整个 Match 块不生效
+fn match_criteria(criteria: &[String], alias: &str, resolved: &Resolved, local_user: &str) -> Result<bool, String> {
+    let mut matched = true;
+    let mut iter = criteria.iter();
+    while let Some(criterion) = iter.next() {
+        let (negate, name) = match criterion.strip_prefix('!') {
+            Some(name) => (true, name),
+            None => (false, criterion.as_str()),
+        };
+        let name = name.to_ascii_lowercase();
+        let result = match name.as_str() {
+            "all" => true,
+            "host" | "originalhost" | "user" | "localuser" => {
+                let Some(arg) = iter.next() else {
+                    return Err(name);
+                };
+                let patterns: Vec<String> = arg.split(',').map(str::to_string).collect();
+                let subject = match name.as_str() {
+                    "host" => resolved.host_name.as_deref().unwrap_or(alias),
+                    "originalhost" => alias,
+                    "user" => resolved.user.as_deref().unwrap_or(local_user),
+                    _ => local_user,
+                };
+                match_pattern_list(&patterns, subject)
+            }
+            _ => return Err(name),
+        };
+        matched = matched && (result != negate);
+    }
+    Ok(matched)
+}
+
+// 与 ssh 一致：任一取反模式命中则不匹配，否则任一普通模式命中即匹配
+fn match_pattern_list(patterns: &[String], subject: &str) -> bool {
+    let subject = subject.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in patterns.iter().flat_map(|pattern| pattern.split(',')) {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, &subject) => return false,
+            Some(_) => {}
+            None => matched = matched || wildcard_match(&pattern, &subject),
+        }
+    }
+    matched
+}
+
+fn wildcard_match(pattern: &str, subject: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let subject: Vec<char> = subject.chars().collect();
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < subject.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == subject[s]) {
+            p += 1;
+            s += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, s));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            s = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// IdentityAgent 优先：指定了 agent 时用 agent，并把 IdentityFile 对应的 .pub 作为要尝试的密钥；
+// 只有 IdentityFile 时用私钥文件；都没有时与 ssh 默认行为一样走 SSH_AUTH_SOCK
+fn auth_type(resolved: &Resolved) -> AuthType {
+    let agent = resolved.identity_agent.as_deref();
+    match (agent, &resolved.identity_file) {
+        (Some(agent), identity) if !agent.eq_ignore_ascii_case("none") => {
+            let agent_sock = match agent {
+                "SSH_AUTH_SOCK" | "$SSH_AUTH_SOCK" | "${SSH_AUTH_SOCK}" => None,
+                path => Some(expand_tilde(path)),
+            };
+            let identity = identity
+                .as_ref()
+                .map(|key| format!("{}.pub", key))
+                .filter(|public| Path::new(public).exists());
+            AuthType::Agent { agent_sock, identity }
+        }
+        (_, Some(key_path)) => AuthType::PrivateKey {
+            key_path: key_path.clone(),
+            key_content: None,
+            passphrase: None,
+        },
+        (Some(_), None) => AuthType::Password {
+            password: String::new(),
+            keychain: None,
+        },
+        (None, None) => AuthType::Agent {
+            agent_sock: None,
+            identity: None,
+        },
+    }
+}
+
+fn expand_tilde(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') => {
+            match resolve_home_dir() {
+                Some(home) => format!("{}{}", home, rest),
+                None => path.to_string(),
+            }
+        }
+        _ => path.to_string(),
+    }
+}
+
+fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_str(config: &str, alias: &str) -> Resolved {
+        resolve(&parse_sections(config), alias, "me")
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let config = "Host web\n  User alice\n  Port 2222\nHost *\n  User root\n  Port 22\n  HostName fallback\n";
+        let resolved = resolve_str(config, "web");
+        assert_eq!(resolved.user.as_deref(), Some("alice"));
+        assert_eq!(resolved.port, Some(2222));
+        assert_eq!(resolved.host_name.as_deref(), Some("fallback"));
+    }
+
+    #[test]
+    fn host_patterns_and_negation() {
+        let config = "Host *.internal !bastion.internal\n  User deploy\n";
+        let cases = [
+            ("app.internal", Some("deploy")),
+            ("bastion.internal", None),
+            ("app.example.com", None),
+        ];
+        for (alias, user) in cases {
+            assert_eq!(resolve_str(config, alias).user.as_deref(), user, "{}", alias);
+        }
+    }
+
+    #[test]
+    fn match_criteria_table() {
+        let config = "\
+Host web
+  HostName web.example.com
+Match host *.example.com
+  Port 2200
+Match user admin
+  IdentityFile /keys/admin
+Match !host *.example.com
+  Port 2300
+Match originalhost web !user root
+  RequestTTY no
+Match exec true
+  User nobody
+";
+        let cases = [
+            // alias, port, identity, request_tty
+            ("web", Some(2200), None, Some(false)),
+            ("db", Some(2300), None, None),
+        ];
+        for (alias, port, identity, tty) in cases {
+            let resolved = resolve_str(config, alias);
+            assert_eq!(resolved.port, port, "{}", alias);
+            assert_eq!(resolved.identity_file.as_deref(), identity, "{}", alias);
+            assert_eq!(resolved.request_tty, tty, "{}", alias);
+            // 不支持的条件使整个块不生效并被记录
+            assert_eq!(resolved.user, None);
+            assert!(resolved.ignored.iter().any(|entry| entry == "Match exec"));
+        }
+
+        let resolved = resolve_str("Host db\n  User admin\nMatch user admin\n  IdentityFile /keys/admin\n", "db");
+        assert_eq!(resolved.identity_file.as_deref(), Some("/keys/admin"));
+    }
+
+    #[test]
+    fn quoted_arguments_and_key_value_syntax() {
+        let config = "Host=box\n  IdentityFile=\"/path with space/id\"\n  Port = 2022\n  SetEnv A=1 \"B=two words\"\n";
+        let resolved = resolve_str(config, "box");
+        assert_eq!(resolved.identity_file.as_deref(), Some("/path with space/id"));
+        assert_eq!(resolved.port, Some(2022));
+        assert_eq!(
+            resolved.env,
+            vec![("A".to_string(), "1".to_string()), ("B".to_string(), "two words".to_string())]
+        );
+        assert_eq!(split_args("a \"\" b"), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn wildcard_match_backtracks() {
+        let cases = [
+            ("*", "", true),
+            ("?", "", false),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("*ab", "aab", true),
+            ("a*a", "aa", true),
+            ("a*?c", "abc", true),
+            ("a*c", "abcd", false),
+            ("*.example.com", "x.y.example.com", true),
+            ("web-??", "web-1", false),
+        ];
+        for (pattern, subject, expected) in cases {
+            assert_eq!(wildcard_match(pattern, subject), expected, "{} ~ {}", pattern, subject);
+        }
+    }
+}
@@ -1,13 +1,14 @@
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use ssh2::Session;
 use ssh2::FileStat;
 use ssh2::{OpenFlags, OpenType};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 #[cfg(target_os = "windows")]
@@ -15,10 +16,11 @@ use std::fs::OpenOptions;
 #[cfg(target_os = "windows")]
 use std::io::ErrorKind;
 #[cfg(target_os = "windows")]
-use std::path::PathBuf;
-#[cfg(target_os = "windows")]
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::Emitter;
+use crate::ssh_agent;
+use crate::thread_stats::{self, ThreadKind};
+use crate::ws_bridge::{WsBridge, WsBridgeInfo, WsBridgeOptions};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SshConnection {
@@ -29,17 +31,208 @@ pub struct SshConnection {
     pub username: String,
     pub auth_type: AuthType,
     pub encoding: Option<String>,
+    pub env: Option<HashMap<String, String>>,
+    // 主机密钥算法偏好（逗号分隔，如 "ssh-ed25519,rsa-sha2-512"），为空时使用 libssh2 默认顺序
+    #[serde(default)]
+    pub host_key_algorithms: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum AuthType {
-    Password { password: String },
+    // keychain 非空时忽略 password，连接时从系统凭据库读取密码（不会回传给前端）
+    Password {
+        #[serde(default)]
+        password: String,
+        #[serde(default)]
+        keychain: Option<KeychainRef>,
+    },
     PrivateKey { 
         key_path: String, 
         key_content: Option<String>,
         passphrase: Option<String> 
     },
+    // 使用 SSH agent；agent_sock 为空时使用 SSH_AUTH_SOCK。
+    // Windows 上可填 "pageant" 或 "openssh" 选择 agent。
+    // identity 指定只尝试 agent 中的某把密钥（注释或 .pub 文件路径），
+    // 避免 agent 逐个尝试所有密钥触发服务端 MaxAuthTries
+    Agent {
+        agent_sock: Option<String>,
+        #[serde(default)]
+        identity: Option<String>,
+    },
+}
+
+// 系统凭据库（Keychain / Secret Service / Credential Manager）中的条目。
+// service 只能是 NoTerm 自己的命名空间，否则前端脚本可以让后端读出任意凭据再发给任意主机
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeychainRef {
+    pub service: String,
+    pub account: String,
+}
+
+const KEYCHAIN_SERVICE: &str = "NoTerm";
+
+impl KeychainRef {
+    // "NoTerm" 或 "NoTerm:<任意后缀>"
+    fn check_service(&self) -> anyhow::Result<()> {
+        let service = self.service.trim();
+        let owned = service
+            .strip_prefix(KEYCHAIN_SERVICE)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'));
+        if !owned {
+            return Err(anyhow::anyhow!(
+                "Keychain service must be '{}' or start with '{}:'",
+                KEYCHAIN_SERVICE,
+                KEYCHAIN_SERVICE
+            ));
+        }
+        Ok(())
+    }
+
+    fn resolve(&self) -> anyhow::Result<String> {
+        self.check_service()?;
+        let entry = keyring::Entry::new(self.service.trim(), self.account.trim())
+            .map_err(|e| anyhow::anyhow!("Failed to open keychain entry: {}", e))?;
+        entry.get_password().map_err(|e| match e {
+            keyring::Error::NoEntry => anyhow::anyhow!(
+                "No keychain entry for service '{}' and account '{}'",
+                self.service.trim(),
+                self.account.trim()
+            ),
+            e => anyhow::anyhow!("Failed to read keychain entry: {}", e),
+        })
+    }
+}
+
+const OPENSSH_AGENT_PIPE: &str = r"\\.\pipe\openssh-ssh-agent";
+
+impl AuthType {
+    // Safe label for logs; never log the variant itself since it carries secrets.
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuthType::Password { keychain: Some(_), .. } => "password-keychain",
+            AuthType::Password { .. } => "password",
+            AuthType::PrivateKey { key_content: Some(content), .. } if !content.is_empty() => {
+                "private-key-content"
+            }
+            AuthType::PrivateKey { .. } => "private-key-file",
+            AuthType::Agent { .. } => "agent",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MultiplexerKind {
+    Tmux,
+    Screen,
+}
+
+// 终端复用器会话：打开 shell 时附加到（或创建）指定名称的 tmux/screen 会话。
+// existing 为 true 时只附加已存在的目标（tmux 可写 session:window），不存在则报错
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiplexerAttach {
+    pub kind: MultiplexerKind,
+    pub name: String,
+    #[serde(default)]
+    pub existing: bool,
+}
+
+impl MultiplexerAttach {
+    pub fn program(&self) -> &'static str {
+        match self.kind {
+            MultiplexerKind::Tmux => "tmux",
+            MultiplexerKind::Screen => "screen",
+        }
+    }
+
+    // Attach to the named session, creating it when it does not exist yet.
+    pub fn args(&self) -> anyhow::Result<Vec<String>> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Multiplexer session name is empty"));
+        }
+        if self.existing {
+            // 已有会话的名称不受我们控制，只拒绝控制字符；SSH 侧拼命令时会整体加引号
+            if name.chars().any(char::is_control) {
+                return Err(anyhow::anyhow!("Invalid multiplexer target '{}'", name.escape_debug()));
+            }
+            let args: &[&str] = match self.kind {
+                MultiplexerKind::Tmux => &["attach-session", "-t"],
+                MultiplexerKind::Screen => &["-D", "-r"],
+            };
+            let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            args.push(name.to_string());
+            return Ok(args);
+        }
+        if !name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid multiplexer session name '{}': use letters, digits, '-' or '_'",
+                name
+            ));
+        }
+        let args: &[&str] = match self.kind {
+            MultiplexerKind::Tmux => &["new-session", "-A", "-s"],
+            MultiplexerKind::Screen => &["-D", "-R", "-S"],
+        };
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        args.push(name.to_string());
+        Ok(args)
+    }
+}
+
+pub const DEFAULT_READ_BUFFER: usize = 8192;
+pub const DEFAULT_KEEPALIVE_SECS: u32 = 15;
+
+// 打开 shell 时的可选参数（SSH 与本地共用）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShellOptions {
+    pub multiplexer: Option<MultiplexerAttach>,
+    // 读取缓冲区大小，限制在 1 KiB..=256 KiB。本地 PTY 每次 read 只返回约 2 KiB，
+    // 实测 256 MiB 连续输出时 8K 与 64K 吞吐相同（约 180 MB/s）；SSH 通道单次读取受包大小限制
+    pub buffer_size: Option<usize>,
+    // shell 启动后依次发送的登录脚本（仅 SSH），用于“先登 A 再手动 ssh B”这类书签
+    pub login_script: Option<Vec<LoginStep>>,
+    // 打开后设置的终端标题（仅 SSH）
+    pub title: Option<String>,
+    // 会话日志（仅 SSH）：原始输出追加写入 log_path，超过 log_max_bytes 时自动轮转
+    pub log_path: Option<String>,
+    pub log_max_bytes: Option<u64>,
+}
+
+// 登录脚本的一步：先等待 wait_for 正则匹配自上一步以来的输出（或等待 delay_ms），再发送 send 并回车
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LoginStep {
+    pub send: String,
+    pub wait_for: Option<String>,
+    pub delay_ms: Option<u64>,
+    pub timeout_ms: Option<u64>, // 等待 wait_for 的超时，默认 DEFAULT_LOGIN_STEP_TIMEOUT
+}
+
+// 输入宏的一步：先发送 send（base64 编码的原始字节），再等待 wait_for 正则匹配发送之后的输出，
+// 或固定等待 wait_ms；两者都给出时先匹配再等待
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MacroStep {
+    pub send: String,
+    pub wait_ms: Option<u64>,
+    pub wait_for: Option<String>,
+    pub timeout_ms: Option<u64>, // 等待 wait_for 的超时，默认 DEFAULT_LOGIN_STEP_TIMEOUT
+}
+
+impl ShellOptions {
+    pub fn read_buffer_size(&self) -> usize {
+        self.buffer_size
+            .unwrap_or(DEFAULT_READ_BUFFER)
+            .clamp(1024, 256 * 1024)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +249,252 @@ pub struct SftpEntry {
     pub size: Option<u64>,
     pub modified: Option<u64>,
     pub perm: Option<u32>,
+    // SFTP 的 stat 不含 inode 与硬链接数，只在 sftp_list_dir 的 with_inodes 为 true 时
+    // 通过 exec（find -printf，退回 ls -lai）补齐；无法执行命令时保持为空
+    pub inode: Option<u64>,
+    pub nlink: Option<u64>,
+}
+
+// 切换目录的结果：path 为解析后的绝对路径
+#[derive(Debug, Clone, Serialize)]
+pub struct SftpDirListing {
+    pub path: String,
+    pub entries: Vec<SftpEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpSyncSummary {
+    pub uploaded: u32,
+    pub skipped: u32,
+    pub deleted: u32,
+    pub excluded: u32, // 被 exclude 排除的本地条目，排除的目录计为一个
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpUploadDirSummary {
+    pub uploaded: u32,
+    pub skipped: u32, // 上次中断前已完成、远端大小一致而跳过的文件
+    pub total: u32,
+    pub cancelled: bool,
+    pub verified: u32,
+    pub mismatched: Vec<SftpVerifyMismatch>, // 校验失败的文件不记入续传清单，下次会重新上传
+    pub excluded: u32,
+}
+
+// 传输后校验：大小几乎无开销，默认开启；哈希需要远端 sha256sum/shasum 并完整读取本地文件
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TransferVerify {
+    pub size: bool,
+    pub hash: bool,
+}
+
+impl Default for TransferVerify {
+    fn default() -> Self {
+        Self {
+            size: true,
+            hash: false,
+        }
+    }
+}
+
+// 递归下载时对符号链接的处理：skip 跳过；preserve 在本地重建链接（目标原样保留，仅 Unix）；
+// follow 跟随链接复制目标内容，已访问过的真实目录不再进入，避免循环
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SymlinkPolicy {
+    #[default]
+    Skip,
+    Preserve,
+    Follow,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpDownloadDirSummary {
+    pub downloaded: u32,
+    pub total: u32,
+    pub symlinks_created: u32,
+    pub symlinks_skipped: u32, // 按策略跳过、目标不存在或本地无法创建的链接
+    pub loops_skipped: u32,    // follow 时指向已访问目录的链接
+    pub names_skipped: u32,    // 本地文件系统上不安全的远端文件名
+    pub cancelled: bool,
+}
+
+// 路径诊断中一次传输的结果；completed 为 false 时 error 说明原因（通常是超时停滞）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathSample {
+    pub direction: TransferDirection,
+    pub bytes: u64,
+    pub transferred: u64,
+    pub elapsed_ms: u64,
+    pub bytes_per_sec: u64,
+    pub completed: bool,
+    pub error: Option<String>,
+}
+
+// 从会话自己的 TCP 连接读取的套接字参数；MSS 反映协商结果，Windows 上不可读
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathSocketInfo {
+    pub address: String, // 对端地址
+    pub local_address: Option<String>,
+    pub mss: Option<u32>,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+    pub nodelay: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathDiagnostics {
+    pub samples: Vec<PathSample>,
+    pub socket: Option<PathSocketInfo>,
+    pub channel_window: Option<u32>, // 远端通告的初始通道窗口
+    pub observations: Vec<String>,
+}
+
+// 路径诊断依次尝试的传输大小；每次读写超过 PATH_STALL_TIMEOUT 没有进展视为停滞
+const PATH_SAMPLE_SIZES: [u64; 5] = [64 * 1024, 256 * 1024, 1024 * 1024, 4 * 1024 * 1024, 8 * 1024 * 1024];
+const PATH_STALL_TIMEOUT: Duration = Duration::from_secs(15);
+const PATH_DIAGNOSTICS_BUDGET: Duration = Duration::from_secs(90);
+// 以太网下常见的 MSS；明显更小通常意味着隧道/VPN 降低了 MTU
+const ETHERNET_MSS: u32 = 1460;
+
+// 下载前遍历远端得到的计划，路径均为相对路径
+#[derive(Default)]
+struct DownloadPlan {
+    dirs: Vec<String>,
+    files: Vec<(String, u64)>,
+    links: Vec<(String, String)>, // 相对路径 -> readlink 得到的目标
+    symlinks_skipped: u32,
+    loops_skipped: u32,
+    names_skipped: u32,
+}
+
+// 目录上传的选项。exclude 为 .gitignore 风格的模式，见 ExcludeFilter
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DirTransferOptions {
+    pub verify: TransferVerify,
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpVerifyMismatch {
+    pub path: String,
+    pub reason: String,
+}
+
+// 同步时比较的文件状态：(大小, 修改时间秒)
+type SyncFiles = HashMap<String, (u64, u64)>;
+
+// 私钥口令错误（或加密私钥未提供口令）：已握手的会话会被保留，前端只需重新询问口令
+#[derive(Debug)]
+pub struct WrongPassphrase;
+
+impl std::fmt::Display for WrongPassphrase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wrong passphrase for private key")
+    }
+}
+
+impl std::error::Error for WrongPassphrase {}
+
+// 认证过程中被服务端断开，通常是尝试的密钥过多（Too many authentication failures）
+#[derive(Debug)]
+pub struct TooManyAuthFailures;
+
+impl std::fmt::Display for TooManyAuthFailures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Server closed the connection during authentication, likely due to too many authentication failures (MaxAuthTries). \
+             If your SSH agent holds many keys, choose a specific agent identity so only that key is offered (like IdentitiesOnly)"
+        )
+    }
+}
+
+impl std::error::Error for TooManyAuthFailures {}
+
+// 服务端不提供 SFTP 子系统（如网络设备），文件列表可退回到 exec 执行 ls
+#[derive(Debug)]
+pub struct SftpUnavailable(String);
+
+impl std::fmt::Display for SftpUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to initialize SFTP subsystem: {}", self.0)
+    }
+}
+
+impl std::error::Error for SftpUnavailable {}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionIssue {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpSessionInfo {
+    pub session_id: String,
+    pub authenticated: bool,
+}
+
+// 建立会话各阶段耗时（毫秒），未经历的阶段为空：Unix socket 无 DNS，嵌套连接的 tcp_ms 为经外层建立隧道的时间。
+// 失败时保留已完成的阶段，failed_phase 为出错的阶段
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectTiming {
+    pub dns_ms: Option<u64>,
+    pub tcp_ms: Option<u64>,
+    pub handshake_ms: Option<u64>,
+    pub auth_ms: Option<u64>,
+    pub total_ms: u64,
+    pub address: Option<String>,
+    pub failed_phase: Option<String>,
+    pub finished_at: u64, // Unix 毫秒
+}
+
+fn elapsed_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+// SFTP 握手（SSH_FXP_VERSION）中服务端给出的协议版本与扩展，如 posix-rename@openssh.com
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpFeatures {
+    pub version: u32,
+    pub extensions: Vec<SftpExtension>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SftpExtension {
+    pub name: String,
+    pub data: String,
+}
+
+impl SftpFeatures {
+    fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|extension| extension.name == name)
+    }
+}
+
+// 文件预览：只读取开头 max_bytes 字节，据此判断文本/二进制。
+// encoding_guess 为 None 表示无法确定编码（此时前端按连接编码尝试显示）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpPreview {
+    pub is_binary: bool,
+    pub encoding_guess: Option<String>,
+    pub content_b64: String,
+    pub truncated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +503,9 @@ pub enum ForwardKind {
     Local,
     Remote,
     Dynamic,
+    // 本地 Unix socket -> 远端 Unix socket（direct-streamlocal），仅 Unix 平台。
+    // 反方向需要 streamlocal-forward 请求，libssh2 没有实现，因此不提供 RemoteUnix
+    LocalUnix,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,1261 +520,8252 @@ pub struct ForwardConfig {
     pub remote_bind_port: Option<u16>,
     pub target_host: Option<String>,
     pub target_port: Option<u16>,
+    // 仅 LocalUnix：本地监听的 socket 路径与远端 socket 路径
+    #[serde(default)]
+    pub local_socket_path: Option<String>,
+    #[serde(default)]
+    pub remote_socket_path: Option<String>,
+    // 仅本地转发：处理完第一个连接后自动停止
+    #[serde(default)]
+    pub one_shot: bool,
 }
 
-#[derive(Clone)]
-struct ForwardHandle {
-    stop: Arc<AtomicBool>,
-    session: Arc<Mutex<Session>>,
+// 转发两端 TCP socket 的 SO_KEEPALIVE 参数：空闲 idle_secs 秒后开始探测，每 interval_secs 秒一次。
+// 与 SSH 层保活互补，对端消失时系统能关闭长时间空闲的转发连接
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardKeepalive {
+    pub idle_secs: u64,
+    pub interval_secs: u64,
 }
 
-#[derive(Clone, Serialize)]
-struct TerminalOutput {
-    session_id: String,
-    data: String,
+impl Default for ForwardKeepalive {
+    fn default() -> Self {
+        Self {
+            idle_secs: 60,
+            interval_secs: 15,
+        }
+    }
 }
 
-#[derive(Clone, Serialize)]
-struct TerminalDisconnected {
-    session_id: String,
-    reason: String,
+// 转发的对外描述（不含连接凭据）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardInfo {
+    pub id: String,
+    pub kind: ForwardKind,
+    pub connection_id: String,
+    pub bind_host: Option<String>,
+    pub bind_port: Option<u16>,
+    pub target_host: Option<String>,
+    pub target_port: Option<u16>,
+    pub local_socket_path: Option<String>,
+    pub remote_socket_path: Option<String>,
+    pub status: ForwardStatus,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct ControlledCommandResult {
-    pub exit_code: i32,
-    pub stdout: String,
-    pub stderr: String,
-    pub duration_ms: u64,
-    pub timed_out: bool,
+pub enum ForwardStatus {
+    Listening,
+    Paused,
+    Error,
+    Stopped,
 }
 
-#[derive(Clone)]
-pub struct SshManager {
-    sessions: Arc<Mutex<HashMap<String, Arc<Mutex<Session>>>>>,
-    channels: Arc<Mutex<HashMap<String, Arc<Mutex<ssh2::Channel>>>>>,
-    sftp_sessions: Arc<Mutex<HashMap<String, Arc<Mutex<Session>>>>>, // 独立的 SFTP 会话
-    connections: Arc<Mutex<HashMap<String, SshConnection>>>, // 存储连接信息
-    forwards: Arc<Mutex<HashMap<String, ForwardHandle>>>, // 端口转发
+// 转发的实际状态，由 accept 循环更新；出错后保留以便查询原因，停止后移除
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardState {
+    pub id: String,
+    pub status: ForwardStatus,
+    pub error: Option<String>,
 }
 
-impl SshManager {
-    const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+// 绑定端口预检结果。远程转发的端口在服务端绑定，本地无法判断，返回 unknown；Unix socket 转发没有端口，同样返回 unknown
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BindAvailability {
+    Free,
+    InUse,
+    Unavailable, // 权限不足、地址不可用等其他绑定错误
+    Unknown,
+}
 
-    fn open_direct_tcpip(
-        session: &Arc<Mutex<Session>>,
-        host: &str,
-        port: u16,
-    ) -> anyhow::Result<ssh2::Channel> {
-        for _ in 0..30 {
-            let result = {
-                let sess = session.lock().unwrap();
-                sess.channel_direct_tcpip(host, port, None)
-            };
-            match result {
-                Ok(channel) => return Ok(channel),
-                Err(err) => {
-                    if matches!(
-                        err.code(),
-                        ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN
-                    ) {
-                        std::thread::sleep(Duration::from_millis(20));
-                        continue;
-                    }
-                    return Err(anyhow::anyhow!(err));
-                }
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BindCheck {
+    pub status: BindAvailability,
+    pub error: Option<String>,
+}
+
+impl ForwardConfig {
+    pub fn info(&self, status: ForwardStatus) -> ForwardInfo {
+        let (bind_host, bind_port) = match self.kind {
+            ForwardKind::Remote => (self.remote_bind_host.clone(), self.remote_bind_port),
+            ForwardKind::Local | ForwardKind::Dynamic => {
+                (self.local_bind_host.clone(), self.local_bind_port)
             }
-        }
-        Err(anyhow::anyhow!("Timed out opening direct-tcpip channel"))
-    }
-    pub fn new() -> Self {
-        Self {
-            sessions: Arc::new(Mutex::new(HashMap::new())),
-            channels: Arc::new(Mutex::new(HashMap::new())),
-            sftp_sessions: Arc::new(Mutex::new(HashMap::new())),
-            connections: Arc::new(Mutex::new(HashMap::new())),
-            forwards: Arc::new(Mutex::new(HashMap::new())),
+            ForwardKind::LocalUnix => (None, None),
+        };
+        ForwardInfo {
+            id: self.id.clone(),
+            kind: self.kind.clone(),
+            connection_id: self.connection.id.clone(),
+            bind_host,
+            bind_port,
+            target_host: self.target_host.clone(),
+            target_port: self.target_port,
+            local_socket_path: self.local_socket_path.clone(),
+            remote_socket_path: self.remote_socket_path.clone(),
+            status,
         }
     }
+}
 
-    // 辅助方法：创建并认证 SSH 会话
-    fn create_authenticated_session(&self, connection: &SshConnection) -> anyhow::Result<Session> {
-        let host = connection.host.trim();
-        if host.is_empty() {
-            return Err(anyhow::anyhow!("Host is empty"));
-        }
+struct KeepaliveEntry {
+    session: Arc<Mutex<Session>>,
+    stop: Option<Arc<AtomicBool>>, // 转发的停止标记；会话为 None
+    interval: u32,
+    next_due: Instant,
+}
 
-        let addrs: Vec<_> = format!("{}:{}", host, connection.port)
-            .to_socket_addrs()?
-            .collect();
-        if addrs.is_empty() {
-            return Err(anyhow::anyhow!("Failed to resolve host: {}", host));
-        }
+// shell 读取线程。stop 置位后线程在下一轮退出，不做清理也不发 terminal-disconnected
+struct ReaderHandle {
+    stop: Arc<AtomicBool>,
+    channel: Arc<Mutex<ssh2::Channel>>,
+    thread: std::thread::JoinHandle<()>,
+}
 
-        let mut sess_opt: Option<Session> = None;
-        let mut attempts: Vec<String> = Vec::new();
-        for addr in addrs {
-            let tcp = match TcpStream::connect_timeout(&addr, Duration::from_secs(10)) {
-                Ok(tcp) => tcp,
-                Err(e) => {
-                    attempts.push(format!("{} connect failed: {}", addr, e));
-                    continue;
-                }
-            };
+// disconnect 实际关闭的资源；forwards_stopped 为按连接 id 匹配到并停止的转发 id
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectSummary {
+    pub shell_closed: bool,
+    pub sftp_closed: bool,
+    pub subsystems_closed: usize,
+    pub upload_streams_dropped: usize,
+    pub forwards_stopped: Vec<String>,
+}
 
-            if let Err(e) = tcp.set_read_timeout(Some(Duration::from_secs(30))) {
-                attempts.push(format!("{} set read timeout failed: {}", addr, e));
-                continue;
-            }
-            if let Err(e) = tcp.set_write_timeout(Some(Duration::from_secs(30))) {
-                attempts.push(format!("{} set write timeout failed: {}", addr, e));
-                continue;
-            }
-            if let Err(e) = tcp.set_nonblocking(false) {
-                attempts.push(format!("{} set blocking mode failed: {}", addr, e));
-                continue;
-            }
+// ssh_gc_sessions 的清理结果。stale_entries 形如 "shell_titles:<会话 id>"
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionGcReport {
+    pub readers_reaped: Vec<String>,
+    pub keepalives_removed: Vec<String>,
+    pub subsystems_closed: Vec<String>,
+    pub stale_entries: Vec<String>,
+}
 
-            let mut sess = Session::new()?;
-            sess.set_tcp_stream(tcp);
-            sess.set_timeout(30000); // 30秒超时
-            if let Err(e) = sess.handshake() {
-                let raw = e.to_string();
-                let reason = if raw.contains("Failed getting banner") {
-                    format!(
-                        "{} handshake failed: {} (target may not be SSH / SSHD not ready / network device interrupted banner)",
-                        addr, raw
-                    )
-                } else {
-                    format!("{} handshake failed: {}", addr, raw)
-                };
-                attempts.push(reason);
-                continue;
-            }
+// 本地/动态转发的监听器由一个共享 accept 线程轮询，转发保活并入共享调度器。
+// 以 20 个转发、每个 10 个连接为例：
+//   之前：20 × (accept + 保活) + 200 × (连接处理 + 上行复制) = 440 个线程
+//   现在：1 个 accept + 1 个保活（与会话共用）+ 200 × 2 = 402 个线程
+// 远程转发的监听由 libssh2 阻塞 accept，仍各占一个线程。
+enum ListenerRole {
+    Local {
+        target_host: String,
+        target_port: u16,
+        one_shot: Option<String>,
+    },
+    Dynamic,
+}
 
-            sess_opt = Some(sess);
+struct ForwardListener {
+    id: String,
+    listener: TcpListener,
+    session: Arc<Mutex<Session>>,
+    stop: Arc<AtomicBool>,
+    role: ListenerRole,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionCount {
+    pub sessions: usize,
+    pub sftp_sessions: usize,
+    pub forwards: usize,
+    pub max_sessions: Option<usize>,
+}
+
+// 转发的暂停控制：暂停时监听与会话保留，新连接接受后立即关闭。
+// live 记录进行中连接的本地 socket，暂停时可选择一并断开
+#[derive(Clone, Default)]
+struct ForwardGate {
+    paused: Arc<AtomicBool>,
+    live: Arc<Mutex<HashMap<u64, LiveShutdown>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+type LiveShutdown = Box<dyn Fn() + Send>;
+
+// 转发两端的本地连接（TCP 或 Unix socket）
+trait ForwardStream: Read + Write + Send + Sized + 'static {
+    fn try_clone_stream(&self) -> std::io::Result<Self>;
+    fn shutdown_stream(&self);
+    fn set_nonblocking_stream(&self, nonblocking: bool) -> std::io::Result<()>;
+    fn set_read_timeout_stream(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl ForwardStream for TcpStream {
+    fn try_clone_stream(&self) -> std::io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn shutdown_stream(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+
+    fn set_nonblocking_stream(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout_stream(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ForwardStream for std::os::unix::net::UnixStream {
+    fn try_clone_stream(&self) -> std::io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn shutdown_stream(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+
+    fn set_nonblocking_stream(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout_stream(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)
+    }
+}
+
+impl ForwardGate {
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn track<S: ForwardStream>(&self, stream: &S) -> Option<u64> {
+        let clone = stream.try_clone_stream().ok()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.live
+            .lock()
+            .unwrap()
+            .insert(id, Box::new(move || clone.shutdown_stream()));
+        Some(id)
+    }
+
+    fn untrack(&self, id: Option<u64>) {
+        if let Some(id) = id {
+            self.live.lock().unwrap().remove(&id);
+        }
+    }
+
+    fn close_live(&self) {
+        for (_, shutdown) in self.live.lock().unwrap().drain() {
+            shutdown();
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ForwardHandle {
+    stop: Arc<AtomicBool>,
+    session: Arc<Mutex<Session>>,
+    config: ForwardConfig,
+}
+
+// 前端分块推送的上传流（无本地文件）
+// 写入时只锁单个流，不占用 upload_streams 表锁
+struct UploadStream {
+    session_id: String,
+    path: String,
+    file: Mutex<ssh2::File>,
+    written: AtomicU64,
+    target: Option<String>, // 原子写入时的最终路径，path 为临时文件
+}
+
+// 目录上传的续传清单；只有会话和两端目录都一致时才沿用
+struct UploadManifest {
+    session_id: String,
+    local_dir: String,
+    remote_dir: String,
+    completed: HashSet<String>, // 已完成的相对路径
+}
+
+// 在独立会话上打开的子系统通道（如 netconf）
+struct SubsystemHandle {
+    session_id: String,
+    session: Arc<Mutex<Session>>,
+    channel: Arc<Mutex<ssh2::Channel>>,
+    stop: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Serialize)]
+struct SubsystemData {
+    channel_id: String,
+    data_b64: String,
+}
+
+#[derive(Clone, Serialize)]
+struct SubsystemClosed {
+    channel_id: String,
+    reason: String,
+}
+
+#[derive(Clone, Serialize)]
+struct TerminalOutput {
+    session_id: String,
+    data: String,
+}
+
+#[derive(Clone, Serialize)]
+struct ForwardStopped {
+    id: String,
+    reason: String,
+}
+
+#[derive(Clone, Serialize)]
+struct SshAuthBanner {
+    connection_id: String,
+    text: String,
+}
+
+#[derive(Clone, Serialize)]
+struct SshEnvResult {
+    session_id: String,
+    applied: Vec<String>,
+    rejected: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct TerminalDisconnected {
+    session_id: String,
+    reason: String,
+    detail: DisconnectReason,
+}
+
+// 断开原因：远端正常退出、被信号结束、远端直接关闭通道，或网络/套接字错误
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub(crate) enum DisconnectReason {
+    #[serde(rename_all = "camelCase")]
+    Exited { exit_status: i32 },
+    #[serde(rename_all = "camelCase")]
+    Signaled {
+        signal: String,
+        message: Option<String>,
+    },
+    Closed,
+    #[serde(rename_all = "camelCase")]
+    NetworkError { message: String },
+}
+
+impl DisconnectReason {
+    // 通道已结束时读取 exit-signal / exit-status。
+    // libssh2 在未收到 exit-status 时返回 0，因此没有 EOF 时不把它当作正常退出
+    fn from_channel(channel: &ssh2::Channel, eof: bool) -> Self {
+        if let Ok(exit) = channel.exit_signal() {
+            if let Some(signal) = exit.exit_signal.filter(|signal| !signal.is_empty()) {
+                return DisconnectReason::Signaled {
+                    signal,
+                    message: exit.error_message.filter(|message| !message.is_empty()),
+                };
+            }
+        }
+        if eof {
+            return DisconnectReason::Exited {
+                exit_status: channel.exit_status().unwrap_or(0),
+            };
+        }
+        DisconnectReason::Closed
+    }
+
+    pub(crate) fn label(&self) -> String {
+        match self {
+            DisconnectReason::Exited { exit_status } => format!("exit: {}", exit_status),
+            DisconnectReason::Signaled { signal, .. } => format!("signal: {}", signal),
+            DisconnectReason::Closed => "closed".to_string(),
+            DisconnectReason::NetworkError { message } => format!("error: {}", message),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct ConnectTimingEvent {
+    connection_id: String,
+    timing: ConnectTiming,
+}
+
+#[derive(Clone, Serialize)]
+struct TerminalThrottled {
+    session_id: String,
+    throttled: bool,
+    limit: u64, // 字节/秒
+}
+
+#[derive(Clone, Serialize)]
+struct TerminalLatency {
+    session_id: String,
+    ms: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct SshConnectionState {
+    connection_id: String,
+    state: String, // connecting / connected / failed
+    error: Option<String>,
+}
+
+// ssh_connect 的结果；username_fallback 表示连接未填用户名，username 为退回的本机用户名
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedSession {
+    pub session_id: String,
+    pub username: String,
+    pub username_fallback: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectResult {
+    pub connection_id: String,
+    pub session_id: Option<String>,
+    pub error: Option<String>,
+}
+
+// 批量连接时同时进行握手/认证的最大数量，批量执行命令也沿用这个上限
+const CONNECT_MANY_CONCURRENCY: usize = 6;
+
+// SSH 与本地终端共用的 bracketed-paste 事件负载
+#[derive(Clone, Serialize)]
+pub(crate) struct BracketedPasteChanged {
+    pub(crate) session_id: String,
+    pub(crate) enabled: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct TerminalTitle {
+    session_id: String,
+    title: String,
+}
+
+#[derive(Clone, Serialize)]
+struct LoginScriptResult {
+    session_id: String,
+    completed: bool,
+    steps_sent: usize,
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct MacroResult {
+    session_id: String,
+    macro_id: String,
+    completed: bool,
+    cancelled: bool,
+    steps_sent: usize,
+    error: Option<String>,
+}
+
+const DEFAULT_LOGIN_STEP_TIMEOUT: Duration = Duration::from_secs(15);
+// 登录脚本匹配时只保留最近的输出
+const LOGIN_SCRIPT_OUTPUT_LIMIT: usize = 64 * 1024;
+
+// 每个 shell 保留的最近原始输出，供 get_screen 生成缩略图
+const SCREEN_TAIL_LIMIT: usize = 64 * 1024;
+type OutputTail = Arc<Mutex<Vec<u8>>>;
+// 分离期间缓存的输出上限，超出后丢弃最早的部分
+const DETACHED_OUTPUT_LIMIT: usize = 256 * 1024;
+
+// 每个会话保留的最近传输记录条数
+const TRANSFER_HISTORY_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+    Copy, // 会话间直接复制，记在目标会话上
+}
+
+// 已结束的单个文件传输；bytes 含续传前已存在的部分
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferRecord {
+    pub path: String,
+    pub direction: TransferDirection,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    pub finished_at: u64, // Unix 毫秒
+}
+
+// 进行中传输的快照；rate_bps 按本次开始后实际传输的字节计算（不含续传前已有的部分）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveTransfer {
+    pub id: String,
+    pub session_id: String,
+    pub direction: TransferDirection,
+    pub path: String,
+    pub transferred: u64,
+    pub total: u64,
+    pub rate_bps: u64,
+    pub elapsed_ms: u64,
+}
+
+struct ActiveTransferEntry {
+    session_id: String,
+    direction: TransferDirection,
+    path: String,
+    started_at: Instant,
+    initial: std::sync::OnceLock<u64>,
+    transferred: AtomicU64,
+    total: AtomicU64,
+}
+
+// 由发出 sftp-transfer-progress 的进度回调更新；释放时从进行中列表移除
+pub struct TransferProgressHandle {
+    id: String,
+    entry: Arc<ActiveTransferEntry>,
+    active: Arc<Mutex<HashMap<String, Arc<ActiveTransferEntry>>>>,
+}
+
+impl TransferProgressHandle {
+    pub fn update(&self, transferred: u64, total: u64) {
+        self.entry.initial.get_or_init(|| transferred);
+        self.entry.transferred.store(transferred, Ordering::Relaxed);
+        self.entry.total.store(total, Ordering::Relaxed);
+    }
+}
+
+impl Drop for TransferProgressHandle {
+    fn drop(&mut self) {
+        if let Ok(mut active) = self.active.lock() {
+            if active.get(&self.id).is_some_and(|current| Arc::ptr_eq(current, &self.entry)) {
+                active.remove(&self.id);
+            }
+        }
+    }
+}
+
+// 未完成的上传/下载。与传输历史不同，断开时不清除，重连（同一 session id）后可续传；
+// offset 为续传起点：下载取本地文件大小，上传取远端 .part 文件大小
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterruptedTransfer {
+    pub id: String,
+    pub session_id: String,
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub total: u64,
+    pub offset: u64,
+    pub error: String,
+    pub interrupted_at: u64, // Unix 毫秒
+}
+
+// 会话期间基本不变的环境变量，读取后按会话缓存
+const CACHED_ENV_NAMES: [&str; 4] = ["HOME", "USER", "LOGNAME", "SHELL"];
+
+// sshd 的 StrictModes 会静默拒绝权限过宽的 ~/.ssh、authorized_keys 和可被他人写入的家目录。
+// 输出 "changed\t说明" / "warn\t说明" 行；stat 兼容 GNU 与 BSD
+const FIX_SSH_PERMS_SCRIPT: &str = r#"perm() { stat -c '%a' "$1" 2>/dev/null || stat -f '%Lp' "$1" 2>/dev/null; }
+owner() { stat -c '%U' "$1" 2>/dev/null || stat -f '%Su' "$1" 2>/dev/null; }
+h="$HOME"; d="$h/.ssh"; k="$d/authorized_keys"; u=$(owner "$h")
+fix_owner() {
+  [ -e "$1" ] || return 0
+  o=$(owner "$1")
+  [ "$o" = "$u" ] && return 0
+  if [ "$(id -u)" = 0 ]; then
+    if chown "$u" "$1" 2>/dev/null; then printf 'changed	%s: owner %s -> %s
+' "$1" "$o" "$u"; else printf 'warn	cannot chown %s to %s
+' "$1" "$u"; fi
+  else
+    printf 'warn	%s is owned by %s, not %s
+' "$1" "$o" "$u"
+  fi
+}
+fix_mode() {
+  [ -e "$1" ] || return 0
+  m=$(perm "$1")
+  [ "$m" = "$2" ] && return 0
+  if chmod "$2" "$1" 2>/dev/null; then printf 'changed	%s: %s -> %s
+' "$1" "$m" "$2"; else printf 'warn	cannot chmod %s (mode %s)
+' "$1" "$m"; fi
+}
+[ -d "$d" ] || printf 'warn	%s does not exist
+' "$d"
+[ -f "$k" ] || printf 'warn	%s does not exist
+' "$k"
+fix_owner "$d"; fix_owner "$k"
+fix_mode "$d" 700; fix_mode "$k" 600
+m=$(perm "$h")
+if [ -n "$m" ] && [ $((0$m & 022)) -ne 0 ]; then
+  if chmod go-w "$h" 2>/dev/null; then printf 'changed	%s: %s -> %s
+' "$h" "$m" "$(perm "$h")"; else printf 'warn	%s is group/world writable (mode %s)
+' "$h" "$m"; fi
+fi
+"#;
+
+// 口令错误时保留已握手会话的时长，需短于服务端的 LoginGraceTime
+const PENDING_AUTH_TTL: Duration = Duration::from_secs(60);
+
+// 深度检查等待服务端应答的时长，超时视为连接已断
+const DEEP_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 超过该时长仍未回显的按键（如密码输入）不计入延迟
+const LATENCY_ECHO_TIMEOUT: Duration = Duration::from_secs(2);
+
+// 非阻塞泵（pump_nonblocking、drain_exec_channel）空闲时的等待范围
+const PUMP_IDLE_MIN: Duration = Duration::from_millis(1);
+const PUMP_IDLE_MAX: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ShellSize {
+    pub cols: u32,
+    pub rows: u32,
+}
+
+// shell 通道的缓冲状态。读取线程收到数据即发出事件，没有额外的合并缓冲；
+// 滞留的数据只可能在 libssh2 的接收缓冲（pending_bytes）或被 SSH 流控窗口挡在远端
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShellBufferStats {
+    pub pending_bytes: u32,    // 已到达本地、尚未被读取线程取走
+    pub window_remaining: u32, // 远端还可发送的字节数
+    pub window_size: u32,
+    pub paused: bool,          // 窗口耗尽，远端暂停发送
+    pub tail_bytes: usize,     // 为 get_screen 保留的输出
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SshPermsReport {
+    pub changed: Vec<String>,
+    pub warnings: Vec<String>, // 无法修复或需要人工处理的问题
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShellSizeReport {
+    pub requested: Option<ShellSize>,
+    pub observed: Option<ShellSize>,
+    pub observed_tty: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpEditInfo {
+    pub edit_id: String,
+    pub local_path: String,
+    pub remote_path: String,
+}
+
+// 会话日志；读取线程与 rotate_log 共用同一把锁，轮转时不会丢失或错写输出
+struct SessionLog {
+    path: PathBuf,
+    file: std::fs::File,
+    written: u64,
+    max_bytes: Option<u64>,
+}
+
+impl SessionLog {
+    fn open(path: PathBuf, max_bytes: Option<u64>) -> anyhow::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to open session log '{}': {}", path.display(), e))?;
+        let written = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes: max_bytes.filter(|max| *max > 0),
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.file.write_all(data)?;
+        self.written += data.len() as u64;
+        if self.max_bytes.is_some_and(|max| self.written >= max) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    // 当前文件改名为 <name>-<unix 秒>[-n].<ext>，再在原路径打开新文件；返回改名后的路径
+    fn rotate(&mut self) -> anyhow::Result<PathBuf> {
+        self.file.flush()?;
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "session".to_string());
+        let extension = self
+            .path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let mut rotated = self.path.with_file_name(format!("{}-{}{}", stem, stamp, extension));
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = self.path.with_file_name(format!("{}-{}-{}{}", stem, stamp, n, extension));
+            n += 1;
+        }
+        std::fs::rename(&self.path, &rotated)
+            .map_err(|e| anyhow::anyhow!("Failed to rotate session log '{}': {}", self.path.display(), e))?;
+        self.file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .map_err(|e| anyhow::anyhow!("Failed to open session log '{}': {}", self.path.display(), e))?;
+        self.written = 0;
+        Ok(rotated)
+    }
+}
+
+// 本地编辑中的远程文件副本
+#[derive(Clone)]
+struct EditSession {
+    session_id: String,
+    remote_path: String,
+    local_path: PathBuf,
+    modified: Option<std::time::SystemTime>,
+}
+
+#[derive(Clone, Serialize)]
+struct SftpEditSynced {
+    edit_id: String,
+    session_id: String,
+    remote_path: String,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostStats {
+    pub mem_total: Option<u64>,
+    pub mem_used: Option<u64>,
+    pub load_average: Option<[f64; 3]>,
+    pub cpu_count: Option<u32>,
+    pub missing: Vec<String>, // 未能获取的指标
+}
+
+// 远端网卡地址，每个地址一行。source 为实际解析的工具（ip / ifconfig），
+// missing 列出不可用的工具；两者都不可用时 interfaces 为空
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceAddress {
+    pub name: String,
+    pub family: String, // inet / inet6
+    pub address: String,
+    pub prefix_len: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceList {
+    pub interfaces: Vec<InterfaceAddress>,
+    pub source: Option<String>,
+    pub missing: Vec<String>,
+}
+
+// 连接时记录的服务端主机密钥，前端可按连接固定（pin）并在之后的连接中比对
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostKeyInfo {
+    pub key_type: String,
+    pub key_b64: String,
+    pub fingerprint_sha256: String, // OpenSSH 格式："SHA256:" + 无填充 base64
+}
+
+impl HostKeyInfo {
+    fn from_session(sess: &Session) -> Option<Self> {
+        let (key, kind) = sess.host_key()?;
+        let key_type = match kind {
+            ssh2::HostKeyType::Rsa => "ssh-rsa",
+            ssh2::HostKeyType::Dss => "ssh-dss",
+            ssh2::HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+            ssh2::HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+            ssh2::HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+            ssh2::HostKeyType::Ed25519 => "ssh-ed25519",
+            ssh2::HostKeyType::Unknown => "unknown",
+        };
+        let fingerprint = sess.host_key_hash(ssh2::HashType::Sha256)?;
+        Some(Self {
+            key_type: key_type.to_string(),
+            key_b64: base64::engine::general_purpose::STANDARD.encode(key),
+            fingerprint_sha256: format!(
+                "SHA256:{}",
+                base64::engine::general_purpose::STANDARD_NO_PAD.encode(fingerprint)
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerAlgorithms {
+    pub server_version: String,
+    pub kex: Vec<String>,
+    pub host_key: Vec<String>,
+    pub ciphers: Vec<String>,
+    pub macs: Vec<String>,
+    pub deprecated: Vec<String>, // 服务端提供的已弃用算法
+}
+
+struct ExecOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: i32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptInterpreter {
+    #[default]
+    Bash,
+    Sh,
+    Python,
+}
+
+impl ScriptInterpreter {
+    fn program(self) -> &'static str {
+        match self {
+            ScriptInterpreter::Bash => "bash",
+            ScriptInterpreter::Sh => "sh",
+            ScriptInterpreter::Python => "python3",
+        }
+    }
+
+    // 从标准输入读取脚本时的参数
+    fn stdin_args(self) -> &'static str {
+        match self {
+            ScriptInterpreter::Bash | ScriptInterpreter::Sh => "-s",
+            ScriptInterpreter::Python => "-",
+        }
+    }
+}
+
+// via_sftp 为 false 表示服务端不支持 SFTP，脚本通过标准输入传给解释器
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptRunResult {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub via_sftp: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlledCommandResult {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub timed_out: bool,
+}
+
+// 合并输出执行的结果；pty_merged 表示 stdout/stderr 由远端 PTY 合并（此时无法区分来源），
+// 否则为 libssh2 在客户端按到达顺序合并扩展数据
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergedExecResult {
+    pub exit_code: i32,
+    pub pty_merged: bool,
+    pub duration_ms: u64,
+    pub cancelled: bool,
+}
+
+// 主机卡片需要的概要信息：一次 exec + 一次 SFTP 探测。
+// 非 POSIX shell（如 Windows cmd）上 uname 不可用，对应字段为空
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostProbe {
+    pub os: Option<String>,      // uname -s，例如 Linux / Darwin / FreeBSD
+    pub release: Option<String>, // uname -r
+    pub arch: Option<String>,    // uname -m
+    pub home: Option<String>,
+    pub sftp: bool,
+    pub tools: HashMap<String, bool>, // PROBE_TOOLS 中各工具是否在 PATH 中
+}
+
+const PROBE_TOOLS: [&str; 5] = ["sha256sum", "shasum", "tar", "df", "tmux"];
+
+// 远端实际登录的用户（用户名留空时连接会退回本机用户名，这里反映服务端看到的身份）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteUserInfo {
+    pub username: String,
+    pub shell: Option<String>,
+    pub home: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub groups: Vec<String>,
+}
+
+// 远端 tmux 会话及其窗口；target 可直接作为 attach -t 的参数
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TmuxSession {
+    pub name: String,
+    pub attached: bool,
+    pub windows: Vec<TmuxWindow>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TmuxWindow {
+    pub index: u32,
+    pub name: String,
+    pub active: bool,
+    pub target: String,
+}
+
+// 批量执行时单台主机的结果；连接或执行失败时 error 非空，exit_code 为 -1
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostCommandResult {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct SshManager {
+    sessions: Arc<Mutex<HashMap<String, Arc<Mutex<Session>>>>>,
+    channels: Arc<Mutex<HashMap<String, Arc<Mutex<ssh2::Channel>>>>>,
+    sftp_sessions: Arc<Mutex<HashMap<String, Arc<Mutex<Session>>>>>, // 独立的 SFTP 会话
+    connections: Arc<Mutex<HashMap<String, SshConnection>>>, // 存储连接信息
+    forwards: Arc<Mutex<HashMap<String, ForwardHandle>>>, // 端口转发
+    cancel_flags: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>, // 流式读写/传输的取消标记
+    upload_streams: Arc<Mutex<HashMap<String, Arc<UploadStream>>>>, // 前端分块上传
+    app_handle: Arc<Mutex<Option<tauri::AppHandle>>>, // 用于内部路径（认证、后台线程）发事件
+    keepalives: Arc<Mutex<HashMap<String, KeepaliveEntry>>>, // 共享保活调度表，按会话 id
+    forward_keepalives: Arc<Mutex<HashMap<String, KeepaliveEntry>>>, // 同一调度器上的转发会话，按转发 id
+    keepalive_scheduler: Arc<AtomicBool>, // 调度线程是否已启动
+    max_sessions: Arc<AtomicUsize>, // 会话数上限，0 表示不限制
+    require_username: Arc<AtomicBool>, // 为 true 时用户名留空直接报错，不退回本机用户名
+    forward_listeners: Arc<Mutex<Vec<ForwardListener>>>, // 共享 accept 线程轮询的监听器
+    forward_acceptor: Arc<AtomicBool>, // accept 线程是否已启动
+    pty_sizes: Arc<Mutex<HashMap<String, ShellSize>>>, // 最近一次请求的 PTY 尺寸
+    pending_auth: Arc<Mutex<HashMap<String, (Session, Instant)>>>, // 因口令错误未完成认证的已握手会话及保留时间
+    edits: Arc<Mutex<HashMap<String, EditSession>>>, // 下载到本地编辑的远程文件
+    latency_monitors: Arc<Mutex<HashMap<String, Option<Instant>>>>, // 开启延迟测量的会话 -> 等待回显的按键时间
+    upload_manifests: Arc<Mutex<HashMap<String, UploadManifest>>>, // 目录上传 transfer_id -> 续传清单
+    shell_titles: Arc<Mutex<HashMap<String, String>>>, // 从输出中的 OSC 0/2 解析出的当前标题
+    sftp_support: Arc<Mutex<HashMap<String, bool>>>, // 会话是否提供 SFTP 子系统（探测结果缓存）
+    sftp_features: Arc<Mutex<HashMap<String, SftpFeatures>>>, // SFTP 握手结果缓存
+    connect_timings: Arc<Mutex<HashMap<String, ConnectTiming>>>, // 连接 id -> 最近一次建立会话的耗时，断开后保留
+    session_logs: Arc<Mutex<HashMap<String, Arc<Mutex<SessionLog>>>>>, // 会话日志
+    nested_outer: Arc<Mutex<HashMap<String, SshConnection>>>, // 内层会话 -> 经由的外层连接
+    host_keys: Arc<Mutex<HashMap<String, HostKeyInfo>>>, // 连接时捕获的主机密钥
+    forward_states: Arc<Mutex<HashMap<String, ForwardState>>>, // 转发 id -> 实际状态
+    forward_gates: Arc<Mutex<HashMap<String, ForwardGate>>>,
+    forward_keepalive: Arc<Mutex<Option<ForwardKeepalive>>>, // 为空时不设置 SO_KEEPALIVE
+    bracketed_paste: Arc<Mutex<HashMap<String, bool>>>, // 远端程序是否开启了 bracketed paste
+    subsystems: Arc<Mutex<HashMap<String, SubsystemHandle>>>, // 子系统通道 id -> 通道
+    shell_encodings: Arc<Mutex<HashMap<String, &'static encoding_rs::Encoding>>>, // shell 输出的解码编码
+    env_cache: Arc<Mutex<HashMap<String, HashMap<String, String>>>>, // 会话 -> CACHED_ENV_NAMES 中已读取的值
+    output_tails: Arc<Mutex<HashMap<String, OutputTail>>>, // 最近的原始输出（SCREEN_TAIL_LIMIT）
+    user_infos: Arc<Mutex<HashMap<String, RemoteUserInfo>>>, // 会话 -> 远端登录用户信息
+    transfer_history: Arc<Mutex<HashMap<String, VecDeque<TransferRecord>>>>, // 最近完成的传输，旧的在前
+    interrupted_transfers: Arc<Mutex<VecDeque<InterruptedTransfer>>>, // 中断的上传/下载，旧的在前
+    active_transfers: Arc<Mutex<HashMap<String, Arc<ActiveTransferEntry>>>>, // 传输 id -> 进度计数
+    output_rate_limits: Arc<Mutex<HashMap<String, u64>>>, // shell 输出上限（字节/秒），未设置则不限
+    detached_shells: Arc<Mutex<HashMap<String, String>>>, // 已分离的 shell -> 分离后缓存的输出
+    auto_answer: Arc<Mutex<HashSet<String>>>, // 无前端应答时由后端回复 DA/DSR 查询的会话
+    readers: Arc<Mutex<HashMap<String, ReaderHandle>>>, // shell 读取线程，供 gc_sessions 回收
+    macro_outputs: Arc<Mutex<HashMap<String, Arc<Mutex<String>>>>>, // 正在播放宏的 shell -> 供匹配的输出
+    ws_bridges: Arc<Mutex<HashMap<String, Arc<WsBridge>>>>, // 会话 -> 本地 WebSocket 桥
+}
+
+impl SshManager {
+    const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+    const LIBSSH2_ERROR_FILE: i32 = -16;
+    const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+    const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+    const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+    const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+    const LIBSSH2_ERROR_CHANNEL_FAILURE: i32 = -21;
+    const LIBSSH2_ERROR_KEX_FAILURE: i32 = -5;
+
+    // 握手前设置主机密钥算法偏好；本地 libssh2 不支持其中任何一个时直接报错
+    fn new_session(host_key_algorithms: Option<&str>) -> anyhow::Result<Session> {
+        let sess = Session::new()?;
+        if let Some(prefs) = normalize_algorithm_list(host_key_algorithms) {
+            sess.method_pref(ssh2::MethodType::HostKey, &prefs).map_err(|e| {
+                let supported = sess
+                    .supported_algs(ssh2::MethodType::HostKey)
+                    .map(|algs| algs.join(","))
+                    .unwrap_or_default();
+                anyhow::anyhow!("Unsupported host key algorithms '{}': {} (supported: {})", prefs, e, supported)
+            })?;
+        }
+        Ok(sess)
+    }
+
+    // 协商失败时补充提示，常见于服务端已禁用 ssh-rsa（SHA-1）主机密钥
+    fn handshake_error(prefix: &str, e: ssh2::Error, host_key_algorithms: Option<&str>) -> String {
+        if e.code() != ssh2::ErrorCode::Session(Self::LIBSSH2_ERROR_KEX_FAILURE) {
+            return format!("{} handshake failed: {}", prefix, e);
+        }
+        let hint = match normalize_algorithm_list(host_key_algorithms) {
+            Some(prefs) => format!("the server accepts none of the configured host key algorithms ({})", prefs),
+            None => "no matching host key type; if the server disabled ssh-rsa, set host key algorithms to e.g. \
+                     rsa-sha2-512,rsa-sha2-256,ssh-ed25519"
+                .to_string(),
+        };
+        format!("{} handshake failed: {} ({})", prefix, e, hint)
+    }
+
+    fn open_direct_tcpip(
+        session: &Arc<Mutex<Session>>,
+        host: &str,
+        port: u16,
+    ) -> anyhow::Result<ssh2::Channel> {
+        Self::open_forward_channel(session, "direct-tcpip", |sess| sess.channel_direct_tcpip(host, port, None))
+    }
+
+    #[cfg(unix)]
+    fn open_direct_streamlocal(session: &Arc<Mutex<Session>>, socket_path: &str) -> anyhow::Result<ssh2::Channel> {
+        Self::open_forward_channel(session, "direct-streamlocal", |sess| {
+            sess.channel_direct_streamlocal(socket_path, None)
+        })
+    }
+
+    // 转发会话是非阻塞的，打开通道遇到 EAGAIN 时重试（间隔内释放会话锁，其他连接照常收发）。
+    // libssh2 每个会话只记录一个进行中的打开请求，放弃后下一次打开会沿用旧的目标，
+    // 所以超时后直接断开整个会话，由 keepalive 把转发标记为出错
+    fn open_forward_channel<F>(session: &Arc<Mutex<Session>>, kind: &str, open: F) -> anyhow::Result<ssh2::Channel>
+    where
+        F: Fn(&Session) -> Result<ssh2::Channel, ssh2::Error>,
+    {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        loop {
+            let result = {
+                let sess = session.lock().unwrap();
+                open(&sess)
+            };
+            match result {
+                Ok(channel) => return Ok(channel),
+                Err(err) if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN) => {
+                    if Instant::now() >= deadline {
+                        let sess = session.lock().unwrap();
+                        sess.set_blocking(true);
+                        let _ = sess.disconnect(None, "channel open timed out", None);
+                        return Err(anyhow::anyhow!("Timed out opening {} channel; forward session closed", kind));
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                Err(err) => return Err(anyhow::anyhow!(err)),
+            }
+        }
+    }
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            sftp_sessions: Arc::new(Mutex::new(HashMap::new())),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            forwards: Arc::new(Mutex::new(HashMap::new())),
+            cancel_flags: Arc::new(Mutex::new(HashMap::new())),
+            upload_streams: Arc::new(Mutex::new(HashMap::new())),
+            app_handle: Arc::new(Mutex::new(None)),
+            keepalives: Arc::new(Mutex::new(HashMap::new())),
+            forward_keepalives: Arc::new(Mutex::new(HashMap::new())),
+            keepalive_scheduler: Arc::new(AtomicBool::new(false)),
+            max_sessions: Arc::new(AtomicUsize::new(0)),
+            require_username: Arc::new(AtomicBool::new(false)),
+            forward_listeners: Arc::new(Mutex::new(Vec::new())),
+            forward_acceptor: Arc::new(AtomicBool::new(false)),
+            pty_sizes: Arc::new(Mutex::new(HashMap::new())),
+            pending_auth: Arc::new(Mutex::new(HashMap::new())),
+            edits: Arc::new(Mutex::new(HashMap::new())),
+            latency_monitors: Arc::new(Mutex::new(HashMap::new())),
+            upload_manifests: Arc::new(Mutex::new(HashMap::new())),
+            shell_titles: Arc::new(Mutex::new(HashMap::new())),
+            sftp_support: Arc::new(Mutex::new(HashMap::new())),
+            sftp_features: Arc::new(Mutex::new(HashMap::new())),
+            connect_timings: Arc::new(Mutex::new(HashMap::new())),
+            detached_shells: Arc::new(Mutex::new(HashMap::new())),
+            auto_answer: Arc::new(Mutex::new(HashSet::new())),
+            readers: Arc::new(Mutex::new(HashMap::new())),
+            macro_outputs: Arc::new(Mutex::new(HashMap::new())),
+            ws_bridges: Arc::new(Mutex::new(HashMap::new())),
+            session_logs: Arc::new(Mutex::new(HashMap::new())),
+            nested_outer: Arc::new(Mutex::new(HashMap::new())),
+            host_keys: Arc::new(Mutex::new(HashMap::new())),
+            forward_states: Arc::new(Mutex::new(HashMap::new())),
+            forward_gates: Arc::new(Mutex::new(HashMap::new())),
+            forward_keepalive: Arc::new(Mutex::new(Some(ForwardKeepalive::default()))),
+            bracketed_paste: Arc::new(Mutex::new(HashMap::new())),
+            subsystems: Arc::new(Mutex::new(HashMap::new())),
+            shell_encodings: Arc::new(Mutex::new(HashMap::new())),
+            env_cache: Arc::new(Mutex::new(HashMap::new())),
+            user_infos: Arc::new(Mutex::new(HashMap::new())),
+            transfer_history: Arc::new(Mutex::new(HashMap::new())),
+            interrupted_transfers: Arc::new(Mutex::new(VecDeque::new())),
+            active_transfers: Arc::new(Mutex::new(HashMap::new())),
+            output_rate_limits: Arc::new(Mutex::new(HashMap::new())),
+            output_tails: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn attach_app_handle(&self, app_handle: tauri::AppHandle) {
+        *self.app_handle.lock().unwrap() = Some(app_handle);
+    }
+
+    fn emit<S: Serialize + Clone>(&self, event: &str, payload: S) {
+        let app_handle = self.app_handle.lock().unwrap().clone();
+        if let Some(app_handle) = app_handle {
+            let _ = app_handle.emit(event, payload);
+        }
+    }
+
+    // 辅助方法：创建并认证 SSH 会话
+    fn create_authenticated_session(&self, connection: &SshConnection) -> anyhow::Result<Session> {
+        let host = connection.host.trim();
+        let _span = tracing::debug_span!(
+            "create_authenticated_session",
+            host = %host,
+            port = connection.port,
+            auth = connection.auth_type.label(),
+        )
+        .entered();
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("Host is empty"));
+        }
+
+        let started_at = Instant::now();
+        let mut timing = ConnectTiming::default();
+        let outer_connection = self.nested_outer.lock().unwrap().get(&connection.id).cloned();
+        let host_key_algorithms = connection.host_key_algorithms.as_deref();
+        let transport = match (outer_connection, host.strip_prefix("unix:")) {
+            (Some(outer_connection), _) => self.open_nested_transport(&outer_connection, connection, &mut timing),
+            (None, Some(socket_path)) => Self::connect_unix_session(socket_path, host_key_algorithms, &mut timing),
+            (None, None) => Self::connect_tcp_session(host, connection.port, host_key_algorithms, &mut timing),
+        };
+        let result = transport.and_then(|sess| {
+            let auth_started = Instant::now();
+            let result = self.authenticate_session(sess, connection);
+            timing.auth_ms = Some(elapsed_ms(auth_started));
+            if result.is_err() {
+                timing.failed_phase = Some("auth".to_string());
+            }
+            result
+        });
+        if result.is_err() && timing.failed_phase.is_none() {
+            timing.failed_phase = Some(
+                match (timing.dns_ms, timing.tcp_ms) {
+                    (None, None) if !host.starts_with("unix:") => "dns",
+                    (_, None) => "tcp",
+                    _ => "handshake",
+                }
+                .to_string(),
+            );
+        }
+        timing.total_ms = elapsed_ms(started_at);
+        timing.finished_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+        tracing::debug!(timing = ?timing, "session setup timing");
+        self.emit("ssh-connect-timing", ConnectTimingEvent {
+            connection_id: connection.id.clone(),
+            timing: timing.clone(),
+        });
+        self.connect_timings.lock().unwrap().insert(connection.id.clone(), timing);
+        result
+    }
+
+    pub fn last_connect_timing(&self, connection_id: &str) -> Option<ConnectTiming> {
+        self.connect_timings.lock().unwrap().get(connection_id).cloned()
+    }
+
+    // 在已完成握手的会话上认证（横幅、口令错误保留会话等）
+    fn authenticate_session(&self, sess: Session, connection: &SshConnection) -> anyhow::Result<Session> {
+        sess.set_keepalive(true, DEFAULT_KEEPALIVE_SECS);
+
+        let effective_username = self.effective_username(connection)?;
+        tracing::debug!(user = %effective_username, "authenticating");
+
+        // 服务器的登录前横幅（法律声明）在首次 userauth 请求后才可读取：
+        // 先用 "none" 方法探测一次，认证前发给前端；认证过程中才出现的横幅在认证后补发。
+        let _ = sess.auth_methods(&effective_username);
+        let mut banner_sent = self.emit_auth_banner(&sess, connection);
+
+        let auth_result = if sess.authenticated() {
+            Ok(())
+        } else {
+            self.authenticate(&sess, connection, &effective_username)
+        };
+        if !banner_sent {
+            banner_sent = self.emit_auth_banner(&sess, connection);
+        }
+        tracing::debug!(banner = banner_sent, "auth banner checked");
+        if let Err(e) = auth_result {
+            tracing::warn!(error = %e, "authentication failed");
+            if e.downcast_ref::<ssh2::Error>().is_some_and(Self::is_auth_disconnect) {
+                return Err(TooManyAuthFailures.into());
+            }
+            if e.is::<WrongPassphrase>() {
+                self.store_pending_auth(&connection.id, sess);
+            }
+            return Err(e);
+        }
+
+        if !sess.authenticated() {
+            tracing::warn!("server did not accept authentication");
+            return Err(anyhow::anyhow!("Authentication failed"));
+        }
+        tracing::debug!("authenticated");
+
+        Ok(sess)
+    }
+
+    fn emit_auth_banner(&self, sess: &Session, connection: &SshConnection) -> bool {
+        let text = match sess.userauth_banner() {
+            Ok(Some(text)) if !text.trim().is_empty() => text.to_string(),
+            _ => return false,
+        };
+        self.emit("ssh-auth-banner", SshAuthBanner {
+            connection_id: connection.id.clone(),
+            text,
+        });
+        true
+    }
+
+    fn connect_tcp_session(
+        host: &str,
+        port: u16,
+        host_key_algorithms: Option<&str>,
+        timing: &mut ConnectTiming,
+    ) -> anyhow::Result<Session> {
+        let dns_started = Instant::now();
+        let addrs: Vec<_> = format!("{}:{}", host, port)
+            .to_socket_addrs()
+            .inspect_err(|e| tracing::warn!(error = %e, "address resolution failed"))?
+            .collect();
+        timing.dns_ms = Some(elapsed_ms(dns_started));
+        if addrs.is_empty() {
+            return Err(anyhow::anyhow!("Failed to resolve host: {}", host));
+        }
+        tracing::debug!(count = addrs.len(), "resolved addresses");
+
+        let mut sess_opt: Option<Session> = None;
+        let mut attempts: Vec<String> = Vec::new();
+        // tcp_ms 包含之前失败的地址所花的时间
+        let tcp_started = Instant::now();
+        for addr in addrs {
+            let tcp = match TcpStream::connect_timeout(&addr, Duration::from_secs(10)) {
+                Ok(tcp) => tcp,
+                Err(e) => {
+                    attempts.push(format!("{} connect failed: {}", addr, e));
+                    continue;
+                }
+            };
+
+            if let Err(e) = tcp.set_read_timeout(Some(Duration::from_secs(30))) {
+                attempts.push(format!("{} set read timeout failed: {}", addr, e));
+                continue;
+            }
+            if let Err(e) = tcp.set_write_timeout(Some(Duration::from_secs(30))) {
+                attempts.push(format!("{} set write timeout failed: {}", addr, e));
+                continue;
+            }
+            if let Err(e) = tcp.set_nonblocking(false) {
+                attempts.push(format!("{} set blocking mode failed: {}", addr, e));
+                continue;
+            }
+
+            timing.tcp_ms = Some(elapsed_ms(tcp_started));
+            timing.address = Some(addr.to_string());
+            let handshake_started = Instant::now();
+            let mut sess = Self::new_session(host_key_algorithms)?;
+            sess.set_tcp_stream(tcp);
+            sess.set_timeout(30000); // 30秒超时
+            let handshake = sess.handshake();
+            timing.handshake_ms = Some(elapsed_ms(handshake_started));
+            if let Err(e) = handshake {
+                let raw = e.to_string();
+                let reason = if raw.contains("Failed getting banner") {
+                    format!(
+                        "{} handshake failed: {} (target may not be SSH / SSHD not ready / network device interrupted banner)",
+                        addr, raw
+                    )
+                } else {
+                    Self::handshake_error(&addr.to_string(), e, host_key_algorithms)
+                };
+                attempts.push(reason);
+                continue;
+            }
+
+            tracing::debug!(%addr, "handshake complete");
+            sess_opt = Some(sess);
             break;
         }
 
-        let sess = sess_opt.ok_or_else(|| {
-            anyhow::anyhow!(
-                "SSH connection failed for {}:{}; tried {} address(es): {}",
-                host,
-                connection.port,
-                attempts.len(),
-                attempts.join(" | ")
-            )
-        })?;
-        sess.set_keepalive(true, 15);
+        for attempt in &attempts {
+            tracing::warn!(attempt = %attempt, "connect attempt failed");
+        }
+        sess_opt.ok_or_else(|| {
+            anyhow::anyhow!(
+                "SSH connection failed for {}:{}; tried {} address(es): {}",
+                host,
+                port,
+                attempts.len(),
+                attempts.join(" | ")
+            )
+        })
+    }
+
+    #[cfg(unix)]
+    fn connect_unix_session(
+        socket_path: &str,
+        host_key_algorithms: Option<&str>,
+        timing: &mut ConnectTiming,
+    ) -> anyhow::Result<Session> {
+        let socket_path = socket_path.trim();
+        if socket_path.is_empty() {
+            return Err(anyhow::anyhow!("Unix socket path is empty"));
+        }
+        let connect_started = Instant::now();
+        let stream = std::os::unix::net::UnixStream::connect(socket_path)
+            .map_err(|e| anyhow::anyhow!("Failed to connect to unix socket '{}': {}", socket_path, e))?;
+        timing.tcp_ms = Some(elapsed_ms(connect_started));
+        timing.address = Some(format!("unix:{}", socket_path));
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+
+        let handshake_started = Instant::now();
+        let mut sess = Self::new_session(host_key_algorithms)?;
+        sess.set_tcp_stream(stream);
+        sess.set_timeout(30000);
+        let handshake = sess.handshake();
+        timing.handshake_ms = Some(elapsed_ms(handshake_started));
+        handshake
+            .map_err(|e| anyhow::anyhow!(Self::handshake_error(&format!("unix:{}", socket_path), e, host_key_algorithms)))?;
+        tracing::debug!(socket = %socket_path, "handshake complete");
+        Ok(sess)
+    }
+
+    #[cfg(not(unix))]
+    fn connect_unix_session(
+        _socket_path: &str,
+        _host_key_algorithms: Option<&str>,
+        _timing: &mut ConnectTiming,
+    ) -> anyhow::Result<Session> {
+        Err(anyhow::anyhow!("Unix socket endpoints are only supported on Unix platforms"))
+    }
+
+    fn authenticate(
+        &self,
+        sess: &Session,
+        connection: &SshConnection,
+        effective_username: &str,
+    ) -> anyhow::Result<()> {
+        match &connection.auth_type {
+            AuthType::Password { keychain: Some(keychain), .. } => {
+                let password = keychain.resolve()?;
+                sess.userauth_password(effective_username, &password)?;
+            }
+            AuthType::Password { password, .. } => {
+                sess.userauth_password(effective_username, password)?;
+            }
+            AuthType::Agent { agent_sock, identity } => {
+                let agent_sock = resolve_agent_sock(agent_sock.as_deref())?;
+                // socket 路径直接交给这个 agent 实例，不修改进程环境变量
+                let agent = ssh_agent::Agent::connect(sess, agent_sock.as_deref())
+                    .map_err(|e| anyhow::anyhow!("Failed to connect to SSH agent: {}", e))?;
+                let identities = agent.identities()?;
+                if identities.is_empty() {
+                    return Err(anyhow::anyhow!("SSH agent has no identities"));
+                }
+                let identities = match identity.as_deref().map(str::trim).filter(|value| !value.is_empty()) {
+                    Some(wanted) => {
+                        let wanted_blob = public_key_blob(wanted);
+                        let matched: Vec<_> = identities
+                            .into_iter()
+                            .filter(|key| key.comment() == wanted || wanted_blob.as_deref() == Some(key.blob()))
+                            .collect();
+                        if matched.is_empty() {
+                            return Err(anyhow::anyhow!("SSH agent has no identity matching '{}'", wanted));
+                        }
+                        matched
+                    }
+                    None => identities,
+                };
+                let mut accepted = false;
+                let mut last_error: Option<ssh2::Error> = None;
+                for key in &identities {
+                    match agent.userauth(effective_username, key) {
+                        Ok(()) if sess.authenticated() => {
+                            accepted = true;
+                            break;
+                        }
+                        Ok(()) => {}
+                        Err(e) => {
+                            let disconnected = Self::is_auth_disconnect(&e);
+                            last_error = Some(e);
+                            if disconnected {
+                                break;
+                            }
+                        }
+                    }
+                }
+                drop(agent);
+                if !accepted {
+                    if let Some(e) = last_error.filter(Self::is_auth_disconnect) {
+                        return Err(e.into());
+                    }
+                    return Err(anyhow::anyhow!(
+                        "SSH agent authentication failed: no identity was accepted"
+                    ));
+                }
+            }
+            AuthType::PrivateKey { key_path, key_content, passphrase } => {
+                let passphrase_str = passphrase.as_deref();
+
+                if let Some(content) = key_content {
+                    if !content.is_empty() {
+                        if let Err(e) = userauth_pubkey_memory_compat(
+                            sess,
+                            effective_username,
+                            content,
+                            passphrase_str,
+                        ) {
+                            if e.downcast_ref::<ssh2::Error>()
+                                .is_some_and(|err| Self::is_wrong_passphrase(err, content))
+                            {
+                                return Err(WrongPassphrase.into());
+                            }
+                            if e.downcast_ref::<ssh2::Error>().is_some_and(Self::is_auth_disconnect) {
+                                return Err(e);
+                            }
+                            return Err(anyhow::anyhow!(
+                                "Private key authentication failed: {}. Please check: 1) Key format (must be valid PEM), 2) Passphrase if key is encrypted, 3) Username is correct",
+                                e
+                            ));
+                        }
+                    } else {
+                        if key_path.is_empty() {
+                            return Err(anyhow::anyhow!("Both key_path and key_content are empty"));
+                        }
+                        sess.userauth_pubkey_file(
+                            effective_username,
+                            None,
+                            Path::new(key_path),
+                            passphrase_str,
+                        )
+                        .map_err(|e| Self::key_file_error(e, key_path))?;
+                    }
+                } else {
+                    if key_path.is_empty() {
+                        return Err(anyhow::anyhow!("key_path is empty"));
+                    }
+                    sess.userauth_pubkey_file(
+                        effective_username,
+                        None,
+                        Path::new(key_path),
+                        passphrase_str,
+                    )
+                    .map_err(|e| Self::key_file_error(e, key_path))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // 私钥解析失败且私钥是加密的，视为口令错误；服务端拒绝公钥是另外的错误码
+    fn is_wrong_passphrase(err: &ssh2::Error, key_text: &str) -> bool {
+        matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_FILE)
+            && private_key_is_encrypted(key_text)
+    }
+
+    fn is_transport_error(err: &ssh2::Error) -> bool {
+        matches!(
+            err.code(),
+            ssh2::ErrorCode::Session(code)
+                if code == Self::LIBSSH2_ERROR_SOCKET_DISCONNECT
+                    || code == Self::LIBSSH2_ERROR_SOCKET_SEND
+                    || code == Self::LIBSSH2_ERROR_SOCKET_RECV
+                    || code == Self::LIBSSH2_ERROR_TIMEOUT
+        )
+    }
+
+    // 保留会话时顺带清理过期条目，用户始终不重试时条目也不会一直留着
+    fn store_pending_auth(&self, connection_id: &str, sess: Session) {
+        let expired: Vec<Session> = {
+            let mut pending = self.pending_auth.lock().unwrap();
+            let ids: Vec<String> = pending
+                .iter()
+                .filter(|(_, (_, since))| since.elapsed() >= PENDING_AUTH_TTL)
+                .map(|(id, _)| id.clone())
+                .collect();
+            let expired = ids.iter().filter_map(|id| pending.remove(id)).map(|(sess, _)| sess).collect();
+            pending.insert(connection_id.to_string(), (sess, Instant::now()));
+            expired
+        };
+        for sess in expired {
+            let _ = sess.disconnect(None, "Authentication not completed", None);
+        }
+    }
+
+    // 超过保留时间的会话不再复用（服务端 LoginGraceTime 默认 120 秒）
+    fn take_pending_auth(&self, connection_id: &str) -> Option<Session> {
+        let (sess, since) = self.pending_auth.lock().unwrap().remove(connection_id)?;
+        if since.elapsed() >= PENDING_AUTH_TTL {
+            let _ = sess.disconnect(None, "Authentication not completed", None);
+            return None;
+        }
+        Some(sess)
+    }
+
+    fn is_auth_disconnect(err: &ssh2::Error) -> bool {
+        matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_SOCKET_DISCONNECT)
+            || err.message().to_ascii_lowercase().contains("too many authentication failures")
+    }
+
+    fn key_file_error(err: ssh2::Error, key_path: &str) -> anyhow::Error {
+        let key_text = std::fs::read_to_string(key_path).unwrap_or_default();
+        if Self::is_wrong_passphrase(&err, &key_text) {
+            WrongPassphrase.into()
+        } else {
+            anyhow::anyhow!(err)
+        }
+    }
+
+    // 用新口令重试认证：复用口令错误时保留的已握手会话，只重做私钥解密；没有保留会话时走完整连接
+    pub fn retry_with_passphrase(
+        &self,
+        connection: &SshConnection,
+        passphrase: &str,
+    ) -> anyhow::Result<String> {
+        let mut connection = connection.clone();
+        match &mut connection.auth_type {
+            AuthType::PrivateKey { passphrase: current, .. } => {
+                *current = Some(passphrase.to_string());
+            }
+            AuthType::Password { .. } | AuthType::Agent { .. } => {
+                return Err(anyhow::anyhow!("Connection does not use private key authentication"));
+            }
+        }
+
+        self.check_session_limit(&connection.id)?;
+        let sess = match self.take_pending_auth(&connection.id) {
+            Some(sess) => sess,
+            None => return self.connect(&connection),
+        };
+
+        let user = self.effective_username(&connection)?;
+        if let Err(e) = self.authenticate(&sess, &connection, &user) {
+            if e.is::<WrongPassphrase>() {
+                self.store_pending_auth(&connection.id, sess);
+                return Err(e);
+            }
+            // 保留期间服务端可能已因 LoginGraceTime 断开：传输层错误时重新完整连接
+            if e.downcast_ref::<ssh2::Error>().is_some_and(Self::is_transport_error) {
+                tracing::debug!(error = %e, "pending session is dead, reconnecting");
+                return self.connect(&connection);
+            }
+            return Err(e);
+        }
+        if !sess.authenticated() {
+            return Err(anyhow::anyhow!("Authentication failed"));
+        }
+        tracing::debug!("authenticated after passphrase retry");
+        self.register_session(&connection, sess)
+    }
+
+    // 登记到共享保活调度器（所有会话和转发共用一个线程）。stop 为 Some 时是转发，
+    // 按转发 id 登记在单独的表中，不会与会话 id 冲突
+    fn register_keepalive(&self, key: String, session: Arc<Mutex<Session>>, stop: Option<Arc<AtomicBool>>) {
+        self.keepalive_table(stop.is_some()).lock().unwrap().insert(
+            key,
+            KeepaliveEntry {
+                session,
+                stop,
+                interval: DEFAULT_KEEPALIVE_SECS,
+                next_due: Instant::now(),
+            },
+        );
+        if !self.keepalive_scheduler.swap(true, Ordering::SeqCst) {
+            let manager = self.clone();
+            thread_stats::spawn(ThreadKind::Keepalive, move || loop {
+                std::thread::sleep(Duration::from_secs(1));
+                manager.run_due_keepalives();
+            });
+        }
+    }
+
+    fn keepalive_table(&self, forward: bool) -> &Mutex<HashMap<String, KeepaliveEntry>> {
+        if forward {
+            &self.forward_keepalives
+        } else {
+            &self.keepalives
+        }
+    }
+
+    fn run_due_keepalives(&self) {
+        let now = Instant::now();
+        let mut due: Vec<_> = Vec::new();
+        for table in [&self.keepalives, &self.forward_keepalives] {
+            let keepalives = table.lock().unwrap();
+            due.extend(
+                keepalives
+                    .iter()
+                    .filter(|(_, entry)| entry.next_due <= now)
+                    .map(|(id, entry)| (id.clone(), entry.session.clone(), entry.stop.clone())),
+            );
+        }
+
+        for (session_id, session, stop) in due {
+            let current = match &stop {
+                Some(stop) => !stop.load(Ordering::Relaxed),
+                None => {
+                    let sessions = self.sessions.lock().unwrap();
+                    sessions
+                        .get(&session_id)
+                        .is_some_and(|current| Arc::ptr_eq(current, &session))
+                }
+            };
+            if !current {
+                match stop {
+                    Some(_) => self.remove_forward_keepalive(&session_id, &session),
+                    None => self.remove_keepalive(&session_id, &session),
+                }
+                continue;
+            }
+
+            // 会话正被其他操作占用时不阻塞调度线程，下一秒再试
+            let result = match session.try_lock() {
+                Ok(sess) => sess.keepalive_send(),
+                Err(_) => Ok(1),
+            };
+            let delay = match result {
+                Ok(wait) => if wait == 0 { 5 } else { wait.min(60) },
+                Err(err) if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN) => 1,
+                Err(e) if stop.is_some() => {
+                    self.remove_forward_keepalive(&session_id, &session);
+                    self.set_forward_status(&session_id, ForwardStatus::Error, Some(format!("Keepalive failed: {}", e)));
+                    continue;
+                }
+                Err(_) => {
+                    // Transport is gone: reap the session so the maps
+                    // (and `is_connected`) reflect reality.
+                    self.forget_session(&session_id, &session);
+                    continue;
+                }
+            };
+
+            let mut keepalives = self.keepalive_table(stop.is_some()).lock().unwrap();
+            if let Some(entry) = keepalives.get_mut(&session_id) {
+                if Arc::ptr_eq(&entry.session, &session) {
+                    entry.next_due = now + Duration::from_secs(delay as u64);
+                }
+            }
+        }
+    }
+
+    fn remove_keepalive(&self, session_id: &str, session: &Arc<Mutex<Session>>) {
+        Self::remove_keepalive_entry(&self.keepalives, session_id, session);
+    }
+
+    fn remove_forward_keepalive(&self, forward_id: &str, session: &Arc<Mutex<Session>>) {
+        Self::remove_keepalive_entry(&self.forward_keepalives, forward_id, session);
+    }
+
+    fn remove_keepalive_entry(table: &Mutex<HashMap<String, KeepaliveEntry>>, id: &str, session: &Arc<Mutex<Session>>) {
+        let mut keepalives = table.lock().unwrap();
+        if keepalives
+            .get(id)
+            .is_some_and(|entry| Arc::ptr_eq(&entry.session, session))
+        {
+            keepalives.remove(id);
+        }
+    }
+
+    // 热更新保活间隔：立即作用于会话，并让调度器在下一轮按新间隔发送
+    pub fn update_keepalive(&self, session_id: &str, secs: u32) -> anyhow::Result<()> {
+        if !(1..=3600).contains(&secs) {
+            return Err(anyhow::anyhow!("Keepalive interval must be between 1 and 3600 seconds"));
+        }
+        let session = {
+            let mut keepalives = self.keepalives.lock().unwrap();
+            let entry = keepalives
+                .get_mut(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Keepalive not running"))?;
+            entry.interval = secs;
+            entry.next_due = Instant::now();
+            entry.session.clone()
+        };
+        session.lock().unwrap().set_keepalive(true, secs);
+        Ok(())
+    }
+
+    // 0 表示不限制
+    pub fn set_max_sessions(&self, limit: usize) {
+        self.max_sessions.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn set_require_username(&self, required: bool) {
+        self.require_username.store(required, Ordering::Relaxed);
+    }
+
+    // 连接实际使用的用户名。留空时默认退回本机 $USER / $USERNAME / root；
+    // 开启 require_username 后改为报错，避免在不知情时以意外的用户登录
+    pub fn effective_username(&self, connection: &SshConnection) -> anyhow::Result<String> {
+        let username = connection.username.trim();
+        if !username.is_empty() {
+            return Ok(username.to_string());
+        }
+        if self.require_username.load(Ordering::Relaxed) {
+            return Err(anyhow::anyhow!("Username is required for {}", connection.host));
+        }
+        let fallback = fallback_username();
+        tracing::info!(user = %fallback, "username empty, using local user name");
+        Ok(fallback)
+    }
+
+    pub fn connection_count(&self) -> ConnectionCount {
+        let limit = self.max_sessions.load(Ordering::Relaxed);
+        ConnectionCount {
+            sessions: self.sessions.lock().unwrap().len(),
+            sftp_sessions: self.sftp_sessions.lock().unwrap().len(),
+            forwards: self.forwards.lock().unwrap().len(),
+            max_sessions: (limit > 0).then_some(limit),
+        }
+    }
+
+    // 连接前的预检，避免超限时仍去握手认证；register_session 持锁时会再检查一次
+    fn check_session_limit(&self, connection_id: &str) -> anyhow::Result<()> {
+        let sessions = self.sessions.lock().unwrap();
+        self.session_limit_allows(&sessions, connection_id)
+    }
+
+    fn session_limit_allows(
+        &self,
+        sessions: &HashMap<String, Arc<Mutex<Session>>>,
+        connection_id: &str,
+    ) -> anyhow::Result<()> {
+        let limit = self.max_sessions.load(Ordering::Relaxed);
+        if limit > 0 {
+            let open = sessions.len() - usize::from(sessions.contains_key(connection_id));
+            if open >= limit {
+                return Err(anyhow::anyhow!(
+                    "Session limit reached ({} open, max {})",
+                    open,
+                    limit
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn connect(&self, connection: &SshConnection) -> anyhow::Result<String> {
+        self.check_session_limit(&connection.id)?;
+
+        let sess = self.create_authenticated_session(connection)?;
+        self.register_session(connection, sess)
+    }
+
+    // 并发（有上限）连接多台主机，结果按输入顺序返回；每台完成时发出 ssh-connection-state
+    pub fn connect_many(&self, connections: Vec<SshConnection>) -> Vec<ConnectResult> {
+        let total = connections.len();
+        let queue = Arc::new(Mutex::new(connections.into_iter().enumerate()));
+        let results: Arc<Mutex<Vec<Option<ConnectResult>>>> = Arc::new(Mutex::new(vec![None; total]));
+
+        let workers: Vec<_> = (0..CONNECT_MANY_CONCURRENCY.min(total))
+            .map(|_| {
+                let manager = self.clone();
+                let queue = queue.clone();
+                let results = results.clone();
+                thread_stats::spawn(ThreadKind::Other, move || loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, connection)) = next else {
+                        break;
+                    };
+                    manager.emit("ssh-connection-state", SshConnectionState {
+                        connection_id: connection.id.clone(),
+                        state: "connecting".to_string(),
+                        error: None,
+                    });
+                    let result = match manager.connect(&connection) {
+                        Ok(session_id) => ConnectResult {
+                            connection_id: connection.id.clone(),
+                            session_id: Some(session_id),
+                            error: None,
+                        },
+                        Err(e) => ConnectResult {
+                            connection_id: connection.id.clone(),
+                            session_id: None,
+                            error: Some(e.to_string()),
+                        },
+                    };
+                    manager.emit("ssh-connection-state", SshConnectionState {
+                        connection_id: connection.id,
+                        state: if result.error.is_none() { "connected" } else { "failed" }.to_string(),
+                        error: result.error.clone(),
+                    });
+                    results.lock().unwrap()[index] = Some(result);
+                })
+            })
+            .collect();
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        let results = std::mem::take(&mut *results.lock().unwrap());
+        results.into_iter().flatten().collect()
+    }
+
+    // 经外层会话的 direct-tcpip 通道连接内层主机，内层注册为普通会话，shell/SFTP/转发等命令都可直接使用。
+    // 记录内层 -> 外层连接信息后，内层的所有传输（含 SFTP、执行、克隆）都经 open_nested_transport 建立
+    pub fn connect_nested(&self, outer_session_id: &str, connection: &SshConnection) -> anyhow::Result<String> {
+        if connection.id == outer_session_id {
+            return Err(anyhow::anyhow!("Inner session id must differ from the outer session"));
+        }
+        let host = connection.host.trim();
+        if host.is_empty() || host.starts_with("unix:") {
+            return Err(anyhow::anyhow!("Inner host must be a host name or address reachable from the outer host"));
+        }
+        let outer_connection = {
+            let connections = self.connections.lock().unwrap();
+            connections
+                .get(outer_session_id)
+                .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", outer_session_id))?
+                .clone()
+        };
+
+        self.nested_outer
+            .lock()
+            .unwrap()
+            .insert(connection.id.clone(), outer_connection);
+        let result = self.connect(connection);
+        if result.is_err() {
+            self.nested_outer.lock().unwrap().remove(&connection.id);
+        }
+        result
+    }
+
+    // 外层使用独立的传输会话（不影响外层的 shell），direct-tcpip 通道与本地回环 socket 之间由泵线程转发；
+    // 内层会话释放后 socket 关闭，泵线程结束并断开外层传输
+    fn open_nested_transport(
+        &self,
+        outer_connection: &SshConnection,
+        connection: &SshConnection,
+        timing: &mut ConnectTiming,
+    ) -> anyhow::Result<Session> {
+        let (host, port) = (connection.host.trim(), connection.port);
+        let host_key_algorithms = connection.host_key_algorithms.as_deref();
+        let tunnel_started = Instant::now();
+        let outer = Arc::new(Mutex::new(self.create_authenticated_session(outer_connection)?));
+        let channel = Self::open_direct_tcpip(&outer, host, port)
+            .map_err(|e| anyhow::anyhow!("{} could not reach {}:{}: {}", outer_connection.host.trim(), host, port, e))?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let inner_stream = TcpStream::connect(listener.local_addr()?)?;
+        let pump_stream = Self::accept_peer(&listener, inner_stream.local_addr()?)?;
+        drop(listener);
+
+        outer.lock().unwrap().set_blocking(false);
+        thread_stats::spawn(ThreadKind::Other, move || {
+            Self::pump_nonblocking(channel, pump_stream);
+            let sess = outer.lock().unwrap();
+            let _ = sess.disconnect(None, "Nested session closed", None);
+        });
+
+        timing.tcp_ms = Some(elapsed_ms(tunnel_started));
+        timing.address = Some(format!("{}:{} via {}", host, port, outer_connection.host.trim()));
+        inner_stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        inner_stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+        let handshake_started = Instant::now();
+        let mut sess = Self::new_session(host_key_algorithms)?;
+        sess.set_tcp_stream(inner_stream);
+        sess.set_timeout(30000);
+        let handshake = sess.handshake();
+        timing.handshake_ms = Some(elapsed_ms(handshake_started));
+        handshake.map_err(|e| {
+            let prefix = format!("{}:{} (via {})", host, port, outer_connection.host.trim());
+            anyhow::anyhow!(Self::handshake_error(&prefix, e, host_key_algorithms))
+        })?;
+        tracing::debug!(host = %host, port, via = %outer_connection.host.trim(), "nested handshake complete");
+        Ok(sess)
+    }
+
+    // 只接受来自 expected 的连接：其他本地进程可能抢先连上这个临时端口，拿到通往内层主机的隧道。
+    // connect 已返回，我们的连接必然在 backlog 中，非阻塞取完 backlog 仍未找到即失败
+    fn accept_peer(listener: &TcpListener, expected: std::net::SocketAddr) -> anyhow::Result<TcpStream> {
+        listener.set_nonblocking(true)?;
+        loop {
+            match listener.accept() {
+                Ok((stream, peer)) if peer == expected => {
+                    stream.set_nonblocking(false)?;
+                    return Ok(stream);
+                }
+                Ok((stream, peer)) => {
+                    tracing::warn!(peer = %peer, "rejected unexpected connection to nested tunnel listener");
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    return Err(anyhow::anyhow!("Nested tunnel connection was not accepted"));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // 并发连接可能都通过了 connect 前的预检，这里持有会话表锁再检查一次，超限时断开新会话
+    fn register_session(&self, connection: &SshConnection, sess: Session) -> anyhow::Result<String> {
+        let session_id = connection.id.clone();
+        let host_key = HostKeyInfo::from_session(&sess);
+        let session_arc = Arc::new(Mutex::new(sess));
+
+        // 存储 shell 会话
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Err(e) = self.session_limit_allows(&sessions, &session_id) {
+            drop(sessions);
+            let _ = session_arc.lock().unwrap().disconnect(None, "Session limit reached", None);
+            return Err(e);
+        }
+        sessions.insert(session_id.clone(), session_arc.clone());
+        drop(sessions);
+
+        match host_key {
+            Some(info) => self.host_keys.lock().unwrap().insert(session_id.clone(), info),
+            None => self.host_keys.lock().unwrap().remove(&session_id),
+        };
+
+        // 存储连接信息（用于后续创建 SFTP 会话）
+        let mut connections = self.connections.lock().unwrap();
+        connections.insert(session_id.clone(), connection.clone());
+        drop(connections);
+
+        self.register_keepalive(session_id.clone(), session_arc, None);
+
+        Ok(session_id)
+    }
+
+    // 复用已保存的连接信息（含口令/密钥）在新 id 下建立独立会话，无需再次输入凭据
+    pub fn clone_session(&self, session_id: &str, new_id: &str) -> anyhow::Result<String> {
+        let new_id = new_id.trim();
+        if new_id.is_empty() {
+            return Err(anyhow::anyhow!("New session id is empty"));
+        }
+        if self.sessions.lock().unwrap().contains_key(new_id) {
+            return Err(anyhow::anyhow!("Session already exists: {}", new_id));
+        }
+
+        let mut connection = {
+            let connections = self.connections.lock().unwrap();
+            connections
+                .get(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", session_id))?
+                .clone()
+        };
+        connection.id = new_id.to_string();
+        let outer_connection = self.nested_outer.lock().unwrap().get(session_id).cloned();
+        if let Some(outer_connection) = outer_connection {
+            self.nested_outer
+                .lock()
+                .unwrap()
+                .insert(connection.id.clone(), outer_connection);
+        }
+        let result = self.connect(&connection);
+        if result.is_err() {
+            self.nested_outer.lock().unwrap().remove(&connection.id);
+        }
+        result
+    }
+
+    pub fn open_shell(
+        &self,
+        session_id: &str,
+        app_handle: tauri::AppHandle,
+        options: ShellOptions,
+    ) -> anyhow::Result<()> {
+        let _span = tracing::debug_span!("open_shell", session_id = %session_id).entered();
+        let session = {
+            let sessions = self.sessions.lock().unwrap();
+            sessions
+                .get(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Session not found"))?
+                .clone()
+        };
+
+        let attach_command = match &options.multiplexer {
+            Some(attach) => {
+                let args = attach.args()?;
+                let program = attach.program();
+                let check = format!("command -v {} >/dev/null 2>&1 && echo ok", program);
+                let found = self.execute_command(session_id, &check)?;
+                if found.trim() != "ok" {
+                    return Err(anyhow::anyhow!("{} is not installed on the remote host", program));
+                }
+                let quoted: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+                Some(format!("{} {}", program, quoted.join(" ")))
+            }
+            None => None,
+        };
+
+        let login_script = match &options.login_script {
+            Some(steps) if !steps.is_empty() => {
+                let mut compiled = Vec::with_capacity(steps.len());
+                for step in steps {
+                    let pattern = match step.wait_for.as_deref().filter(|p| !p.is_empty()) {
+                        Some(pattern) => Some(regex::Regex::new(pattern).map_err(|e| {
+                            anyhow::anyhow!("Invalid login script pattern '{}': {}", pattern, e)
+                        })?),
+                        None => None,
+                    };
+                    compiled.push((step.clone(), pattern));
+                }
+                Some(compiled)
+            }
+            _ => None,
+        };
+        let script_output = login_script.as_ref().map(|_| Arc::new(Mutex::new(String::new())));
+        let session_log = match options.log_path.as_deref().map(str::trim).filter(|path| !path.is_empty()) {
+            Some(path) => Some(Arc::new(Mutex::new(SessionLog::open(
+                PathBuf::from(path),
+                options.log_max_bytes,
+            )?))),
+            None => None,
+        };
+
+        let env = {
+            let connections = self.connections.lock().unwrap();
+            connections
+                .get(session_id)
+                .and_then(|connection| connection.env.clone())
+                .unwrap_or_default()
+        };
+
+        let sess = session.lock().unwrap();
+        let mut channel = sess.channel_session()?;
+        channel.request_pty("xterm-256color", None, Some((80, 24, 0, 0)))?;
+        self.pty_sizes
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), ShellSize { cols: 80, rows: 24 });
+
+        // 服务端未在 AcceptEnv 中放行的变量会被拒绝，记录下来告知前端
+        let mut env_vars: Vec<(&String, &String)> = env.iter().collect();
+        env_vars.sort();
+        let mut applied: Vec<String> = Vec::new();
+        let mut rejected: Vec<String> = Vec::new();
+        for (name, value) in env_vars {
+            match channel.setenv(name, value) {
+                Ok(()) => applied.push(name.clone()),
+                Err(e) => {
+                    tracing::debug!(name = %name, error = %e, "setenv rejected");
+                    rejected.push(name.clone());
+                }
+            }
+        }
+
+        match &attach_command {
+            Some(command) => channel.exec(command)?,
+            None => channel.shell()?,
+        }
+        tracing::debug!(multiplexer = ?attach_command, "shell started");
+        if !env.is_empty() {
+            let _ = app_handle.emit("ssh-env-result", SshEnvResult {
+                session_id: session_id.to_string(),
+                applied,
+                rejected,
+            });
+        }
+        
+        // Set channel to non-blocking mode
+        sess.set_blocking(false);
+        drop(sess);
+
+        let channel_arc = Arc::new(Mutex::new(channel));
+        let mut channels = self.channels.lock().unwrap();
+        channels.insert(session_id.to_string(), channel_arc.clone());
+        drop(channels);
+
+        // Start reading output in background
+        let buffer_size = options.read_buffer_size();
+        let session_id_clone = session_id.to_string();
+        let channel_clone = channel_arc.clone();
+        let app_handle = app_handle.clone();
+        let manager = self.clone();
+        let sessions_map = self.sessions.clone();
+        let latency_map = self.latency_monitors.clone();
+        let reader_script_output = script_output.clone();
+        let titles_map = self.shell_titles.clone();
+        let logs_map = self.session_logs.clone();
+        let tails_map = self.output_tails.clone();
+        let rate_limits_map = self.output_rate_limits.clone();
+        let detached_map = self.detached_shells.clone();
+        detached_map.lock().unwrap().remove(session_id);
+        let auto_answer_set = self.auto_answer.clone();
+        let readers_map = self.readers.clone();
+        let macro_map = self.macro_outputs.clone();
+        let bridges_map = self.ws_bridges.clone();
+        let reader_stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = reader_stop.clone();
+        let paste_map = self.bracketed_paste.clone();
+        paste_map.lock().unwrap().remove(session_id);
+        let encodings_map = self.shell_encodings.clone();
+        let initial_encoding = self
+            .connection_encoding(session_id)
+            .and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes()))
+            .unwrap_or(encoding_rs::UTF_8);
+        encodings_map
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), initial_encoding);
+        let output_tail = Arc::new(Mutex::new(Vec::new()));
+        tails_map
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), output_tail.clone());
+        match &session_log {
+            Some(log) => logs_map.lock().unwrap().insert(session_id.to_string(), log.clone()),
+            None => logs_map.lock().unwrap().remove(session_id),
+        };
+        let reader_thread = thread_stats::spawn(ThreadKind::Reader, move || {
+            let mut buffer = vec![0u8; buffer_size];
+            let mut paste_tracker = BracketedPasteTracker::default();
+            let mut query_responder = TerminalQueryResponder::default();
+            // 流式解码：跨读取块的多字节字符留在 decoder 中，与下一块拼接
+            let mut encoding = initial_encoding;
+            let mut decoder = encoding.new_decoder();
+            let mut disconnected_reason: Option<DisconnectReason> = None;
+            let mut zero_read_streak: u8 = 0;
+            let mut throttle = OutputThrottle::default();
+            loop {
+                if stop_flag.load(Ordering::Relaxed) {
+                    break;
+                }
+                let mut channel_lock = match channel_clone.lock() {
+                    Ok(ch) => ch,
+                    Err(_) => break,
+                };
+                
+                let rate_limit = rate_limits_map
+                    .lock()
+                    .ok()
+                    .and_then(|limits| limits.get(&session_id_clone).copied());
+                let mut throttle_delay = None;
+                match channel_lock.read(&mut buffer) {
+                    Ok(n) if n > 0 => {
+                        zero_read_streak = 0;
+                        let (delay, changed) = throttle.record(n, rate_limit);
+                        throttle_delay = delay;
+                        if let Some(throttled) = changed {
+                            let _ = app_handle.emit("terminal-throttled", TerminalThrottled {
+                                session_id: session_id_clone.clone(),
+                                throttled,
+                                limit: rate_limit.unwrap_or(0),
+                            });
+                        }
+                        if let Some(log) = &session_log {
+                            if let Err(e) = log.lock().unwrap().write(&buffer[..n]) {
+                                tracing::warn!(session_id = %session_id_clone, error = %e, "session log write failed");
+                            }
+                        }
+                        if let Ok(mut tail) = output_tail.lock() {
+                            tail.extend_from_slice(&buffer[..n]);
+                            if tail.len() > SCREEN_TAIL_LIMIT {
+                                let cut = tail.len() - SCREEN_TAIL_LIMIT;
+                                tail.drain(..cut);
+                            }
+                        }
+                        if let Some(enabled) = paste_tracker.feed(&buffer[..n]) {
+                            let changed = paste_map
+                                .lock()
+                                .ok()
+                                .map(|mut modes| modes.insert(session_id_clone.clone(), enabled) != Some(enabled))
+                                .unwrap_or(false);
+                            if changed {
+                                let _ = app_handle.emit("bracketed-paste", BracketedPasteChanged {
+                                    session_id: session_id_clone.clone(),
+                                    enabled,
+                                });
+                            }
+                        }
+                        let mut output = String::new();
+                        // 编码切换只在块边界生效：先冲刷旧 decoder 中残留的半个字符
+                        let requested = encodings_map
+                            .lock()
+                            .ok()
+                            .and_then(|encodings| encodings.get(&session_id_clone).copied())
+                            .unwrap_or(encoding);
+                        if requested != encoding {
+                            decode_chunk(&mut decoder, &[], &mut output, true);
+                            encoding = requested;
+                            decoder = encoding.new_decoder();
+                        }
+                        decode_chunk(&mut decoder, &buffer[..n], &mut output, false);
+                        if let Some(script_output) = &reader_script_output {
+                            if let Ok(mut collected) = script_output.lock() {
+                                push_capped(&mut collected, &output, LOGIN_SCRIPT_OUTPUT_LIMIT);
+                            }
+                        }
+                        let macro_output = macro_map
+                            .lock()
+                            .ok()
+                            .and_then(|outputs| outputs.get(&session_id_clone).cloned());
+                        if let Some(macro_output) = macro_output {
+                            if let Ok(mut collected) = macro_output.lock() {
+                                push_capped(&mut collected, &output, LOGIN_SCRIPT_OUTPUT_LIMIT);
+                            }
+                        }
+                        let bridge = bridges_map
+                            .lock()
+                            .ok()
+                            .and_then(|bridges| bridges.get(&session_id_clone).cloned());
+                        if let Some(bridge) = bridge {
+                            bridge.broadcast(&output);
+                        }
+                        if let Some(title) = parse_osc_title(&output) {
+                            let changed = titles_map
+                                .lock()
+                                .ok()
+                                .map(|mut titles| titles.insert(session_id_clone.clone(), title.clone()).as_ref() != Some(&title))
+                                .unwrap_or(false);
+                            if changed {
+                                let _ = app_handle.emit("terminal-title", TerminalTitle {
+                                    session_id: session_id_clone.clone(),
+                                    title,
+                                });
+                            }
+                        }
+                        // 分离期间照常读取（远端不会因窗口填满而阻塞），输出缓存到重新附加时回放
+                        let detached = detached_map
+                            .lock()
+                            .ok()
+                            .map(|mut detached| match detached.get_mut(&session_id_clone) {
+                                Some(pending) => {
+                                    push_capped(pending, &output, DETACHED_OUTPUT_LIMIT);
+                                    true
+                                }
+                                None => false,
+                            })
+                            .unwrap_or(false);
+                        if detached {
+                            // 分离期间没有 xterm 应答查询，开启后由这里代答，避免远端程序等待超时
+                            let answering = auto_answer_set
+                                .lock()
+                                .is_ok_and(|sessions| sessions.contains(&session_id_clone));
+                            if answering {
+                                let answer = query_responder.feed(&buffer[..n]);
+                                if !answer.is_empty() {
+                                    if let Err(e) = SshManager::write_channel_nonblocking(&mut channel_lock, &answer) {
+                                        tracing::debug!(session_id = %session_id_clone, error = %e, "terminal query answer failed");
+                                    }
+                                }
+                            }
+                            drop(channel_lock);
+                            std::thread::sleep(throttle_delay.unwrap_or(std::time::Duration::from_millis(10)));
+                            continue;
+                        }
+                        let _ = app_handle.emit("terminal-output", TerminalOutput {
+                            session_id: session_id_clone.clone(),
+                            data: output,
+                        });
+                        let sent_at = latency_map
+                            .lock()
+                            .ok()
+                            .and_then(|mut monitors| monitors.get_mut(&session_id_clone)?.take());
+                        if let Some(sent_at) = sent_at {
+                            let elapsed = sent_at.elapsed();
+                            if elapsed < LATENCY_ECHO_TIMEOUT {
+                                let _ = app_handle.emit("terminal-latency", TerminalLatency {
+                                    session_id: session_id_clone.clone(),
+                                    ms: elapsed.as_millis() as u64,
+                                });
+                            }
+                        }
+                    }
+                    Ok(_) => {
+                        // In non-blocking mode, occasional zero-byte reads can happen transiently.
+                        // Only treat it as a disconnect when EOF is confirmed or it repeats.
+                        if channel_lock.eof() {
+                            disconnected_reason = Some(DisconnectReason::from_channel(&channel_lock, true));
+                            break;
+                        }
+                        zero_read_streak = zero_read_streak.saturating_add(1);
+                        if zero_read_streak >= 3 {
+                            disconnected_reason = Some(DisconnectReason::from_channel(&channel_lock, false));
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        zero_read_streak = 0;
+                        // No data available in non-blocking mode, continue
+                        if let (_, Some(throttled)) = throttle.record(0, rate_limit) {
+                            let _ = app_handle.emit("terminal-throttled", TerminalThrottled {
+                                session_id: session_id_clone.clone(),
+                                throttled,
+                                limit: rate_limit.unwrap_or(0),
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        disconnected_reason = Some(DisconnectReason::NetworkError { message: e.to_string() });
+                        break;
+                    }
+                }
+                drop(channel_lock);
+                // 限速时睡到当前窗口结束；期间不读通道，SSH 窗口填满后远端随之暂停
+                std::thread::sleep(throttle_delay.unwrap_or(std::time::Duration::from_millis(10)));
+            }
+            if let Some(detail) = disconnected_reason {
+                let reason = detail.label();
+                tracing::info!(session_id = %session_id_clone, reason = %reason, "shell disconnected");
+                if let Ok(mut sessions) = sessions_map.lock() {
+                    sessions.remove(&session_id_clone);
+                }
+                manager.forget_session_state(&session_id_clone);
+                if let Ok(mut readers) = readers_map.lock() {
+                    if readers
+                        .get(&session_id_clone)
+                        .is_some_and(|reader| Arc::ptr_eq(&reader.channel, &channel_clone))
+                    {
+                        readers.remove(&session_id_clone);
+                    }
+                }
+                let _ = app_handle.emit("terminal-disconnected", TerminalDisconnected {
+                    session_id: session_id_clone.clone(),
+                    reason,
+                    detail,
+                });
+            }
+        });
+        // 重新打开 shell 时旧的读取线程随之停止
+        let previous = self.readers.lock().unwrap().insert(
+            session_id.to_string(),
+            ReaderHandle {
+                stop: reader_stop,
+                channel: channel_arc,
+                thread: reader_thread,
+            },
+        );
+        if let Some(previous) = previous {
+            previous.stop.store(true, Ordering::Relaxed);
+        }
+
+        if let Some(title) = options.title.as_deref().filter(|title| !title.trim().is_empty()) {
+            if let Err(e) = self.set_shell_title(session_id, title) {
+                tracing::warn!(error = %e, "failed to set initial shell title");
+            }
+        }
+
+        if let (Some(steps), Some(output)) = (login_script, script_output) {
+            let manager = self.clone();
+            let session_id = session_id.to_string();
+            thread_stats::spawn(ThreadKind::Other, move || {
+                let result = manager.run_login_script(&session_id, &steps, &output);
+                let (steps_sent, error) = match result {
+                    Ok(sent) => (sent, None),
+                    Err((sent, e)) => (sent, Some(e.to_string())),
+                };
+                tracing::debug!(session_id = %session_id, steps_sent, error = ?error, "login script finished");
+                manager.emit("login-script-result", LoginScriptResult {
+                    session_id,
+                    completed: error.is_none(),
+                    steps_sent,
+                    error,
+                });
+            });
+        }
+
+        Ok(())
+    }
+
+    // 返回已发送的步数；失败时一并返回失败前已发送的步数
+    fn run_login_script(
+        &self,
+        session_id: &str,
+        steps: &[(LoginStep, Option<regex::Regex>)],
+        output: &Mutex<String>,
+    ) -> Result<usize, (usize, anyhow::Error)> {
+        for (index, (step, pattern)) in steps.iter().enumerate() {
+            if let Some(pattern) = pattern {
+                let timeout = step.timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_LOGIN_STEP_TIMEOUT);
+                let deadline = Instant::now() + timeout;
+                loop {
+                    if pattern.is_match(&output.lock().unwrap()) {
+                        break;
+                    }
+                    if !self.channels.lock().unwrap().contains_key(session_id) {
+                        return Err((index, anyhow::anyhow!("Shell closed")));
+                    }
+                    if Instant::now() >= deadline {
+                        return Err((
+                            index,
+                            anyhow::anyhow!("Timed out waiting for '{}' before step {}", pattern.as_str(), index + 1),
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            }
+            if let Some(delay) = step.delay_ms.filter(|delay| *delay > 0) {
+                std::thread::sleep(Duration::from_millis(delay));
+            }
+            // 清空后再发送，下一步只匹配本次发送之后的输出
+            output.lock().unwrap().clear();
+            self.write_to_shell(session_id, &format!("{}\r", step.send))
+                .map_err(|e| (index, e))?;
+        }
+        Ok(steps.len())
+    }
+
+    // 在后台线程中按步骤向 shell 写入并等待，结束时发出 macro-result；可通过 cancel(macro_id) 中止。
+    // 同一 shell 同时只能播放一个宏
+    pub fn play_macro(&self, session_id: &str, macro_id: &str, steps: Vec<MacroStep>) -> anyhow::Result<()> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        let mut compiled = Vec::with_capacity(steps.len());
+        for (index, step) in steps.iter().enumerate() {
+            let data = base64::engine::general_purpose::STANDARD
+                .decode(step.send.trim())
+                .map_err(|e| anyhow::anyhow!("Invalid input in step {}: {}", index + 1, e))?;
+            let pattern = match step.wait_for.as_deref().filter(|pattern| !pattern.is_empty()) {
+                Some(pattern) => Some(
+                    regex::Regex::new(pattern)
+                        .map_err(|e| anyhow::anyhow!("Invalid pattern in step {}: {}", index + 1, e))?,
+                ),
+                None => None,
+            };
+            compiled.push((data, pattern, step.clone()));
+        }
+
+        let output = Arc::new(Mutex::new(String::new()));
+        {
+            let mut outputs = self.macro_outputs.lock().unwrap();
+            if outputs.contains_key(session_id) {
+                return Err(anyhow::anyhow!("A macro is already playing in this shell"));
+            }
+            outputs.insert(session_id.to_string(), output.clone());
+        }
+        let cancel_flag = self.register_cancel(macro_id);
+
+        let manager = self.clone();
+        let session_id = session_id.to_string();
+        let macro_id = macro_id.to_string();
+        thread_stats::spawn(ThreadKind::Other, move || {
+            let result = manager.run_macro(&session_id, &compiled, &output, &cancel_flag);
+            manager.macro_outputs.lock().unwrap().remove(&session_id);
+            manager.clear_cancel(&macro_id);
+            let cancelled = cancel_flag.load(Ordering::Relaxed);
+            let (steps_sent, error) = match result {
+                Ok(sent) => (sent, None),
+                Err((sent, e)) => (sent, Some(e.to_string())),
+            };
+            tracing::debug!(session_id = %session_id, macro_id = %macro_id, steps_sent, cancelled, error = ?error, "macro finished");
+            manager.emit("macro-result", MacroResult {
+                session_id,
+                macro_id,
+                completed: error.is_none() && !cancelled,
+                cancelled,
+                steps_sent,
+                error,
+            });
+        });
+        Ok(())
+    }
+
+    // 返回已发送的步数；中止时返回 Ok，由调用方根据取消标记区分
+    fn run_macro(
+        &self,
+        session_id: &str,
+        steps: &[(Vec<u8>, Option<regex::Regex>, MacroStep)],
+        output: &Mutex<String>,
+        cancel_flag: &AtomicBool,
+    ) -> Result<usize, (usize, anyhow::Error)> {
+        for (index, (data, pattern, step)) in steps.iter().enumerate() {
+            if cancel_flag.load(Ordering::Relaxed) {
+                return Ok(index);
+            }
+            // 清空后再发送，本步只匹配发送之后的输出
+            output.lock().unwrap().clear();
+            if !data.is_empty() {
+                self.write_bytes_to_shell(session_id, data).map_err(|e| (index, e))?;
+            }
+            let sent = index + 1;
+            if let Some(pattern) = pattern {
+                let timeout = step.timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_LOGIN_STEP_TIMEOUT);
+                let deadline = Instant::now() + timeout;
+                while !pattern.is_match(&output.lock().unwrap()) {
+                    if cancel_flag.load(Ordering::Relaxed) {
+                        return Ok(sent);
+                    }
+                    if !self.channels.lock().unwrap().contains_key(session_id) {
+                        return Err((sent, anyhow::anyhow!("Shell closed")));
+                    }
+                    if Instant::now() >= deadline {
+                        return Err((
+                            sent,
+                            anyhow::anyhow!("Timed out waiting for '{}' after step {}", pattern.as_str(), sent),
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            }
+            if let Some(wait) = step.wait_ms.filter(|wait| *wait > 0) {
+                let deadline = Instant::now() + Duration::from_millis(wait);
+                while Instant::now() < deadline {
+                    if cancel_flag.load(Ordering::Relaxed) {
+                        return Ok(sent);
+                    }
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()).min(Duration::from_millis(50)));
+                }
+            }
+        }
+        Ok(steps.len())
+    }
+
+    // 同一会话只保留一个桥，重复启动会先停掉旧的
+    pub fn start_ws_bridge(&self, session_id: &str, options: &WsBridgeOptions) -> anyhow::Result<WsBridgeInfo> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        self.stop_ws_bridge(session_id);
+        let bridge = Arc::new(WsBridge::start(self.clone(), session_id, options)?);
+        let info = bridge.info();
+        self.ws_bridges.lock().unwrap().insert(session_id.to_string(), bridge);
+        Ok(info)
+    }
+
+    pub fn stop_ws_bridge(&self, session_id: &str) -> bool {
+        let bridge = self.ws_bridges.lock().unwrap().remove(session_id);
+        match bridge {
+            Some(bridge) => {
+                bridge.stop();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn stop_all_ws_bridges(&self) -> usize {
+        let bridges: Vec<Arc<WsBridge>> = self.ws_bridges.lock().unwrap().drain().map(|(_, bridge)| bridge).collect();
+        for bridge in &bridges {
+            bridge.stop();
+        }
+        bridges.len()
+    }
+
+    pub fn list_ws_bridges(&self) -> Vec<WsBridgeInfo> {
+        self.ws_bridges.lock().unwrap().values().map(|bridge| bridge.info()).collect()
+    }
+
+    pub fn write_to_shell(&self, session_id: &str, data: &str) -> anyhow::Result<()> {
+        self.write_bytes_to_shell(session_id, data.as_bytes())
+    }
+
+    pub fn write_bytes_to_shell(&self, session_id: &str, data: &[u8]) -> anyhow::Result<()> {
+        let channels = self.channels.lock().unwrap();
+        let channel = channels
+            .get(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
+
+        let mut ch = channel.lock().unwrap();
+        Self::write_channel_nonblocking(&mut ch, data)?;
+        drop(ch);
+        drop(channels);
+
+        // 单个按键（非粘贴）才作为延迟探针，等待中的探针不被覆盖
+        if data.len() <= 4 {
+            let mut monitors = self.latency_monitors.lock().unwrap();
+            if let Some(pending) = monitors.get_mut(session_id) {
+                let expired = pending.is_some_and(|sent_at| sent_at.elapsed() >= LATENCY_ECHO_TIMEOUT);
+                if pending.is_none() || expired {
+                    *pending = Some(Instant::now());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_channel_nonblocking(ch: &mut ssh2::Channel, data: &[u8]) -> anyhow::Result<()> {
+        // Interactive shell channel runs in non-blocking mode.
+        // Treat WouldBlock/EAGAIN as transient and retry briefly, instead of
+        // failing fast and triggering unnecessary frontend reconnects.
+        let mut remaining = data;
+        let deadline = Instant::now() + Duration::from_secs(8);
+
+        while !remaining.is_empty() {
+            match ch.write(remaining) {
+                Ok(0) => return Err(anyhow::anyhow!("SSH write returned 0 bytes")),
+                Ok(written) => {
+                    remaining = &remaining[written..];
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(anyhow::anyhow!("SSH write timed out (would block)"));
+                    }
+                    std::thread::sleep(Duration::from_millis(6));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        loop {
+            match ch.flush() {
+                Ok(_) => break,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(anyhow::anyhow!("SSH flush timed out (would block)"));
+                    }
+                    std::thread::sleep(Duration::from_millis(6));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // 在独立会话上请求子系统（RFC 4254 "subsystem"），通道数据以 subsystem-data（base64）推送，
+    // 结束时发出 subsystem-closed。SFTP 仍由 libssh2 的 sftp 初始化自行打开通道，这里用于 netconf 等其他子系统
+    pub fn open_subsystem(&self, session_id: &str, name: &str) -> anyhow::Result<String> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(anyhow::anyhow!("Invalid subsystem name: {}", name));
+        }
+        let connection = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        let sess = self.create_authenticated_session(&connection)?;
+        let mut channel = sess.channel_session()?;
+        channel
+            .subsystem(name)
+            .map_err(|e| anyhow::anyhow!("Server refused subsystem '{}': {}", name, e))?;
+        sess.set_blocking(false);
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let channel_id = format!("subsystem-{}-{}", name, nanos);
+        let channel = Arc::new(Mutex::new(channel));
+        let stop = Arc::new(AtomicBool::new(false));
+        self.subsystems.lock().unwrap().insert(
+            channel_id.clone(),
+            SubsystemHandle {
+                session_id: session_id.to_string(),
+                session: Arc::new(Mutex::new(sess)),
+                channel: channel.clone(),
+                stop: stop.clone(),
+            },
+        );
+        tracing::debug!(session_id = %session_id, subsystem = %name, channel_id = %channel_id, "subsystem opened");
+
+        let manager = self.clone();
+        let id = channel_id.clone();
+        thread_stats::spawn(ThreadKind::Reader, move || manager.pump_subsystem(&id, channel, stop));
+        Ok(channel_id)
+    }
+
+    fn pump_subsystem(&self, channel_id: &str, channel: Arc<Mutex<ssh2::Channel>>, stop: Arc<AtomicBool>) {
+        let mut buffer = vec![0u8; DEFAULT_READ_BUFFER];
+        let reason = loop {
+            if stop.load(Ordering::Relaxed) {
+                break "closed".to_string();
+            }
+            let read = {
+                let mut ch = channel.lock().unwrap();
+                match ch.read(&mut buffer) {
+                    Ok(0) if ch.eof() => Err("eof".to_string()),
+                    Ok(n) => Ok(n),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
+                    Err(e) => Err(format!("error: {}", e)),
+                }
+            };
+            match read {
+                Ok(0) => std::thread::sleep(Duration::from_millis(10)),
+                Ok(n) => self.emit(
+                    "subsystem-data",
+                    SubsystemData {
+                        channel_id: channel_id.to_string(),
+                        data_b64: base64::engine::general_purpose::STANDARD.encode(&buffer[..n]),
+                    },
+                ),
+                Err(reason) => break reason,
+            }
+        };
+
+        let handle = {
+            let mut subsystems = self.subsystems.lock().unwrap();
+            match subsystems.get(channel_id) {
+                Some(handle) if Arc::ptr_eq(&handle.stop, &stop) => subsystems.remove(channel_id),
+                _ => None,
+            }
+        };
+        if let Some(handle) = handle {
+            Self::close_subsystem_handle(handle);
+        }
+        tracing::debug!(channel_id = %channel_id, reason = %reason, "subsystem closed");
+        self.emit(
+            "subsystem-closed",
+            SubsystemClosed {
+                channel_id: channel_id.to_string(),
+                reason,
+            },
+        );
+    }
+
+    pub fn write_subsystem(&self, channel_id: &str, data: &[u8]) -> anyhow::Result<()> {
+        let channel = self
+            .subsystems
+            .lock()
+            .unwrap()
+            .get(channel_id)
+            .map(|handle| handle.channel.clone())
+            .ok_or_else(|| anyhow::anyhow!("Subsystem channel not found"))?;
+        let mut ch = channel.lock().unwrap();
+        Self::write_channel_nonblocking(&mut ch, data)
+    }
+
+    pub fn close_subsystem(&self, channel_id: &str) -> anyhow::Result<()> {
+        let handle = self
+            .subsystems
+            .lock()
+            .unwrap()
+            .remove(channel_id)
+            .ok_or_else(|| anyhow::anyhow!("Subsystem channel not found"))?;
+        Self::close_subsystem_handle(handle);
+        Ok(())
+    }
+
+    fn close_subsystem_handle(handle: SubsystemHandle) {
+        handle.stop.store(true, Ordering::Relaxed);
+        if let Ok(mut ch) = handle.channel.lock() {
+            let _ = ch.close();
+        }
+        if let Ok(sess) = handle.session.lock() {
+            let _ = sess.disconnect(None, "Subsystem closed", None);
+        }
+    }
+
+    // 开启后以按键回显时间估算交互延迟，收到回显时发出 terminal-latency
+    pub fn set_latency_monitor(&self, session_id: &str, enabled: bool) -> anyhow::Result<()> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        let mut monitors = self.latency_monitors.lock().unwrap();
+        if enabled {
+            monitors.entry(session_id.to_string()).or_insert(None);
+        } else {
+            monitors.remove(session_id);
+        }
+        Ok(())
+    }
+
+    // 让远端 shell 输出 OSC 0 设置标题；回显的序列由读取线程解析并更新 shell_titles。
+    // 命令前加空格，HISTCONTROL=ignorespace 时不进入历史
+    pub fn set_shell_title(&self, session_id: &str, title: &str) -> anyhow::Result<()> {
+        let title: String = title.chars().filter(|ch| !ch.is_control()).collect();
+        let command = format!(" printf '\\033]0;%s\\007' {}\r", shell_quote(&title));
+        self.write_to_shell(session_id, &command)?;
+        self.shell_titles
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), title);
+        Ok(())
+    }
+
+    // 手动轮转会话日志，返回改名后的旧日志路径
+    pub fn rotate_log(&self, session_id: &str) -> anyhow::Result<String> {
+        let log = self
+            .session_logs
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session logging is not enabled"))?;
+        let rotated = log.lock().unwrap().rotate()?;
+        Ok(rotated.to_string_lossy().to_string())
+    }
+
+    pub fn host_key(&self, session_id: &str) -> anyhow::Result<HostKeyInfo> {
+        self.host_keys
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Host key not available for session: {}", session_id))
+    }
+
+    pub fn shell_title(&self, session_id: &str) -> Option<String> {
+        self.shell_titles.lock().unwrap().get(session_id).cloned()
+    }
+
+    pub fn bracketed_paste(&self, session_id: &str) -> bool {
+        self.bracketed_paste
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    // 最近一屏的原始输出，供会话切换器渲染缩略图。全屏程序从最后一次清屏/进入备用屏开始截取，
+    // 否则取最后 rows 行（rows 为最近请求的 PTY 行数）
+    pub fn get_screen(&self, session_id: &str) -> anyhow::Result<String> {
+        let tail = self
+            .output_tails
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
+        let rows = self
+            .pty_sizes
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|size| size.rows)
+            .unwrap_or(24)
+            .max(1) as usize;
+        let tail = tail.lock().unwrap();
+        Ok(String::from_utf8_lossy(last_screen(&tail, rows)).to_string())
+    }
+
+    // bytes_per_sec 为 None 或 0 时取消限速
+    pub fn set_output_rate_limit(&self, session_id: &str, bytes_per_sec: Option<u64>) -> anyhow::Result<()> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        let mut limits = self.output_rate_limits.lock().unwrap();
+        match bytes_per_sec.filter(|limit| *limit > 0) {
+            Some(limit) => limits.insert(session_id.to_string(), limit),
+            None => limits.remove(session_id),
+        };
+        Ok(())
+    }
+
+    // 分离：停止推送 terminal-output，通道与会话保持不变，远端 shell 继续运行。
+    // 重复分离不会清空已缓存的输出
+    pub fn detach_shell(&self, session_id: &str) -> anyhow::Result<()> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        self.detached_shells
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_default();
+        tracing::debug!(session_id = %session_id, "shell detached");
+        Ok(())
+    }
+
+    // 恢复推送并返回分离期间缓存的输出，由前端先写入终端再接收后续 terminal-output；
+    // 未分离时返回空串
+    pub fn attach_shell(&self, session_id: &str) -> anyhow::Result<String> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        let pending = self.detached_shells.lock().unwrap().remove(session_id);
+        tracing::debug!(session_id = %session_id, replay = pending.as_ref().map(String::len), "shell attached");
+        Ok(pending.unwrap_or_default())
+    }
+
+    // 开启后，shell 分离期间以及 exec_merged 的 PTY 模式下，由后端回复 DA/DSR 查询
+    pub fn set_terminal_auto_answer(&self, session_id: &str, enabled: bool) -> anyhow::Result<()> {
+        if !self.connections.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Session not found"));
+        }
+        let mut sessions = self.auto_answer.lock().unwrap();
+        if enabled {
+            sessions.insert(session_id.to_string());
+        } else {
+            sessions.remove(session_id);
+        }
+        Ok(())
+    }
+
+    pub fn shell_buffer_stats(&self, session_id: &str) -> anyhow::Result<ShellBufferStats> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
+        let window = channel.lock().unwrap().read_window();
+        let tail_bytes = self
+            .output_tails
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|tail| tail.lock().unwrap().len())
+            .unwrap_or(0);
+        Ok(ShellBufferStats {
+            pending_bytes: window.available,
+            window_remaining: window.remaining,
+            window_size: window.window_size_initial,
+            paused: window.remaining == 0,
+            tail_bytes,
+        })
+    }
+
+    // 向 shell 通道发送信号（RFC 4254 "signal" 请求）。ssh2 未直接封装该请求，
+    // 通过 process_startup 发送同样格式的 channel request。
+    pub fn signal_shell(&self, session_id: &str, signal: &str) -> anyhow::Result<()> {
+        let name = normalize_signal_name(signal)?;
+
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
+
+        // 重试间隔内不持有通道锁，读取线程可以继续收发
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let result = channel.lock().unwrap().process_startup("signal", Some(name));
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN) => {
+                    if Instant::now() >= deadline {
+                        return Err(anyhow::anyhow!("Sending signal {} timed out", name));
+                    }
+                    std::thread::sleep(Duration::from_millis(6));
+                }
+                Err(err) => {
+                    return Err(anyhow::anyhow!("Server refused signal {}: {}", name, err));
+                }
+            }
+        }
+    }
+
+    // stop_forwards 为 true 时一并停止同一连接（按连接 id 匹配）的端口转发，转发使用独立会话，
+    // 否则会在断开后继续运行。该连接仍有其他会话打开时不停止
+    pub fn disconnect(&self, session_id: &str, stop_forwards: bool) -> anyhow::Result<DisconnectSummary> {
+        let mut summary = DisconnectSummary::default();
+        let connection_id = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|connection| connection.id.clone());
+        self.pending_auth.lock().unwrap().remove(session_id);
+        if let Some(reader) = self.readers.lock().unwrap().remove(session_id) {
+            reader.stop.store(true, Ordering::Relaxed);
+        }
+        let subsystems: Vec<SubsystemHandle> = {
+            let mut subsystems = self.subsystems.lock().unwrap();
+            let ids: Vec<String> = subsystems
+                .iter()
+                .filter(|(_, handle)| handle.session_id == session_id)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().filter_map(|id| subsystems.remove(id)).collect()
+        };
+        summary.subsystems_closed = subsystems.len();
+        for handle in subsystems {
+            Self::close_subsystem_handle(handle);
+        }
+
+        // Close SFTP session first
+        let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
+        if let Some(sftp_session) = sftp_sessions.remove(session_id) {
+            let sess = sftp_session.lock().unwrap();
+            let _ = sess.disconnect(None, "User disconnected", None);
+            summary.sftp_closed = true;
+        }
+        drop(sftp_sessions);
+
+        // Drop unfinished upload streams
+        let mut upload_streams = self.upload_streams.lock().unwrap();
+        let before = upload_streams.len();
+        upload_streams.retain(|_, stream| stream.session_id != session_id);
+        summary.upload_streams_dropped = before - upload_streams.len();
+        drop(upload_streams);
+
+        // Close shell channel
+        let mut channels = self.channels.lock().unwrap();
+        self.pty_sizes.lock().unwrap().remove(session_id);
+        if let Some(channel) = channels.remove(session_id) {
+            let mut ch = channel.lock().unwrap();
+            let _ = ch.close();
+            let _ = ch.wait_close();
+            summary.shell_closed = true;
+        }
+        drop(channels);
+
+        // Close shell session
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions.remove(session_id) {
+            let sess = session.lock().unwrap();
+            let _ = sess.disconnect(None, "User disconnected", None);
+            drop(sess);
+            self.remove_keepalive(session_id, &session);
+        }
+        drop(sessions);
+
+        // Remove connection info and the rest of the per-session state
+        self.forget_session_state(session_id);
+        // 同一连接还有其他会话在用时保留转发
+        let connection_id = connection_id.filter(|id| {
+            !self.connections.lock().unwrap().values().any(|connection| &connection.id == id)
+        });
+
+        if let (true, Some(connection_id)) = (stop_forwards, connection_id) {
+            let ids: Vec<String> = self
+                .forwards
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, handle)| handle.config.connection.id == connection_id)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in ids {
+                match self.stop_forward(&id) {
+                    Ok(()) => summary.forwards_stopped.push(id),
+                    Err(e) => tracing::warn!(forward_id = %id, error = %e, "failed to stop forward on disconnect"),
+                }
+            }
+        }
+        tracing::debug!(session_id = %session_id, summary = ?summary, "disconnected");
+
+        Ok(summary)
+    }
+
+    pub fn execute_command(&self, session_id: &str, command: &str) -> anyhow::Result<String> {
+        let output = self.execute_raw(session_id, command)?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    pub fn host_probe(&self, session_id: &str) -> anyhow::Result<HostProbe> {
+        let script = format!(
+            "export LC_ALL=C; \
+            echo '@@os'; uname -s 2>/dev/null; \
+            echo '@@release'; uname -r 2>/dev/null; \
+            echo '@@arch'; uname -m 2>/dev/null; \
+            echo '@@home'; echo \"$HOME\"; \
+            echo '@@tools'; for tool in {}; do command -v \"$tool\" >/dev/null 2>&1 && echo \"$tool\"; done; true",
+            PROBE_TOOLS.join(" ")
+        );
+        let output = self.execute_raw(session_id, &script)?;
+        let stdout = decode_bytes(&output.stdout, self.connection_encoding(session_id).as_deref());
+
+        let sections = split_sections(&stdout);
+        let field = |name: &str| {
+            sections
+                .get(name)
+                .and_then(|lines| lines.first())
+                .map(|value| value.trim().to_string())
+        };
+        let found: Vec<&str> = sections
+            .get("tools")
+            .map(|lines| lines.iter().map(|line| line.trim()).collect())
+            .unwrap_or_default();
+
+        Ok(HostProbe {
+            os: field("os"),
+            release: field("release"),
+            arch: field("arch"),
+            home: field("home"),
+            sftp: self.has_sftp(session_id).unwrap_or(false),
+            tools: PROBE_TOOLS
+                .iter()
+                .map(|tool| (tool.to_string(), found.contains(tool)))
+                .collect(),
+        })
+    }
+
+    // 结果按会话缓存，断开时清除
+    pub fn user_info(&self, session_id: &str) -> anyhow::Result<RemoteUserInfo> {
+        if let Some(info) = self.user_infos.lock().unwrap().get(session_id) {
+            return Ok(info.clone());
+        }
+
+        // exec 通道不一定设置 SHELL，退回 passwd 中的登录 shell
+        let script = "export LC_ALL=C; user=$(id -un 2>/dev/null || whoami 2>/dev/null); \
+            echo '@@user'; echo \"$user\"; \
+            echo '@@shell'; echo \"${SHELL:-$(getent passwd \"$user\" 2>/dev/null | cut -d: -f7)}\"; \
+            echo '@@home'; echo \"${HOME:-$(getent passwd \"$user\" 2>/dev/null | cut -d: -f6)}\"; \
+            echo '@@uid'; id -u 2>/dev/null; \
+            echo '@@gid'; id -g 2>/dev/null; \
+            echo '@@groups'; id -Gn 2>/dev/null; true";
+        let output = self.execute_raw(session_id, script)?;
+        let stdout = decode_bytes(&output.stdout, self.connection_encoding(session_id).as_deref());
+
+        let sections = split_sections(&stdout);
+        let field = |name: &str| {
+            sections
+                .get(name)
+                .and_then(|lines| lines.first())
+                .map(|value| value.trim().to_string())
+        };
+
+        let username = field("user")
+            .ok_or_else(|| anyhow::anyhow!("Failed to determine remote user"))?;
+        let info = RemoteUserInfo {
+            username,
+            shell: field("shell"),
+            home: field("home"),
+            uid: field("uid").and_then(|value| value.parse().ok()),
+            gid: field("gid").and_then(|value| value.parse().ok()),
+            groups: field("groups")
+                .map(|value| value.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+        };
+        self.user_infos
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), info.clone());
+        Ok(info)
+    }
+
+    // 列出远端 tmux 的会话与窗口。没有运行中的 tmux server 时返回空列表
+    pub fn tmux_list(&self, session_id: &str) -> anyhow::Result<Vec<TmuxSession>> {
+        let format = "#{session_name}\t#{session_attached}\t#{window_index}\t#{window_active}\t#{window_name}";
+        let command = format!(
+            "command -v tmux >/dev/null 2>&1 || exit 127; tmux list-windows -a -F {}",
+            shell_quote(format)
+        );
+        let output = self.execute_raw(session_id, &command)?;
+        if output.exit_code == 127 {
+            return Err(anyhow::anyhow!("tmux is not installed on the remote host"));
+        }
+        if output.exit_code != 0 {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("no server running") || stderr.contains("error connecting") {
+                return Ok(Vec::new());
+            }
+            return Err(anyhow::anyhow!("tmux list-windows failed: {}", stderr.trim()));
+        }
+
+        let mut sessions: Vec<TmuxSession> = Vec::new();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let mut fields = line.splitn(5, '\t');
+            let (Some(session), Some(attached), Some(index), Some(active), Some(window)) = (
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+            ) else {
+                continue;
+            };
+            let Ok(index) = index.parse::<u32>() else {
+                continue;
+            };
+            let window = TmuxWindow {
+                index,
+                name: window.to_string(),
+                active: active == "1",
+                target: format!("{}:{}", session, index),
+            };
+            match sessions.iter_mut().find(|existing| existing.name == session) {
+                Some(existing) => existing.windows.push(window),
+                None => sessions.push(TmuxSession {
+                    name: session.to_string(),
+                    attached: attached.parse::<u32>().unwrap_or(0) > 0,
+                    windows: vec![window],
+                }),
+            }
+        }
+        Ok(sessions)
+    }
+
+    // 读取远端环境变量；变量名限定为 [A-Za-z_][A-Za-z0-9_]*，避免拼进命令时被注入。未设置时返回 None
+    pub fn get_env(&self, session_id: &str, name: &str) -> anyhow::Result<Option<String>> {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(anyhow::anyhow!("Invalid environment variable name: {}", name));
+        }
+        let cacheable = CACHED_ENV_NAMES.contains(&name);
+        if cacheable {
+            let cache = self.env_cache.lock().unwrap();
+            if let Some(value) = cache.get(session_id).and_then(|values| values.get(name)) {
+                return Ok(Some(value.clone()));
+            }
+        }
+
+        let command = format!("[ -n \"${{{0}+x}}\" ] && printf '%s' \"${0}\"", name);
+        let output = self.execute_raw(session_id, &command)?;
+        if output.exit_code != 0 {
+            return Ok(None);
+        }
+        let value = String::from_utf8_lossy(&output.stdout).to_string();
+        if cacheable {
+            self.env_cache
+                .lock()
+                .unwrap()
+                .entry(session_id.to_string())
+                .or_default()
+                .insert(name.to_string(), value.clone());
+        }
+        Ok(Some(value))
+    }
+
+    // 在专用的阻塞会话上执行命令，返回原始字节输出和退出码
+    fn execute_raw(&self, session_id: &str, command: &str) -> anyhow::Result<ExecOutput> {
+        self.execute_raw_input(session_id, command, None)
+    }
+
+    // input 非空时写入命令的标准输入（与读取输出交替进行）后发送 EOF。
+    // 只有 exec 之前的失败（缓存会话已失效）才重建会话重试，exec 之后重试可能让命令执行两次
+    fn execute_raw_input(&self, session_id: &str, command: &str, input: Option<&[u8]>) -> anyhow::Result<ExecOutput> {
+        let mut last_error: Option<anyhow::Error> = None;
+
+        // Use a dedicated blocking session (shared with SFTP pool) to avoid
+        // "session would block" conflicts with the interactive shell session.
+        for attempt in 0..2 {
+            let command_session = self.get_or_create_sftp(session_id)?;
+
+            let mut executed = false;
+            let result = (|| {
+                let sess = command_session.lock().unwrap();
+                let mut channel = sess.channel_session()?;
+                channel.exec(command)?;
+                executed = true;
+
+                let (stdout, stderr) = Self::drain_exec_channel(&sess, &mut channel, input)?;
+                channel.wait_close()?;
+                anyhow::Ok(ExecOutput {
+                    stdout,
+                    stderr,
+                    exit_code: channel.exit_status().unwrap_or(-1),
+                })
+            })();
+
+            match result {
+                Ok(output) => return Ok(output),
+                Err(error) if executed => return Err(error),
+                Err(error) => {
+                    last_error = Some(error);
+                    if attempt == 0 {
+                        // Drop cached dedicated session and recreate once.
+                        let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
+                        sftp_sessions.remove(session_id);
+                    }
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Failed to execute command")))
+    }
+
+    // 非阻塞地交替写入 input、读取 stdout 与 stderr 直到 EOF：顺序读写时任一方向写满通道窗口，
+    // 两端都会互相等待。返回前恢复会话为阻塞模式
+    fn drain_exec_channel(
+        sess: &Session,
+        channel: &mut ssh2::Channel,
+        input: Option<&[u8]>,
+    ) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        sess.set_blocking(false);
+        let result = (|| {
+            let mut stdout = Vec::new();
+            let mut stderr = Vec::new();
+            let mut buf = vec![0u8; 32 * 1024];
+            let mut idle_wait = PUMP_IDLE_MIN;
+            let mut pending = input; // 尚未写出的输入；写完并发送 EOF 后为 None
+            loop {
+                // 先取 EOF 状态再读：EOF 之前到达的数据此时都已在缓冲区中，读完即可返回
+                let at_eof = channel.eof();
+                let mut progress = false;
+                match pending {
+                    // 远端已结束时不再写入剩余输入
+                    Some(_) if at_eof => pending = None,
+                    Some([]) => match channel.send_eof() {
+                        Ok(()) => {
+                            pending = None;
+                            progress = true;
+                        }
+                        Err(e) if matches!(e.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN) => {}
+                        Err(e) => return Err(anyhow::anyhow!("Failed to close command input: {}", e)),
+                    },
+                    Some(rest) => match channel.write(rest) {
+                        Ok(n) => {
+                            pending = Some(&rest[n..]);
+                            progress = progress || n > 0;
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(e) => return Err(anyhow::anyhow!("Failed to write command input: {}", e)),
+                    },
+                    None => {}
+                }
+                for (stream_id, collected) in [(0, &mut stdout), (1, &mut stderr)] {
+                    let mut stream = channel.stream(stream_id);
+                    loop {
+                        match stream.read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                collected.extend_from_slice(&buf[..n]);
+                                progress = true;
+                            }
+                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                            Err(e) => return Err(anyhow::anyhow!("Failed to read command output: {}", e)),
+                        }
+                    }
+                }
+                if at_eof {
+                    return Ok((stdout, stderr));
+                }
+                if progress {
+                    idle_wait = PUMP_IDLE_MIN;
+                } else {
+                    std::thread::sleep(idle_wait);
+                    idle_wait = (idle_wait * 2).min(PUMP_IDLE_MAX);
+                }
+            }
+        })();
+        sess.set_blocking(true);
+        result
+    }
+
+    // 脚本先经 SFTP 写入远端临时文件再交给解释器执行，结束后删除，避免手工转义多行脚本；
+    // 脚本本身因此仍可读取标准输入。临时文件由远端 mktemp 在 $TMPDIR（缺省 /tmp）下以 0600 创建。
+    // 不支持 SFTP 或 mktemp 时退回通过标准输入传入脚本
+    pub fn run_script(&self, session_id: &str, script: &str, interpreter: ScriptInterpreter) -> anyhow::Result<ScriptRunResult> {
+        let encoding = self.connection_encoding(session_id);
+        let started_at = Instant::now();
+        let program = interpreter.program();
+        let script_path = if self.has_sftp(session_id)? {
+            self.execute_raw(session_id, r#"mktemp "${TMPDIR:-/tmp}/.noterm-script.XXXXXX""#)
+                .ok()
+                .filter(|output| output.exit_code == 0)
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                .filter(|path| path.starts_with('/') && !path.contains('\n'))
+        } else {
+            None
+        };
+        let via_sftp = script_path.is_some();
+        let output = if let Some(path) = script_path {
+            let written = self.with_sftp(session_id, |sftp| {
+                let mut file = sftp
+                    .open_mode(
+                        Path::new(&path),
+                        OpenFlags::WRITE | OpenFlags::TRUNCATE,
+                        0o600,
+                        OpenType::File,
+                    )
+                    .map_err(|e| anyhow::anyhow!("Failed to create remote script '{}': {}", path, e))?;
+                file.write_all(script.as_bytes())?;
+                Ok(())
+            });
+            let output = written.and_then(|_| self.execute_raw(session_id, &format!("{} {}", program, shell_quote(&path))));
+            if let Err(e) = self.with_sftp_once(session_id, |sftp| Ok(sftp.unlink(Path::new(&path))?)) {
+                tracing::warn!(error = %e, path = %path, "failed to remove remote script");
+            }
+            output?
+        } else {
+            let command = format!("{} {}", program, interpreter.stdin_args());
+            self.execute_raw_input(session_id, &command, Some(script.as_bytes()))?
+        };
+        Ok(ScriptRunResult {
+            exit_code: output.exit_code,
+            stdout: decode_bytes(&output.stdout, encoding.as_deref()),
+            stderr: decode_bytes(&output.stderr, encoding.as_deref()),
+            duration_ms: started_at.elapsed().as_millis().min(u128::from(u64::MAX)) as u64,
+            via_sftp,
+        })
+    }
+
+    // 在多个会话上并发（有上限）执行同一命令，各自使用专用的执行会话。
+    // 重复的会话 id 只执行一次，结果按 id 返回
+    pub fn execute_many(&self, session_ids: Vec<String>, command: &str) -> HashMap<String, HostCommandResult> {
+        let mut seen = HashSet::new();
+        let session_ids: Vec<String> = session_ids.into_iter().filter(|id| seen.insert(id.clone())).collect();
+        let total = session_ids.len();
+        let queue = Arc::new(Mutex::new(session_ids.into_iter()));
+        let results: Arc<Mutex<HashMap<String, HostCommandResult>>> = Arc::new(Mutex::new(HashMap::new()));
+
+        let workers: Vec<_> = (0..CONNECT_MANY_CONCURRENCY.min(total))
+            .map(|_| {
+                let manager = self.clone();
+                let command = command.to_string();
+                let queue = queue.clone();
+                let results = results.clone();
+                thread_stats::spawn(ThreadKind::Other, move || loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some(session_id) = next else {
+                        break;
+                    };
+                    let started_at = Instant::now();
+                    let output = manager.execute_raw(&session_id, &command);
+                    let duration_ms = started_at.elapsed().as_millis().min(u128::from(u64::MAX)) as u64;
+                    let result = match output {
+                        Ok(output) => HostCommandResult {
+                            exit_code: output.exit_code,
+                            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                            duration_ms,
+                            error: None,
+                        },
+                        Err(e) => HostCommandResult {
+                            exit_code: -1,
+                            stdout: String::new(),
+                            stderr: String::new(),
+                            duration_ms,
+                            error: Some(e.to_string()),
+                        },
+                    };
+                    results.lock().unwrap().insert(session_id, result);
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        let results = std::mem::take(&mut *results.lock().unwrap());
+        results
+    }
+
+    pub fn execute_command_controlled(
+        &self,
+        session_id: &str,
+        command: &str,
+        timeout_sec: u64,
+    ) -> anyhow::Result<ControlledCommandResult> {
+        let connection = {
+            let connections = self.connections.lock().unwrap();
+            connections
+                .get(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", session_id))?
+                .clone()
+        };
+
+        let timeout_sec = timeout_sec.clamp(3, 300);
+        let timeout = Duration::from_secs(timeout_sec);
+        let started_at = Instant::now();
+
+        let session = self.create_authenticated_session(&connection)?;
+        session.set_blocking(false);
+        session.set_timeout((timeout_sec * 1000) as u32);
+
+        let mut channel = session.channel_session()?;
+        channel.exec(command)?;
+
+        let mut stdout = Vec::<u8>::new();
+        let mut stderr = Vec::<u8>::new();
+        let mut timed_out = false;
+        let mut buf = [0u8; 8192];
+
+        loop {
+            let mut had_progress = false;
+
+            loop {
+                match channel.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        stdout.extend_from_slice(&buf[..n]);
+                        had_progress = true;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(anyhow::anyhow!("Failed to read stdout: {}", err)),
+                }
+            }
+
+            loop {
+                let mut stderr_stream = channel.stderr();
+                match stderr_stream.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        stderr.extend_from_slice(&buf[..n]);
+                        had_progress = true;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(anyhow::anyhow!("Failed to read stderr: {}", err)),
+                }
+            }
+
+            if channel.eof() {
+                break;
+            }
+
+            if started_at.elapsed() >= timeout {
+                timed_out = true;
+                let _ = channel.close();
+                break;
+            }
+
+            if !had_progress {
+                std::thread::sleep(Duration::from_millis(12));
+            }
+        }
+
+        let _ = channel.wait_close();
+        let exit_code = if timed_out {
+            -1
+        } else {
+            channel.exit_status().unwrap_or(-1)
+        };
+
+        if timed_out {
+            if !stderr.is_empty() {
+                stderr.extend_from_slice(b"\n");
+            }
+            stderr.extend_from_slice(b"Command timed out");
+        }
+
+        let _ = session.disconnect(None, "command finished", None);
+
+        Ok(ControlledCommandResult {
+            exit_code,
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+            duration_ms: started_at.elapsed().as_millis().min(u128::from(u64::MAX)) as u64,
+            timed_out,
+        })
+    }
+
+    // 以单一有序流执行命令（构建日志等需要保留 stdout/stderr 交错顺序的场景）。
+    // use_pty 为 true 时申请 PTY，由远端合并两路输出；否则设置 ExtendedData::Merge，
+    // stderr 按到达顺序并入 stdout。复用命令专用会话，每次读写只短暂持有会话锁，
+    // 期间 SFTP 等操作可以穿插进行。可通过 cancel(stream_id) 中止，远端命令会一并结束。
+    pub fn exec_merged<F>(
+        &self,
+        session_id: &str,
+        command: &str,
+        stream_id: &str,
+        use_pty: bool,
+        mut on_chunk: F,
+    ) -> anyhow::Result<MergedExecResult>
+    where
+        F: FnMut(u64, &str),
+    {
+        let encoding = {
+            let connections = self.connections.lock().unwrap();
+            connections
+                .get(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", session_id))?
+                .encoding
+                .as_deref()
+                .and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes()))
+                .unwrap_or(encoding_rs::UTF_8)
+        };
+        let started_at = Instant::now();
+
+        let (command_session, mut channel) = self.open_merged_channel(session_id, command, use_pty)?;
+
+        let cancel_flag = self.register_cancel(stream_id);
+        let mut decoder = encoding.new_decoder();
+        let mut responder = (use_pty && self.auto_answer.lock().unwrap().contains(session_id))
+            .then(TerminalQueryResponder::default);
+        let mut text = String::new();
+        let mut seq = 0u64;
+        let mut cancelled = false;
+        let mut buf = [0u8; 8192];
+
+        let result = loop {
+            if cancel_flag.load(Ordering::Relaxed) {
+                cancelled = true;
+                Self::stop_remote_command(&command_session, &mut channel);
+                break Ok(());
+            }
+            // 会话与其他使用者共享，只在持锁期间切换为非阻塞
+            let (read, at_eof) = {
+                let sess = command_session.lock().unwrap();
+                sess.set_blocking(false);
+                let read = channel.read(&mut buf);
+                let at_eof = channel.eof();
+                sess.set_blocking(true);
+                (read, at_eof)
+            };
+            match read {
+                Ok(0) if at_eof => break Ok(()),
+                Ok(0) => std::thread::sleep(Duration::from_millis(12)),
+                Ok(n) => {
+                    if let Some(responder) = responder.as_mut() {
+                        let answer = responder.feed(&buf[..n]);
+                        if !answer.is_empty() {
+                            let sess = command_session.lock().unwrap();
+                            sess.set_blocking(false);
+                            if let Err(e) = Self::write_channel_nonblocking(&mut channel, &answer) {
+                                tracing::debug!(error = %e, "terminal query answer failed");
+                            }
+                            sess.set_blocking(true);
+                        }
+                    }
+                    text.clear();
+                    decode_chunk(&mut decoder, &buf[..n], &mut text, false);
+                    if !text.is_empty() {
+                        on_chunk(seq, &text);
+                        seq += 1;
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    if at_eof {
+                        break Ok(());
+                    }
+                    std::thread::sleep(Duration::from_millis(12));
+                }
+                Err(err) => break Err(anyhow::anyhow!("Failed to read output: {}", err)),
+            }
+        };
+        self.clear_cancel(stream_id);
+
+        text.clear();
+        decode_chunk(&mut decoder, &[], &mut text, true);
+        if !text.is_empty() {
+            on_chunk(seq, &text);
+        }
+
+        let exit_code = {
+            let _sess = command_session.lock().unwrap();
+            let _ = channel.wait_close();
+            if cancelled { -1 } else { channel.exit_status().unwrap_or(-1) }
+        };
+        result?;
+
+        Ok(MergedExecResult {
+            exit_code,
+            pty_merged: use_pty,
+            duration_ms: started_at.elapsed().as_millis().min(u128::from(u64::MAX)) as u64,
+            cancelled,
+        })
+    }
+
+    // 在命令专用会话上打开通道并执行命令；缓存的会话失效时重建一次（同 execute_raw_input）
+    fn open_merged_channel(
+        &self,
+        session_id: &str,
+        command: &str,
+        use_pty: bool,
+    ) -> anyhow::Result<(Arc<Mutex<Session>>, ssh2::Channel)> {
+        let mut last_error: Option<anyhow::Error> = None;
+        for attempt in 0..2 {
+            let command_session = self.get_or_create_sftp(session_id)?;
+            let opened = (|| {
+                let sess = command_session.lock().unwrap();
+                let mut channel = sess.channel_session()?;
+                if use_pty {
+                    channel.request_pty("dumb", None, Some((200, 50, 0, 0)))?;
+                } else {
+                    channel.handle_extended_data(ssh2::ExtendedData::Merge)?;
+                }
+                channel.exec(command)?;
+                anyhow::Ok(channel)
+            })();
+            match opened {
+                Ok(channel) => return Ok((command_session, channel)),
+                Err(error) => {
+                    last_error = Some(error);
+                    if attempt == 0 {
+                        self.sftp_sessions.lock().unwrap().remove(session_id);
+                    }
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Failed to execute command")))
+    }
+
+    // 关闭通道前先请求远端发送 TERM；服务器不支持 signal 请求时，PTY 模式下关闭通道会使远端挂断（SIGHUP），
+    // 非 PTY 模式下命令在下次写输出时因管道关闭退出
+    fn stop_remote_command(session: &Arc<Mutex<Session>>, channel: &mut ssh2::Channel) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let result = {
+                let sess = session.lock().unwrap();
+                sess.set_blocking(false);
+                let result = channel.process_startup("signal", Some("TERM"));
+                sess.set_blocking(true);
+                result
+            };
+            match result {
+                Err(err)
+                    if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN)
+                        && Instant::now() < deadline =>
+                {
+                    std::thread::sleep(Duration::from_millis(6));
+                }
+                Err(err) => {
+                    tracing::debug!(error = %err, "signal request for cancelled command failed");
+                    break;
+                }
+                Ok(()) => break,
+            }
+        }
+        let _sess = session.lock().unwrap();
+        let _ = channel.close();
+    }
+
+    // Map membership is kept accurate by the keepalive reaper; `deep` additionally
+    // probes the transport and reaps the session if the probe fails.
+    pub fn is_connected(&self, session_id: &str, deep: bool) -> bool {
+        let session = {
+            let sessions = self.sessions.lock().unwrap();
+            match sessions.get(session_id) {
+                Some(session) => session.clone(),
+                None => return false,
+            }
+        };
+
+        if !deep {
+            return true;
+        }
+
+        if Self::probe_session(&session, DEEP_CHECK_TIMEOUT) {
+            true
+        } else {
+            self.forget_session(session_id, &session);
+            false
+        }
+    }
+
+    fn probe_session(session: &Arc<Mutex<Session>>, timeout: Duration) -> bool {
+        let sess = match session.lock() {
+            Ok(sess) => sess,
+            Err(_) => return false,
+        };
+        sess.authenticated()
+            && !with_session_socket(&sess, socket_peer_closed)
+            && Self::session_responds(&sess, timeout)
+    }
+
+    // keepalive_send 只把请求放进发送队列，不等应答，对端静默断开（没有 RST）时仍会成功。
+    // 这里打开一个 session 通道并等待服务端确认或拒绝，期限内没有应答即视为断开
+    fn session_responds(sess: &Session, timeout: Duration) -> bool {
+        let blocking = sess.is_blocking();
+        sess.set_blocking(false);
+        let deadline = Instant::now() + timeout;
+        let responded = loop {
+            match sess.channel_session() {
+                Ok(mut channel) => {
+                    let _ = channel.close();
+                    break true;
+                }
+                Err(err) => match err.code() {
+                    ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN => {
+                        if Instant::now() >= deadline {
+                            break false;
+                        }
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    // 服务端拒绝打开通道（如超过 MaxSessions）同样是应答
+                    ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_CHANNEL_FAILURE => break true,
+                    _ => break false,
+                },
+            }
+        };
+        sess.set_blocking(blocking);
+        responded
+    }
+
+    // 移除已失效会话的所有状态（仅当映射中仍是同一个会话时）
+    fn forget_session(&self, session_id: &str, session: &Arc<Mutex<Session>>) {
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get(session_id) {
+                Some(current) if Arc::ptr_eq(current, session) => {
+                    sessions.remove(session_id);
+                }
+                _ => return,
+            }
+        }
+        self.remove_keepalive(session_id, session);
+        if let Some(reader) = self.readers.lock().unwrap().remove(session_id) {
+            reader.stop.store(true, Ordering::Relaxed);
+        }
+        self.forget_session_state(session_id);
+    }
+
+    // 按会话 id 记录的状态表。断开、读取线程退出、保活回收与 gc_sessions 都经由 forget_session_state 清理，
+    // 新增按会话的表时加在这里
+    fn session_tables(&self) -> [(&'static str, &dyn SessionTable); 22] {
+        [
+            ("channels", &*self.channels),
+            ("pty_sizes", &*self.pty_sizes),
+            ("sftp_sessions", &*self.sftp_sessions),
+            ("connections", &*self.connections),
+            ("latency_monitors", &*self.latency_monitors),
+            ("shell_titles", &*self.shell_titles),
+            ("sftp_support", &*self.sftp_support),
+            ("sftp_features", &*self.sftp_features),
+            ("session_logs", &*self.session_logs),
+            ("output_tails", &*self.output_tails),
+            ("env_cache", &*self.env_cache),
+            ("user_infos", &*self.user_infos),
+            ("transfer_history", &*self.transfer_history),
+            ("output_rate_limits", &*self.output_rate_limits),
+            ("detached_shells", &*self.detached_shells),
+            ("auto_answer", &*self.auto_answer),
+            ("bracketed_paste", &*self.bracketed_paste),
+            ("shell_encodings", &*self.shell_encodings),
+            ("macro_outputs", &*self.macro_outputs),
+            ("ws_bridges", &*self.ws_bridges),
+            ("nested_outer", &*self.nested_outer),
+            ("host_keys", &*self.host_keys),
+        ]
+    }
+
+    // 清除会话在 session_tables 中的所有条目，返回有条目的表名。会话本身、保活、读取线程、
+    // 子系统与上传流由调用方按各自的归属判断处理
+    fn forget_session_state(&self, session_id: &str) -> Vec<&'static str> {
+        let mut removed = Vec::new();
+        if let Some(bridge) = self.ws_bridges.lock().unwrap().remove(session_id) {
+            bridge.stop();
+            removed.push("ws_bridges");
+        }
+        for (name, table) in self.session_tables() {
+            if table.remove_session(session_id) {
+                removed.push(name);
+            }
+        }
+        removed
+    }
+
+    // 以 sessions 为准审计各表：回收会话已不存在（或通道已被替换、线程已退出）的读取线程，
+    // 移除失效的保活条目、子系统通道与按会话记录的状态。异常断开路径遗漏的条目由这里兜底
+    pub fn gc_sessions(&self) -> SessionGcReport {
+        let mut report = SessionGcReport::default();
+        let live: HashMap<String, Arc<Mutex<Session>>> = self.sessions.lock().unwrap().clone();
+
+        let stale_readers: Vec<(String, ReaderHandle)> = {
+            let mut readers = self.readers.lock().unwrap();
+            let channels = self.channels.lock().unwrap();
+            let ids: Vec<String> = readers
+                .iter()
+                .filter(|(id, reader)| {
+                    reader.thread.is_finished()
+                        || !live.contains_key(*id)
+                        || !channels
+                            .get(*id)
+                            .is_some_and(|channel| Arc::ptr_eq(channel, &reader.channel))
+                })
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| readers.remove(&id).map(|reader| (id, reader)))
+                .collect()
+        };
+        for (id, reader) in stale_readers {
+            reader.stop.store(true, Ordering::Relaxed);
+            if reader.thread.join().is_err() {
+                tracing::warn!(session_id = %id, "reader thread panicked");
+            }
+            report.readers_reaped.push(id);
+        }
+
+        self.keepalives.lock().unwrap().retain(|id, entry| {
+            let valid = live.get(id).is_some_and(|session| Arc::ptr_eq(session, &entry.session));
+            if !valid {
+                report.keepalives_removed.push(id.clone());
+            }
+            valid
+        });
+        self.forward_keepalives.lock().unwrap().retain(|id, entry| {
+            let valid = entry.stop.as_ref().is_some_and(|stop| !stop.load(Ordering::Relaxed));
+            if !valid {
+                report.keepalives_removed.push(format!("forward:{}", id));
+            }
+            valid
+        });
+
+        let subsystems: Vec<(String, SubsystemHandle)> = {
+            let mut subsystems = self.subsystems.lock().unwrap();
+            let ids: Vec<String> = subsystems
+                .iter()
+                .filter(|(_, handle)| !live.contains_key(&handle.session_id))
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| subsystems.remove(&id).map(|handle| (id, handle)))
+                .collect()
+        };
+        for (id, handle) in subsystems {
+            Self::close_subsystem_handle(handle);
+            report.subsystems_closed.push(id);
+        }
+
+        let stale_ids: BTreeSet<String> = self
+            .session_tables()
+            .iter()
+            .flat_map(|(_, table)| table.session_ids())
+            .filter(|id| !live.contains_key(id))
+            .collect();
+        let stale = &mut report.stale_entries;
+        for id in stale_ids {
+            // 快照之后新登记的会话不清理
+            if self.sessions.lock().unwrap().contains_key(&id) {
+                continue;
+            }
+            for name in self.forget_session_state(&id) {
+                stale.push(format!("{}:{}", name, id));
+            }
+        }
+        self.upload_streams.lock().unwrap().retain(|id, stream| {
+            let valid = live.contains_key(&stream.session_id);
+            if !valid {
+                stale.push(format!("upload_streams:{}", id));
+            }
+            valid
+        });
+
+        tracing::info!(
+            readers = report.readers_reaped.len(),
+            keepalives = report.keepalives_removed.len(),
+            subsystems = report.subsystems_closed.len(),
+            entries = report.stale_entries.len(),
+            "session gc finished"
+        );
+        report
+    }
+
+    // 崩溃恢复用：当前会话的连接信息（同一连接只保留一份）与运行中转发的配置，均按 id 排序
+    pub fn recovery_state(&self) -> (Vec<SshConnection>, Vec<ForwardConfig>) {
+        let mut connections: Vec<SshConnection> = Vec::new();
+        for connection in self.connections.lock().unwrap().values() {
+            if !connections.iter().any(|existing| existing.id == connection.id) {
+                connections.push(connection.clone());
+            }
+        }
+        connections.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut forwards: Vec<ForwardConfig> = self
+            .forwards
+            .lock()
+            .unwrap()
+            .values()
+            .map(|handle| handle.config.clone())
+            .collect();
+        forwards.sort_by(|a, b| a.id.cmp(&b.id));
+        (connections, forwards)
+    }
+
+    pub fn shell_count(&self) -> usize {
+        self.channels.lock().unwrap().len()
+    }
+
+    pub fn list_sessions(&self) -> Vec<String> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.keys().cloned().collect()
+    }
+
+    // libssh2 不公开握手结果，这里在专用会话上另开一个 sftp 子系统通道自行完成握手，结果按会话缓存
+    pub fn sftp_features(&self, session_id: &str) -> anyhow::Result<SftpFeatures> {
+        if let Some(features) = self.sftp_features.lock().unwrap().get(session_id) {
+            return Ok(features.clone());
+        }
+        let sftp_session = self.get_or_create_sftp(session_id)?;
+        let (mut channel, features) = {
+            let sess = sftp_session.lock().unwrap();
+            open_raw_sftp(&sess)?
+        };
+        let _ = channel.close();
+        self.sftp_features
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), features.clone());
+        Ok(features)
+    }
+
+    // 用目标文件替换 to：服务端支持 posix-rename@openssh.com 时原子覆盖；
+    // 否则先尝试普通 rename，目标已存在导致失败时删除后再改名（此时有短暂的无文件窗口）
+    fn replace_remote_file(&self, session_id: &str, from: &str, to: &str) -> anyhow::Result<()> {
+        let posix_rename = self
+            .sftp_features(session_id)
+            .map(|features| features.has_extension("posix-rename@openssh.com"))
+            .unwrap_or(false);
+        if posix_rename {
+            let sftp_session = self.get_or_create_sftp(session_id)?;
+            let result = {
+                let sess = sftp_session.lock().unwrap();
+                open_raw_sftp(&sess).and_then(|(mut channel, _)| {
+                    let result = sftp_posix_rename(&mut channel, from, to);
+                    let _ = channel.close();
+                    result
+                })
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => tracing::debug!(error = %e, "posix-rename failed, falling back to rename"),
+            }
+        }
+        // SFTP v3 的 rename 不覆盖已存在的目标：退化为先删除再改名，两步之间目标短暂不存在
+        self.with_sftp_once(session_id, |sftp| {
+            if sftp.rename(Path::new(from), Path::new(to), None).is_err() {
+                let _ = sftp.unlink(Path::new(to));
+                sftp.rename(Path::new(from), Path::new(to), None)
+                    .map_err(|e| anyhow::anyhow!("Failed to replace '{}': {}", to, e))?;
+            }
+            Ok(())
+        })
+    }
+
+    // 列出缓存的 SFTP/命令执行会话（每个连接隐藏的第二个会话）
+    pub fn sftp_session_info(&self) -> Vec<SftpSessionInfo> {
+        let sftp_sessions = self.sftp_sessions.lock().unwrap();
+        let mut info: Vec<SftpSessionInfo> = sftp_sessions
+            .iter()
+            .map(|(session_id, session)| SftpSessionInfo {
+                session_id: session_id.clone(),
+                authenticated: session
+                    .lock()
+                    .map(|sess| sess.authenticated())
+                    .unwrap_or(false),
+            })
+            .collect();
+        info.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        info
+    }
+
+    // 关闭缓存的 SFTP 会话，不影响 shell；下次文件操作时会重新创建
+    pub fn close_sftp_session(&self, session_id: &str) -> bool {
+        let session = {
+            let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
+            sftp_sessions.remove(session_id)
+        };
+        match session {
+            Some(session) => {
+                if let Ok(sess) = session.lock() {
+                    let _ = sess.disconnect(None, "SFTP session closed", None);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get_or_create_sftp(&self, session_id: &str) -> anyhow::Result<Arc<Mutex<Session>>> {
+        // 先检查是否已经有缓存的 SFTP 会话
+        {
+            let sftp_sessions = self.sftp_sessions.lock().unwrap();
+            if let Some(session) = sftp_sessions.get(session_id) {
+                // 检查会话是否仍然有效
+                let sess = session.lock().unwrap();
+                if sess.authenticated() {
+                    return Ok(session.clone());
+                }
+                // 如果会话无效，继续创建新的
+            }
+        }
+
+        // 获取连接信息
+        let connections = self.connections.lock().unwrap();
+        let connection = connections
+            .get(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", session_id))?
+            .clone();
+        drop(connections);
+
+        // 创建新的独立 SSH 会话专门用于 SFTP
+        let sess = self.create_authenticated_session(&connection)?;
+
+        // 设置为阻塞模式（SFTP 需要）
+        sess.set_blocking(true);
+        sess.set_timeout(30000);
+
+        let session_arc = Arc::new(Mutex::new(sess));
+
+        // 缓存 SFTP 会话
+        let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
+        sftp_sessions.insert(session_id.to_string(), session_arc.clone());
+
+        Ok(session_arc)
+    }
+
+    // 在缓存的 SFTP 会话上执行操作。失败时用 stat(".") 探测会话是否存活：
+    // 已失效（如空闲后 TCP 被断开）则丢弃缓存、重建会话并重试一次；存活则直接返回原错误。
+    // 只读或可重复执行的操作：会话的传输层已断开时重建 SFTP 会话并重试一次
+    fn with_sftp<T, F>(&self, session_id: &str, op: F) -> anyhow::Result<T>
+    where
+        F: FnMut(&ssh2::Sftp) -> anyhow::Result<T>,
+    {
+        self.run_sftp(session_id, true, op)
+    }
+
+    // 改名、删除、创建等重复执行结果不同的操作：传输层断开时只丢弃失效会话，不重试
+    fn with_sftp_once<T, F>(&self, session_id: &str, op: F) -> anyhow::Result<T>
+    where
+        F: FnMut(&ssh2::Sftp) -> anyhow::Result<T>,
+    {
+        self.run_sftp(session_id, false, op)
+    }
+
+    fn run_sftp<T, F>(&self, session_id: &str, retry: bool, mut op: F) -> anyhow::Result<T>
+    where
+        F: FnMut(&ssh2::Sftp) -> anyhow::Result<T>,
+    {
+        let mut retry = retry;
+        loop {
+            let sftp_session = self.get_or_create_sftp(session_id)?;
+            let (error, dead) = {
+                let sess = sftp_session.lock().unwrap();
+                match sess.sftp() {
+                    Ok(sftp) => match op(&sftp) {
+                        Ok(value) => return Ok(value),
+                        // 权限、路径不存在等操作本身的失败不算断开，再探测一次传输层
+                        Err(error) => {
+                            let dead = sftp
+                                .stat(Path::new("."))
+                                .err()
+                                .is_some_and(|e| Self::is_transport_error(&e));
+                            (error, dead)
+                        }
+                    },
+                    // 服务端没有 SFTP 子系统时打开请求被拒绝，会话本身仍可用
+                    Err(e) => {
+                        let dead = Self::is_transport_error(&e);
+                        (SftpUnavailable(e.to_string()).into(), dead)
+                    }
+                }
+            };
+            if !dead {
+                return Err(error);
+            }
+            let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
+            if sftp_sessions
+                .get(session_id)
+                .is_some_and(|current| Arc::ptr_eq(current, &sftp_session))
+            {
+                sftp_sessions.remove(session_id);
+            }
+            drop(sftp_sessions);
+            if !retry {
+                return Err(error);
+            }
+            retry = false;
+            tracing::debug!(session_id = %session_id, error = %error, "sftp session dead, recreating");
+        }
+    }
+
+    // 探测服务端是否提供 SFTP 子系统，结果按会话缓存。探测打开的 SFTP 会话会留给后续文件操作复用
+    pub fn has_sftp(&self, session_id: &str) -> anyhow::Result<bool> {
+        if let Some(supported) = self.sftp_support.lock().unwrap().get(session_id) {
+            return Ok(*supported);
+        }
+        let supported = match self.with_sftp(session_id, |_| Ok(())) {
+            Ok(()) => true,
+            Err(e) if e.is::<SftpUnavailable>() => false,
+            Err(e) => return Err(e),
+        };
+        self.sftp_support
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), supported);
+        Ok(supported)
+    }
+
+    pub fn sftp_list_dir(&self, session_id: &str, path: &str, with_inodes: bool) -> anyhow::Result<Vec<SftpEntry>> {
+        let mut entries = self.list_dir_entries(session_id, path)?;
+        if with_inodes {
+            match self.dir_link_info(session_id, path) {
+                Ok(links) => {
+                    for entry in entries.iter_mut() {
+                        if let Some((inode, nlink)) = links.get(&entry.name) {
+                            entry.inode = Some(*inode);
+                            entry.nlink = Some(*nlink);
+                        }
+                    }
+                }
+                Err(e) => tracing::debug!(session_id = %session_id, error = %e, "inode lookup via exec failed"),
+            }
+        }
+        Ok(entries)
+    }
+
+    // 名称 -> (inode, 硬链接数)，不含 "." 与 ".."
+    fn dir_link_info(&self, session_id: &str, path: &str) -> anyhow::Result<HashMap<String, (u64, u64)>> {
+        let clean_path = if path.trim().is_empty() { "." } else { path.trim() };
+        let quoted = shell_quote(clean_path);
+        let command = format!(
+            "find -H {0} -mindepth 1 -maxdepth 1 -printf '%i %n %f\\n' 2>/dev/null || LC_ALL=C ls -lai -- {0}",
+            quoted
+        );
+        let output = self.execute_raw(session_id, &command)?;
+        if output.exit_code != 0 {
+            let stderr = decode_bytes(&output.stderr, self.connection_encoding(session_id).as_deref());
+            return Err(anyhow::anyhow!("Failed to read inodes of '{}': {}", clean_path, stderr.trim()));
+        }
+        let stdout = decode_bytes(&output.stdout, self.connection_encoding(session_id).as_deref());
+        let mut links = HashMap::new();
+        for line in stdout.lines() {
+            let Some((inode, rest)) = line.trim_start().split_once(' ') else {
+                continue;
+            };
+            let Ok(inode) = inode.parse::<u64>() else {
+                continue;
+            };
+            // find 输出 "inode nlink name"；ls -lai 输出 "inode perms nlink ..."
+            let parsed = match parse_ls_line(rest.trim_start()) {
+                Some(entry) => rest
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|nlink| nlink.parse::<u64>().ok())
+                    .map(|nlink| (entry.name, nlink)),
+                None => rest
+                    .split_once(' ')
+                    .and_then(|(nlink, name)| Some((name.to_string(), nlink.parse::<u64>().ok()?))),
+            };
+            if let Some((name, nlink)) = parsed.filter(|(name, _)| name != "." && name != "..") {
+                links.insert(name, (inode, nlink));
+            }
+        }
+        Ok(links)
+    }
+
+    fn list_dir_entries(&self, session_id: &str, path: &str) -> anyhow::Result<Vec<SftpEntry>> {
+        if self.sftp_support.lock().unwrap().get(session_id) == Some(&false) {
+            return self.list_dir_via_exec(session_id, path);
+        }
+        let result = self.with_sftp(session_id, |sftp| {
+            let clean_path = if path.trim().is_empty() { "." } else { path.trim() };
+
+            // 规范化路径以检查是否在根目录
+            let normalized_path = Path::new(clean_path);
+
+            let entries = sftp.readdir(normalized_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read directory '{}': {}", clean_path, e))?;
+
+            let output: Vec<SftpEntry> = entries
+                .into_iter()
+                .filter_map(|(p, stat)| {
+                    let name = p.file_name()?.to_string_lossy().to_string();
+                    Some(SftpEntry {
+                        name,
+                        is_dir: stat.is_dir(),
+                        size: stat.size,
+                        modified: stat.mtime,
+                        perm: stat.perm,
+                        inode: None,
+                        nlink: None,
+                    })
+                })
+                .collect();
+
+            Ok(finish_dir_listing(output, clean_path))
+        });
+
+        match result {
+            Err(e) if e.is::<SftpUnavailable>() => {
+                tracing::debug!(session_id = %session_id, error = %e, "sftp unavailable, listing via exec");
+                self.list_dir_via_exec(session_id, path)
+            }
+            other => other,
+        }
+    }
+
+    // 先 stat（跟随符号链接）确认目标是目录，再解析为绝对路径并列出，
+    // 避免对文件 readdir 时得到含糊的错误
+    pub fn sftp_change_dir(&self, session_id: &str, path: &str) -> anyhow::Result<SftpDirListing> {
+        let clean_path = if path.trim().is_empty() { "." } else { path.trim() };
+        let result = self.with_sftp(session_id, |sftp| {
+            let stat = sftp
+                .stat(Path::new(clean_path))
+                .map_err(|e| anyhow::anyhow!("Failed to open '{}': {}", clean_path, e))?;
+            if !stat.is_dir() {
+                return Err(anyhow::anyhow!("Not a directory: {}", clean_path));
+            }
+            let resolved = sftp
+                .realpath(Path::new(clean_path))
+                .map_err(|e| anyhow::anyhow!("Failed to resolve path '{}': {}", clean_path, e))?;
+            Ok(resolved.to_string_lossy().to_string())
+        });
+        // 无 SFTP 时 sftp_realpath 走 cd && pwd -P，目标不是目录时同样失败
+        let resolved = match result {
+            Err(e) if e.is::<SftpUnavailable>() => self.sftp_realpath(session_id, clean_path)?,
+            other => other?,
+        };
+        let entries = self.sftp_list_dir(session_id, &resolved, false)?;
+        Ok(SftpDirListing { path: resolved, entries })
+    }
+
+    // 一次 exec 批量采集内存、负载和 CPU 数；Linux 用 free/nproc，BSD/macOS 退回 sysctl/vm_stat。
+    // 单项失败只记入 missing，不影响其他指标
+    pub fn host_stats(&self, session_id: &str) -> anyhow::Result<HostStats> {
+        let script = "export LC_ALL=C; \
+            echo '@@free'; free -b 2>/dev/null; \
+            echo '@@uptime'; uptime 2>/dev/null; \
+            echo '@@ncpu'; nproc 2>/dev/null || sysctl -n hw.ncpu 2>/dev/null; \
+            echo '@@memsize'; sysctl -n hw.memsize 2>/dev/null || sysctl -n hw.physmem 2>/dev/null; \
+            echo '@@vm_stat'; vm_stat 2>/dev/null; true";
+        let output = self.execute_raw(session_id, script)?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+        let sections = split_sections(&stdout);
+        let section = |name: &str| sections.get(name).cloned().unwrap_or_default();
+
+        let mut stats = HostStats::default();
+        if let Some((total, used)) = parse_free_mem(&section("free")) {
+            stats.mem_total = Some(total);
+            stats.mem_used = Some(used);
+        } else {
+            stats.mem_total = section("memsize")
+                .first()
+                .and_then(|line| line.trim().parse().ok());
+            stats.mem_used = parse_vm_stat_used(&section("vm_stat"));
+        }
+        stats.load_average = section("uptime").iter().find_map(|line| parse_load_average(line));
+        stats.cpu_count = section("ncpu")
+            .first()
+            .and_then(|line| line.trim().parse().ok());
+
+        for (name, present) in [
+            ("memTotal", stats.mem_total.is_some()),
+            ("memUsed", stats.mem_used.is_some()),
+            ("loadAverage", stats.load_average.is_some()),
+            ("cpuCount", stats.cpu_count.is_some()),
+        ] {
+            if !present {
+                stats.missing.push(name.to_string());
+            }
+        }
+        Ok(stats)
+    }
+
+    pub fn list_interfaces(&self, session_id: &str) -> anyhow::Result<InterfaceList> {
+        let script = "export LC_ALL=C; PATH=$PATH:/sbin:/usr/sbin; \
+            echo '@@ip'; ip -o addr show 2>/dev/null; \
+            echo '@@ifconfig'; ifconfig -a 2>/dev/null; true";
+        let output = self.execute_raw(session_id, script)?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+        let sections = split_sections(&stdout);
+        let section = |name: &str| sections.get(name).cloned().unwrap_or_default();
+
+        let mut list = InterfaceList::default();
+        let ip_lines = section("ip");
+        let ifconfig_lines = section("ifconfig");
+        if ip_lines.is_empty() {
+            list.missing.push("ip".to_string());
+        }
+        if ifconfig_lines.is_empty() {
+            list.missing.push("ifconfig".to_string());
+        }
+        let from_ip = parse_ip_addr(&ip_lines);
+        if !from_ip.is_empty() {
+            list.interfaces = from_ip;
+            list.source = Some("ip".to_string());
+        } else {
+            let from_ifconfig = parse_ifconfig(&ifconfig_lines);
+            if !from_ifconfig.is_empty() {
+                list.interfaces = from_ifconfig;
+                list.source = Some("ifconfig".to_string());
+            }
+        }
+        Ok(list)
+    }
+
+    // 无 SFTP 的主机：通过 exec 执行 ls -la 并解析。优先用 GNU 的 --time-style=+%s 拿到时间戳，
+    // 不支持时退回普通 ls -la（此时修改时间为空）
+    pub fn list_dir_via_exec(&self, session_id: &str, path: &str) -> anyhow::Result<Vec<SftpEntry>> {
+        let clean_path = if path.trim().is_empty() { "." } else { path.trim() };
+        let quoted = shell_quote(clean_path);
+        let command = format!(
+            "LC_ALL=C ls -la --time-style=+%s -- {0} 2>/dev/null || LC_ALL=C ls -la -- {0}",
+            quoted
+        );
+        let output = self.execute_raw(session_id, &command)?;
+        let encoding = self.connection_encoding(session_id);
+        if output.exit_code != 0 {
+            let stderr = decode_bytes(&output.stderr, encoding.as_deref());
+            return Err(anyhow::anyhow!(
+                "Failed to read directory '{}': {}",
+                clean_path,
+                stderr.trim()
+            ));
+        }
+        let stdout = decode_bytes(&output.stdout, encoding.as_deref());
+        let entries = stdout.lines().filter_map(parse_ls_line).collect();
+        Ok(finish_dir_listing(entries, clean_path))
+    }
+
+    // 运行中切换 shell 输出的解码编码（如远端 export LANG 之后），同时用于之后的 exec 输出。
+    // 返回规范化后的编码名
+    pub fn set_session_encoding(&self, session_id: &str, label: &str) -> anyhow::Result<String> {
+        let encoding = encoding_rs::Encoding::for_label(label.trim().as_bytes())
+            .ok_or_else(|| anyhow::anyhow!("Unsupported encoding: {}", label))?;
+        if !self.sessions.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Session not found"));
+        }
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(session_id) {
+            connection.encoding = Some(encoding.name().to_string());
+        }
+        let mut encodings = self.shell_encodings.lock().unwrap();
+        if let Some(current) = encodings.get_mut(session_id) {
+            *current = encoding;
+        }
+        tracing::debug!(session_id = %session_id, encoding = %encoding.name(), "session encoding changed");
+        Ok(encoding.name().to_string())
+    }
+
+    fn connection_encoding(&self, session_id: &str) -> Option<String> {
+        let connections = self.connections.lock().unwrap();
+        connections
+            .get(session_id)
+            .and_then(|connection| connection.encoding.clone())
+    }
+
+    // 读取远程文件末尾 n 行：优先 exec `tail`，不可用时退回 SFTP stat + seek
+    pub fn sftp_tail_lines(&self, session_id: &str, path: &str, n: usize) -> anyhow::Result<String> {
+        let n = n.clamp(1, 100_000);
+        let encoding = self.connection_encoding(session_id);
+
+        let command = format!("tail -n {} -- {}", n, shell_quote(path));
+        match self.execute_raw(session_id, &command) {
+            Ok(output) if output.exit_code == 0 => {
+                return Ok(decode_bytes(&output.stdout, encoding.as_deref()));
+            }
+            // 126/127: tail missing or not executable; anything else is a real failure
+            Ok(output) if output.exit_code != 126 && output.exit_code != 127 => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow::anyhow!("tail failed for '{}': {}", path, stderr.trim()));
+            }
+            Ok(_) | Err(_) => {}
+        }
+
+        let sftp_session = self.get_or_create_sftp(session_id)?;
+        let sess = sftp_session.lock().unwrap();
+
+        let sftp = sess.sftp()
+            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+
+        let size = sftp
+            .stat(Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to stat '{}': {}", path, e))?
+            .size
+            .unwrap_or(0);
+        let mut remote_file = sftp.open(Path::new(path))
+            .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", path, e))?;
+
+        // 从末尾开始逐步扩大读取窗口，直到包含足够的换行或到达文件开头
+        let mut window: u64 = 64 * 1024;
+        let tail = loop {
+            let start = size.saturating_sub(window);
+            remote_file
+                .seek(SeekFrom::Start(start))
+                .map_err(|e| anyhow::anyhow!("Failed to seek remote file '{}': {}", path, e))?;
+            let mut data = Vec::with_capacity((size - start) as usize);
+            (&mut remote_file)
+                .take(size - start)
+                .read_to_end(&mut data)
+                .map_err(|e| anyhow::anyhow!("Failed to read remote file '{}': {}", path, e))?;
+
+            let newlines = data.iter().filter(|b| **b == b'\n').count();
+            if start == 0 || newlines > n || window >= 16 * 1024 * 1024 {
+                break last_lines(&data, n).to_vec();
+            }
+            window *= 4;
+        };
+
+        Ok(decode_bytes(&tail, encoding.as_deref()))
+    }
+
+    pub fn sftp_preview(&self, session_id: &str, path: &str, max_bytes: usize) -> anyhow::Result<SftpPreview> {
+        let max_bytes = max_bytes.clamp(1, 8 * 1024 * 1024);
+        let encoding = self.connection_encoding(session_id);
+        let mut data = self.with_sftp(session_id, |sftp| {
+            let remote_file = sftp
+                .open(Path::new(path))
+                .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", path, e))?;
+            let mut data = Vec::new();
+            // 多读一个字节用于判断是否截断
+            remote_file
+                .take(max_bytes as u64 + 1)
+                .read_to_end(&mut data)
+                .map_err(|e| anyhow::anyhow!("Failed to read remote file '{}': {}", path, e))?;
+            Ok(data)
+        })?;
+        let truncated = data.len() > max_bytes;
+        data.truncate(max_bytes);
+
+        let (is_binary, encoding_guess) = detect_text_encoding(&data, truncated, encoding.as_deref());
+        Ok(SftpPreview {
+            is_binary,
+            encoding_guess,
+            content_b64: base64::engine::general_purpose::STANDARD.encode(&data),
+            truncated,
+        })
+    }
+
+    pub fn sftp_rename(&self, session_id: &str, from_path: &str, to_path: &str) -> anyhow::Result<()> {
+        self.with_sftp_once(session_id, |sftp| {
+            sftp.rename(Path::new(from_path), Path::new(to_path), None)
+                .map_err(|e| anyhow::anyhow!("Failed to rename '{}': {}", from_path, e))?;
+
+            Ok(())
+        })
+    }
+
+    pub fn sftp_chmod(&self, session_id: &str, path: &str, mode: u32) -> anyhow::Result<()> {
+        self.with_sftp(session_id, |sftp| {
+            let stat = FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: Some(mode),
+                atime: None,
+                mtime: None,
+            };
+
+            sftp.setstat(Path::new(path), stat)
+                .map_err(|e| anyhow::anyhow!("Failed to chmod '{}': {}", path, e))?;
+
+            Ok(())
+        })
+    }
+
+    pub fn sftp_delete(&self, session_id: &str, path: &str, is_dir: bool) -> anyhow::Result<()> {
+        self.with_sftp_once(session_id, |sftp| {
+            if is_dir {
+                sftp.rmdir(Path::new(path))
+                    .map_err(|e| anyhow::anyhow!("Failed to remove directory '{}': {}", path, e))?;
+            } else {
+                sftp.unlink(Path::new(path))
+                    .map_err(|e| anyhow::anyhow!("Failed to delete file '{}': {}", path, e))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    // 解析 "."、".."、符号链接为绝对规范路径；"." 即登录用户的主目录。无 SFTP 时用 exec 执行 pwd -P
+    pub fn sftp_realpath(&self, session_id: &str, path: &str) -> anyhow::Result<String> {
+        let clean_path = if path.trim().is_empty() { "." } else { path.trim() };
+        let result = self.with_sftp(session_id, |sftp| {
+            let resolved = sftp
+                .realpath(Path::new(clean_path))
+                .map_err(|e| anyhow::anyhow!("Failed to resolve path '{}': {}", clean_path, e))?;
+            Ok(resolved.to_string_lossy().to_string())
+        });
+        match result {
+            Err(e) if e.is::<SftpUnavailable>() => {
+                let output = self.execute_raw(session_id, &format!("cd -- {} && pwd -P", shell_quote(clean_path)))?;
+                let resolved = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if output.exit_code != 0 || !resolved.starts_with('/') {
+                    return Err(anyhow::anyhow!(
+                        "Failed to resolve path '{}': {}",
+                        clean_path,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                Ok(resolved)
+            }
+            other => other,
+        }
+    }
+
+    pub fn sftp_mkdir(&self, session_id: &str, path: &str) -> anyhow::Result<()> {
+        self.with_sftp_once(session_id, |sftp| {
+            sftp.mkdir(Path::new(path), 0o755)
+                .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", path, e))?;
+
+            Ok(())
+        })
+    }
+
+    // 像素尺寸供 Sixel/Kitty 等图像协议换算单元格大小，未知时传 None
+    pub fn resize_pty(
+        &self,
+        session_id: &str,
+        cols: u32,
+        rows: u32,
+        pixel_width: Option<u32>,
+        pixel_height: Option<u32>,
+    ) -> anyhow::Result<()> {
+        let channels = self.channels.lock().unwrap();
+        let channel = channels
+            .get(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
+
+        let mut ch = channel.lock().unwrap();
+        ch.request_pty_size(cols, rows, pixel_width, pixel_height)?;
+        self.pty_sizes
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), ShellSize { cols, rows });
+
+        Ok(())
+    }
+
+    // 修正免密登录最常见的失败原因：~/.ssh 设为 0700、authorized_keys 设为 0600，
+    // 家目录去掉组/其他用户写权限；以 root 登录时把属主改回家目录的属主
+    pub fn fix_ssh_perms(&self, session_id: &str) -> anyhow::Result<SshPermsReport> {
+        let output = self.execute_raw(session_id, FIX_SSH_PERMS_SCRIPT)?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let mut report = SshPermsReport::default();
+        for line in stdout.lines() {
+            match line.split_once('\t') {
+                Some(("changed", text)) => report.changed.push(text.to_string()),
+                Some(("warn", text)) => report.warnings.push(text.to_string()),
+                _ => {}
+            }
+        }
+        if output.exit_code != 0 {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if !stderr.is_empty() {
+                report.warnings.push(stderr);
+            }
+        }
+        tracing::info!(
+            session_id = %session_id,
+            changed = report.changed.len(),
+            warnings = report.warnings.len(),
+            "ssh permissions checked"
+        );
+        Ok(report)
+    }
+
+    // 返回最近请求的 PTY 尺寸，并尽量读取远端实际尺寸。exec 通道拿不到 shell 的 tty，
+    // 这里取当前用户最近活动的 pts 执行 stty size，同时返回该 tty 供判断是否对应本会话。
+    pub fn shell_size(&self, session_id: &str) -> anyhow::Result<ShellSizeReport> {
+        if !self.channels.lock().unwrap().contains_key(session_id) {
+            return Err(anyhow::anyhow!("Shell not found"));
+        }
+        let requested = self.pty_sizes.lock().unwrap().get(session_id).copied();
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        // 查询走独立的 exec 会话，拿不到 shell 通道的 tty。按 shell 所在连接的本地端口，
+        // 在远端进程环境中找 SSH_CONNECTION 客户端端口相同的进程，取它的 SSH_TTY。
+        // 经跳板、NAT 或没有 /proc 时找不到，返回 None 而不是猜测其他终端
+        let Some(port) = session_local_port(&session.lock().unwrap()) else {
+            return Ok(ShellSizeReport { requested, observed: None, observed_tty: None });
+        };
+
+        let script = format!(
+            "for e in /proc/[0-9]*/environ; do \
+               env=$(tr '\\0' '\\n' < \"$e\") || continue; \
+               set -- $(printf '%s\\n' \"$env\" | sed -n 's/^SSH_CONNECTION=//p'); \
+               [ \"$2\" = {} ] || continue; \
+               t=$(printf '%s\\n' \"$env\" | sed -n 's/^SSH_TTY=//p'); \
+               [ -n \"$t\" ] || continue; \
+               echo \"$t\"; stty size < \"$t\"; break; \
+             done 2>/dev/null",
+            port
+        );
+        let (observed, observed_tty) = match self.execute_raw(session_id, &script) {
+            Ok(output) if output.exit_code == 0 => {
+                let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+                let mut lines = stdout.lines().map(str::trim).filter(|line| !line.is_empty());
+                let tty = lines.next().map(str::to_string);
+                let size = lines.next().and_then(|line| {
+                    let mut parts = line.split_whitespace();
+                    let rows = parts.next()?.parse().ok()?;
+                    let cols = parts.next()?.parse().ok()?;
+                    Some(ShellSize { cols, rows })
+                });
+                (size, size.and(tty))
+            }
+            Ok(_) => (None, None),
+            Err(e) => {
+                tracing::debug!(session_id = %session_id, error = %e, "stty size probe failed");
+                (None, None)
+            }
+        };
+
+        Ok(ShellSizeReport {
+            requested,
+            observed,
+            observed_tty,
+        })
+    }
+
+    fn register_cancel(&self, id: &str) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut cancel_flags = self.cancel_flags.lock().unwrap();
+        cancel_flags.insert(id.to_string(), flag.clone());
+        flag
+    }
+
+    fn clear_cancel(&self, id: &str) {
+        let mut cancel_flags = self.cancel_flags.lock().unwrap();
+        cancel_flags.remove(id);
+    }
+
+    pub fn cancel(&self, id: &str) -> bool {
+        let cancel_flags = self.cancel_flags.lock().unwrap();
+        match cancel_flags.get(id) {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 分块读取远程文件并逐块回调（不落盘），可通过 cancel(stream_id) 中止。
+    // 回调参数：序号、数据块、是否结束、是否因取消而结束。
+    pub fn sftp_stream_read<F>(
+        &self,
+        session_id: &str,
+        path: &str,
+        stream_id: &str,
+        chunk_size: usize,
+        mut on_chunk: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, &[u8], bool, bool) + Send,
+    {
+        let stop = self.register_cancel(stream_id);
+        let result = (|| {
+            let sftp_session = self.get_or_create_sftp(session_id)?;
+            let sess = sftp_session.lock().unwrap();
+
+            let sftp = sess.sftp()
+                .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+
+            let mut remote_file = sftp.open(Path::new(path))
+                .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", path, e))?;
+
+            let mut buf = vec![0u8; chunk_size.clamp(4 * 1024, 1024 * 1024)];
+            let mut seq: u64 = 0;
+            loop {
+                if stop.load(Ordering::Relaxed) {
+                    on_chunk(seq, &[], true, true);
+                    return Ok(());
+                }
+                let read = remote_file
+                    .read(&mut buf)
+                    .map_err(|e| anyhow::anyhow!("Failed to read remote file '{}': {}", path, e))?;
+                if read == 0 {
+                    on_chunk(seq, &[], true, false);
+                    return Ok(());
+                }
+                on_chunk(seq, &buf[..read], false, false);
+                seq += 1;
+            }
+        })();
+        self.clear_cancel(stream_id);
+        result
+    }
+
+    // atomic 时先写入同目录的 <path>.noterm-tmp（已存在则失败，不覆盖别人的临时文件），
+    // 并沿用目标原有的权限位，结束时替换目标；失败或中止时删除临时文件，目标因此只会是旧文件或完整的新文件。
+    // 服务端不支持 posix-rename 时替换退化为先删后改名（见 replace_remote_file），中间有短暂的目标缺失窗口
+    pub fn sftp_stream_write_begin(
+        &self,
+        session_id: &str,
+        stream_id: &str,
+        path: &str,
+        atomic: bool,
+    ) -> anyhow::Result<()> {
+        {
+            let upload_streams = self.upload_streams.lock().unwrap();
+            if upload_streams.contains_key(stream_id) {
+                return Err(anyhow::anyhow!("Upload stream already open: {}", stream_id));
+            }
+        }
+
+        let sftp_session = self.get_or_create_sftp(session_id)?;
+        let sess = sftp_session.lock().unwrap();
+
+        let sftp = sess.sftp()
+            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
 
-        let effective_username = if connection.username.trim().is_empty() {
-            std::env::var("USER")
+        let (write_path, flags, perm) = if atomic {
+            let perm = sftp
+                .stat(Path::new(path))
                 .ok()
-                .filter(|name| !name.trim().is_empty())
-                .or_else(|| {
-                    std::env::var("USERNAME")
-                        .ok()
-                        .filter(|name| !name.trim().is_empty())
+                .and_then(|stat| stat.perm)
+                .map(|perm| perm & 0o7777);
+            (format!("{}.noterm-tmp", path), OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE, perm)
+        } else {
+            (path.to_string(), OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE, None)
+        };
+        let mut file = sftp
+            .open_mode(Path::new(&write_path), flags, perm.unwrap_or(0o644) as i32, OpenType::File)
+            .map_err(|e| anyhow::anyhow!("Failed to create remote file '{}': {}", write_path, e))?;
+        // 创建时的 mode 受服务端 umask 影响，显式设置一次
+        if let Some(perm) = perm {
+            let stat = FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: Some(perm),
+                atime: None,
+                mtime: None,
+            };
+            if let Err(e) = file.setstat(stat) {
+                drop(file);
+                let _ = sftp.unlink(Path::new(&write_path));
+                return Err(anyhow::anyhow!("Failed to copy permissions to '{}': {}", write_path, e));
+            }
+        }
+
+        let mut upload_streams = self.upload_streams.lock().unwrap();
+        upload_streams.insert(
+            stream_id.to_string(),
+            Arc::new(UploadStream {
+                session_id: session_id.to_string(),
+                path: write_path,
+                file: Mutex::new(file),
+                written: AtomicU64::new(0),
+                target: atomic.then(|| path.to_string()),
+            }),
+        );
+        Ok(())
+    }
+
+    // 原子写入失败/中止时删除临时文件；等待进行中的写入结束后再关闭句柄
+    fn discard_upload_stream(&self, stream: Arc<UploadStream>) {
+        let _ = stream.file.lock().unwrap().close();
+        if stream.target.is_some() {
+            if let Err(e) = self.with_sftp_once(&stream.session_id, |sftp| Ok(sftp.unlink(Path::new(&stream.path))?)) {
+                tracing::warn!(error = %e, path = %stream.path, "failed to remove temporary upload file");
+            }
+        }
+    }
+
+    // 放弃上传流；原子写入时目标文件保持不变
+    pub fn sftp_stream_write_abort(&self, stream_id: &str) -> anyhow::Result<()> {
+        let stream = self
+            .upload_streams
+            .lock()
+            .unwrap()
+            .remove(stream_id)
+            .ok_or_else(|| anyhow::anyhow!("Upload stream not found: {}", stream_id))?;
+        self.discard_upload_stream(stream);
+        Ok(())
+    }
+
+    pub fn sftp_stream_write_chunk(&self, stream_id: &str, data: &[u8]) -> anyhow::Result<u64> {
+        let stream = self
+            .upload_streams
+            .lock()
+            .unwrap()
+            .get(stream_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Upload stream not found: {}", stream_id))?;
+
+        let mut file = stream.file.lock().unwrap();
+        if let Err(e) = file.write_all(data) {
+            drop(file);
+            // 只移除仍是同一个流的条目（期间可能已结束或被同 id 的新流替换）
+            let removed = {
+                let mut upload_streams = self.upload_streams.lock().unwrap();
+                match upload_streams.get(stream_id) {
+                    Some(current) if Arc::ptr_eq(current, &stream) => upload_streams.remove(stream_id),
+                    _ => None,
+                }
+            };
+            if let Some(stream) = removed {
+                self.discard_upload_stream(stream);
+            }
+            return Err(anyhow::anyhow!("Failed to write remote file '{}': {}", stream.path, e));
+        }
+        let written = stream.written.fetch_add(data.len() as u64, Ordering::SeqCst);
+        Ok(written.saturating_add(data.len() as u64))
+    }
+
+    // 结束上传流并关闭远程文件句柄，返回写入的总字节数
+    pub fn sftp_stream_write_end(&self, stream_id: &str) -> anyhow::Result<u64> {
+        let stream = {
+            let mut upload_streams = self.upload_streams.lock().unwrap();
+            upload_streams
+                .remove(stream_id)
+                .ok_or_else(|| anyhow::anyhow!("Upload stream not found: {}", stream_id))?
+        };
+        // 等待进行中的写入结束
+        let mut file = stream.file.lock().unwrap();
+        let UploadStream { session_id, path, target, .. } = &*stream;
+        let written = stream.written.load(Ordering::SeqCst);
+        let closed = file
+            .flush()
+            .map_err(|e| anyhow::anyhow!("Failed to flush remote file '{}': {}", path, e))
+            .and_then(|_| {
+                file.close()
+                    .map_err(|e| anyhow::anyhow!("Failed to close remote file '{}': {}", path, e))
+            });
+        drop(file);
+        let Some(target) = target else {
+            closed?;
+            return Ok(written);
+        };
+        let result = closed.and_then(|_| self.replace_remote_file(session_id, path, target));
+        if let Err(e) = result {
+            let _ = self.with_sftp_once(session_id, |sftp| Ok(sftp.unlink(Path::new(path))?));
+            return Err(e);
+        }
+        Ok(written)
+    }
+
+    pub fn sftp_download_file<F>(
+        &self,
+        session_id: &str,
+        remote_path: &str,
+        local_path: &str,
+        mut on_progress: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        let started_at = Instant::now();
+        let mut transferred_bytes = 0u64;
+        let mut total_bytes = 0u64;
+        let mut on_progress = |done: u64, total: u64| {
+            transferred_bytes = done;
+            total_bytes = total;
+            on_progress(done, total);
+        };
+        let result = self.with_sftp(session_id, |sftp| {
+            // 打开远程文件
+            let mut remote_file = sftp.open(Path::new(remote_path))
+                .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", remote_path, e))?;
+
+            let total = sftp
+                .stat(Path::new(remote_path))
+                .ok()
+                .and_then(|stat| stat.size)
+                .unwrap_or(0);
+            let local_existing = std::fs::metadata(local_path).map(|meta| meta.len()).unwrap_or(0);
+            let can_resume = local_existing > 0 && local_existing < total;
+
+            let mut local_file = if can_resume {
+                remote_file
+                    .seek(SeekFrom::Start(local_existing))
+                    .map_err(|e| anyhow::anyhow!("Failed to seek remote file '{}': {}", remote_path, e))?;
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(local_path)
+                    .map_err(|e| anyhow::anyhow!("Failed to open local file '{}': {}", local_path, e))?
+            } else {
+                std::fs::File::create(local_path)
+                    .map_err(|e| anyhow::anyhow!("Failed to create local file '{}': {}", local_path, e))?
+            };
+
+            let mut transferred: u64 = if can_resume { local_existing } else { 0 };
+            let mut buf = [0u8; 64 * 1024];
+
+            on_progress(transferred, total);
+            loop {
+                let read = remote_file
+                    .read(&mut buf)
+                    .map_err(|e| anyhow::anyhow!("Failed to read remote file '{}': {}", remote_path, e))?;
+                if read == 0 {
+                    break;
+                }
+                local_file
+                    .write_all(&buf[..read])
+                    .map_err(|e| anyhow::anyhow!("Failed to write local file '{}': {}", local_path, e))?;
+                transferred = transferred.saturating_add(read as u64);
+                on_progress(transferred, total);
+            }
+            if total > 0 && transferred < total {
+                on_progress(total, total);
+            }
+
+            Ok(())
+        });
+        self.record_transfer(session_id, remote_path, TransferDirection::Download, started_at, transferred_bytes, &result);
+        self.track_interrupted(
+            session_id,
+            TransferDirection::Download,
+            (local_path, remote_path),
+            (transferred_bytes, total_bytes),
+            &result,
+        );
+        result
+    }
+
+    pub fn sftp_upload_file<F>(
+        &self,
+        session_id: &str,
+        local_path: &str,
+        remote_path: &str,
+        on_progress: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        self.upload_file_with_stop(session_id, local_path, remote_path, None, on_progress)
+    }
+
+    // stop 置位时中止并保留 .part 文件，下次上传同一路径会从已写入的位置续传
+    fn upload_file_with_stop<F>(
+        &self,
+        session_id: &str,
+        local_path: &str,
+        remote_path: &str,
+        stop: Option<&AtomicBool>,
+        mut on_progress: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        let started_at = Instant::now();
+        let mut transferred_bytes = 0u64;
+        let mut total_bytes = 0u64;
+        let mut on_progress = |done: u64, total: u64| {
+            transferred_bytes = done;
+            total_bytes = total;
+            on_progress(done, total);
+        };
+        let result = self.with_sftp_once(session_id, |sftp| {
+            // 打开本地文件
+            let mut local_file = std::fs::File::open(local_path)
+                .map_err(|e| anyhow::anyhow!("Failed to open local file '{}': {}", local_path, e))?;
+
+            let total = local_file
+                .metadata()
+                .map(|meta| meta.len())
+                .unwrap_or(0);
+            // Upload to a temporary file first, then atomically rename to final name.
+            // This prevents users from opening an incomplete file by the final name.
+            let temp_remote_path = format!("{}.part", remote_path);
+            let temp_remote_path_ref = Path::new(&temp_remote_path);
+
+            let remote_existing = sftp
+                .stat(temp_remote_path_ref)
+                .ok()
+                .and_then(|stat| stat.size)
+                .unwrap_or(0);
+            let can_resume = remote_existing > 0 && remote_existing < total;
+
+            let mut remote_file = if can_resume {
+                local_file
+                    .seek(SeekFrom::Start(remote_existing))
+                    .map_err(|e| anyhow::anyhow!("Failed to seek local file '{}': {}", local_path, e))?;
+                sftp.open_mode(
+                    temp_remote_path_ref,
+                    OpenFlags::WRITE | OpenFlags::APPEND,
+                    0o644,
+                    OpenType::File,
+                )
+                .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", temp_remote_path, e))?
+            } else {
+                // Fallback to full overwrite when remote file does not exist,
+                // is empty, or is larger than local file.
+                sftp.open_mode(
+                    temp_remote_path_ref,
+                    OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                    0o644,
+                    OpenType::File,
+                )
+                .map_err(|e| anyhow::anyhow!("Failed to create remote file '{}': {}", temp_remote_path, e))?
+            };
+
+            let mut transferred: u64 = if can_resume { remote_existing } else { 0 };
+            let mut buf = [0u8; 64 * 1024];
+
+            on_progress(transferred, total);
+            loop {
+                if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+                    return Err(anyhow::anyhow!("Upload cancelled"));
+                }
+                let read = local_file
+                    .read(&mut buf)
+                    .map_err(|e| anyhow::anyhow!("Failed to read local file '{}': {}", local_path, e))?;
+                if read == 0 {
+                    break;
+                }
+                remote_file
+                    .write_all(&buf[..read])
+                    .map_err(|e| anyhow::anyhow!("Failed to write remote file '{}': {}", temp_remote_path, e))?;
+                transferred = transferred.saturating_add(read as u64);
+                on_progress(transferred, total);
+            }
+            if total > 0 && transferred < total {
+                on_progress(total, total);
+            }
+
+            drop(remote_file);
+            Ok(())
+        })
+        // Replace final file with temp file atomically when possible.
+        .and_then(|_| {
+            self.replace_remote_file(session_id, &format!("{}.part", remote_path), remote_path)
+                .map_err(|e| anyhow::anyhow!("Failed to finalize uploaded file '{}': {}", remote_path, e))
+        });
+        self.record_transfer(session_id, remote_path, TransferDirection::Upload, started_at, transferred_bytes, &result);
+        self.track_interrupted(
+            session_id,
+            TransferDirection::Upload,
+            (local_path, remote_path),
+            (transferred_bytes, total_bytes),
+            &result,
+        );
+        result
+    }
+
+    // 在两个会话之间直接流式复制文件（A 读 → B 写），数据不落本地磁盘。
+    // 同样先写 .part 再经 replace_remote_file 替换目标；transfer_id 可用于 cancel
+    pub fn sftp_remote_to_remote<F>(
+        &self,
+        src_session: &str,
+        src_path: &str,
+        dst_session: &str,
+        dst_path: &str,
+        transfer_id: &str,
+        mut on_progress: F,
+    ) -> anyhow::Result<u64>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        let started_at = Instant::now();
+        let mut transferred_bytes = 0u64;
+        let mut on_progress = |done: u64, total: u64| {
+            transferred_bytes = done;
+            on_progress(done, total);
+        };
+        let stop = self.register_cancel(transfer_id);
+        // 同一会话不能嵌套加锁，直接在同一个 SFTP 通道上完成；
+        // 不同会话按 id 顺序加锁，否则并发的 A->B 与 B->A 复制会互相等待对方的锁
+        let result = if src_session == dst_session {
+            self.with_sftp_once(src_session, |sftp| {
+                copy_between_sftp(sftp, src_path, sftp, dst_path, &stop, &mut on_progress)
+            })
+        } else if src_session < dst_session {
+            self.with_sftp_once(src_session, |src| {
+                self.with_sftp_once(dst_session, |dst| {
+                    copy_between_sftp(src, src_path, dst, dst_path, &stop, &mut on_progress)
                 })
-                .unwrap_or_else(|| "root".to_string())
+            })
         } else {
-            connection.username.trim().to_string()
+            self.with_sftp_once(dst_session, |dst| {
+                self.with_sftp_once(src_session, |src| {
+                    copy_between_sftp(src, src_path, dst, dst_path, &stop, &mut on_progress)
+                })
+            })
         };
+        self.clear_cancel(transfer_id);
+        // 替换失败时同样删除 .part，不在目标目录留下半截文件
+        let temp_path = format!("{}.part", dst_path);
+        let result = result.and_then(|transferred| {
+            match self.replace_remote_file(dst_session, &temp_path, dst_path) {
+                Ok(()) => Ok(transferred),
+                Err(e) => {
+                    let _ = self.with_sftp_once(dst_session, |sftp| Ok(sftp.unlink(Path::new(&temp_path))?));
+                    Err(anyhow::anyhow!("Failed to finalize copied file '{}': {}", dst_path, e))
+                }
+            }
+        });
+        self.record_transfer(dst_session, dst_path, TransferDirection::Copy, started_at, transferred_bytes, &result);
+        result
+    }
 
-        match &connection.auth_type {
-            AuthType::Password { password } => {
-                sess.userauth_password(&effective_username, password)?;
+    fn record_transfer<T>(
+        &self,
+        session_id: &str,
+        path: &str,
+        direction: TransferDirection,
+        started_at: Instant,
+        bytes: u64,
+        result: &anyhow::Result<T>,
+    ) {
+        let record = TransferRecord {
+            path: path.to_string(),
+            direction,
+            bytes,
+            duration_ms: started_at.elapsed().as_millis().min(u128::from(u64::MAX)) as u64,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            finished_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or(0),
+        };
+        let mut history = self.transfer_history.lock().unwrap();
+        let records = history.entry(session_id.to_string()).or_default();
+        records.push_back(record);
+        while records.len() > TRANSFER_HISTORY_LIMIT {
+            records.pop_front();
+        }
+    }
+
+    // 同一会话、方向和路径只保留一条：失败时替换，成功时移除
+    fn track_interrupted<T>(
+        &self,
+        session_id: &str,
+        direction: TransferDirection,
+        paths: (&str, &str),
+        progress: (u64, u64),
+        result: &anyhow::Result<T>,
+    ) {
+        let (local_path, remote_path) = paths;
+        let (offset, total) = progress;
+        let mut interrupted = self.interrupted_transfers.lock().unwrap();
+        interrupted.retain(|entry| {
+            !(entry.session_id == session_id
+                && entry.direction == direction
+                && entry.local_path == local_path
+                && entry.remote_path == remote_path)
+        });
+        let Err(error) = result else {
+            return;
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        interrupted.push_back(InterruptedTransfer {
+            id: format!("resume-{}", now.as_nanos()),
+            session_id: session_id.to_string(),
+            direction,
+            local_path: local_path.to_string(),
+            remote_path: remote_path.to_string(),
+            total,
+            offset,
+            error: error.to_string(),
+            interrupted_at: now.as_millis() as u64,
+        });
+        while interrupted.len() > TRANSFER_HISTORY_LIMIT {
+            interrupted.pop_front();
+        }
+    }
+
+    // 续传起点按当前文件实际大小重新计算；会话未连接时保留记录时的进度
+    pub fn interrupted_transfers(&self, session_id: &str) -> Vec<InterruptedTransfer> {
+        let mut entries: Vec<InterruptedTransfer> = self
+            .interrupted_transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.session_id == session_id)
+            .cloned()
+            .collect();
+        let connected = self.sessions.lock().unwrap().contains_key(session_id);
+        for entry in &mut entries {
+            let offset = match entry.direction {
+                TransferDirection::Upload if connected => self
+                    .with_sftp(session_id, |sftp| {
+                        Ok(sftp.stat(Path::new(&format!("{}.part", entry.remote_path)))?.size.unwrap_or(0))
+                    })
+                    .ok(),
+                TransferDirection::Download => std::fs::metadata(&entry.local_path).map(|meta| meta.len()).ok(),
+                _ => None,
+            };
+            if let Some(offset) = offset {
+                entry.offset = offset;
             }
-            AuthType::PrivateKey { key_path, key_content, passphrase } => {
-                let passphrase_str = passphrase.as_deref();
+        }
+        entries
+    }
 
-                if let Some(content) = key_content {
-                    if !content.is_empty() {
-                        if let Err(e) = userauth_pubkey_memory_compat(
-                            &sess,
-                            &effective_username,
-                            content,
-                            passphrase_str,
-                        ) {
-                            return Err(anyhow::anyhow!(
-                                "Private key authentication failed: {}. Please check: 1) Key format (must be valid PEM), 2) Passphrase if key is encrypted, 3) Username is correct",
-                                e
-                            ));
-                        }
-                    } else {
-                        if key_path.is_empty() {
-                            return Err(anyhow::anyhow!("Both key_path and key_content are empty"));
-                        }
-                        sess.userauth_pubkey_file(
-                            &effective_username,
-                            None,
-                            Path::new(key_path),
-                            passphrase_str,
-                        )?;
-                    }
+    pub fn interrupted_transfer(&self, transfer_id: &str) -> anyhow::Result<InterruptedTransfer> {
+        self.interrupted_transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.id == transfer_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Interrupted transfer not found: {}", transfer_id))
+    }
+
+    // 在当前（重连后的）会话上续传；成功后记录被移除，再次失败则更新
+    pub fn resume_transfer<F>(&self, transfer_id: &str, on_progress: F) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        let entry = self.interrupted_transfer(transfer_id)?;
+        if !self.sessions.lock().unwrap().contains_key(&entry.session_id) {
+            return Err(anyhow::anyhow!("Session {} is not connected", entry.session_id));
+        }
+        match entry.direction {
+            TransferDirection::Download => {
+                self.sftp_download_file(&entry.session_id, &entry.remote_path, &entry.local_path, on_progress)?
+            }
+            _ => self.sftp_upload_file(&entry.session_id, &entry.local_path, &entry.remote_path, on_progress)?,
+        }
+        Ok(())
+    }
+
+    // 同一 id 重复登记时以新的为准
+    pub fn track_transfer(
+        &self,
+        transfer_id: &str,
+        session_id: &str,
+        direction: TransferDirection,
+        path: &str,
+    ) -> TransferProgressHandle {
+        let entry = Arc::new(ActiveTransferEntry {
+            session_id: session_id.to_string(),
+            direction,
+            path: path.to_string(),
+            started_at: Instant::now(),
+            initial: std::sync::OnceLock::new(),
+            transferred: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+        });
+        self.active_transfers
+            .lock()
+            .unwrap()
+            .insert(transfer_id.to_string(), entry.clone());
+        TransferProgressHandle {
+            id: transfer_id.to_string(),
+            entry,
+            active: self.active_transfers.clone(),
+        }
+    }
+
+    pub fn active_transfers(&self) -> Vec<ActiveTransfer> {
+        let mut transfers: Vec<ActiveTransfer> = self
+            .active_transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| {
+                let transferred = entry.transferred.load(Ordering::Relaxed);
+                let elapsed = entry.started_at.elapsed();
+                let moved = transferred.saturating_sub(entry.initial.get().copied().unwrap_or(transferred));
+                let secs = elapsed.as_secs_f64();
+                ActiveTransfer {
+                    id: id.clone(),
+                    session_id: entry.session_id.clone(),
+                    direction: entry.direction,
+                    path: entry.path.clone(),
+                    transferred,
+                    total: entry.total.load(Ordering::Relaxed),
+                    rate_bps: if secs > 0.0 { (moved as f64 / secs) as u64 } else { 0 },
+                    elapsed_ms: elapsed.as_millis().min(u128::from(u64::MAX)) as u64,
+                }
+            })
+            .collect();
+        transfers.sort_by_key(|transfer| std::cmp::Reverse(transfer.elapsed_ms));
+        transfers
+    }
+
+    pub fn transfer_history(&self, session_id: &str) -> Vec<TransferRecord> {
+        self.transfer_history
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|records| records.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // 下载远程文件到临时目录供本地编辑，记录下载后的修改时间用于判断是否需要回传
+    pub fn sftp_edit_begin(&self, session_id: &str, remote_path: &str) -> anyhow::Result<SftpEditInfo> {
+        let file_name = Path::new(remote_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Remote path has no file name: {}", remote_path))?;
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let edit_id = format!("edit-{}", nanos);
+        // 0700 的独立目录，副本不对其他本地用户可见
+        let dir = crate::create_private_temp_dir("noterm-edit")?;
+        let local_path = dir.join(file_name);
+
+        if let Err(e) = self.sftp_download_file(session_id, remote_path, &local_path.to_string_lossy(), |_, _| {}) {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+        let modified = std::fs::metadata(&local_path).and_then(|meta| meta.modified()).ok();
+
+        self.edits.lock().unwrap().insert(
+            edit_id.clone(),
+            EditSession {
+                session_id: session_id.to_string(),
+                remote_path: remote_path.to_string(),
+                local_path: local_path.clone(),
+                modified,
+            },
+        );
+        Ok(SftpEditInfo {
+            edit_id,
+            local_path: local_path.display().to_string(),
+            remote_path: remote_path.to_string(),
+        })
+    }
+
+    // 本地副本有改动时上传回远端；返回是否上传
+    pub fn sftp_edit_commit(&self, edit_id: &str) -> anyhow::Result<bool> {
+        let edit = self
+            .edits
+            .lock()
+            .unwrap()
+            .get(edit_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Edit not found"))?;
+        let modified = std::fs::metadata(&edit.local_path)
+            .and_then(|meta| meta.modified())
+            .map_err(|e| anyhow::anyhow!("Failed to stat '{}': {}", edit.local_path.display(), e))?;
+        if edit.modified == Some(modified) {
+            return Ok(false);
+        }
+
+        self.sftp_upload_file(
+            &edit.session_id,
+            &edit.local_path.to_string_lossy(),
+            &edit.remote_path,
+            |_, _| {},
+        )?;
+        if let Some(current) = self.edits.lock().unwrap().get_mut(edit_id) {
+            current.modified = Some(modified);
+        }
+        Ok(true)
+    }
+
+    pub fn sftp_edit_close(&self, edit_id: &str) -> anyhow::Result<()> {
+        let edit = self
+            .edits
+            .lock()
+            .unwrap()
+            .remove(edit_id)
+            .ok_or_else(|| anyhow::anyhow!("Edit not found"))?;
+        if let Some(dir) = edit.local_path.parent() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+        Ok(())
+    }
+
+    // 轮询本地副本，保存后自动回传并发出 sftp-edit-synced；编辑关闭或连接断开后停止
+    pub fn watch_edit(&self, edit_id: &str) {
+        let manager = self.clone();
+        let edit_id = edit_id.to_string();
+        thread_stats::spawn(ThreadKind::Other, move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let edit = match manager.edits.lock().unwrap().get(&edit_id) {
+                Some(edit) => edit.clone(),
+                None => break,
+            };
+            if !manager.connections.lock().unwrap().contains_key(&edit.session_id) {
+                break;
+            }
+            let modified = std::fs::metadata(&edit.local_path).and_then(|meta| meta.modified()).ok();
+            if modified.is_none() || modified == edit.modified {
+                continue;
+            }
+            let result = manager.sftp_edit_commit(&edit_id);
+            if let Err(e) = &result {
+                tracing::warn!(edit_id = %edit_id, error = %e, "edit upload failed");
+                // 失败后记下当前时间戳，避免每秒重试；下次保存会再次触发
+                if let Some(current) = manager.edits.lock().unwrap().get_mut(&edit_id) {
+                    current.modified = modified;
+                }
+            }
+            manager.emit(
+                "sftp-edit-synced",
+                SftpEditSynced {
+                    edit_id: edit_id.clone(),
+                    session_id: edit.session_id.clone(),
+                    remote_path: edit.remote_path.clone(),
+                    error: result.err().map(|e| e.to_string()),
+                },
+            );
+        });
+    }
+
+    // 递归上传本地目录。transfer_id 对应的清单记录已完成的相对路径，
+    // 中断或 cancel(transfer_id) 后以同一 transfer_id 再次调用时，跳过清单中远端大小一致的文件
+    pub fn sftp_upload_dir<F>(
+        &self,
+        session_id: &str,
+        local_dir: &str,
+        remote_dir: &str,
+        transfer_id: &str,
+        options: &DirTransferOptions,
+        mut on_progress: F,
+    ) -> anyhow::Result<SftpUploadDirSummary>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        let local_root = Path::new(local_dir);
+        if !local_root.is_dir() {
+            return Err(anyhow::anyhow!("Local directory not found: {}", local_dir));
+        }
+        let remote_dir = remote_dir.trim();
+        if remote_dir.is_empty() {
+            return Err(anyhow::anyhow!("Remote directory is empty"));
+        }
+
+        let verify = options.verify;
+        let exclude = ExcludeFilter::new(&options.exclude);
+        let mut local_files = SyncFiles::new();
+        let mut local_dirs = Vec::new();
+        let mut excluded = 0;
+        walk_local_dir(local_root, "", &exclude, &mut local_files, &mut local_dirs, &mut excluded)?;
+        local_dirs.sort();
+        let mut rel_paths: Vec<&String> = local_files.keys().collect();
+        rel_paths.sort();
+
+        let completed = self
+            .upload_manifests
+            .lock()
+            .unwrap()
+            .get(transfer_id)
+            .filter(|manifest| {
+                manifest.session_id == session_id
+                    && manifest.local_dir == local_dir
+                    && manifest.remote_dir == remote_dir
+            })
+            .map(|manifest| manifest.completed.clone())
+            .unwrap_or_default();
+        let mut summary = SftpUploadDirSummary {
+            total: rel_paths.len() as u32,
+            excluded,
+            ..Default::default()
+        };
+
+        // 清单中的文件需要远端大小仍然一致才跳过
+        let skip: HashSet<&String> = self.with_sftp(session_id, |sftp| {
+            if sftp.stat(Path::new(remote_dir)).is_err() {
+                sftp.mkdir(Path::new(remote_dir), 0o755)
+                    .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", remote_dir, e))?;
+            }
+            for dir in &local_dirs {
+                let path = remote_join(remote_dir, dir);
+                if sftp.stat(Path::new(&path)).is_err() {
+                    sftp.mkdir(Path::new(&path), 0o755)
+                        .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", path, e))?;
+                }
+            }
+            Ok(rel_paths
+                .iter()
+                .filter(|rel| completed.contains(**rel))
+                .filter(|rel| {
+                    let remote_size = sftp
+                        .stat(Path::new(&remote_join(remote_dir, rel)))
+                        .ok()
+                        .and_then(|stat| stat.size);
+                    remote_size == Some(local_files[**rel].0)
+                })
+                .copied()
+                .collect())
+        })?;
+
+        let total_bytes: u64 = local_files.values().map(|(size, _)| size).sum();
+        let mut done_bytes: u64 = skip.iter().map(|rel| local_files[*rel].0).sum();
+        summary.skipped = skip.len() as u32;
+        self.upload_manifests
+            .lock()
+            .unwrap()
+            .insert(
+                transfer_id.to_string(),
+                UploadManifest {
+                    session_id: session_id.to_string(),
+                    local_dir: local_dir.to_string(),
+                    remote_dir: remote_dir.to_string(),
+                    completed: skip.iter().map(|rel| rel.to_string()).collect(),
+                },
+            );
+
+        let stop = self.register_cancel(transfer_id);
+        on_progress(done_bytes, total_bytes);
+        let mut result = Ok(());
+        for rel in rel_paths.iter().filter(|rel| !skip.contains(**rel)) {
+            if stop.load(Ordering::Relaxed) {
+                summary.cancelled = true;
+                break;
+            }
+            let local_path = local_root.join(rel);
+            let remote_path = remote_join(remote_dir, rel);
+            let base = done_bytes;
+            let uploaded = self.upload_file_with_stop(
+                session_id,
+                &local_path.to_string_lossy(),
+                &remote_path,
+                Some(&stop),
+                |transferred, _| on_progress(base + transferred, total_bytes),
+            );
+            if let Err(e) = uploaded {
+                if stop.load(Ordering::Relaxed) {
+                    summary.cancelled = true;
                 } else {
-                    if key_path.is_empty() {
-                        return Err(anyhow::anyhow!("key_path is empty"));
+                    result = Err(e);
+                }
+                break;
+            }
+            done_bytes += local_files[*rel].0;
+            summary.uploaded += 1;
+            if verify.size || verify.hash {
+                match self.sftp_verify_file(session_id, &local_path.to_string_lossy(), &remote_path, verify) {
+                    Ok(None) => summary.verified += 1,
+                    Ok(Some(reason)) => {
+                        summary.mismatched.push(SftpVerifyMismatch {
+                            path: rel.to_string(),
+                            reason,
+                        });
+                        continue;
+                    }
+                    Err(e) => {
+                        summary.mismatched.push(SftpVerifyMismatch {
+                            path: rel.to_string(),
+                            reason: format!("Verification failed: {}", e),
+                        });
+                        continue;
                     }
-                    sess.userauth_pubkey_file(
-                        &effective_username,
-                        None,
-                        Path::new(key_path),
-                        passphrase_str,
-                    )?;
                 }
             }
+            if let Some(manifest) = self.upload_manifests.lock().unwrap().get_mut(transfer_id) {
+                manifest.completed.insert(rel.to_string());
+            }
         }
-
-        if !sess.authenticated() {
-            return Err(anyhow::anyhow!("Authentication failed"));
+        self.clear_cancel(transfer_id);
+
+        if !summary.mismatched.is_empty() {
+            tracing::warn!(
+                transfer_id = %transfer_id,
+                verified = summary.verified,
+                mismatched = summary.mismatched.len(),
+                "upload verification found mismatches"
+            );
         }
-
-        Ok(sess)
+        // 全部完成后清单不再需要；失败、取消或有校验不一致时保留以便续传
+        if result.is_ok() && !summary.cancelled && summary.mismatched.is_empty() {
+            self.upload_manifests.lock().unwrap().remove(transfer_id);
+        }
+        result.map(|_| summary)
     }
 
-    fn spawn_keepalive_for_session(
+    // 递归下载远端目录。文件之间检查 cancel(transfer_id)，已下载的文件保留；
+    // 单个文件沿用 sftp_download_file 的续传逻辑（本地较小时从末尾继续）
+    pub fn sftp_download_dir<F>(
         &self,
-        session_id: String,
-        session: Arc<Mutex<Session>>,
-    ) {
-        let sessions = self.sessions.clone();
-        std::thread::spawn(move || {
-            loop {
-                {
-                    let sessions_guard = sessions.lock().unwrap();
-                    if !sessions_guard.contains_key(&session_id) {
-                        break;
-                    }
+        session_id: &str,
+        remote_dir: &str,
+        local_dir: &str,
+        transfer_id: &str,
+        symlink_policy: SymlinkPolicy,
+        mut on_progress: F,
+    ) -> anyhow::Result<SftpDownloadDirSummary>
+    where
+        F: FnMut(u64, u64) + Send,
+    {
+        let remote_dir = remote_dir.trim();
+        if remote_dir.is_empty() {
+            return Err(anyhow::anyhow!("Remote directory is empty"));
+        }
+        let plan = self.with_sftp(session_id, |sftp| {
+            let stat = sftp
+                .stat(Path::new(remote_dir))
+                .map_err(|e| anyhow::anyhow!("Failed to open '{}': {}", remote_dir, e))?;
+            if !stat.is_dir() {
+                return Err(anyhow::anyhow!("Remote path is not a directory: {}", remote_dir));
+            }
+            let real = sftp
+                .realpath(Path::new(remote_dir))
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_else(|_| remote_dir.to_string());
+            let mut visited = HashSet::from([real.clone()]);
+            let mut plan = DownloadPlan::default();
+            walk_remote_for_download(sftp, remote_dir, "", &real, symlink_policy, &mut visited, &mut plan)?;
+            Ok(plan)
+        })?;
+
+        let local_root = Path::new(local_dir);
+        std::fs::create_dir_all(local_root)
+            .map_err(|e| anyhow::anyhow!("Failed to create local directory '{}': {}", local_dir, e))?;
+        for dir in &plan.dirs {
+            let path = local_root.join(dir);
+            std::fs::create_dir_all(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to create local directory '{}': {}", path.display(), e))?;
+        }
+
+        let mut summary = SftpDownloadDirSummary {
+            total: plan.files.len() as u32,
+            symlinks_skipped: plan.symlinks_skipped,
+            loops_skipped: plan.loops_skipped,
+            names_skipped: plan.names_skipped,
+            ..Default::default()
+        };
+        let canonical_root = std::fs::canonicalize(local_root)
+            .map_err(|e| anyhow::anyhow!("Failed to resolve local directory '{}': {}", local_dir, e))?;
+        for (rel, target) in &plan.links {
+            let path = local_root.join(rel);
+            if !local_parent_inside(&canonical_root, &path) {
+                tracing::debug!(path = %path.display(), "symlink parent escapes download root");
+                summary.symlinks_skipped += 1;
+                continue;
+            }
+            match create_local_symlink(target, &path) {
+                Ok(()) => summary.symlinks_created += 1,
+                Err(e) => {
+                    tracing::debug!(path = %path.display(), error = %e, "failed to recreate symlink");
+                    summary.symlinks_skipped += 1;
                 }
-                let wait = {
-                    let sess = session.lock().unwrap();
-                    match sess.keepalive_send() {
-                        Ok(wait) => wait,
-                        Err(err) => {
-                            if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN) {
-                                1
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                };
-                let sleep_secs = if wait == 0 { 5 } else { wait.min(60) };
-                std::thread::sleep(Duration::from_secs(sleep_secs as u64));
             }
-        });
-    }
+        }
 
-    fn spawn_keepalive_for_forward(
-        &self,
-        session: Arc<Mutex<Session>>,
-        stop: Arc<AtomicBool>,
-    ) {
-        std::thread::spawn(move || {
-            loop {
-                if stop.load(Ordering::Relaxed) {
+        let total_bytes: u64 = plan.files.iter().map(|(_, size)| size).sum();
+        let mut done_bytes = 0u64;
+        let stop = self.register_cancel(transfer_id);
+        on_progress(done_bytes, total_bytes);
+        let mut result = Ok(());
+        for (rel, size) in &plan.files {
+            if stop.load(Ordering::Relaxed) {
+                summary.cancelled = true;
+                break;
+            }
+            let local_path = local_root.join(rel);
+            // 本地已有的链接（旧版本创建或用户放置）不能被写穿到下载目录之外
+            if !local_parent_inside(&canonical_root, &local_path) {
+                result = Err(anyhow::anyhow!("Local path escapes download directory: {}", local_path.display()));
+                break;
+            }
+            if local_path.symlink_metadata().is_ok_and(|meta| meta.file_type().is_symlink()) {
+                if let Err(e) = std::fs::remove_file(&local_path) {
+                    result = Err(anyhow::anyhow!("Failed to replace symlink '{}': {}", local_path.display(), e));
                     break;
                 }
-                let wait = {
-                    let sess = session.lock().unwrap();
-                    match sess.keepalive_send() {
-                        Ok(wait) => wait,
-                        Err(err) => {
-                            if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN) {
-                                1
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                };
-                let sleep_secs = if wait == 0 { 5 } else { wait.min(60) };
-                std::thread::sleep(Duration::from_secs(sleep_secs as u64));
             }
-        });
+            let base = done_bytes;
+            let downloaded = self.sftp_download_file(
+                session_id,
+                &remote_join(remote_dir, rel),
+                &local_path.to_string_lossy(),
+                |transferred, _| on_progress(base + transferred, total_bytes),
+            );
+            if let Err(e) = downloaded {
+                result = Err(e);
+                break;
+            }
+            done_bytes += size;
+            summary.downloaded += 1;
+        }
+        self.clear_cancel(transfer_id);
+        tracing::info!(
+            session_id = %session_id,
+            downloaded = summary.downloaded,
+            total = summary.total,
+            symlinks_created = summary.symlinks_created,
+            symlinks_skipped = summary.symlinks_skipped,
+            loops_skipped = summary.loops_skipped,
+            names_skipped = summary.names_skipped,
+            cancelled = summary.cancelled,
+            "sftp directory download finished"
+        );
+        result.map(|_| summary)
     }
 
-    pub fn connect(&self, connection: &SshConnection) -> anyhow::Result<String> {
-        let sess = self.create_authenticated_session(connection)?;
-
-        let session_id = connection.id.clone();
-        let session_arc = Arc::new(Mutex::new(sess));
-
-        // 存储连接信息（用于后续创建 SFTP 会话）
-        let mut connections = self.connections.lock().unwrap();
-        connections.insert(session_id.clone(), connection.clone());
-        drop(connections);
+    // 启发式诊断"小传输正常、大传输卡住"：在独立会话上依次下载/上传递增大小的数据
+    // （head -c /dev/zero 与 cat > /dev/null），某个大小开始停滞或吞吐骤降时给出提示，
+    // 并读取会话所用 TCP 连接的 MSS 等参数。某一方向停滞后不再尝试更大的传输
+    pub fn path_diagnostics(&self, session_id: &str) -> anyhow::Result<PathDiagnostics> {
+        let connection = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let live = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let nested = self.nested_outer.lock().unwrap().contains_key(&connection.id);
+        let mut report = PathDiagnostics::default();
 
-        // 存储 shell 会话
-        let mut sessions = self.sessions.lock().unwrap();
-        sessions.insert(session_id.clone(), session_arc.clone());
-        drop(sessions);
+        let host = connection.host.trim();
+        if nested || host.starts_with("unix:") {
+            report
+                .observations
+                .push("Socket options are not available for jump-host or Unix socket connections".to_string());
+        } else {
+            let socket = with_session_socket(&live.lock().unwrap(), session_socket_info);
+            match socket {
+                Ok(socket) => report.socket = Some(socket),
+                Err(e) => report.observations.push(format!("Could not read socket options: {}", e)),
+            }
+        }
 
-        self.spawn_keepalive_for_session(session_id.clone(), session_arc);
+        let sess = self.create_authenticated_session(&connection)?;
+        sess.set_timeout(PATH_STALL_TIMEOUT.as_millis() as u32);
+        let started_at = Instant::now();
+        for direction in [TransferDirection::Download, TransferDirection::Upload] {
+            for bytes in PATH_SAMPLE_SIZES {
+                if started_at.elapsed() >= PATH_DIAGNOSTICS_BUDGET {
+                    report.observations.push(format!(
+                        "Stopped after {} s; remaining sizes were not measured",
+                        PATH_DIAGNOSTICS_BUDGET.as_secs()
+                    ));
+                    break;
+                }
+                let sample = path_sample(&sess, direction, bytes, &mut report.channel_window);
+                let stalled = !sample.completed;
+                report.samples.push(sample);
+                if stalled {
+                    break;
+                }
+            }
+            if started_at.elapsed() >= PATH_DIAGNOSTICS_BUDGET {
+                break;
+            }
+        }
+        let _ = sess.disconnect(None, "Path diagnostics finished", None);
+
+        report.observations.extend(path_observations(&report));
+        tracing::info!(
+            session_id = %session_id,
+            samples = report.samples.len(),
+            mss = ?report.socket.as_ref().and_then(|socket| socket.mss),
+            observations = report.observations.len(),
+            "path diagnostics finished"
+        );
+        Ok(report)
+    }
 
-        Ok(session_id)
+    // 比较本地文件与远端文件；一致时返回 None，否则返回不一致的原因
+    pub fn sftp_verify_file(
+        &self,
+        session_id: &str,
+        local_path: &str,
+        remote_path: &str,
+        verify: TransferVerify,
+    ) -> anyhow::Result<Option<String>> {
+        if verify.size {
+            let local_size = std::fs::metadata(local_path)
+                .map_err(|e| anyhow::anyhow!("Failed to stat local file '{}': {}", local_path, e))?
+                .len();
+            let remote_size = self.with_sftp(session_id, |sftp| {
+                sftp.stat(Path::new(remote_path))
+                    .map_err(|e| anyhow::anyhow!("Failed to stat remote file '{}': {}", remote_path, e))
+                    .map(|stat| stat.size)
+            })?;
+            if remote_size != Some(local_size) {
+                return Ok(Some(format!(
+                    "Size mismatch: local {} bytes, remote {} bytes",
+                    local_size,
+                    remote_size.map(|size| size.to_string()).unwrap_or_else(|| "unknown".to_string())
+                )));
+            }
+        }
+        if verify.hash {
+            let local_hash = local_sha256(Path::new(local_path))?;
+            let quoted = shell_quote(remote_path);
+            let command = format!(
+                "sha256sum -- {0} 2>/dev/null || shasum -a 256 -- {0} 2>/dev/null",
+                quoted
+            );
+            let output = self.execute_raw(session_id, &command)?;
+            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+            let remote_hash = stdout
+                .split_whitespace()
+                .next()
+                .filter(|hash| output.exit_code == 0 && hash.len() == 64)
+                .map(str::to_ascii_lowercase)
+                .ok_or_else(|| anyhow::anyhow!("Remote sha256sum/shasum unavailable"))?;
+            if remote_hash != local_hash {
+                return Ok(Some(format!(
+                    "SHA-256 mismatch: local {}, remote {}",
+                    local_hash, remote_hash
+                )));
+            }
+        }
+        Ok(None)
     }
 
-    pub fn open_shell(&self, session_id: &str, app_handle: tauri::AppHandle) -> anyhow::Result<()> {
-        let sessions = self.sessions.lock().unwrap();
-        let session = sessions
-            .get(session_id)
-            .ok_or_else(|| anyhow::anyhow!("Session not found"))?
-            .clone();
+    // 单向同步本地目录到远端：大小或修改时间不同的文件才上传，上传后把远端 mtime 设为本地值，
+    // delete_extraneous 时删除本地不存在的远端文件和目录。匹配 exclude 的条目两端都不处理，
+    // 远端被排除的文件也不会被删除
+    pub fn sftp_sync(
+        &self,
+        session_id: &str,
+        local_dir: &str,
+        remote_dir: &str,
+        delete_extraneous: bool,
+        exclude: &[String],
+    ) -> anyhow::Result<SftpSyncSummary> {
+        let local_root = Path::new(local_dir);
+        if !local_root.is_dir() {
+            return Err(anyhow::anyhow!("Local directory not found: {}", local_dir));
+        }
+        let remote_dir = remote_dir.trim();
+        if remote_dir.is_empty() {
+            return Err(anyhow::anyhow!("Remote directory is empty"));
+        }
 
-        let sess = session.lock().unwrap();
-        let mut channel = sess.channel_session()?;
-        channel.request_pty("xterm-256color", None, Some((80, 24, 0, 0)))?;
-        channel.shell()?;
-        
-        // Set channel to non-blocking mode
-        sess.set_blocking(false);
-        drop(sess);
+        let exclude = ExcludeFilter::new(exclude);
+        let mut local_files = SyncFiles::new();
+        let mut local_dirs = Vec::new();
+        let mut excluded = 0;
+        walk_local_dir(local_root, "", &exclude, &mut local_files, &mut local_dirs, &mut excluded)?;
+
+        let (remote_files, remote_dirs) = self.with_sftp(session_id, |sftp| {
+            let mut files = SyncFiles::new();
+            let mut dirs = Vec::new();
+            match sftp.stat(Path::new(remote_dir)) {
+                Ok(stat) if stat.is_dir() => {
+                    walk_remote_dir(sftp, remote_dir, "", &exclude, &mut files, &mut dirs, &mut 0)?
+                }
+                Ok(_) => return Err(anyhow::anyhow!("Remote path is not a directory: {}", remote_dir)),
+                Err(_) => sftp
+                    .mkdir(Path::new(remote_dir), 0o755)
+                    .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", remote_dir, e))?,
+            }
+            Ok((files, dirs))
+        })?;
 
-        let channel_arc = Arc::new(Mutex::new(channel));
-        let mut channels = self.channels.lock().unwrap();
-        channels.insert(session_id.to_string(), channel_arc.clone());
-        drop(channels);
+        let mut summary = SftpSyncSummary {
+            excluded,
+            ..Default::default()
+        };
 
-        // Start reading output in background
-        let session_id_clone = session_id.to_string();
-        let channel_clone = channel_arc.clone();
-        let app_handle = app_handle.clone();
-        let sessions_map = self.sessions.clone();
-        let channels_map = self.channels.clone();
-        let sftp_sessions_map = self.sftp_sessions.clone();
-        let connections_map = self.connections.clone();
-        std::thread::spawn(move || {
-            let mut buffer = [0u8; 8192];
-            let mut disconnected_reason: Option<String> = None;
-            let mut zero_read_streak: u8 = 0;
-            loop {
-                let mut channel_lock = match channel_clone.lock() {
-                    Ok(ch) => ch,
-                    Err(_) => break,
-                };
-                
-                match channel_lock.read(&mut buffer) {
-                    Ok(n) if n > 0 => {
-                        zero_read_streak = 0;
-                        let output = String::from_utf8_lossy(&buffer[..n]).to_string();
-                        let _ = app_handle.emit("terminal-output", TerminalOutput {
-                            session_id: session_id_clone.clone(),
-                            data: output,
-                        });
-                    }
-                    Ok(_) => {
-                        // In non-blocking mode, occasional zero-byte reads can happen transiently.
-                        // Only treat it as a disconnect when EOF is confirmed or it repeats.
-                        if channel_lock.eof() {
-                            disconnected_reason = Some("eof".to_string());
-                            break;
-                        }
-                        zero_read_streak = zero_read_streak.saturating_add(1);
-                        if zero_read_streak >= 3 {
-                            disconnected_reason = Some("eof-like-zero-read".to_string());
-                            break;
-                        }
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        zero_read_streak = 0;
-                        // No data available in non-blocking mode, continue
-                    }
-                    Err(e) => {
-                        disconnected_reason = Some(format!("error: {}", e));
-                        break;
+        // 目录按路径排序，保证父目录先于子目录创建
+        local_dirs.sort();
+        let missing_dirs: Vec<&String> = local_dirs
+            .iter()
+            .filter(|dir| !remote_dirs.contains(*dir))
+            .collect();
+        if !missing_dirs.is_empty() {
+            self.with_sftp(session_id, |sftp| {
+                for dir in &missing_dirs {
+                    let path = remote_join(remote_dir, dir);
+                    if sftp.stat(Path::new(&path)).is_err() {
+                        sftp.mkdir(Path::new(&path), 0o755)
+                            .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", path, e))?;
                     }
                 }
-                drop(channel_lock);
-                std::thread::sleep(std::time::Duration::from_millis(10));
+                Ok(())
+            })?;
+        }
+
+        let mut rel_paths: Vec<&String> = local_files.keys().collect();
+        rel_paths.sort();
+        for rel in rel_paths {
+            let (size, mtime) = local_files[rel];
+            if remote_files.get(rel) == Some(&(size, mtime)) {
+                summary.skipped += 1;
+                continue;
             }
-            if let Some(reason) = disconnected_reason {
-                if let Ok(mut channels) = channels_map.lock() {
-                    channels.remove(&session_id_clone);
-                }
-                if let Ok(mut sftp_sessions) = sftp_sessions_map.lock() {
-                    sftp_sessions.remove(&session_id_clone);
-                }
-                if let Ok(mut sessions) = sessions_map.lock() {
-                    sessions.remove(&session_id_clone);
+            let local_path = local_root.join(rel);
+            let remote_path = remote_join(remote_dir, rel);
+            self.sftp_upload_file(session_id, &local_path.to_string_lossy(), &remote_path, |_, _| {})?;
+            self.with_sftp(session_id, |sftp| {
+                let stat = FileStat {
+                    size: None,
+                    uid: None,
+                    gid: None,
+                    perm: None,
+                    atime: Some(mtime),
+                    mtime: Some(mtime),
+                };
+                sftp.setstat(Path::new(&remote_path), stat)
+                    .map_err(|e| anyhow::anyhow!("Failed to set mtime on '{}': {}", remote_path, e))
+            })?;
+            summary.uploaded += 1;
+        }
+
+        if delete_extraneous {
+            let mut extra_files: Vec<&String> = remote_files
+                .keys()
+                .filter(|rel| !local_files.contains_key(*rel))
+                .collect();
+            extra_files.sort();
+            // 目录逆序删除，子目录先于父目录
+            let mut extra_dirs: Vec<&String> = remote_dirs
+                .iter()
+                .filter(|dir| !local_dirs.contains(*dir))
+                .collect();
+            extra_dirs.sort();
+            extra_dirs.reverse();
+
+            summary.deleted += self.with_sftp_once(session_id, |sftp| {
+                let mut deleted = 0;
+                for rel in &extra_files {
+                    let path = remote_join(remote_dir, rel);
+                    sftp.unlink(Path::new(&path))
+                        .map_err(|e| anyhow::anyhow!("Failed to delete file '{}': {}", path, e))?;
+                    deleted += 1;
                 }
-                if let Ok(mut connections) = connections_map.lock() {
-                    connections.remove(&session_id_clone);
+                for rel in &extra_dirs {
+                    let path = remote_join(remote_dir, rel);
+                    sftp.rmdir(Path::new(&path))
+                        .map_err(|e| anyhow::anyhow!("Failed to remove directory '{}': {}", path, e))?;
+                    deleted += 1;
                 }
-                let _ = app_handle.emit("terminal-disconnected", TerminalDisconnected {
-                    session_id: session_id_clone.clone(),
-                    reason,
-                });
+                Ok(deleted)
+            })?;
+        }
+
+        tracing::info!(
+            session_id = %session_id,
+            uploaded = summary.uploaded,
+            skipped = summary.skipped,
+            deleted = summary.deleted,
+            excluded = summary.excluded,
+            "sftp sync finished"
+        );
+        Ok(summary)
+    }
+
+    pub fn start_forward(&self, config: ForwardConfig) -> anyhow::Result<()> {
+        let _span = tracing::debug_span!(
+            "start_forward",
+            id = %config.id,
+            kind = ?config.kind,
+            host = %config.connection.host,
+        )
+        .entered();
+        {
+            let forwards = self.forwards.lock().unwrap();
+            if forwards.contains_key(&config.id) {
+                return Err(anyhow::anyhow!("Forward already running"));
             }
-        });
+        }
+        if config.one_shot && !matches!(config.kind, ForwardKind::Local) {
+            return Err(anyhow::anyhow!("One-shot is only supported for local forwards"));
+        }
+
+        let session = self.create_authenticated_session(&config.connection)?;
+        // 所有连接共用这个会话，非阻塞模式下每个连接由一个线程双向轮询（pump_nonblocking）
+        session.set_blocking(false);
+        let session = Arc::new(Mutex::new(session));
+        let stop = Arc::new(AtomicBool::new(false));
+        self.register_keepalive(config.id.clone(), session.clone(), Some(stop.clone()));
+        self.forward_gates
+            .lock()
+            .unwrap()
+            .insert(config.id.clone(), ForwardGate::default());
+
+        let started = self.start_forward_listener(&config, session.clone(), stop.clone());
+        if let Err(e) = &started {
+            tracing::warn!(error = %e, "forward setup failed");
+            stop.store(true, Ordering::Relaxed);
+            self.forward_gates.lock().unwrap().remove(&config.id);
+            self.set_forward_status(&config.id, ForwardStatus::Error, Some(e.to_string()));
+        }
+        started?;
+        tracing::info!(
+            bind_host = ?config.local_bind_host.as_ref().or(config.remote_bind_host.as_ref()),
+            bind_port = ?config.local_bind_port.or(config.remote_bind_port),
+            target_host = ?config.target_host,
+            target_port = ?config.target_port,
+            "forward started"
+        );
+
+        let mut forwards = self.forwards.lock().unwrap();
+        // 一次性转发可能在登记前就已结束
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.set_forward_status(&config.id, ForwardStatus::Listening, None);
+        forwards.insert(
+            config.id.clone(),
+            ForwardHandle {
+                stop,
+                session,
+                config,
+            },
+        );
+        Ok(())
+    }
 
+    fn start_forward_listener(
+        &self,
+        config: &ForwardConfig,
+        session: Arc<Mutex<Session>>,
+        stop: Arc<AtomicBool>,
+    ) -> anyhow::Result<()> {
+        let config = config.clone();
+        match config.kind {
+            ForwardKind::Local => {
+                let bind_host = config.local_bind_host.unwrap_or_else(|| "127.0.0.1".to_string());
+                let bind_port = config.local_bind_port.ok_or_else(|| anyhow::anyhow!("Local bind port missing"))?;
+                let target_host = config.target_host.ok_or_else(|| anyhow::anyhow!("Target host missing"))?;
+                let target_port = config.target_port.ok_or_else(|| anyhow::anyhow!("Target port missing"))?;
+                let one_shot = config.one_shot.then(|| config.id.clone());
+                self.start_local_forward(session.clone(), stop.clone(), config.id.clone(), (bind_host, bind_port), (target_host, target_port), one_shot)?;
+            }
+            ForwardKind::Remote => {
+                let bind_host = config.remote_bind_host.unwrap_or_else(|| "0.0.0.0".to_string());
+                let bind_port = config.remote_bind_port.ok_or_else(|| anyhow::anyhow!("Remote bind port missing"))?;
+                let target_host = config.target_host.ok_or_else(|| anyhow::anyhow!("Target host missing"))?;
+                let target_port = config.target_port.ok_or_else(|| anyhow::anyhow!("Target port missing"))?;
+                self.start_remote_forward(session.clone(), stop.clone(), config.id.clone(), (bind_host, bind_port), (target_host, target_port))?;
+            }
+            ForwardKind::Dynamic => {
+                let bind_host = config.local_bind_host.unwrap_or_else(|| "127.0.0.1".to_string());
+                let bind_port = config.local_bind_port.ok_or_else(|| anyhow::anyhow!("Local bind port missing"))?;
+                self.start_dynamic_forward(session.clone(), stop.clone(), config.id.clone(), bind_host, bind_port)?;
+            }
+            #[cfg(unix)]
+            ForwardKind::LocalUnix => {
+                let local_path = config.local_socket_path.ok_or_else(|| anyhow::anyhow!("Local socket path missing"))?;
+                let remote_path = config.remote_socket_path.ok_or_else(|| anyhow::anyhow!("Remote socket path missing"))?;
+                self.start_local_unix_forward(session.clone(), stop.clone(), config.id.clone(), local_path, remote_path)?;
+            }
+            #[cfg(not(unix))]
+            ForwardKind::LocalUnix => {
+                return Err(anyhow::anyhow!("Unix socket forwarding is only supported on Unix platforms"));
+            }
+        }
         Ok(())
     }
 
-    pub fn write_to_shell(&self, session_id: &str, data: &str) -> anyhow::Result<()> {
-        let channels = self.channels.lock().unwrap();
-        let channel = channels
-            .get(session_id)
-            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
-
-        let mut ch = channel.lock().unwrap();
-
-        // Interactive shell channel runs in non-blocking mode.
-        // Treat WouldBlock/EAGAIN as transient and retry briefly, instead of
-        // failing fast and triggering unnecessary frontend reconnects.
-        let mut remaining = data.as_bytes();
-        let deadline = Instant::now() + Duration::from_secs(8);
+    pub fn stop_forward(&self, id: &str) -> anyhow::Result<()> {
+        let handle = {
+            let mut forwards = self.forwards.lock().unwrap();
+            forwards.remove(id)
+        };
 
-        while !remaining.is_empty() {
-            match ch.write(remaining) {
-                Ok(0) => return Err(anyhow::anyhow!("SSH write returned 0 bytes")),
-                Ok(written) => {
-                    remaining = &remaining[written..];
-                }
-                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
-                    if Instant::now() >= deadline {
-                        return Err(anyhow::anyhow!("SSH write timed out (would block)"));
-                    }
-                    std::thread::sleep(Duration::from_millis(6));
-                }
-                Err(err) => return Err(err.into()),
+        if let Some(handle) = handle {
+            handle.stop.store(true, Ordering::Relaxed);
+            self.forward_gates.lock().unwrap().remove(id);
+            self.set_forward_status(id, ForwardStatus::Stopped, None);
+            self.remove_forward_keepalive(id, &handle.session);
+            if let Ok(sess) = handle.session.lock() {
+                sess.set_blocking(true);
+                let _ = sess.disconnect(None, "Forward stopped", None);
             }
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Forward not found"))
         }
+    }
 
-        loop {
-            match ch.flush() {
-                Ok(_) => break,
-                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
-                    if Instant::now() >= deadline {
-                        return Err(anyhow::anyhow!("SSH flush timed out (would block)"));
-                    }
-                    std::thread::sleep(Duration::from_millis(6));
-                }
-                Err(err) => return Err(err.into()),
+    // 一次性转发结束：移除登记并通知前端
+    fn finish_one_shot(&self, id: &str, session: &Arc<Mutex<Session>>, stop: &Arc<AtomicBool>) {
+        stop.store(true, Ordering::Relaxed);
+        {
+            let mut forwards = self.forwards.lock().unwrap();
+            if forwards
+                .get(id)
+                .is_some_and(|handle| Arc::ptr_eq(&handle.stop, stop))
+            {
+                forwards.remove(id);
+                self.forward_gates.lock().unwrap().remove(id);
             }
         }
+        self.remove_forward_keepalive(id, session);
+        if let Ok(sess) = session.lock() {
+            sess.set_blocking(true);
+            let _ = sess.disconnect(None, "Forward stopped", None);
+        }
+        self.set_forward_status(id, ForwardStatus::Stopped, None);
+        tracing::info!(id = %id, "one-shot forward finished");
+        self.emit(
+            "forward-stopped",
+            ForwardStopped {
+                id: id.to_string(),
+                reason: "one_shot".to_string(),
+            },
+        );
+    }
 
+    // 暂停：不再转发新连接，监听和 SSH 会话保持，恢复时无需重新认证。
+    // keep_existing 为 false 时同时断开进行中的连接
+    pub fn pause_forward(&self, id: &str, keep_existing: bool) -> anyhow::Result<()> {
+        if !self.forwards.lock().unwrap().contains_key(id) {
+            return Err(anyhow::anyhow!("Forward not found"));
+        }
+        let gate = self.forward_gate(id);
+        gate.paused.store(true, Ordering::Relaxed);
+        if !keep_existing {
+            gate.close_live();
+        }
+        self.set_forward_status(id, ForwardStatus::Paused, None);
         Ok(())
     }
 
-    pub fn disconnect(&self, session_id: &str) -> anyhow::Result<()> {
-        // Close SFTP session first
-        let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
-        if let Some(sftp_session) = sftp_sessions.remove(session_id) {
-            let sess = sftp_session.lock().unwrap();
-            let _ = sess.disconnect(None, "User disconnected", None);
+    pub fn resume_forward(&self, id: &str) -> anyhow::Result<()> {
+        if !self.forwards.lock().unwrap().contains_key(id) {
+            return Err(anyhow::anyhow!("Forward not found"));
         }
-        drop(sftp_sessions);
+        self.forward_gate(id).paused.store(false, Ordering::Relaxed);
+        self.set_forward_status(id, ForwardStatus::Listening, None);
+        Ok(())
+    }
 
-        // Close shell channel
-        let mut channels = self.channels.lock().unwrap();
-        if let Some(channel) = channels.remove(session_id) {
-            let mut ch = channel.lock().unwrap();
-            let _ = ch.close();
-            let _ = ch.wait_close();
+    // 只影响之后建立的转发连接
+    pub fn set_forward_keepalive(&self, keepalive: Option<ForwardKeepalive>) {
+        *self.forward_keepalive.lock().unwrap() = keepalive;
+    }
+
+    fn apply_forward_keepalive(&self, stream: &TcpStream) {
+        let Some(keepalive) = *self.forward_keepalive.lock().unwrap() else {
+            return;
+        };
+        let params = socket2::TcpKeepalive::new().with_time(Duration::from_secs(keepalive.idle_secs.max(1)));
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        let params = params.with_interval(Duration::from_secs(keepalive.interval_secs.max(1)));
+        if let Err(e) = socket2::SockRef::from(stream).set_tcp_keepalive(&params) {
+            tracing::debug!(error = %e, "failed to set forward keepalive");
         }
-        drop(channels);
+    }
 
-        // Close shell session
-        let mut sessions = self.sessions.lock().unwrap();
-        if let Some(session) = sessions.remove(session_id) {
-            let sess = session.lock().unwrap();
-            let _ = sess.disconnect(None, "User disconnected", None);
+    fn forward_gate(&self, id: &str) -> ForwardGate {
+        self.forward_gates
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // 状态变化时才发出 forward-status 事件
+    fn set_forward_status(&self, id: &str, status: ForwardStatus, error: Option<String>) {
+        let state = ForwardState {
+            id: id.to_string(),
+            status,
+            error,
+        };
+        {
+            let mut states = self.forward_states.lock().unwrap();
+            // 状态相同但错误信息不同（如换了一种失败原因）仍需通知
+            if states.get(id).is_some_and(|current| *current == state) {
+                return;
+            }
+            if status == ForwardStatus::Stopped {
+                states.remove(id);
+            } else {
+                states.insert(id.to_string(), state.clone());
+            }
         }
-        drop(sessions);
+        if status == ForwardStatus::Error {
+            tracing::warn!(id = %id, error = ?state.error, "forward failed");
+        }
+        self.emit("forward-status", state);
+    }
 
-        // Remove connection info
-        let mut connections = self.connections.lock().unwrap();
-        connections.remove(session_id);
+    pub fn forward_status(&self, id: &str) -> anyhow::Result<ForwardState> {
+        self.forward_states
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Forward not found"))
+    }
 
-        Ok(())
+    pub fn list_forwards(&self) -> Vec<String> {
+        let forwards = self.forwards.lock().unwrap();
+        forwards.keys().cloned().collect()
     }
 
-    pub fn execute_command(&self, session_id: &str, command: &str) -> anyhow::Result<String> {
-        let mut last_error: Option<anyhow::Error> = None;
+    // 按目标地址查找转发（主机名不区分大小写；端口为空时匹配任意端口）
+    pub fn find_forwards(&self, target_host: &str, target_port: Option<u16>) -> Vec<ForwardInfo> {
+        let target_host = target_host.trim();
+        let forwards = self.forwards.lock().unwrap();
+        let states = self.forward_states.lock().unwrap();
+        let mut found: Vec<ForwardInfo> = forwards
+            .values()
+            .map(|handle| {
+                let status = states
+                    .get(&handle.config.id)
+                    .map(|state| state.status)
+                    .unwrap_or(ForwardStatus::Listening);
+                handle.config.info(status)
+            })
+            .filter(|info| {
+                info.target_host
+                    .as_deref()
+                    .is_some_and(|host| host.trim().eq_ignore_ascii_case(target_host))
+                    && target_port.is_none_or(|port| info.target_port == Some(port))
+            })
+            .collect();
+        found.sort_by(|a, b| a.id.cmp(&b.id));
+        found
+    }
 
-        // Use a dedicated blocking session (shared with SFTP pool) to avoid
-        // "session would block" conflicts with the interactive shell session.
-        for attempt in 0..2 {
-            let command_session = self.get_or_create_sftp(session_id)?;
+    fn start_local_forward(
+        &self,
+        session: Arc<Mutex<Session>>,
+        stop: Arc<AtomicBool>,
+        id: String,
+        bind: (String, u16),
+        target: (String, u16),
+        one_shot: Option<String>,
+    ) -> anyhow::Result<()> {
+        let (bind_host, bind_port) = bind;
+        let (target_host, target_port) = target;
+        let listener = TcpListener::bind((bind_host.as_str(), bind_port))?;
+        listener.set_nonblocking(true)?;
+        self.add_forward_listener(ForwardListener {
+            id,
+            listener,
+            session,
+            stop,
+            role: ListenerRole::Local {
+                target_host,
+                target_port,
+                one_shot,
+            },
+        });
+        Ok(())
+    }
 
-            let result = {
-                let sess = command_session.lock().unwrap();
-                let mut channel = sess.channel_session()?;
-                channel.exec(command)?;
+    fn start_dynamic_forward(
+        &self,
+        session: Arc<Mutex<Session>>,
+        stop: Arc<AtomicBool>,
+        id: String,
+        bind_host: String,
+        bind_port: u16,
+    ) -> anyhow::Result<()> {
+        let listener = TcpListener::bind((bind_host.as_str(), bind_port))?;
+        listener.set_nonblocking(true)?;
+        self.add_forward_listener(ForwardListener {
+            id,
+            listener,
+            session,
+            stop,
+            role: ListenerRole::Dynamic,
+        });
+        Ok(())
+    }
 
-                let mut output = String::new();
-                channel.read_to_string(&mut output)?;
-                channel.wait_close()?;
-                anyhow::Ok(output)
-            };
+    // Unix socket 监听不进共享 accept 轮询（那里只处理 TcpListener），单独一个线程；
+    // 停止后删除 socket 文件
+    #[cfg(unix)]
+    fn start_local_unix_forward(
+        &self,
+        session: Arc<Mutex<Session>>,
+        stop: Arc<AtomicBool>,
+        id: String,
+        local_path: String,
+        remote_path: String,
+    ) -> anyhow::Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
 
-            match result {
-                Ok(output) => return Ok(output),
-                Err(error) => {
-                    last_error = Some(error);
-                    if attempt == 0 {
-                        // Drop cached dedicated session and recreate once.
-                        let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
-                        sftp_sessions.remove(session_id);
+        // 上次异常退出留下的 socket 文件可以覆盖；仍有进程在监听或不是 socket 时报错
+        if let Ok(meta) = std::fs::symlink_metadata(&local_path) {
+            if !meta.file_type().is_socket() {
+                return Err(anyhow::anyhow!("'{}' exists and is not a socket", local_path));
+            }
+            if UnixStream::connect(&local_path).is_ok() {
+                return Err(anyhow::anyhow!("Socket '{}' is already in use", local_path));
+            }
+            std::fs::remove_file(&local_path)?;
+        }
+        let listener = UnixListener::bind(&local_path)
+            .map_err(|e| anyhow::anyhow!("Failed to bind '{}': {}", local_path, e))?;
+        listener.set_nonblocking(true)?;
+
+        let manager = self.clone();
+        thread_stats::spawn(ThreadKind::Forward, move || {
+            while !stop.load(Ordering::Relaxed) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(50));
+                        continue;
                     }
+                    Err(e) => {
+                        manager.set_forward_status(&id, ForwardStatus::Error, Some(e.to_string()));
+                        break;
+                    }
+                };
+                let gate = manager.forward_gate(&id);
+                if gate.is_paused() {
+                    stream.shutdown_stream();
+                    continue;
                 }
+                let session = session.clone();
+                let remote_path = remote_path.clone();
+                thread_stats::spawn(ThreadKind::Forward, move || {
+                    let _ = stream.set_nonblocking(false);
+                    let conn = gate.track(&stream);
+                    match Self::open_direct_streamlocal(&session, &remote_path) {
+                        Ok(channel) => Self::pump_nonblocking(channel, stream),
+                        Err(_) => stream.shutdown_stream(),
+                    }
+                    gate.untrack(conn);
+                });
             }
+            let _ = std::fs::remove_file(&local_path);
+        });
+        Ok(())
+    }
+
+    fn add_forward_listener(&self, listener: ForwardListener) {
+        self.forward_listeners.lock().unwrap().push(listener);
+        if !self.forward_acceptor.swap(true, Ordering::SeqCst) {
+            let manager = self.clone();
+            thread_stats::spawn(ThreadKind::Forward, move || loop {
+                if !manager.accept_forward_connections() {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            });
         }
+    }
 
-        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Failed to execute command")))
+    // 轮询所有非阻塞监听器一轮；返回是否接受了新连接。已停止或出错的监听器被移除（端口随之释放）
+    fn accept_forward_connections(&self) -> bool {
+        let mut accepted = false;
+        let mut listeners = self.forward_listeners.lock().unwrap();
+        listeners.retain(|entry| {
+            if entry.stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            match entry.listener.accept() {
+                Ok((stream, _)) => {
+                    accepted = true;
+                    let gate = self.forward_gate(&entry.id);
+                    if gate.is_paused() {
+                        let _ = stream.shutdown(Shutdown::Both);
+                        return true;
+                    }
+                    self.apply_forward_keepalive(&stream);
+                    self.spawn_forward_connection(entry, stream, gate);
+                    // 一次性转发只接受一个连接
+                    !matches!(entry.role, ListenerRole::Local { one_shot: Some(_), .. })
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => true,
+                Err(e) => {
+                    self.set_forward_status(&entry.id, ForwardStatus::Error, Some(e.to_string()));
+                    false
+                }
+            }
+        });
+        accepted
     }
 
-    pub fn execute_command_controlled(
+    fn spawn_forward_connection(&self, entry: &ForwardListener, mut stream: TcpStream, gate: ForwardGate) {
+        let session = entry.session.clone();
+        let stop = entry.stop.clone();
+        match &entry.role {
+            ListenerRole::Local {
+                target_host,
+                target_port,
+                one_shot,
+            } => {
+                let target_host = target_host.clone();
+                let target_port = *target_port;
+                let one_shot = one_shot.clone();
+                let manager = self.clone();
+                thread_stats::spawn(ThreadKind::Forward, move || {
+                    if stop.load(Ordering::Relaxed) {
+                        let _ = stream.shutdown(Shutdown::Both);
+                        return;
+                    }
+                    let _ = stream.set_nonblocking(false);
+                    let conn = gate.track(&stream);
+                    match Self::open_direct_tcpip(&session, &target_host, target_port) {
+                        Ok(channel) => Self::pump_nonblocking(channel, stream),
+                        Err(_) => {
+                            let _ = stream.shutdown(Shutdown::Both);
+                        }
+                    }
+                    gate.untrack(conn);
+                    if let Some(id) = one_shot {
+                        manager.finish_one_shot(&id, &session, &stop);
+                    }
+                });
+            }
+            ListenerRole::Dynamic => {
+                thread_stats::spawn(ThreadKind::Forward, move || {
+                    if stop.load(Ordering::Relaxed) {
+                        let _ = stream.shutdown(Shutdown::Both);
+                        return;
+                    }
+                    let _ = stream.set_nonblocking(false);
+                    let target = match Self::socks5_handshake(&mut stream) {
+                        Ok(target) => target,
+                        Err(_) => {
+                            let _ = stream.shutdown(Shutdown::Both);
+                            return;
+                        }
+                    };
+                    let _ = stream.set_read_timeout(None);
+                    let _ = stream.set_write_timeout(None);
+                    let conn = gate.track(&stream);
+                    match Self::open_direct_tcpip(&session, &target.0, target.1) {
+                        Ok(channel) => {
+                            let _ = stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+                            Self::pump_nonblocking(channel, stream);
+                        }
+                        Err(_) => {
+                            let _ = stream.write_all(&[0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+                            let _ = stream.shutdown(Shutdown::Both);
+                        }
+                    }
+                    gate.untrack(conn);
+                });
+            }
+        }
+    }
+
+    fn start_remote_forward(
         &self,
-        session_id: &str,
-        command: &str,
-        timeout_sec: u64,
-    ) -> anyhow::Result<ControlledCommandResult> {
-        let connection = {
-            let connections = self.connections.lock().unwrap();
-            connections
-                .get(session_id)
-                .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", session_id))?
-                .clone()
+        session: Arc<Mutex<Session>>,
+        stop: Arc<AtomicBool>,
+        id: String,
+        bind: (String, u16),
+        target: (String, u16),
+    ) -> anyhow::Result<()> {
+        let (bind_host, bind_port) = bind;
+        let (target_host, target_port) = target;
+        // 转发会话是非阻塞的，请求监听遇到 EAGAIN 时短暂重试
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut listener = loop {
+            let result = session.lock().unwrap().channel_forward_listen(bind_port, Some(&bind_host), None);
+            match result {
+                Ok((listener, _)) => break listener,
+                Err(err)
+                    if matches!(err.code(), ssh2::ErrorCode::Session(code) if code == Self::LIBSSH2_ERROR_EAGAIN)
+                        && Instant::now() < deadline =>
+                {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                Err(err) => return Err(err.into()),
+            }
         };
 
-        let timeout_sec = timeout_sec.clamp(3, 300);
-        let timeout = Duration::from_secs(timeout_sec);
-        let started_at = Instant::now();
-
-        let session = self.create_authenticated_session(&connection)?;
-        session.set_blocking(false);
-        session.set_timeout((timeout_sec * 1000) as u32);
-
-        let mut channel = session.channel_session()?;
-        channel.exec(command)?;
-
-        let mut stdout = Vec::<u8>::new();
-        let mut stderr = Vec::<u8>::new();
-        let mut timed_out = false;
-        let mut buf = [0u8; 8192];
+        let manager = self.clone();
+        thread_stats::spawn(ThreadKind::Forward, move || {
+            loop {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let mut channel = match listener.accept() {
+                    Ok(channel) => channel,
+                    Err(err) => {
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        // 超时/EAGAIN 只是暂无连接；其他错误说明监听已被服务端撤销或连接断开
+                        if !matches!(
+                            err.code(),
+                            ssh2::ErrorCode::Session(code)
+                                if code == Self::LIBSSH2_ERROR_EAGAIN || code == Self::LIBSSH2_ERROR_TIMEOUT
+                        ) {
+                            manager.set_forward_status(&id, ForwardStatus::Error, Some(err.to_string()));
+                            break;
+                        }
+                        std::thread::sleep(Duration::from_millis(80));
+                        continue;
+                    }
+                };
+                let gate = manager.forward_gate(&id);
+                if gate.is_paused() {
+                    let _ = channel.close();
+                    continue;
+                }
+                let target_host = target_host.clone();
+                let stop = stop.clone();
+                let manager = manager.clone();
+                thread_stats::spawn(ThreadKind::Forward, move || {
+                    if stop.load(Ordering::Relaxed) {
+                        let _ = channel.close();
+                        return;
+                    }
+                    match TcpStream::connect((target_host.as_str(), target_port)) {
+                        Ok(stream) => {
+                            manager.apply_forward_keepalive(&stream);
+                            let conn = gate.track(&stream);
+                            Self::pump_nonblocking(channel, stream);
+                            gate.untrack(conn);
+                        }
+                        Err(_) => {
+                            let _ = channel.close();
+                        }
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
 
+    // 非阻塞会话上的双向转发：单线程轮询，避免阻塞读占住会话锁导致另一方向无法写入。
+    // 转发会话与嵌套会话的外层都以非阻塞模式运行，每个连接只占一个线程
+    fn pump_nonblocking<S: ForwardStream>(mut channel: ssh2::Channel, mut stream: S) {
+        if stream.set_nonblocking_stream(true).is_err() {
+            return;
+        }
+        let mut buf = vec![0u8; 32 * 1024];
+        let mut downstream: Vec<u8> = Vec::new(); // 通道 -> socket 尚未写出的数据
+        let mut upstream: Vec<u8> = Vec::new(); // socket -> 通道 尚未写出的数据
+        let mut idle_wait = PUMP_IDLE_MIN;
         loop {
-            let mut had_progress = false;
-
-            loop {
+            let mut progress = false;
+            if downstream.is_empty() {
                 match channel.read(&mut buf) {
+                    Ok(0) if channel.eof() => break,
+                    Ok(0) => {}
+                    Ok(n) => {
+                        downstream.extend_from_slice(&buf[..n]);
+                        progress = true;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(_) => break,
+                }
+            }
+            if !downstream.is_empty() {
+                match stream.write(&downstream) {
                     Ok(0) => break,
                     Ok(n) => {
-                        stdout.extend_from_slice(&buf[..n]);
-                        had_progress = true;
+                        downstream.drain(..n);
+                        progress = true;
                     }
-                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
-                    Err(err) => return Err(anyhow::anyhow!("Failed to read stdout: {}", err)),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(_) => break,
                 }
             }
-
-            loop {
-                let mut stderr_stream = channel.stderr();
-                match stderr_stream.read(&mut buf) {
+            if upstream.is_empty() {
+                match stream.read(&mut buf) {
                     Ok(0) => break,
                     Ok(n) => {
-                        stderr.extend_from_slice(&buf[..n]);
-                        had_progress = true;
+                        upstream.extend_from_slice(&buf[..n]);
+                        progress = true;
                     }
-                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
-                    Err(err) => return Err(anyhow::anyhow!("Failed to read stderr: {}", err)),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(_) => break,
                 }
             }
-
-            if channel.eof() {
-                break;
+            if !upstream.is_empty() {
+                match channel.write(&upstream) {
+                    Ok(n) => {
+                        upstream.drain(..n);
+                        progress = progress || n > 0;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(_) => break,
+                }
             }
-
-            if started_at.elapsed() >= timeout {
-                timed_out = true;
-                let _ = channel.close();
-                break;
+            if progress {
+                idle_wait = PUMP_IDLE_MIN;
+                continue;
             }
-
-            if !had_progress {
-                std::thread::sleep(Duration::from_millis(12));
+            // 空闲时阻塞读本地 socket（带超时，超时后回头检查通道），本地数据到达立即唤醒；
+            // 超时随空闲时间加倍，有数据后复位，避免空转
+            if upstream.is_empty() {
+                if stream.set_nonblocking_stream(false).is_err() || stream.set_read_timeout_stream(Some(idle_wait)).is_err() {
+                    break;
+                }
+                let result = stream.read(&mut buf);
+                if stream.set_nonblocking_stream(true).is_err() {
+                    break;
+                }
+                match result {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        upstream.extend_from_slice(&buf[..n]);
+                        idle_wait = PUMP_IDLE_MIN;
+                        continue;
+                    }
+                    Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                    Err(_) => break,
+                }
+            } else {
+                std::thread::sleep(idle_wait);
             }
+            idle_wait = (idle_wait * 2).min(PUMP_IDLE_MAX);
         }
+        let _ = channel.close();
+        stream.shutdown_stream();
+    }
 
-        let _ = channel.wait_close();
-        let exit_code = if timed_out {
-            -1
-        } else {
-            channel.exit_status().unwrap_or(-1)
-        };
+    fn socks5_handshake(stream: &mut TcpStream) -> anyhow::Result<(String, u16)> {
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
 
-        if timed_out {
-            if !stderr.is_empty() {
-                stderr.extend_from_slice(b"\n");
-            }
-            stderr.extend_from_slice(b"Command timed out");
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header)?;
+        if header[0] != 0x05 {
+            return Err(anyhow::anyhow!("Unsupported SOCKS version"));
+        }
+        let nmethods = header[1] as usize;
+        let mut methods = vec![0u8; nmethods];
+        stream.read_exact(&mut methods)?;
+        if !methods.contains(&0x00) {
+            let _ = stream.write_all(&[0x05, 0xFF]);
+            return Err(anyhow::anyhow!("No supported auth method"));
         }
+        stream.write_all(&[0x05, 0x00])?;
 
-        let _ = session.disconnect(None, "command finished", None);
+        let mut req = [0u8; 4];
+        stream.read_exact(&mut req)?;
+        if req[0] != 0x05 || req[1] != 0x01 {
+            let _ = stream.write_all(&[0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+            return Err(anyhow::anyhow!("Unsupported command"));
+        }
+        let addr_type = req[3];
+        let host = match addr_type {
+            0x01 => {
+                let mut buf = [0u8; 4];
+                stream.read_exact(&mut buf)?;
+                format!("{}.{}.{}.{}", buf[0], buf[1], buf[2], buf[3])
+            }
+            0x03 => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len)?;
+                let mut buf = vec![0u8; len[0] as usize];
+                stream.read_exact(&mut buf)?;
+                String::from_utf8_lossy(&buf).to_string()
+            }
+            0x04 => {
+                let mut buf = [0u8; 16];
+                stream.read_exact(&mut buf)?;
+                let segments: Vec<String> = buf
+                    .chunks(2)
+                    .map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
+                    .collect();
+                segments.join(":")
+            }
+            _ => return Err(anyhow::anyhow!("Unsupported address type")),
+        };
 
-        Ok(ControlledCommandResult {
-            exit_code,
-            stdout: String::from_utf8_lossy(&stdout).to_string(),
-            stderr: String::from_utf8_lossy(&stderr).to_string(),
-            duration_ms: started_at.elapsed().as_millis().min(u128::from(u64::MAX)) as u64,
-            timed_out,
-        })
+        let mut port_buf = [0u8; 2];
+        stream.read_exact(&mut port_buf)?;
+        let port = u16::from_be_bytes(port_buf);
+        Ok((host, port))
     }
+}
 
-    pub fn is_connected(&self, session_id: &str) -> bool {
-        let sessions = self.sessions.lock().unwrap();
-        sessions.contains_key(session_id)
+// 连接前的本地校验（不访问网络），返回字段级问题列表
+pub fn validate_connection(connection: &SshConnection) -> Vec<ConnectionIssue> {
+    let mut issues: Vec<ConnectionIssue> = Vec::new();
+    let mut issue = |field: &str, message: String| {
+        issues.push(ConnectionIssue {
+            field: field.to_string(),
+            message,
+        });
+    };
+
+    let host = connection.host.trim();
+    let unix_socket = host.strip_prefix("unix:");
+    if host.is_empty() {
+        issue("host", "Host is empty".to_string());
+    } else if let Some(socket_path) = unix_socket {
+        if !cfg!(unix) {
+            issue("host", "Unix socket endpoints are only supported on Unix platforms".to_string());
+        } else if !Path::new(socket_path.trim()).exists() {
+            issue("host", format!("Unix socket not found: {}", socket_path.trim()));
+        }
+    } else if host.chars().any(char::is_whitespace) {
+        issue("host", "Host must not contain whitespace".to_string());
     }
 
-    pub fn list_sessions(&self) -> Vec<String> {
-        let sessions = self.sessions.lock().unwrap();
-        sessions.keys().cloned().collect()
+    if unix_socket.is_none() && connection.port == 0 {
+        issue("port", "Port must be between 1 and 65535".to_string());
     }
 
-    fn get_or_create_sftp(&self, session_id: &str) -> anyhow::Result<Arc<Mutex<Session>>> {
-        // 先检查是否已经有缓存的 SFTP 会话
-        {
-            let sftp_sessions = self.sftp_sessions.lock().unwrap();
-            if let Some(session) = sftp_sessions.get(session_id) {
-                // 检查会话是否仍然有效
-                let sess = session.lock().unwrap();
-                if sess.authenticated() {
-                    return Ok(session.clone());
+    if let AuthType::Password { keychain: Some(keychain), .. } = &connection.auth_type {
+        if keychain.service.trim().is_empty() {
+            issue("keychain", "Keychain service is empty".to_string());
+        } else if let Err(e) = keychain.check_service() {
+            issue("keychain", e.to_string());
+        }
+        if keychain.account.trim().is_empty() {
+            issue("keychain", "Keychain account is empty".to_string());
+        }
+    }
+
+    if let AuthType::Agent { agent_sock, .. } = &connection.auth_type {
+        if let Err(e) = resolve_agent_sock(agent_sock.as_deref()) {
+            issue("agent_sock", e.to_string());
+        }
+    }
+
+    if let AuthType::PrivateKey { key_path, key_content, passphrase } = &connection.auth_type {
+        let inline = key_content.as_deref().filter(|content| !content.trim().is_empty());
+        let content = match inline {
+            Some(content) => Some(content.to_string()),
+            None if key_path.trim().is_empty() => {
+                issue("key_path", "Private key path is empty".to_string());
+                None
+            }
+            None => match std::fs::read_to_string(key_path.trim()) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    issue("key_path", format!("Key file not found: {}", key_path.trim()));
+                    None
                 }
-                // 如果会话无效，继续创建新的
+                Err(e) => {
+                    issue("key_path", format!("Key file is not readable: {}", e));
+                    None
+                }
+            },
+        };
+
+        // 内容问题报告在实际提供密钥的字段上
+        let key_field = if inline.is_some() { "key_content" } else { "key_path" };
+        if let Some(content) = content {
+            if !content.contains("PRIVATE KEY") {
+                issue(key_field, "Key does not look like a PEM/OpenSSH private key".to_string());
+            } else if private_key_is_encrypted(&content)
+                && passphrase.as_deref().unwrap_or("").is_empty()
+            {
+                issue("passphrase", "Key is encrypted but no passphrase is set".to_string());
             }
         }
+    }
 
-        // 获取连接信息
-        let connections = self.connections.lock().unwrap();
-        let connection = connections
-            .get(session_id)
-            .ok_or_else(|| anyhow::anyhow!("Connection info not found for session: {}", session_id))?
-            .clone();
-        drop(connections);
+    issues
+}
 
-        // 创建新的独立 SSH 会话专门用于 SFTP
-        let sess = self.create_authenticated_session(&connection)?;
+// 只做版本交换并读取服务端的 SSH_MSG_KEXINIT，不进行密钥交换与认证
+pub fn server_algorithms(host: &str, port: u16) -> anyhow::Result<ServerAlgorithms> {
+    let addr = format!("{}:{}", host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Failed to resolve host: {}", host))?;
+    let mut tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(10))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(10)))?;
+    tcp.set_write_timeout(Some(Duration::from_secs(10)))?;
+    tcp.write_all(b"SSH-2.0-NoTerm_probe\r\n")?;
+
+    // 版本行之前服务端可以先发送若干其他行
+    let mut server_version = String::new();
+    for _ in 0..32 {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while line.len() < 255 {
+            tcp.read_exact(&mut byte)?;
+            if byte[0] == b'\n' {
+                break;
+            }
+            line.push(byte[0]);
+        }
+        let line = String::from_utf8_lossy(&line).trim_end_matches('\r').to_string();
+        if line.starts_with("SSH-") {
+            server_version = line;
+            break;
+        }
+    }
+    if server_version.is_empty() {
+        return Err(anyhow::anyhow!("Server did not send an SSH version line"));
+    }
 
-        // 设置为阻塞模式（SFTP 需要）
-        sess.set_blocking(true);
-        sess.set_timeout(30000);
+    let mut header = [0u8; 5];
+    tcp.read_exact(&mut header)?;
+    let packet_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let padding_len = header[4] as usize;
+    if !(16..=35000).contains(&packet_len) || padding_len + 1 >= packet_len {
+        return Err(anyhow::anyhow!("Invalid KEXINIT packet length: {}", packet_len));
+    }
+    let mut body = vec![0u8; packet_len - 1];
+    tcp.read_exact(&mut body)?;
+    let _ = tcp.shutdown(Shutdown::Both);
+
+    let payload = &body[..packet_len - 1 - padding_len];
+    // 1 字节消息类型（20）+ 16 字节 cookie，之后是 name-list
+    if payload.first() != Some(&20) || payload.len() < 17 {
+        return Err(anyhow::anyhow!("Expected KEXINIT from server"));
+    }
+    let mut rest = &payload[17..];
+    let mut lists: Vec<Vec<String>> = Vec::new();
+    for _ in 0..6 {
+        if rest.len() < 4 {
+            return Err(anyhow::anyhow!("Truncated KEXINIT"));
+        }
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let value = rest
+            .get(4..4 + len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated KEXINIT"))?;
+        lists.push(
+            String::from_utf8_lossy(value)
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+        );
+        rest = &rest[4 + len..];
+    }
 
-        let session_arc = Arc::new(Mutex::new(sess));
+    // 服务端到客户端方向的列表与反方向通常一致，这里取客户端到服务端方向
+    let macs = lists.remove(4);
+    let ciphers = lists.remove(2);
+    let host_key = lists.remove(1);
+    let kex = lists.remove(0);
+    let deprecated = kex
+        .iter()
+        .chain(&host_key)
+        .chain(&ciphers)
+        .chain(&macs)
+        .filter(|name| is_deprecated_algorithm(name))
+        .cloned()
+        .collect();
+    Ok(ServerAlgorithms {
+        server_version,
+        kex,
+        host_key,
+        ciphers,
+        macs,
+        deprecated,
+    })
+}
 
-        // 缓存 SFTP 会话
-        let mut sftp_sessions = self.sftp_sessions.lock().unwrap();
-        sftp_sessions.insert(session_id.to_string(), session_arc.clone());
+// 参考 OpenSSH 默认已禁用的算法（SHA-1 签名、CBC/RC4 加密、MD5/96 位 MAC 等）
+fn is_deprecated_algorithm(name: &str) -> bool {
+    matches!(
+        name,
+        "ssh-rsa"
+            | "ssh-dss"
+            | "ssh-rsa-cert-v01@openssh.com"
+            | "ssh-dss-cert-v01@openssh.com"
+            | "diffie-hellman-group1-sha1"
+            | "diffie-hellman-group14-sha1"
+            | "diffie-hellman-group-exchange-sha1"
+            | "hmac-md5"
+            | "hmac-md5-96"
+            | "hmac-sha1-96"
+            | "hmac-md5-etm@openssh.com"
+            | "hmac-md5-96-etm@openssh.com"
+            | "hmac-sha1-96-etm@openssh.com"
+            | "umac-64@openssh.com"
+    ) || name.ends_with("-cbc")
+        || name.ends_with("-cbc@lysator.liu.se")
+        || name.starts_with("arcfour")
+}
 
-        Ok(session_arc)
+// 追加后超过 limit 字节时丢弃开头的内容，截断点落在字符边界上
+fn push_capped(buffer: &mut String, text: &str, limit: usize) {
+    buffer.push_str(text);
+    if buffer.len() > limit {
+        let mut cut = buffer.len() - limit;
+        while !buffer.is_char_boundary(cut) {
+            cut += 1;
+        }
+        buffer.drain(..cut);
     }
+}
 
-    pub fn sftp_list_dir(&self, session_id: &str, path: &str) -> anyhow::Result<Vec<SftpEntry>> {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
+// 按 "@@名称" 标记行拆分批量脚本的输出，每段保留非空行（未去除首尾空白）
+fn split_sections(output: &str) -> HashMap<&str, Vec<&str>> {
+    let mut sections: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut current = "";
+    for line in output.lines() {
+        if let Some(name) = line.trim().strip_prefix("@@") {
+            current = name;
+            continue;
+        }
+        if !line.trim().is_empty() {
+            sections.entry(current).or_default().push(line);
+        }
+    }
+    sections
+}
 
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+// free -b 的 "Mem:" 行：total used ...
+fn parse_free_mem(lines: &[&str]) -> Option<(u64, u64)> {
+    let line = lines.iter().find(|line| line.trim_start().starts_with("Mem:"))?;
+    let mut fields = line.split_whitespace().skip(1);
+    let total = fields.next()?.parse().ok()?;
+    let used = fields.next()?.parse().ok()?;
+    Some((total, used))
+}
 
-        let clean_path = if path.trim().is_empty() { "." } else { path.trim() };
+// macOS vm_stat：已用 = (active + wired + compressor) × 页大小
+fn parse_vm_stat_used(lines: &[&str]) -> Option<u64> {
+    let page_size: u64 = lines.first().and_then(|header| {
+        let start = header.find("page size of ")? + "page size of ".len();
+        header[start..].split_whitespace().next()?.parse().ok()
+    })?;
+    let pages = |label: &str| -> u64 {
+        lines
+            .iter()
+            .find_map(|line| line.trim().strip_prefix(label))
+            .and_then(|rest| rest.trim_start_matches(':').trim().trim_end_matches('.').parse().ok())
+            .unwrap_or(0)
+    };
+    let used = pages("Pages active") + pages("Pages wired down") + pages("Pages occupied by compressor");
+    (used > 0).then_some(used * page_size)
+}
 
-        // 规范化路径以检查是否在根目录
-        let normalized_path = Path::new(clean_path);
-        let is_root = clean_path == "/" || clean_path == "." || clean_path.is_empty();
+// "load average: 0.10, 0.20, 0.30"（Linux）或 "load averages: 1.5 1.6 1.7"（macOS/BSD）
+fn parse_load_average(line: &str) -> Option<[f64; 3]> {
+    let start = line.find("load average")?;
+    let rest = &line[start..];
+    let values: Vec<f64> = rest[rest.find(':')? + 1..]
+        .split([',', ' '])
+        .filter_map(|value| value.trim().parse().ok())
+        .take(3)
+        .collect();
+    (values.len() == 3).then(|| [values[0], values[1], values[2]])
+}
 
-        let entries = sftp.readdir(normalized_path)
-            .map_err(|e| anyhow::anyhow!("Failed to read directory '{}': {}", clean_path, e))?;
+// `ip -o addr show`："2: eth0    inet 10.0.0.5/24 brd ... scope global eth0"
+fn parse_ip_addr(lines: &[&str]) -> Vec<InterfaceAddress> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let name = fields.next()?.trim_end_matches(':');
+            let name = name.split('@').next().unwrap_or(name);
+            let family = fields.next()?;
+            if family != "inet" && family != "inet6" {
+                return None;
+            }
+            let (address, prefix) = match fields.next()?.split_once('/') {
+                Some((address, prefix)) => (address, prefix.parse().ok()),
+                None => (fields.next()?, None),
+            };
+            Some(InterfaceAddress {
+                name: name.to_string(),
+                family: family.to_string(),
+                address: address.to_string(),
+                prefix_len: prefix,
+            })
+        })
+        .collect()
+}
 
-        let mut output: Vec<SftpEntry> = entries
-            .into_iter()
-            .filter_map(|(p, stat)| {
-                let name = p.file_name()?.to_string_lossy().to_string();
-                if name.is_empty() || name == "." {
-                    return None;
-                }
-                // 过滤掉原始的 ".." 条目，稍后手动添加
-                if name == ".." {
-                    return None;
-                }
+// ifconfig 的三种常见格式：
+//   net-tools 新版 / BSD / macOS："inet 10.0.0.5 netmask 255.255.255.0"、"inet 10.0.0.5 netmask 0xffffff00"、
+//                                 "inet6 fe80::1%lo0 prefixlen 64"
+//   net-tools 旧版："inet addr:10.0.0.5  Bcast:... Mask:255.255.255.0"、"inet6 addr: fe80::1/64 Scope:Link"
+fn parse_ifconfig(lines: &[&str]) -> Vec<InterfaceAddress> {
+    let mut result = Vec::new();
+    let mut name = String::new();
+    for line in lines {
+        if !line.starts_with([' ', '\t']) {
+            name = line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches(':')
+                .to_string();
+            continue;
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(family) = tokens.first().copied().filter(|family| *family == "inet" || *family == "inet6") else {
+            continue;
+        };
+        let mut rest = tokens[1..].iter().copied();
+        let mut address = match rest.next() {
+            Some("addr:") => rest.next().unwrap_or_default(),
+            Some(token) => token.strip_prefix("addr:").unwrap_or(token),
+            None => continue,
+        };
+        let mut prefix_len = None;
+        if let Some((plain, prefix)) = address.split_once('/') {
+            address = plain;
+            prefix_len = prefix.parse().ok();
+        }
+        let address = address.split('%').next().unwrap_or(address);
+        for (i, token) in tokens.iter().enumerate() {
+            let next = tokens.get(i + 1).copied();
+            let value = match (*token, next) {
+                ("netmask", Some(mask)) | ("prefixlen", Some(mask)) => Some(mask),
+                _ => token.strip_prefix("Mask:"),
+            };
+            if let Some(value) = value {
+                prefix_len = prefix_len.or_else(|| netmask_prefix(value));
+            }
+        }
+        if address.is_empty() || name.is_empty() {
+            continue;
+        }
+        result.push(InterfaceAddress {
+            name: name.clone(),
+            family: family.to_string(),
+            address: address.to_string(),
+            prefix_len,
+        });
+    }
+    result
+}
 
-                Some(SftpEntry {
-                    name,
-                    is_dir: stat.is_dir(),
-                    size: stat.size,
-                    modified: stat.mtime,
-                    perm: stat.perm,
-                })
-            })
-            .collect();
+// 前缀长度："64"、"255.255.255.0" 或 "0xffffff00"
+fn netmask_prefix(mask: &str) -> Option<u8> {
+    if let Some(hex) = mask.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16).ok().map(|bits| bits.count_ones() as u8);
+    }
+    if let Ok(addr) = mask.parse::<std::net::Ipv4Addr>() {
+        return Some(u32::from(addr).count_ones() as u8);
+    }
+    mask.parse::<u8>().ok().filter(|prefix| *prefix <= 128)
+}
+
+// 去掉 "." 和原始 ".."，非根目录时在最前面补一个 ".."；目录在前，按名称排序
+fn finish_dir_listing(entries: Vec<SftpEntry>, clean_path: &str) -> Vec<SftpEntry> {
+    let is_root = clean_path == "/" || clean_path == "." || clean_path.is_empty();
+    let mut output: Vec<SftpEntry> = entries
+        .into_iter()
+        .filter(|entry| !entry.name.is_empty() && entry.name != "." && entry.name != "..")
+        .collect();
+
+    if !is_root {
+        output.insert(0, SftpEntry {
+            name: "..".to_string(),
+            is_dir: true,
+            size: None,
+            modified: None,
+            perm: None,
+            inode: None,
+            nlink: None,
+        });
+    }
+
+    output.sort_by(|a, b| {
+        // ".." 始终排在最前面
+        if a.name == ".." {
+            return std::cmp::Ordering::Less;
+        }
+        if b.name == ".." {
+            return std::cmp::Ordering::Greater;
+        }
+        // 文件夹在前，文件在后
+        match (a.is_dir, b.is_dir) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        }
+    });
+    output
+}
+
+const LS_MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// 取前 n 个空白分隔字段，剩余部分（可能含空格的文件名）原样返回
+fn split_ls_fields(line: &str, n: usize) -> Option<(Vec<&str>, &str)> {
+    let mut fields = Vec::with_capacity(n);
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    Some((fields, rest))
+}
+
+// 解析一行 ls -la 输出，支持：
+//   GNU --time-style=+%s：perms links owner group size epoch name
+//   常见格式：          perms links owner group size Mon DD HH:MM|YYYY name
+//   无属组列（部分设备）：perms links owner size Mon DD HH:MM|YYYY name
+// 设备文件（大小列为 "major, minor"）和 total 行跳过
+fn parse_ls_line(line: &str) -> Option<SftpEntry> {
+    let perms = line.split_whitespace().next()?;
+    let kind = perms.chars().next()?;
+    if perms.len() < 10 || !"-dlps".contains(kind) {
+        return None;
+    }
+    let is_number = |value: &str| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+
+    let (size, modified, name) = if let Some((fields, rest)) = split_ls_fields(line, 6)
+        .filter(|(fields, _)| is_number(fields[4]) && is_number(fields[5]) && fields[5].len() >= 9)
+    {
+        (fields[4], fields[5].parse().ok(), rest)
+    } else if let Some((fields, rest)) =
+        split_ls_fields(line, 8).filter(|(fields, _)| LS_MONTHS.contains(&fields[5]))
+    {
+        (fields[4], None, rest)
+    } else if let Some((fields, rest)) =
+        split_ls_fields(line, 7).filter(|(fields, _)| LS_MONTHS.contains(&fields[4]))
+    {
+        (fields[3], None, rest)
+    } else {
+        return None;
+    };
+
+    // 符号链接显示为 "name -> target"
+    let name = if kind == 'l' {
+        name.split(" -> ").next().unwrap_or(name)
+    } else {
+        name
+    };
+
+    Some(SftpEntry {
+        name: name.to_string(),
+        is_dir: kind == 'd',
+        size: size.parse().ok(),
+        modified,
+        perm: parse_ls_mode(perms),
+        inode: None,
+        nlink: None,
+    })
+}
 
-        // 如果不在根目录，添加 ".." 条目用于返回上级
-        if !is_root {
-            output.insert(0, SftpEntry {
-                name: "..".to_string(),
-                is_dir: true,
-                size: None,
-                modified: None,
-                perm: None,
-            });
+// "drwxr-sr-t" -> 含文件类型位的 st_mode，与 SFTP 返回的 perm 一致
+fn parse_ls_mode(perms: &str) -> Option<u32> {
+    let chars: Vec<char> = perms.chars().take(10).collect();
+    if chars.len() < 10 {
+        return None;
+    }
+    let mut mode = match chars[0] {
+        'd' => 0o040000,
+        'l' => 0o120000,
+        'p' => 0o010000,
+        's' => 0o140000,
+        _ => 0o100000,
+    };
+    for (i, (ch, bit)) in chars[1..10].iter().zip([0o400, 0o200, 0o100, 0o40, 0o20, 0o10, 0o4, 0o2, 0o1]).enumerate() {
+        let special = match i {
+            2 => 0o4000,
+            5 => 0o2000,
+            8 => 0o1000,
+            _ => 0,
+        };
+        match ch {
+            'r' | 'w' | 'x' => mode |= bit,
+            's' | 't' => mode |= bit | special,
+            'S' | 'T' => mode |= special,
+            _ => {}
         }
+    }
+    Some(mode)
+}
 
-        output.sort_by(|a, b| {
-            // ".." 始终排在最前面
-            if a.name == ".." {
-                return std::cmp::Ordering::Less;
-            }
-            if b.name == ".." {
-                return std::cmp::Ordering::Greater;
+// 解析要使用的 agent socket；None 表示沿用当前 SSH_AUTH_SOCK（Windows 上由 libssh2 自动选择）
+fn resolve_agent_sock(agent_sock: Option<&str>) -> anyhow::Result<Option<String>> {
+    let sock = match agent_sock.map(str::trim).filter(|sock| !sock.is_empty()) {
+        Some(sock) => sock,
+        None => return Ok(None),
+    };
+
+    if cfg!(target_os = "windows") {
+        // libssh2 在 Windows 上先尝试 Pageant，再尝试 OpenSSH agent 的命名管道
+        let pageant_running = std::process::Command::new("tasklist")
+            .args(["/FI", "IMAGENAME eq pageant.exe", "/NH"])
+            .output()
+            .map(|out| String::from_utf8_lossy(&out.stdout).to_ascii_lowercase().contains("pageant.exe"))
+            .unwrap_or(false);
+        match sock.to_ascii_lowercase().as_str() {
+            "pageant" => {
+                if !pageant_running {
+                    return Err(anyhow::anyhow!("Pageant is not running"));
+                }
+                return Ok(None);
             }
-            // 文件夹在前，文件在后
-            match (a.is_dir, b.is_dir) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            "openssh" => {
+                if pageant_running {
+                    return Err(anyhow::anyhow!(
+                        "Pageant is running and takes precedence; close it to use the OpenSSH agent"
+                    ));
+                }
+                if !Path::new(OPENSSH_AGENT_PIPE).exists() {
+                    return Err(anyhow::anyhow!("OpenSSH agent is not running (ssh-agent service)"));
+                }
+                return Ok(None);
             }
-        });
-
-        Ok(output)
+            _ => {}
+        }
     }
 
-    pub fn sftp_rename(&self, session_id: &str, from_path: &str, to_path: &str) -> anyhow::Result<()> {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
-
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
-
-        sftp.rename(Path::new(from_path), Path::new(to_path), None)
-            .map_err(|e| anyhow::anyhow!("Failed to rename '{}': {}", from_path, e))?;
+    if !Path::new(sock).exists() {
+        return Err(anyhow::anyhow!("SSH agent socket not found: {}", sock));
+    }
+    Ok(Some(sock.to_string()))
+}
 
-        Ok(())
+// 取输出中最后一个 OSC 0/2 标题序列（ESC ] 0; 标题 BEL 或 ESC \\）；跨读取块的序列不处理
+fn parse_osc_title(output: &str) -> Option<String> {
+    let mut title = None;
+    let mut rest = output;
+    while let Some(start) = rest.find("\x1b]") {
+        let body = &rest[start + 2..];
+        let text = body.strip_prefix("0;").or_else(|| body.strip_prefix("2;"));
+        let end = body.find(['\x07', '\x1b']);
+        match (text, end) {
+            (Some(text), Some(end)) if end >= 2 => {
+                title = Some(text[..end - 2].to_string());
+                rest = &body[end..];
+            }
+            (_, Some(end)) => rest = &body[end.max(1)..],
+            (_, None) => break,
+        }
     }
+    title
+}
 
-    pub fn sftp_chmod(&self, session_id: &str, path: &str, mode: u32) -> anyhow::Result<()> {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
+const BRACKETED_PASTE_ON: &[u8] = b"\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &[u8] = b"\x1b[?2004l";
 
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+// 跟踪远端程序开关 bracketed paste（ESC [?2004h / ESC [?2004l）。保留上一块末尾的几个字节，
+// 以识别跨读取块的序列
+#[derive(Default)]
+pub(crate) struct BracketedPasteTracker {
+    carry: Vec<u8>,
+}
 
-        let stat = FileStat {
-            size: None,
-            uid: None,
-            gid: None,
-            perm: Some(mode),
-            atime: None,
-            mtime: None,
+impl BracketedPasteTracker {
+    // 返回本块中最后一次设置的状态；没有相关序列时为 None
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Option<bool> {
+        let mut joined = std::mem::take(&mut self.carry);
+        joined.extend_from_slice(data);
+        let rfind = |marker: &[u8]| joined.windows(marker.len()).rposition(|window| window == marker);
+        let state = match (rfind(BRACKETED_PASTE_ON), rfind(BRACKETED_PASTE_OFF)) {
+            (Some(on), Some(off)) => Some(on > off),
+            (Some(_), None) => Some(true),
+            (None, Some(_)) => Some(false),
+            (None, None) => None,
         };
-
-        sftp.setstat(Path::new(path), stat)
-            .map_err(|e| anyhow::anyhow!("Failed to chmod '{}': {}", path, e))?;
-
-        Ok(())
+        let keep = joined.len().saturating_sub(BRACKETED_PASTE_ON.len() - 1);
+        self.carry = joined.split_off(keep);
+        state
     }
+}
 
-    pub fn sftp_delete(&self, session_id: &str, path: &str, is_dir: bool) -> anyhow::Result<()> {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
-
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+// 没有终端模拟器应答时代为回复的查询：DA1、DA2、DSR 状态、光标位置（位置未知，固定回 1;1）
+const TERMINAL_QUERY_ANSWERS: &[(&[u8], &[u8])] = &[
+    (b"\x1b[c", b"\x1b[?1;2c"),
+    (b"\x1b[0c", b"\x1b[?1;2c"),
+    (b"\x1b[>c", b"\x1b[>0;276;0c"),
+    (b"\x1b[>0c", b"\x1b[>0;276;0c"),
+    (b"\x1b[5n", b"\x1b[0n"),
+    (b"\x1b[6n", b"\x1b[1;1R"),
+];
+
+// 识别输出中的终端查询并生成应答。末尾不完整的序列留到下一块
+#[derive(Default)]
+pub(crate) struct TerminalQueryResponder {
+    carry: Vec<u8>,
+}
 
-        if is_dir {
-            sftp.rmdir(Path::new(path))
-                .map_err(|e| anyhow::anyhow!("Failed to remove directory '{}': {}", path, e))?;
-        } else {
-            sftp.unlink(Path::new(path))
-                .map_err(|e| anyhow::anyhow!("Failed to delete file '{}': {}", path, e))?;
+impl TerminalQueryResponder {
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut joined = std::mem::take(&mut self.carry);
+        joined.extend_from_slice(data);
+        let mut answer = Vec::new();
+        let mut i = 0;
+        while i < joined.len() {
+            if joined[i] == 0x1b {
+                let rest = &joined[i..];
+                if let Some((query, reply)) = TERMINAL_QUERY_ANSWERS.iter().find(|(query, _)| rest.starts_with(query)) {
+                    answer.extend_from_slice(reply);
+                    i += query.len();
+                    continue;
+                }
+                if TERMINAL_QUERY_ANSWERS.iter().any(|(query, _)| query.starts_with(rest)) {
+                    self.carry = rest.to_vec();
+                    break;
+                }
+            }
+            i += 1;
         }
-
-        Ok(())
+        answer
     }
+}
 
-    pub fn sftp_mkdir(&self, session_id: &str, path: &str) -> anyhow::Result<()> {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
+// 按会话 id 索引的状态表，见 SshManager::session_tables
+trait SessionTable {
+    fn session_ids(&self) -> Vec<String>;
+    fn remove_session(&self, session_id: &str) -> bool;
+}
 
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+impl<V> SessionTable for Mutex<HashMap<String, V>> {
+    fn session_ids(&self) -> Vec<String> {
+        self.lock().unwrap().keys().cloned().collect()
+    }
 
-        sftp.mkdir(Path::new(path), 0o755)
-            .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", path, e))?;
+    fn remove_session(&self, session_id: &str) -> bool {
+        self.lock().unwrap().remove(session_id).is_some()
+    }
+}
 
-        Ok(())
+impl SessionTable for Mutex<HashSet<String>> {
+    fn session_ids(&self) -> Vec<String> {
+        self.lock().unwrap().iter().cloned().collect()
     }
 
-    pub fn resize_pty(&self, session_id: &str, cols: u32, rows: u32) -> anyhow::Result<()> {
-        let channels = self.channels.lock().unwrap();
-        let channel = channels
-            .get(session_id)
-            .ok_or_else(|| anyhow::anyhow!("Shell not found"))?;
+    fn remove_session(&self, session_id: &str) -> bool {
+        self.lock().unwrap().remove(session_id)
+    }
+}
 
-        let mut ch = channel.lock().unwrap();
-        ch.request_pty_size(cols, rows, None, None)?;
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_EXTENDED: u8 = 200;
+
+fn write_sftp_packet(channel: &mut ssh2::Channel, packet_type: u8, body: &[u8]) -> anyhow::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.extend_from_slice(&(body.len() as u32 + 1).to_be_bytes());
+    packet.push(packet_type);
+    packet.extend_from_slice(body);
+    channel.write_all(&packet)?;
+    channel.flush()?;
+    Ok(())
+}
 
-        Ok(())
+// 返回（类型, 内容）
+fn read_sftp_packet(channel: &mut ssh2::Channel) -> anyhow::Result<(u8, Vec<u8>)> {
+    let mut len = [0u8; 4];
+    channel.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > 256 * 1024 {
+        return Err(anyhow::anyhow!("Invalid SFTP packet length: {}", len));
     }
+    let mut packet = vec![0u8; len];
+    channel.read_exact(&mut packet)?;
+    let body = packet.split_off(1);
+    Ok((packet[0], body))
+}
 
-    pub fn sftp_download_file<F>(
-        &self,
-        session_id: &str,
-        remote_path: &str,
-        local_path: &str,
-        mut on_progress: F,
-    ) -> anyhow::Result<()>
-    where
-        F: FnMut(u64, u64) + Send,
-    {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
+fn put_sftp_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value);
+}
 
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+// 在独立通道上发送 SSH_FXP_INIT（版本 3）并读取 SSH_FXP_VERSION，通道留给调用方继续发请求
+fn open_raw_sftp(sess: &Session) -> anyhow::Result<(ssh2::Channel, SftpFeatures)> {
+    let mut channel = sess.channel_session()?;
+    channel
+        .subsystem("sftp")
+        .map_err(|e| SftpUnavailable(e.to_string()))?;
+    write_sftp_packet(&mut channel, SSH_FXP_INIT, &3u32.to_be_bytes())?;
+    let (packet_type, body) = read_sftp_packet(&mut channel)?;
+    if packet_type != SSH_FXP_VERSION {
+        return Err(anyhow::anyhow!("Unexpected SFTP packet type {} during handshake", packet_type));
+    }
+    Ok((channel, parse_sftp_version(&body)?))
+}
 
-        // 打开远程文件
-        let mut remote_file = sftp.open(Path::new(remote_path))
-            .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", remote_path, e))?;
+// posix-rename@openssh.com：目标存在时原子覆盖
+fn sftp_posix_rename(channel: &mut ssh2::Channel, from: &str, to: &str) -> anyhow::Result<()> {
+    let request_id = 1u32;
+    let mut body = request_id.to_be_bytes().to_vec();
+    put_sftp_string(&mut body, b"posix-rename@openssh.com");
+    put_sftp_string(&mut body, from.as_bytes());
+    put_sftp_string(&mut body, to.as_bytes());
+    write_sftp_packet(channel, SSH_FXP_EXTENDED, &body)?;
+
+    let (packet_type, body) = read_sftp_packet(channel)?;
+    if packet_type != SSH_FXP_STATUS || body.len() < 8 {
+        return Err(anyhow::anyhow!("Unexpected SFTP reply type {} to posix-rename", packet_type));
+    }
+    let code = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+    if code != 0 {
+        let message = body
+            .get(12..)
+            .and_then(|rest| {
+                let len = u32::from_be_bytes(body.get(8..12)?.try_into().ok()?) as usize;
+                rest.get(..len)
+            })
+            .map(|message| String::from_utf8_lossy(message).to_string())
+            .unwrap_or_default();
+        return Err(anyhow::anyhow!("posix-rename '{}' -> '{}' failed (status {}): {}", from, to, code, message));
+    }
+    Ok(())
+}
 
-        let total = sftp
-            .stat(Path::new(remote_path))
-            .ok()
-            .and_then(|stat| stat.size)
-            .unwrap_or(0);
-        let local_existing = std::fs::metadata(local_path).map(|meta| meta.len()).unwrap_or(0);
-        let can_resume = local_existing > 0 && local_existing < total;
+// SSH_FXP_VERSION 去掉类型字节后的内容：uint32 版本，之后是若干 (string 名称, string 数据)
+fn parse_sftp_version(body: &[u8]) -> anyhow::Result<SftpFeatures> {
+    fn read_u32(data: &[u8], pos: &mut usize) -> Option<u32> {
+        let bytes = data.get(*pos..*pos + 4)?;
+        *pos += 4;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn read_string(data: &[u8], pos: &mut usize) -> Option<String> {
+        let len = read_u32(data, pos)? as usize;
+        let bytes = data.get(*pos..pos.checked_add(len)?)?;
+        *pos += len;
+        Some(String::from_utf8_lossy(bytes).to_string())
+    }
 
-        let mut local_file = if can_resume {
-            remote_file
-                .seek(SeekFrom::Start(local_existing))
-                .map_err(|e| anyhow::anyhow!("Failed to seek remote file '{}': {}", remote_path, e))?;
-            std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(local_path)
-                .map_err(|e| anyhow::anyhow!("Failed to open local file '{}': {}", local_path, e))?
-        } else {
-            std::fs::File::create(local_path)
-                .map_err(|e| anyhow::anyhow!("Failed to create local file '{}': {}", local_path, e))?
+    let mut pos = 0;
+    let version = read_u32(body, &mut pos).ok_or_else(|| anyhow::anyhow!("Truncated SFTP version packet"))?;
+    let mut extensions = Vec::new();
+    while pos < body.len() {
+        let (Some(name), Some(data)) = (read_string(body, &mut pos), read_string(body, &mut pos)) else {
+            break;
         };
+        extensions.push(SftpExtension { name, data });
+    }
+    Ok(SftpFeatures { version, extensions })
+}
 
-        let mut transferred: u64 = if can_resume { local_existing } else { 0 };
-        let mut buf = [0u8; 64 * 1024];
+// 逗号或空白分隔的算法列表 -> libssh2 需要的逗号分隔形式；为空返回 None
+fn normalize_algorithm_list(value: Option<&str>) -> Option<String> {
+    let algorithms: Vec<&str> = value?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .collect();
+    (!algorithms.is_empty()).then(|| algorithms.join(","))
+}
 
-        on_progress(transferred, total);
-        loop {
-            let read = remote_file
-                .read(&mut buf)
-                .map_err(|e| anyhow::anyhow!("Failed to read remote file '{}': {}", remote_path, e))?;
-            if read == 0 {
-                break;
+// shell 输出限速：按 1 秒窗口统计字节数，超过上限后等待到窗口结束。
+// 某个窗口回到上限以内时解除限速状态
+#[derive(Default)]
+struct OutputThrottle {
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    throttled: bool,
+}
+
+impl OutputThrottle {
+    // 返回（需要等待的时间，限速状态变化）
+    fn record(&mut self, bytes: usize, limit: Option<u64>) -> (Option<Duration>, Option<bool>) {
+        let limit = limit.filter(|limit| *limit > 0);
+        let now = Instant::now();
+        let mut changed = None;
+        let window_start = *self.window_start.get_or_insert(now);
+        if now.duration_since(window_start) >= Duration::from_secs(1) {
+            if self.throttled && limit.is_none_or(|limit| self.window_bytes <= limit) {
+                self.throttled = false;
+                changed = Some(false);
             }
-            local_file
-                .write_all(&buf[..read])
-                .map_err(|e| anyhow::anyhow!("Failed to write local file '{}': {}", local_path, e))?;
-            transferred = transferred.saturating_add(read as u64);
-            on_progress(transferred, total);
-        }
-        if total > 0 && transferred < total {
-            on_progress(total, total);
+            self.window_start = Some(now);
+            self.window_bytes = 0;
         }
+        self.window_bytes = self.window_bytes.saturating_add(bytes as u64);
 
-        Ok(())
+        let Some(limit) = limit else {
+            return (None, changed);
+        };
+        if self.window_bytes <= limit {
+            return (None, changed);
+        }
+        if !self.throttled {
+            self.throttled = true;
+            changed = Some(true);
+        }
+        let elapsed = self.window_start.map(|start| now.duration_since(start)).unwrap_or_default();
+        (Some(Duration::from_secs(1).saturating_sub(elapsed)), changed)
     }
+}
 
-    pub fn sftp_upload_file<F>(
-        &self,
-        session_id: &str,
-        local_path: &str,
-        remote_path: &str,
-        mut on_progress: F,
-    ) -> anyhow::Result<()>
-    where
-        F: FnMut(u64, u64) + Send,
-    {
-        let sftp_session = self.get_or_create_sftp(session_id)?;
-        let sess = sftp_session.lock().unwrap();
+// 公钥文本（"ssh-ed25519 AAAA... comment"）或 .pub 文件路径 -> 公钥 blob
+fn public_key_blob(value: &str) -> Option<Vec<u8>> {
+    let text = if Path::new(value).is_file() {
+        std::fs::read_to_string(value).ok()?
+    } else {
+        value.to_string()
+    };
+    let encoded = text.split_whitespace().nth(1)?;
+    base64::engine::general_purpose::STANDARD.decode(encoded).ok()
+}
 
-        let sftp = sess.sftp()
-            .map_err(|e| anyhow::anyhow!("Failed to initialize SFTP subsystem: {}", e))?;
+fn fallback_username() -> String {
+    std::env::var("USER")
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .or_else(|| {
+            std::env::var("USERNAME")
+                .ok()
+                .filter(|name| !name.trim().is_empty())
+        })
+        .unwrap_or_else(|| "root".to_string())
+}
+
+// 判断样本是文本还是二进制，并猜测文本编码。UTF-16 靠 BOM 识别（否则会因 NUL 被判为二进制）；
+// 截断处的半个 UTF-8 字符不算无效。非 UTF-8 时若控制字符占比较高则视为二进制，
+// 否则用连接配置的编码尝试无错解码
+fn detect_text_encoding(sample: &[u8], truncated: bool, configured: Option<&str>) -> (bool, Option<String>) {
+    if sample.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return (false, Some("UTF-8".to_string()));
+    }
+    if sample.starts_with(&[0xFF, 0xFE]) {
+        return (false, Some("UTF-16LE".to_string()));
+    }
+    if sample.starts_with(&[0xFE, 0xFF]) {
+        return (false, Some("UTF-16BE".to_string()));
+    }
+    if sample.contains(&0) {
+        return (true, None);
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => return (false, Some("UTF-8".to_string())),
+        Err(e) if truncated && e.error_len().is_none() => return (false, Some("UTF-8".to_string())),
+        Err(_) => {}
+    }
 
-        // 打开本地文件
-        let mut local_file = std::fs::File::open(local_path)
-            .map_err(|e| anyhow::anyhow!("Failed to open local file '{}': {}", local_path, e))?;
+    let control = sample
+        .iter()
+        .filter(|b| (**b < 0x20 && !matches!(**b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || **b == 0x7f)
+        .count();
+    if control * 10 > sample.len() {
+        return (true, None);
+    }
+    let guess = configured
+        .and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes()))
+        .filter(|encoding| *encoding != encoding_rs::UTF_8)
+        .filter(|encoding| encoding.decode_without_bom_handling_and_without_replacement(sample).is_some())
+        .map(|encoding| encoding.name().to_string());
+    (false, guess)
+}
 
-        let total = local_file
-            .metadata()
-            .map(|meta| meta.len())
-            .unwrap_or(0);
-        // Upload to a temporary file first, then atomically rename to final name.
-        // This prevents users from opening an incomplete file by the final name.
-        let temp_remote_path = format!("{}.part", remote_path);
-        let temp_remote_path_ref = Path::new(&temp_remote_path);
-
-        let remote_existing = sftp
-            .stat(temp_remote_path_ref)
-            .ok()
-            .and_then(|stat| stat.size)
-            .unwrap_or(0);
-        let can_resume = remote_existing > 0 && remote_existing < total;
-
-        let mut remote_file = if can_resume {
-            local_file
-                .seek(SeekFrom::Start(remote_existing))
-                .map_err(|e| anyhow::anyhow!("Failed to seek local file '{}': {}", local_path, e))?;
-            sftp.open_mode(
-                temp_remote_path_ref,
-                OpenFlags::WRITE | OpenFlags::APPEND,
-                0o644,
-                OpenType::File,
-            )
-            .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", temp_remote_path, e))?
-        } else {
-            // Fallback to full overwrite when remote file does not exist,
-            // is empty, or is larger than local file.
-            sftp.open_mode(
-                temp_remote_path_ref,
-                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
-                0o644,
-                OpenType::File,
-            )
-            .map_err(|e| anyhow::anyhow!("Failed to create remote file '{}': {}", temp_remote_path, e))?
+// 临时绑定后立即释放；只说明此刻端口可用，真正启动前仍可能被占用
+pub fn check_bind(kind: &ForwardKind, bind_host: Option<&str>, bind_port: u16) -> BindCheck {
+    if matches!(kind, ForwardKind::Remote | ForwardKind::LocalUnix) {
+        return BindCheck {
+            status: BindAvailability::Unknown,
+            error: None,
         };
+    }
+    let bind_host = bind_host
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .unwrap_or("127.0.0.1");
+    match TcpListener::bind((bind_host, bind_port)) {
+        Ok(listener) => {
+            drop(listener);
+            BindCheck {
+                status: BindAvailability::Free,
+                error: None,
+            }
+        }
+        Err(e) => BindCheck {
+            status: if e.kind() == std::io::ErrorKind::AddrInUse {
+                BindAvailability::InUse
+            } else {
+                BindAvailability::Unavailable
+            },
+            error: Some(e.to_string()),
+        },
+    }
+}
 
-        let mut transferred: u64 = if can_resume { remote_existing } else { 0 };
+fn copy_between_sftp<F>(
+    src: &ssh2::Sftp,
+    src_path: &str,
+    dst: &ssh2::Sftp,
+    dst_path: &str,
+    stop: &AtomicBool,
+    on_progress: &mut F,
+) -> anyhow::Result<u64>
+where
+    F: FnMut(u64, u64),
+{
+    let mut src_file = src
+        .open(Path::new(src_path))
+        .map_err(|e| anyhow::anyhow!("Failed to open remote file '{}': {}", src_path, e))?;
+    let total = src_file.stat().ok().and_then(|stat| stat.size).unwrap_or(0);
+
+    let temp_path = format!("{}.part", dst_path);
+    let mut dst_file = dst
+        .open_mode(
+            Path::new(&temp_path),
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            0o644,
+            OpenType::File,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to create remote file '{}': {}", temp_path, e))?;
+
+    // 取消或读写失败时删除 .part；成功时留给调用方替换目标文件
+    let result = (|| -> anyhow::Result<u64> {
+        let mut transferred: u64 = 0;
         let mut buf = [0u8; 64 * 1024];
-
         on_progress(transferred, total);
         loop {
-            let read = local_file
+            if stop.load(Ordering::Relaxed) {
+                return Err(anyhow::anyhow!("Transfer cancelled"));
+            }
+            let read = src_file
                 .read(&mut buf)
-                .map_err(|e| anyhow::anyhow!("Failed to read local file '{}': {}", local_path, e))?;
+                .map_err(|e| anyhow::anyhow!("Failed to read remote file '{}': {}", src_path, e))?;
             if read == 0 {
                 break;
             }
-            remote_file
+            dst_file
                 .write_all(&buf[..read])
-                .map_err(|e| anyhow::anyhow!("Failed to write remote file '{}': {}", temp_remote_path, e))?;
+                .map_err(|e| anyhow::anyhow!("Failed to write remote file '{}': {}", temp_path, e))?;
             transferred = transferred.saturating_add(read as u64);
             on_progress(transferred, total);
         }
-        if total > 0 && transferred < total {
-            on_progress(total, total);
-        }
+        Ok(transferred)
+    })();
+    drop(dst_file);
 
-        drop(remote_file);
+    if result.is_err() {
+        let _ = dst.unlink(Path::new(&temp_path));
+    }
+    result
+}
 
-        // Replace final file with temp file atomically when possible.
-        if sftp
-            .rename(temp_remote_path_ref, Path::new(remote_path), None)
-            .is_err()
-        {
-            let _ = sftp.unlink(Path::new(remote_path));
-            sftp
-                .rename(temp_remote_path_ref, Path::new(remote_path), None)
-                .map_err(|e| anyhow::anyhow!("Failed to finalize uploaded file '{}': {}", remote_path, e))?;
+fn local_sha256(path: &Path) -> anyhow::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open local file '{}': {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
         }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
 
-        Ok(())
+fn last_screen(output: &[u8], rows: usize) -> &[u8] {
+    const REPAINT_MARKERS: [&[u8]; 3] = [b"\x1b[?1049h", b"\x1b[2J", b"\x1bc"];
+    let rfind = |marker: &[u8]| output.windows(marker.len()).rposition(|window| window == marker);
+    let repaint = REPAINT_MARKERS.iter().filter_map(|marker| rfind(marker)).max();
+    // 之后又退出了备用屏，说明全屏程序已结束，按普通输出处理
+    let left_alt_screen = rfind(b"\x1b[?1049l");
+    if let Some(start) = repaint.filter(|start| left_alt_screen.is_none_or(|left| left < *start)) {
+        return &output[start..];
     }
+    // 末尾的换行不算新的一行
+    let body = output.strip_suffix(b"\n").unwrap_or(output);
+    let start = body
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(rows.saturating_sub(1))
+        .map(|(index, _)| index + 1)
+        .unwrap_or(0);
+    &output[start..]
+}
 
-    pub fn start_forward(&self, config: ForwardConfig) -> anyhow::Result<()> {
-        {
-            let forwards = self.forwards.lock().unwrap();
-            if forwards.contains_key(&config.id) {
-                return Err(anyhow::anyhow!("Forward already running"));
-            }
-        }
+fn remote_join(base: &str, rel: &str) -> String {
+    if rel.is_empty() {
+        return base.to_string();
+    }
+    format!("{}/{}", base.trim_end_matches('/'), rel)
+}
 
-        let session = self.create_authenticated_session(&config.connection)?;
-        let session = Arc::new(Mutex::new(session));
-        let stop = Arc::new(AtomicBool::new(false));
-        self.spawn_keepalive_for_forward(session.clone(), stop.clone());
+fn sync_rel_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
 
-        match config.kind {
-            ForwardKind::Local => {
-                let bind_host = config.local_bind_host.unwrap_or_else(|| "127.0.0.1".to_string());
-                let bind_port = config.local_bind_port.ok_or_else(|| anyhow::anyhow!("Local bind port missing"))?;
-                let target_host = config.target_host.ok_or_else(|| anyhow::anyhow!("Target host missing"))?;
-                let target_port = config.target_port.ok_or_else(|| anyhow::anyhow!("Target port missing"))?;
-                self.start_local_forward(session.clone(), stop.clone(), bind_host, bind_port, target_host, target_port)?;
-            }
-            ForwardKind::Remote => {
-                let bind_host = config.remote_bind_host.unwrap_or_else(|| "0.0.0.0".to_string());
-                let bind_port = config.remote_bind_port.ok_or_else(|| anyhow::anyhow!("Remote bind port missing"))?;
-                let target_host = config.target_host.ok_or_else(|| anyhow::anyhow!("Target host missing"))?;
-                let target_port = config.target_port.ok_or_else(|| anyhow::anyhow!("Target port missing"))?;
-                self.start_remote_forward(session.clone(), stop.clone(), bind_host, bind_port, target_host, target_port)?;
-            }
-            ForwardKind::Dynamic => {
-                let bind_host = config.local_bind_host.unwrap_or_else(|| "127.0.0.1".to_string());
-                let bind_port = config.local_bind_port.ok_or_else(|| anyhow::anyhow!("Local bind port missing"))?;
-                self.start_dynamic_forward(session.clone(), stop.clone(), bind_host, bind_port)?;
+// .gitignore 风格的排除规则，匹配相对路径（/ 分隔）：
+//   *  匹配一段路径内的任意字符，?  匹配单个字符，** 可跨越目录（"**/" 也可匹配零层）
+//   结尾的 / 表示只匹配目录；除结尾外含 / 的模式从根开始匹配整个相对路径，否则匹配任意层级的名称
+// 以 # 开头的行和空行忽略，不支持 ! 取反。目录被排除时不再进入
+pub(crate) struct ExcludeFilter {
+    patterns: Vec<ExcludePattern>,
+}
+
+struct ExcludePattern {
+    glob: Vec<char>,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl ExcludeFilter {
+    pub(crate) fn new(patterns: &[String]) -> Self {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty() && !pattern.starts_with('#'))
+            .filter_map(|pattern| {
+                let dir_only = pattern.ends_with('/');
+                let pattern = pattern.trim_end_matches('/');
+                let anchored = pattern.contains('/');
+                let pattern = pattern.trim_start_matches('/');
+                (!pattern.is_empty()).then(|| ExcludePattern {
+                    glob: pattern.chars().collect(),
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    pub(crate) fn is_excluded(&self, rel: &str, is_dir: bool) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let path: Vec<char> = rel.chars().collect();
+        let name: Vec<char> = rel.rsplit('/').next().unwrap_or(rel).chars().collect();
+        self.patterns.iter().any(|pattern| {
+            (is_dir || !pattern.dir_only)
+                && glob_match(&pattern.glob, if pattern.anchored { &path } else { &name })
+        })
+    }
+}
+
+fn glob_match(pattern: &[char], subject: &[char]) -> bool {
+    match pattern.first() {
+        None => subject.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if rest.first() == Some(&'/') && glob_match(&rest[1..], subject) {
+                return true;
             }
+            (0..=subject.len()).any(|skip| glob_match(rest, &subject[skip..]))
         }
+        Some('*') => (0..=subject.len())
+            .take_while(|skip| *skip == 0 || subject[*skip - 1] != '/')
+            .any(|skip| glob_match(&pattern[1..], &subject[skip..])),
+        Some('?') => subject.first().is_some_and(|c| *c != '/') && glob_match(&pattern[1..], &subject[1..]),
+        Some(c) => subject.first() == Some(c) && glob_match(&pattern[1..], &subject[1..]),
+    }
+}
 
-        let mut forwards = self.forwards.lock().unwrap();
-        forwards.insert(
-            config.id.clone(),
-            ForwardHandle {
-                stop,
-                session,
-            },
-        );
-        Ok(())
+// 递归收集本地文件（相对路径用 / 分隔），跳过符号链接等特殊文件以及被排除的条目
+fn walk_local_dir(
+    root: &Path,
+    rel: &str,
+    exclude: &ExcludeFilter,
+    files: &mut SyncFiles,
+    dirs: &mut Vec<String>,
+    excluded: &mut u32,
+) -> anyhow::Result<()> {
+    let dir = if rel.is_empty() { root.to_path_buf() } else { root.join(rel) };
+    let entries = std::fs::read_dir(&dir)
+        .map_err(|e| anyhow::anyhow!("Failed to read local directory '{}': {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let meta = entry.metadata()?;
+        let child = sync_rel_path(rel, &name);
+        if (meta.is_dir() || meta.is_file()) && exclude.is_excluded(&child, meta.is_dir()) {
+            *excluded += 1;
+            continue;
+        }
+        if meta.is_dir() {
+            dirs.push(child.clone());
+            walk_local_dir(root, &child, exclude, files, dirs, excluded)?;
+        } else if meta.is_file() {
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            files.insert(child, (meta.len(), mtime));
+        }
     }
+    Ok(())
+}
 
-    pub fn stop_forward(&self, id: &str) -> anyhow::Result<()> {
-        let handle = {
-            let mut forwards = self.forwards.lock().unwrap();
-            forwards.remove(id)
+fn walk_remote_dir(
+    sftp: &ssh2::Sftp,
+    root: &str,
+    rel: &str,
+    exclude: &ExcludeFilter,
+    files: &mut SyncFiles,
+    dirs: &mut Vec<String>,
+    excluded: &mut u32,
+) -> anyhow::Result<()> {
+    let dir = remote_join(root, rel);
+    let entries = sftp
+        .readdir(Path::new(&dir))
+        .map_err(|e| anyhow::anyhow!("Failed to read directory '{}': {}", dir, e))?;
+    for (path, stat) in entries {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
         };
-
-        if let Some(handle) = handle {
-            handle.stop.store(true, Ordering::Relaxed);
-            if let Ok(sess) = handle.session.lock() {
-                let _ = sess.disconnect(None, "Forward stopped", None);
-            }
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Forward not found"))
+        if name == "." || name == ".." {
+            continue;
+        }
+        let child = sync_rel_path(rel, &name);
+        if (stat.is_dir() || stat.is_file()) && exclude.is_excluded(&child, stat.is_dir()) {
+            *excluded += 1;
+            continue;
+        }
+        if stat.is_dir() {
+            dirs.push(child.clone());
+            walk_remote_dir(sftp, root, &child, exclude, files, dirs, excluded)?;
+        } else if stat.is_file() {
+            files.insert(child, (stat.size.unwrap_or(0), stat.mtime.unwrap_or(0)));
         }
     }
+    Ok(())
+}
 
-    pub fn list_forwards(&self) -> Vec<String> {
-        let forwards = self.forwards.lock().unwrap();
-        forwards.keys().cloned().collect()
+// 在会话自己的 TCP 连接上操作，不另开连接
+fn with_session_socket<T>(sess: &Session, f: impl FnOnce(socket2::SockRef<'_>) -> T) -> T {
+    #[cfg(unix)]
+    let socket = unsafe { std::os::fd::BorrowedFd::borrow_raw(std::os::fd::AsRawFd::as_raw_fd(sess)) };
+    #[cfg(windows)]
+    let socket =
+        unsafe { std::os::windows::io::BorrowedSocket::borrow_raw(std::os::windows::io::AsRawSocket::as_raw_socket(sess)) };
+    f(socket2::SockRef::from(&socket))
+}
+
+// 会话 TCP 连接的本地端口
+fn session_local_port(sess: &Session) -> Option<u16> {
+    with_session_socket(sess, |socket| socket.local_addr().ok()?.as_socket().map(|addr| addr.port()))
+}
+
+// 对端已关闭（收到 FIN 或 RST）时返回 true。只窥视不消耗数据，暂无数据时不下结论。
+// libssh2 握手后套接字本就是非阻塞的，这里再设置一次以免窥视阻塞
+fn socket_peer_closed(socket: socket2::SockRef<'_>) -> bool {
+    let _ = socket.set_nonblocking(true);
+    let mut buf = [std::mem::MaybeUninit::<u8>::uninit(); 1];
+    match socket.peek(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => !matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted),
     }
+}
 
-    fn start_local_forward(
-        &self,
-        session: Arc<Mutex<Session>>,
-        stop: Arc<AtomicBool>,
-        bind_host: String,
-        bind_port: u16,
-        target_host: String,
-        target_port: u16,
-    ) -> anyhow::Result<()> {
-        let listener = TcpListener::bind((bind_host.as_str(), bind_port))?;
-        listener.set_nonblocking(true)?;
-        std::thread::spawn(move || {
+fn session_socket_info(socket: socket2::SockRef<'_>) -> anyhow::Result<PathSocketInfo> {
+    let peer = socket
+        .peer_addr()?
+        .as_socket()
+        .ok_or_else(|| anyhow::anyhow!("Session is not using a TCP connection"))?;
+    #[cfg(unix)]
+    let mss = socket.tcp_mss().ok();
+    #[cfg(not(unix))]
+    let mss = None;
+    Ok(PathSocketInfo {
+        address: peer.to_string(),
+        local_address: socket.local_addr().ok().and_then(|addr| addr.as_socket()).map(|addr| addr.to_string()),
+        mss,
+        send_buffer: socket.send_buffer_size().ok(),
+        recv_buffer: socket.recv_buffer_size().ok(),
+        nodelay: socket.tcp_nodelay().ok(),
+    })
+}
+
+fn path_sample(sess: &Session, direction: TransferDirection, bytes: u64, channel_window: &mut Option<u32>) -> PathSample {
+    let started_at = Instant::now();
+    let mut transferred = 0u64;
+    let result = (|| -> anyhow::Result<()> {
+        let mut channel = sess.channel_session()?;
+        if direction == TransferDirection::Download {
+            channel.exec(&format!("head -c {} /dev/zero", bytes))?;
+            channel_window.get_or_insert(channel.read_window().window_size_initial);
+            let mut buf = [0u8; 32 * 1024];
             loop {
-                if stop.load(Ordering::Relaxed) {
+                let read = channel.read(&mut buf)?;
+                if read == 0 {
                     break;
                 }
-                match listener.accept() {
-                    Ok((stream, _)) => {
-                        let session = session.clone();
-                        let target_host = target_host.clone();
-                        let stop = stop.clone();
-                        std::thread::spawn(move || {
-                            if stop.load(Ordering::Relaxed) {
-                                let _ = stream.shutdown(Shutdown::Both);
-                                return;
-                            }
-                            let _ = stream.set_nonblocking(false);
-                            match Self::open_direct_tcpip(&session, &target_host, target_port) {
-                                Ok(channel) => Self::pipe_streams(channel, stream),
-                                Err(_) => {
-                                    let _ = stream.shutdown(Shutdown::Both);
-                                }
-                            }
-                        });
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        std::thread::sleep(Duration::from_millis(50));
-                    }
-                    Err(_) => break,
-                }
+                transferred += read as u64;
             }
-        });
+        } else {
+            channel.exec("cat > /dev/null")?;
+            let chunk = [0u8; 32 * 1024];
+            while transferred < bytes {
+                let len = (bytes - transferred).min(chunk.len() as u64) as usize;
+                channel.write_all(&chunk[..len])?;
+                transferred += len as u64;
+            }
+            channel.send_eof()?;
+        }
+        channel.wait_close()?;
+        if transferred < bytes {
+            return Err(anyhow::anyhow!("Only {} of {} bytes transferred", transferred, bytes));
+        }
         Ok(())
+    })();
+    let elapsed = started_at.elapsed();
+    let bytes_per_sec = if elapsed.as_secs_f64() > 0.0 {
+        (transferred as f64 / elapsed.as_secs_f64()) as u64
+    } else {
+        0
+    };
+    PathSample {
+        direction,
+        bytes,
+        transferred,
+        elapsed_ms: elapsed.as_millis().min(u128::from(u64::MAX)) as u64,
+        bytes_per_sec,
+        completed: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
     }
+}
 
-    fn start_dynamic_forward(
-        &self,
-        session: Arc<Mutex<Session>>,
-        stop: Arc<AtomicBool>,
-        bind_host: String,
-        bind_port: u16,
-    ) -> anyhow::Result<()> {
-        let listener = TcpListener::bind((bind_host.as_str(), bind_port))?;
-        listener.set_nonblocking(true)?;
-        std::thread::spawn(move || {
-            loop {
-                if stop.load(Ordering::Relaxed) {
-                    break;
-                }
-                match listener.accept() {
-                    Ok((mut stream, _)) => {
-                        let session = session.clone();
-                        let stop = stop.clone();
-                        std::thread::spawn(move || {
-                            if stop.load(Ordering::Relaxed) {
-                                let _ = stream.shutdown(Shutdown::Both);
-                                return;
-                            }
-                            let _ = stream.set_nonblocking(false);
-                            let target = match Self::socks5_handshake(&mut stream) {
-                                Ok(target) => target,
-                                Err(_) => {
-                                    let _ = stream.shutdown(Shutdown::Both);
-                                    return;
-                                }
-                            };
-                            let _ = stream.set_read_timeout(None);
-                            let _ = stream.set_write_timeout(None);
-                            match Self::open_direct_tcpip(&session, &target.0, target.1) {
-                                Ok(channel) => {
-                                    let _ = stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
-                                    Self::pipe_streams(channel, stream);
-                                }
-                                Err(_) => {
-                                    let _ = stream.write_all(&[0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
-                                    let _ = stream.shutdown(Shutdown::Both);
-                                }
-                            }
-                        });
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        std::thread::sleep(Duration::from_millis(50));
-                    }
-                    Err(_) => break,
-                }
+fn path_observations(report: &PathDiagnostics) -> Vec<String> {
+    let mut observations = Vec::new();
+    if let Some(mss) = report.socket.as_ref().and_then(|socket| socket.mss) {
+        if mss < ETHERNET_MSS - 100 {
+            observations.push(format!(
+                "TCP MSS is {} bytes (path MTU about {}), below the Ethernet default of {}; a VPN or tunnel is reducing the MTU",
+                mss,
+                mss + 40,
+                ETHERNET_MSS
+            ));
+        }
+    }
+    for direction in [TransferDirection::Download, TransferDirection::Upload] {
+        let samples: Vec<&PathSample> = report.samples.iter().filter(|sample| sample.direction == direction).collect();
+        let label = if direction == TransferDirection::Download { "Downloads" } else { "Uploads" };
+        let largest_ok = samples.iter().filter(|sample| sample.completed).map(|sample| sample.bytes).max();
+        if let Some(stalled) = samples.iter().find(|sample| !sample.completed) {
+            observations.push(match largest_ok {
+                Some(ok) => format!(
+                    "{} of {} completed but {} stalled after {} bytes; this pattern usually points to an MTU/PMTUD black hole (try lowering the MTU on the VPN interface)",
+                    label,
+                    format_size(ok),
+                    format_size(stalled.bytes),
+                    stalled.transferred
+                ),
+                None => format!(
+                    "{} stalled even at {}: {}",
+                    label,
+                    format_size(stalled.bytes),
+                    stalled.error.as_deref().unwrap_or("no progress")
+                ),
+            });
+            continue;
+        }
+        // 小传输受往返延迟影响，吞吐应随大小增加；较大传输明显变慢提示窗口或缓冲问题
+        let peak = samples.iter().map(|sample| sample.bytes_per_sec).max().unwrap_or(0);
+        if let Some(last) = samples.last() {
+            if samples.len() > 1 && peak > 0 && last.bytes_per_sec < peak / 4 {
+                observations.push(format!(
+                    "{} slow down sharply at {} ({} B/s vs. a peak of {} B/s); check window sizes or packet loss on large transfers",
+                    label,
+                    format_size(last.bytes),
+                    last.bytes_per_sec,
+                    peak
+                ));
             }
-        });
-        Ok(())
+        }
     }
+    if observations.is_empty() && !report.samples.is_empty() {
+        observations.push("No MTU or throughput problems detected".to_string());
+    }
+    observations
+}
 
-    fn start_remote_forward(
-        &self,
-        session: Arc<Mutex<Session>>,
-        stop: Arc<AtomicBool>,
-        bind_host: String,
-        bind_port: u16,
-        target_host: String,
-        target_port: u16,
-    ) -> anyhow::Result<()> {
-        let mut listener = {
-            let sess = session.lock().unwrap();
-            let (listener, _) = sess.channel_forward_listen(bind_port, Some(&bind_host), None)?;
-            listener
-        };
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{} MiB", bytes / (1024 * 1024))
+    } else {
+        format!("{} KiB", bytes / 1024)
+    }
+}
 
-        std::thread::spawn(move || {
-            loop {
-                if stop.load(Ordering::Relaxed) {
-                    break;
-                }
-                let mut channel = match listener.accept() {
-                    Ok(channel) => channel,
-                    Err(_) => {
-                        if stop.load(Ordering::Relaxed) {
-                            break;
+// real 为当前目录的真实路径，用于 follow 时识别循环：普通子目录的真实路径由父目录拼接得到，
+// 只有跟随链接时才调用 realpath
+fn walk_remote_for_download(
+    sftp: &ssh2::Sftp,
+    root: &str,
+    rel: &str,
+    real: &str,
+    policy: SymlinkPolicy,
+    visited: &mut HashSet<String>,
+    plan: &mut DownloadPlan,
+) -> anyhow::Result<()> {
+    let dir = remote_join(root, rel);
+    let entries = sftp
+        .readdir(Path::new(&dir))
+        .map_err(|e| anyhow::anyhow!("Failed to read directory '{}': {}", dir, e))?;
+    for (path, stat) in entries {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        if name == "." || name == ".." {
+            continue;
+        }
+        if !local_name_is_safe(&name) {
+            tracing::debug!(dir = %dir, name = %name, "skipping remote name unsafe for local filesystem");
+            plan.names_skipped += 1;
+            continue;
+        }
+        let child = sync_rel_path(rel, &name);
+        let remote_path = remote_join(root, &child);
+        // READDIR 的属性不跟随链接；服务端未给出类型时补一次 lstat
+        let stat = match stat.perm {
+            Some(_) => stat,
+            None => sftp
+                .lstat(Path::new(&remote_path))
+                .map_err(|e| anyhow::anyhow!("Failed to stat '{}': {}", remote_path, e))?,
+        };
+        if stat.file_type().is_symlink() {
+            match policy {
+                SymlinkPolicy::Skip => plan.symlinks_skipped += 1,
+                SymlinkPolicy::Preserve => match sftp.readlink(Path::new(&remote_path)) {
+                    Ok(target) => {
+                        let target = target.to_string_lossy().to_string();
+                        if link_target_stays_inside(&child, &target) {
+                            plan.links.push((child, target));
+                        } else {
+                            tracing::debug!(path = %remote_path, target = %target, "symlink target escapes download root");
+                            plan.symlinks_skipped += 1;
                         }
-                        std::thread::sleep(Duration::from_millis(80));
-                        continue;
                     }
-                };
-                let target_host = target_host.clone();
-                let stop = stop.clone();
-                std::thread::spawn(move || {
-                    if stop.load(Ordering::Relaxed) {
-                        let _ = channel.close();
-                        return;
+                    Err(e) => {
+                        tracing::debug!(path = %remote_path, error = %e, "readlink failed");
+                        plan.symlinks_skipped += 1;
                     }
-                    match TcpStream::connect((target_host.as_str(), target_port)) {
-                        Ok(stream) => {
-                            Self::pipe_streams(channel, stream);
-                        }
-                        Err(_) => {
-                            let _ = channel.close();
+                },
+                SymlinkPolicy::Follow => {
+                    let Ok(target) = sftp.stat(Path::new(&remote_path)) else {
+                        // 悬空链接
+                        plan.symlinks_skipped += 1;
+                        continue;
+                    };
+                    if target.is_dir() {
+                        let target_real = sftp
+                            .realpath(Path::new(&remote_path))
+                            .map_err(|e| anyhow::anyhow!("Failed to resolve '{}': {}", remote_path, e))?
+                            .to_string_lossy()
+                            .to_string();
+                        if !visited.insert(target_real.clone()) {
+                            plan.loops_skipped += 1;
+                            continue;
                         }
+                        plan.dirs.push(child.clone());
+                        walk_remote_for_download(sftp, root, &child, &target_real, policy, visited, plan)?;
+                    } else if target.is_file() {
+                        plan.files.push((child, target.size.unwrap_or(0)));
+                    } else {
+                        plan.symlinks_skipped += 1;
                     }
-                });
+                }
             }
-        });
-        Ok(())
+        } else if stat.is_dir() {
+            let child_real = remote_join(real, &name);
+            if !visited.insert(child_real.clone()) {
+                plan.loops_skipped += 1;
+                continue;
+            }
+            plan.dirs.push(child.clone());
+            walk_remote_for_download(sftp, root, &child, &child_real, policy, visited, plan)?;
+        } else if stat.is_file() {
+            plan.files.push((child, stat.size.unwrap_or(0)));
+        }
     }
+    Ok(())
+}
 
-    fn pipe_streams(channel: ssh2::Channel, stream: TcpStream) {
-        let mut channel_read = channel.clone();
-        let mut channel_write = channel;
-        let mut stream_read = match stream.try_clone() {
-            Ok(s) => s,
-            Err(_) => return,
-        };
-        let mut stream_write = stream;
+// 远端文件名在本地是否只表示当前目录下的一项。Windows 上 \\ 是分隔符，: 会变成盘符或备用数据流
+fn local_name_is_safe(name: &str) -> bool {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return false;
+    }
+    !(cfg!(windows) && (name.contains('\\') || name.contains(':')))
+}
 
-        std::thread::spawn(move || {
-            let _ = std::io::copy(&mut stream_read, &mut channel_write);
-            let _ = channel_write.close();
-        });
+// 按词法判断链接目标是否留在下载根目录内。绝对路径一律拒绝；.. 只允许出现在开头，
+// 此时经过的都是下载时创建的真实目录，词法结果与实际解析一致。之后的部分可能经过其他链接，
+// 那里的 .. 会按链接目标的父目录解析，无法词法判断，因此拒绝
+fn link_target_stays_inside(link_rel: &str, target: &str) -> bool {
+    if target.is_empty() || target.starts_with('/') || (cfg!(windows) && (target.contains('\\') || target.contains(':'))) {
+        return false;
+    }
+    let mut depth = link_rel.split('/').filter(|part| !part.is_empty()).count().saturating_sub(1);
+    let mut descended = false;
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if descended => return false,
+            ".." => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            _ => descended = true,
+        }
+    }
+    true
+}
 
-        std::thread::spawn(move || {
-            let _ = std::io::copy(&mut channel_read, &mut stream_write);
-            let _ = stream_write.shutdown(Shutdown::Both);
-        });
+// path 的父目录解析后是否仍在下载根目录内（中间目录可能是已有的链接）
+fn local_parent_inside(canonical_root: &Path, path: &Path) -> bool {
+    path.parent()
+        .and_then(|parent| std::fs::canonicalize(parent).ok())
+        .is_some_and(|parent| parent.starts_with(canonical_root))
+}
+
+#[cfg(unix)]
+fn create_local_symlink(target: &str, path: &Path) -> std::io::Result<()> {
+    // 重复下载时替换已有的文件或链接
+    if path.symlink_metadata().is_ok_and(|meta| !meta.is_dir()) {
+        std::fs::remove_file(path)?;
     }
+    std::os::unix::fs::symlink(target, path)
+}
 
-    fn socks5_handshake(stream: &mut TcpStream) -> anyhow::Result<(String, u16)> {
-        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+#[cfg(not(unix))]
+fn create_local_symlink(_target: &str, _path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Recreating symlinks is only supported on Unix",
+    ))
+}
 
-        let mut header = [0u8; 2];
-        stream.read_exact(&mut header)?;
-        if header[0] != 0x05 {
-            return Err(anyhow::anyhow!("Unsupported SOCKS version"));
-        }
-        let nmethods = header[1] as usize;
-        let mut methods = vec![0u8; nmethods];
-        stream.read_exact(&mut methods)?;
-        if !methods.contains(&0x00) {
-            let _ = stream.write_all(&[0x05, 0xFF]);
-            return Err(anyhow::anyhow!("No supported auth method"));
-        }
-        stream.write_all(&[0x05, 0x00])?;
+fn private_key_is_encrypted(content: &str) -> bool {
+    // Legacy PEM / PKCS#8
+    if content.contains("Proc-Type: 4,ENCRYPTED") || content.contains("BEGIN ENCRYPTED PRIVATE KEY") {
+        return true;
+    }
+    if !content.contains("BEGIN OPENSSH PRIVATE KEY") {
+        return false;
+    }
 
-        let mut req = [0u8; 4];
-        stream.read_exact(&mut req)?;
-        if req[0] != 0x05 || req[1] != 0x01 {
-            let _ = stream.write_all(&[0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
-            return Err(anyhow::anyhow!("Unsupported command"));
-        }
-        let addr_type = req[3];
-        let host = match addr_type {
-            0x01 => {
-                let mut buf = [0u8; 4];
-                stream.read_exact(&mut buf)?;
-                format!("{}.{}.{}.{}", buf[0], buf[1], buf[2], buf[3])
-            }
-            0x03 => {
-                let mut len = [0u8; 1];
-                stream.read_exact(&mut len)?;
-                let mut buf = vec![0u8; len[0] as usize];
-                stream.read_exact(&mut buf)?;
-                String::from_utf8_lossy(&buf).to_string()
-            }
-            0x04 => {
-                let mut buf = [0u8; 16];
-                stream.read_exact(&mut buf)?;
-                let segments: Vec<String> = buf
-                    .chunks(2)
-                    .map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
-                    .collect();
-                segments.join(":")
+    // OpenSSH format: "openssh-key-v1\0" followed by the cipher name as an SSH string
+    let body: String = content
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    let raw = match base64::engine::general_purpose::STANDARD.decode(body.as_bytes()) {
+        Ok(raw) => raw,
+        Err(_) => return false,
+    };
+    let magic = b"openssh-key-v1\0";
+    if raw.len() < magic.len() + 4 || !raw.starts_with(magic) {
+        return false;
+    }
+    let len_bytes = &raw[magic.len()..magic.len() + 4];
+    let len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+    let start = magic.len() + 4;
+    match raw.get(start..start + len) {
+        Some(cipher) => cipher != b"none",
+        None => false,
+    }
+}
+
+// 接受 "TERM"/"SIGTERM"/"term" 等写法，返回 RFC 4254 规定的信号名
+fn normalize_signal_name(signal: &str) -> anyhow::Result<&'static str> {
+    const SIGNALS: [&str; 13] = [
+        "ABRT", "ALRM", "FPE", "HUP", "ILL", "INT", "KILL", "PIPE", "QUIT", "SEGV", "TERM",
+        "USR1", "USR2",
+    ];
+    let upper = signal.trim().to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|name| **name == bare)
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("Unsupported signal: {}", signal))
+}
+
+// 按连接配置的编码解码远程输出（未配置或无法识别时按 UTF-8 宽松解码）
+pub(crate) fn decode_bytes(bytes: &[u8], encoding: Option<&str>) -> String {
+    let encoding = encoding
+        .and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+fn decode_chunk(decoder: &mut encoding_rs::Decoder, bytes: &[u8], output: &mut String, last: bool) {
+    let needed = decoder
+        .max_utf8_buffer_length(bytes.len())
+        .unwrap_or(bytes.len() * 3 + 16);
+    output.reserve(needed);
+    let _ = decoder.decode_to_string(bytes, output, last);
+}
+
+fn last_lines(data: &[u8], n: usize) -> &[u8] {
+    // Ignore a trailing newline so it does not count as an empty last line.
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    let mut seen = 0;
+    for (idx, byte) in body.iter().enumerate().rev() {
+        if *byte == b'\n' {
+            seen += 1;
+            if seen == n {
+                return &data[idx + 1..];
             }
-            _ => return Err(anyhow::anyhow!("Unsupported address type")),
-        };
+        }
+    }
+    data
+}
 
-        let mut port_buf = [0u8; 2];
-        stream.read_exact(&mut port_buf)?;
-        let port = u16::from_be_bytes(port_buf);
-        Ok((host, port))
+// 将参数转义为 POSIX shell 单引号字符串，用于拼接到 exec 命令中的路径/参数。
+// 单引号内除 `'` 外均为字面量（包括空格、$、反引号、换行），`'` 以 '\'' 拼接。
+pub(crate) fn shell_quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('\'');
+    for ch in value.chars() {
+        if ch == '\'' {
+            out.push_str("'\\''");
+        } else {
+            out.push(ch);
+        }
     }
+    out.push('\'');
+    out
 }
 
 #[cfg(target_os = "windows")]
@@ -1384,3 +8817,319 @@ fn userauth_pubkey_memory_compat(
         .userauth_pubkey_memory(username, None, content, passphrase)
         .map_err(|e| anyhow::anyhow!(e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 模拟连接已断开但映射尚未被保活线程更新的会话：对端关闭了 TCP，会话也未完成认证
+    fn dropped_session() -> Arc<Mutex<Session>> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        drop(listener.accept().unwrap());
+        let mut sess = Session::new().unwrap();
+        sess.set_tcp_stream(stream);
+        Arc::new(Mutex::new(sess))
+    }
+
+    #[test]
+    fn peer_closed_is_detected_on_the_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        assert!(!socket_peer_closed(socket2::SockRef::from(&stream)));
+
+        // 对端有未读数据时也不算关闭，且窥视不消耗数据
+        (&peer).write_all(b"x").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!socket_peer_closed(socket2::SockRef::from(&stream)));
+        let mut byte = [0u8; 1];
+        (&stream).read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"x");
+
+        drop(peer);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(socket_peer_closed(socket2::SockRef::from(&stream)));
+    }
+
+    fn test_connection(id: &str) -> SshConnection {
+        SshConnection {
+            id: id.to_string(),
+            name: id.to_string(),
+            host: "example.com".to_string(),
+            port: 22,
+            username: "me".to_string(),
+            auth_type: AuthType::Password { password: String::new(), keychain: None },
+            encoding: None,
+            env: None,
+            host_key_algorithms: None,
+        }
+    }
+
+    // 各清理路径都应清空同一组表
+    fn assert_session_state_cleared(manager: &SshManager, session_id: &str) {
+        for (name, table) in manager.session_tables() {
+            assert!(!table.session_ids().iter().any(|id| id == session_id), "{} still has {}", name, session_id);
+        }
+    }
+
+    fn seed_session_state(manager: &SshManager, session_id: &str) {
+        manager.connections.lock().unwrap().insert(session_id.to_string(), test_connection(session_id));
+        manager.nested_outer.lock().unwrap().insert(session_id.to_string(), test_connection("outer"));
+        manager.macro_outputs.lock().unwrap().insert(session_id.to_string(), Arc::default());
+        manager.auto_answer.lock().unwrap().insert(session_id.to_string());
+        manager.shell_titles.lock().unwrap().insert(session_id.to_string(), "title".to_string());
+    }
+
+    #[test]
+    fn gc_releases_all_state_of_gone_sessions() {
+        let manager = SshManager::new();
+        manager.sessions.lock().unwrap().insert("live".to_string(), dropped_session());
+        seed_session_state(&manager, "live");
+        seed_session_state(&manager, "gone");
+
+        let report = manager.gc_sessions();
+        assert_session_state_cleared(&manager, "gone");
+        assert!(report.stale_entries.contains(&"nested_outer:gone".to_string()));
+        assert!(report.stale_entries.contains(&"macro_outputs:gone".to_string()));
+        assert!(manager.connections.lock().unwrap().contains_key("live"));
+        assert!(manager.nested_outer.lock().unwrap().contains_key("live"));
+    }
+
+    #[test]
+    fn forget_session_releases_all_state() {
+        let manager = SshManager::new();
+        let session = dropped_session();
+        manager.sessions.lock().unwrap().insert("s1".to_string(), session.clone());
+        seed_session_state(&manager, "s1");
+        manager.forget_session("s1", &session);
+        assert_session_state_cleared(&manager, "s1");
+    }
+
+    #[test]
+    fn socket_info_reports_both_ends_of_the_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let info = session_socket_info(socket2::SockRef::from(&stream)).unwrap();
+        assert_eq!(info.address, listener.local_addr().unwrap().to_string());
+        assert_eq!(info.local_address, Some(stream.local_addr().unwrap().to_string()));
+    }
+
+    #[test]
+    fn deep_check_reaps_dropped_session() {
+        let manager = SshManager::new();
+        manager.sessions.lock().unwrap().insert("s1".to_string(), dropped_session());
+
+        // 浅检查只看映射
+        assert!(manager.is_connected("s1", false));
+        assert!(!manager.is_connected("s1", true));
+        assert!(!manager.sessions.lock().unwrap().contains_key("s1"));
+        assert!(!manager.is_connected("s1", false));
+    }
+
+    #[test]
+    fn forget_session_keeps_replacement() {
+        let manager = SshManager::new();
+        let stale = dropped_session();
+        let current = dropped_session();
+        manager.sessions.lock().unwrap().insert("s1".to_string(), current.clone());
+
+        // 重连后旧会话的清理不能移除新会话
+        manager.forget_session("s1", &stale);
+        let sessions = manager.sessions.lock().unwrap();
+        assert!(sessions.get("s1").is_some_and(|session| Arc::ptr_eq(session, &current)));
+    }
+
+    #[test]
+    fn disconnect_clears_session_state() {
+        let manager = SshManager::new();
+        manager.sessions.lock().unwrap().insert("s1".to_string(), dropped_session());
+        seed_session_state(&manager, "s1");
+        manager.disconnect("s1", false).unwrap();
+        assert_session_state_cleared(&manager, "s1");
+        assert!(!manager.is_connected("s1", false));
+        assert!(manager.list_sessions().is_empty());
+    }
+
+    #[test]
+    fn register_session_rechecks_the_session_limit() {
+        let manager = SshManager::new();
+        manager.set_max_sessions(1);
+        manager.sessions.lock().unwrap().insert("s1".to_string(), dropped_session());
+        let connection = test_connection("s2");
+        assert!(manager.register_session(&connection, Session::new().unwrap()).is_err());
+        assert_eq!(manager.list_sessions(), vec!["s1".to_string()]);
+        assert!(manager.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn push_capped_keeps_the_tail_on_a_char_boundary() {
+        let mut buffer = String::from("abc");
+        push_capped(&mut buffer, "de", 8);
+        assert_eq!(buffer, "abcde");
+        push_capped(&mut buffer, "fg", 4);
+        assert_eq!(buffer, "defg");
+        // 截断点落在多字节字符中间时向后移，结果可能略短于上限
+        push_capped(&mut buffer, "中文", 5);
+        assert_eq!(buffer, "文");
+    }
+
+    #[test]
+    fn split_sections_groups_lines_by_marker() {
+        let output = "@@os\nLinux\n\n@@tools\n  git\nrsync\n@@empty\n@@free\n       total used\n";
+        let sections = split_sections(output);
+        assert_eq!(sections["os"], vec!["Linux"]);
+        assert_eq!(sections["tools"], vec!["  git", "rsync"]);
+        assert_eq!(sections["free"], vec!["       total used"]);
+        assert!(!sections.contains_key("empty"));
+    }
+
+    #[test]
+    fn forward_state_tracks_error_changes_and_is_removed_on_stop() {
+        let manager = SshManager::new();
+        manager.set_forward_status("f1", ForwardStatus::Error, Some("bind failed".to_string()));
+        manager.set_forward_status("f1", ForwardStatus::Error, Some("keepalive failed".to_string()));
+        let state = manager.forward_status("f1").unwrap();
+        assert_eq!(state.error.as_deref(), Some("keepalive failed"));
+
+        manager.set_forward_status("f1", ForwardStatus::Stopped, None);
+        assert!(manager.forward_status("f1").is_err());
+    }
+
+    #[test]
+    fn inline_key_issues_are_reported_on_key_content() {
+        let connection = |key_path: &str, key_content: Option<&str>| SshConnection {
+            id: "c".to_string(),
+            name: "c".to_string(),
+            host: "example.com".to_string(),
+            port: 22,
+            username: "me".to_string(),
+            auth_type: AuthType::PrivateKey {
+                key_path: key_path.to_string(),
+                key_content: key_content.map(str::to_string),
+                passphrase: None,
+            },
+            encoding: None,
+            env: None,
+            host_key_algorithms: None,
+        };
+        let fields = |connection: &SshConnection| -> Vec<String> {
+            validate_connection(connection).into_iter().map(|issue| issue.field).collect()
+        };
+
+        assert_eq!(fields(&connection("", Some("not a key"))), vec!["key_content"]);
+        let missing = std::env::temp_dir().join("noterm-missing-key");
+        assert_eq!(fields(&connection(&missing.to_string_lossy(), None)), vec!["key_path"]);
+    }
+
+    #[test]
+    fn shell_quote_escapes_special_characters() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("with space"), "'with space'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("`id`"), "'`id`'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    // 经过 sh 解析后应原样还原
+    #[cfg(unix)]
+    #[test]
+    fn shell_quote_round_trips_through_sh() {
+        for value in ["a b", "$HOME", "`id`", "it's", "'; rm -rf x; '", "$(echo hi)\n"] {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", shell_quote(value)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), value);
+        }
+    }
+
+    #[test]
+    fn exclude_filter_follows_gitignore_rules() {
+        let filter = ExcludeFilter::new(&[
+            "node_modules/".to_string(),
+            "/build".to_string(),
+            "**/*.log".to_string(),
+            "a/**/b".to_string(),
+            "# comment".to_string(),
+            "*.tmp".to_string(),
+        ]);
+        let cases = [
+            // 路径, 是否目录, 是否排除
+            ("node_modules", true, true),
+            ("src/node_modules", true, true),
+            ("node_modules", false, false),
+            ("build", true, true),
+            ("build", false, true),
+            ("src/build", true, false),
+            ("app.log", false, true),
+            ("logs/deep/app.log", false, true),
+            ("app.log.txt", false, false),
+            ("a/b", false, true),
+            ("a/x/b", true, true),
+            ("a/x/y/b", false, true),
+            ("ab/b", false, false),
+            ("a/bc", false, false),
+            ("dir/file.tmp", false, true),
+            ("# comment", false, false),
+            ("src/main.rs", false, false),
+        ];
+        for (path, is_dir, expected) in cases {
+            assert_eq!(filter.is_excluded(path, is_dir), expected, "{}", path);
+        }
+        assert!(!ExcludeFilter::new(&[]).is_excluded("anything", false));
+    }
+
+    #[test]
+    fn glob_match_handles_stars() {
+        let glob = |pattern: &str, subject: &str| {
+            glob_match(&pattern.chars().collect::<Vec<_>>(), &subject.chars().collect::<Vec<_>>())
+        };
+        assert!(glob("*.rs", "main.rs"));
+        assert!(!glob("*.rs", "src/main.rs"));
+        assert!(glob("**/*.rs", "main.rs"));
+        assert!(glob("**/*.rs", "src/bin/main.rs"));
+        assert!(glob("src/**", "src/a/b"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(!glob("a/**/b", "a/xb"));
+        assert!(glob("?.txt", "a.txt"));
+        assert!(!glob("?", "/"));
+    }
+
+    #[test]
+    fn link_targets_must_stay_inside_download_root() {
+        assert!(link_target_stays_inside("a", "b"));
+        assert!(link_target_stays_inside("dir/link", "../file"));
+        assert!(link_target_stays_inside("dir/link", "./sub/file"));
+        assert!(!link_target_stays_inside("link", "../outside"));
+        assert!(!link_target_stays_inside("dir/link", "../../outside"));
+        assert!(!link_target_stays_inside("link", "/etc/passwd"));
+        assert!(!link_target_stays_inside("link", "sub/../../x"));
+        assert!(!link_target_stays_inside("link", ""));
+    }
+
+    #[test]
+    fn unsafe_remote_names_are_rejected() {
+        assert!(local_name_is_safe("file.txt"));
+        assert!(!local_name_is_safe(".."));
+        assert!(!local_name_is_safe("a/b"));
+        assert_eq!(local_name_is_safe("a\\b"), !cfg!(windows));
+        assert_eq!(local_name_is_safe("c:x"), !cfg!(windows));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_parent_through_escaping_link_is_detected() {
+        let root = crate::create_private_temp_dir("noterm-test-root").unwrap();
+        let outside = crate::create_private_temp_dir("noterm-test-outside").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        std::fs::create_dir(root.join("real")).unwrap();
+        let canonical = std::fs::canonicalize(&root).unwrap();
+        assert!(local_parent_inside(&canonical, &root.join("real/file")));
+        assert!(!local_parent_inside(&canonical, &root.join("link/file")));
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_dir_all(&outside);
+    }
+}
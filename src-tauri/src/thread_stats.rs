@@ -0,0 +1,72 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+
+// 后台线程计数（SSH 与本地终端共用）。通过 spawn 启动的线程在开始时加一、结束时减一，
+// panic 退出也会减一
+#[derive(Clone, Copy)]
+pub enum ThreadKind {
+    Reader,    // 终端输出读取、子系统通道泵
+    Keepalive, // 保活调度
+    Forward,   // 端口转发的 accept 与连接线程
+    Other,
+}
+
+static READER: AtomicUsize = AtomicUsize::new(0);
+static KEEPALIVE: AtomicUsize = AtomicUsize::new(0);
+static FORWARD: AtomicUsize = AtomicUsize::new(0);
+static OTHER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadStats {
+    pub reader: usize,
+    pub keepalive: usize,
+    pub forward: usize,
+    pub other: usize,
+    pub total: usize,
+}
+
+fn counter(kind: ThreadKind) -> &'static AtomicUsize {
+    match kind {
+        ThreadKind::Reader => &READER,
+        ThreadKind::Keepalive => &KEEPALIVE,
+        ThreadKind::Forward => &FORWARD,
+        ThreadKind::Other => &OTHER,
+    }
+}
+
+struct ThreadGuard(ThreadKind);
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        counter(self.0).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn spawn<F, T>(kind: ThreadKind, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    counter(kind).fetch_add(1, Ordering::Relaxed);
+    let guard = ThreadGuard(kind);
+    std::thread::spawn(move || {
+        let _guard = guard;
+        f()
+    })
+}
+
+pub fn snapshot() -> ThreadStats {
+    let reader = READER.load(Ordering::Relaxed);
+    let keepalive = KEEPALIVE.load(Ordering::Relaxed);
+    let forward = FORWARD.load(Ordering::Relaxed);
+    let other = OTHER.load(Ordering::Relaxed);
+    ThreadStats {
+        reader,
+        keepalive,
+        forward,
+        other,
+        total: reader + keepalive + forward + other,
+    }
+}
@@ -0,0 +1,517 @@
+use crate::ssh_manager::SshManager;
+use crate::thread_stats::{self, ThreadKind};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// 终端的 WebSocket 桥：会话输出以文本帧推给所有客户端，客户端发来的文本/二进制帧原样写入 shell，
+// 供外部面板、自动化测试把 NoTerm 当作可编程终端后端。默认关闭，需先 set_enabled(true)；
+// 默认只绑定 127.0.0.1，绑定非回环地址需显式 allow_remote。
+// 回环地址挡不住浏览器里的网页，因此握手必须带上每个桥随机生成的 token（查询参数 token= 或
+// Sec-WebSocket-Protocol），带 Origin 的请求还必须在 allowed_origins 中
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_FRAME: u64 = 1024 * 1024;
+const MAX_HANDSHAKE: usize = 8 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const TOKEN_BYTES: usize = 32;
+const CLIENT_QUEUE: usize = 1024; // 每个客户端待发送的帧；队列满说明客户端跟不上，直接断开，不拖慢读取线程
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WsBridgeOptions {
+    #[serde(default)]
+    pub port: u16, // 0 = 由系统分配，实际端口见 WsBridgeInfo.address
+    pub bind_address: Option<String>,
+    #[serde(default)]
+    pub allow_remote: bool,
+    #[serde(default)]
+    pub allowed_origins: Vec<String>, // 允许的浏览器 Origin，精确匹配；为空时拒绝所有带 Origin 的请求
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WsBridgeInfo {
+    pub session_id: String,
+    pub address: String,
+    pub token: String, // 客户端握手时必须提供
+    pub clients: usize,
+}
+
+// 握手校验所需的访问控制
+struct Access {
+    token: String,
+    allowed_origins: Vec<String>,
+}
+
+type ClientList = Arc<Mutex<Vec<(u64, SyncSender<Vec<u8>>)>>>;
+
+pub struct WsBridge {
+    session_id: String,
+    address: SocketAddr,
+    access: Arc<Access>,
+    stop: Arc<AtomicBool>,
+    clients: ClientList,
+}
+
+impl WsBridge {
+    pub fn start(manager: SshManager, session_id: &str, options: &WsBridgeOptions) -> anyhow::Result<WsBridge> {
+        if !is_enabled() {
+            return Err(anyhow::anyhow!("WebSocket bridge is disabled"));
+        }
+        let ip = match options.bind_address.as_deref().map(str::trim).filter(|addr| !addr.is_empty()) {
+            Some("localhost") | None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            Some(addr) => addr
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow::anyhow!("Invalid bind address '{}'", addr))?,
+        };
+        if !ip.is_loopback() && !options.allow_remote {
+            return Err(anyhow::anyhow!(
+                "Refusing to bind non-loopback address {} without allowRemote",
+                ip
+            ));
+        }
+        let listener = TcpListener::bind((ip, options.port))
+            .map_err(|e| anyhow::anyhow!("Failed to bind {}:{}: {}", ip, options.port, e))?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let access = Arc::new(Access {
+            token: generate_token()?,
+            allowed_origins: options
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let clients: ClientList = Arc::new(Mutex::new(Vec::new()));
+        let bridge = WsBridge {
+            session_id: session_id.to_string(),
+            address,
+            access: access.clone(),
+            stop: stop.clone(),
+            clients: clients.clone(),
+        };
+        let session_id = session_id.to_string();
+        let next_id = Arc::new(AtomicU64::new(1));
+        thread_stats::spawn(ThreadKind::Other, move || {
+            while !stop.load(Ordering::Relaxed) {
+                let (stream, peer) = match listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(50));
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!(session_id = %session_id, error = %e, "ws bridge accept failed");
+                        break;
+                    }
+                };
+                let manager = manager.clone();
+                let session_id = session_id.clone();
+                let clients = clients.clone();
+                let stop = stop.clone();
+                let access = access.clone();
+                let client_id = next_id.fetch_add(1, Ordering::Relaxed);
+                thread_stats::spawn(ThreadKind::Other, move || {
+                    if let Err(e) = serve_client(&manager, &session_id, stream, &access, client_id, &clients, &stop) {
+                        tracing::debug!(session_id = %session_id, peer = %peer, error = %e, "ws bridge client closed");
+                    }
+                    if let Ok(mut clients) = clients.lock() {
+                        clients.retain(|(id, _)| *id != client_id);
+                    }
+                });
+            }
+        });
+        tracing::info!(session_id = %bridge.session_id, address = %address, "ws bridge started");
+        Ok(bridge)
+    }
+
+    // 由 shell 读取线程调用：只做 try_send，跟不上的客户端被移除
+    pub fn broadcast(&self, output: &str) {
+        if output.is_empty() {
+            return;
+        }
+        let frame = encode_frame(OP_TEXT, output.as_bytes());
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.retain(|(_, tx)| tx.try_send(frame.clone()).is_ok());
+        }
+    }
+
+    // 丢弃发送端后写线程退出并关闭连接，读线程随之结束
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Ok(mut clients) = self.clients.lock() {
+            for (_, tx) in clients.drain(..) {
+                let _ = tx.try_send(encode_frame(OP_CLOSE, &1001u16.to_be_bytes()));
+            }
+        }
+    }
+
+    pub fn info(&self) -> WsBridgeInfo {
+        WsBridgeInfo {
+            session_id: self.session_id.clone(),
+            address: self.address.to_string(),
+            token: self.access.token.clone(),
+            clients: self.clients.lock().map(|clients| clients.len()).unwrap_or(0),
+        }
+    }
+}
+
+fn serve_client(
+    manager: &SshManager,
+    session_id: &str,
+    mut stream: TcpStream,
+    access: &Access,
+    client_id: u64,
+    clients: &ClientList,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let _ = stream.set_nodelay(true);
+    handshake(&mut stream, access)?;
+    stream.set_read_timeout(None)?;
+
+    let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(CLIENT_QUEUE);
+    let mut writer = stream.try_clone()?;
+    thread_stats::spawn(ThreadKind::Other, move || {
+        for frame in rx {
+            let closing = frame.first().is_some_and(|b| b & 0x0f == OP_CLOSE);
+            if writer.write_all(&frame).is_err() || closing {
+                break;
+            }
+        }
+        let _ = writer.shutdown(Shutdown::Both);
+    });
+    if stop.load(Ordering::Relaxed) {
+        let _ = tx.try_send(encode_frame(OP_CLOSE, &1001u16.to_be_bytes()));
+        return Ok(());
+    }
+    clients.lock().unwrap().push((client_id, tx.clone()));
+
+    loop {
+        let (opcode, payload) = read_frame(&mut stream)?;
+        match opcode {
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                if !payload.is_empty() {
+                    manager.write_bytes_to_shell(session_id, &payload)?;
+                }
+            }
+            OP_PING => {
+                if tx.try_send(encode_frame(OP_PONG, &payload)).is_err() {
+                    break;
+                }
+            }
+            OP_PONG => {}
+            OP_CLOSE => {
+                let _ = tx.try_send(encode_frame(OP_CLOSE, &payload[..payload.len().min(2)]));
+                break;
+            }
+            other => return Err(anyhow::anyhow!("Unsupported opcode {:#x}", other)),
+        }
+    }
+    Ok(())
+}
+
+fn handshake(stream: &mut TcpStream, access: &Access) -> anyhow::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_HANDSHAKE {
+            return Err(anyhow::anyhow!("Handshake too large"));
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(anyhow::anyhow!("Connection closed during handshake"));
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    match upgrade_response(&String::from_utf8_lossy(&request), access) {
+        Ok(response) => {
+            stream.write_all(response.as_bytes())?;
+            Ok(())
+        }
+        Err((status, reason)) => {
+            let _ = stream.write_all(format!("HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n", status).as_bytes());
+            Err(anyhow::anyhow!(reason))
+        }
+    }
+}
+
+// 校验升级请求并生成 101 响应；失败时返回 (状态行, 原因)
+fn upgrade_response(request: &str, access: &Access) -> Result<String, (&'static str, String)> {
+    let header = |name: &str| {
+        request.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+        })
+    };
+    let target = request
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|rest| rest.split_whitespace().next());
+    let upgrade = header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let (target, key) = match (target, header("Sec-WebSocket-Key")) {
+        (Some(target), Some(key)) if upgrade => (target, key),
+        _ => return Err(("400 Bad Request", "Not a WebSocket upgrade request".to_string())),
+    };
+    if let Some(origin) = header("Origin") {
+        let origin = origin.trim_end_matches('/');
+        if !access.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            return Err(("403 Forbidden", format!("Origin '{}' is not allowed", origin)));
+        }
+    }
+    let query_token = target
+        .split_once('?')
+        .and_then(|(_, query)| query.split('&').find_map(|pair| pair.strip_prefix("token=")));
+    // 浏览器的 WebSocket 不能设置自定义头，token 也可作为子协议传入，此时必须原样回显
+    let protocol = header("Sec-WebSocket-Protocol").and_then(|protocols| {
+        protocols
+            .split(',')
+            .map(str::trim)
+            .find(|protocol| token_matches(protocol, &access.token))
+            .map(str::to_string)
+    });
+    if protocol.is_none() && !query_token.is_some_and(|token| token_matches(token, &access.token)) {
+        return Err(("401 Unauthorized", "Missing or invalid bridge token".to_string()));
+    }
+    let accept = base64::engine::general_purpose::STANDARD.encode(Sha1::digest(format!("{}{}", key, WS_GUID)));
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n",
+        accept
+    );
+    if let Some(protocol) = protocol {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+    }
+    response.push_str("\r\n");
+    Ok(response)
+}
+
+// 比较耗时与首个不同字节的位置无关
+fn token_matches(candidate: &str, token: &str) -> bool {
+    candidate.len() == token.len()
+        && candidate
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn generate_token() -> anyhow::Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow::anyhow!("Failed to generate bridge token: {}", e))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// 客户端帧必须带掩码（RFC 6455 5.1）；分片帧不重组，数据按到达顺序写入 shell
+fn read_frame(stream: &mut impl Read) -> anyhow::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0f;
+    if head[1] & 0x80 == 0 {
+        return Err(anyhow::anyhow!("Unmasked client frame"));
+    }
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut ext = [0u8; 2];
+            stream.read_exact(&mut ext)?;
+            u16::from_be_bytes(ext) as u64
+        }
+        127 => {
+            let mut ext = [0u8; 8];
+            stream.read_exact(&mut ext)?;
+            u64::from_be_bytes(ext)
+        }
+        len => len as u64,
+    };
+    if len > MAX_FRAME {
+        return Err(anyhow::anyhow!("Frame of {} bytes exceeds limit", len));
+    }
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access() -> Access {
+        Access {
+            token: "secret".to_string(),
+            allowed_origins: vec!["http://localhost:5173".to_string()],
+        }
+    }
+
+    fn request(target: &str, extra: &str) -> String {
+        format!(
+            "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+            target, extra
+        )
+    }
+
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![first];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn accepts_token_in_query() {
+        let response = upgrade_response(&request("/?token=secret", ""), &access()).unwrap();
+        // RFC 6455 1.3 的示例
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(!response.contains("Sec-WebSocket-Protocol"));
+    }
+
+    #[test]
+    fn accepts_token_as_subprotocol() {
+        let response = upgrade_response(
+            &request("/", "Sec-WebSocket-Protocol: chat, secret\r\nOrigin: http://localhost:5173\r\n"),
+            &access(),
+        )
+        .unwrap();
+        assert!(response.contains("Sec-WebSocket-Protocol: secret\r\n"));
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_token() {
+        assert_eq!(upgrade_response(&request("/", ""), &access()).unwrap_err().0, "401 Unauthorized");
+        assert_eq!(upgrade_response(&request("/?token=secreT", ""), &access()).unwrap_err().0, "401 Unauthorized");
+        assert_eq!(upgrade_response(&request("/?token=secret2", ""), &access()).unwrap_err().0, "401 Unauthorized");
+    }
+
+    #[test]
+    fn rejects_unlisted_origin() {
+        let err = upgrade_response(&request("/?token=secret", "Origin: https://evil.example\r\n"), &access()).unwrap_err();
+        assert_eq!(err.0, "403 Forbidden");
+        let err = upgrade_response(&request("/?token=secret", "Origin: null\r\n"), &access()).unwrap_err();
+        assert_eq!(err.0, "403 Forbidden");
+    }
+
+    #[test]
+    fn rejects_non_upgrade_request() {
+        let plain = "GET /?token=secret HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n";
+        assert_eq!(upgrade_response(plain, &access()).unwrap_err().0, "400 Bad Request");
+    }
+
+    #[test]
+    fn reads_masked_frames() {
+        let mut input: &[u8] = &masked(0x81, b"Hello");
+        assert_eq!(read_frame(&mut input).unwrap(), (OP_TEXT, b"Hello".to_vec()));
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn reads_extended_lengths() {
+        for len in [125usize, 126, 65535, 65536, 200_000] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut input: &[u8] = &masked(0x82, &payload);
+            assert_eq!(read_frame(&mut input).unwrap(), (OP_BINARY, payload));
+        }
+    }
+
+    #[test]
+    fn reads_fragmented_message_in_order() {
+        let mut stream = masked(0x01, b"ec");
+        stream.extend(masked(0x89, b"ping"));
+        stream.extend(masked(0x80, b"ho\n"));
+        let mut input: &[u8] = &stream;
+        assert_eq!(read_frame(&mut input).unwrap(), (OP_TEXT, b"ec".to_vec()));
+        assert_eq!(read_frame(&mut input).unwrap(), (OP_PING, b"ping".to_vec()));
+        assert_eq!(read_frame(&mut input).unwrap(), (OP_CONTINUATION, b"ho\n".to_vec()));
+    }
+
+    #[test]
+    fn rejects_unmasked_and_oversized_frames() {
+        let mut input: &[u8] = &[0x81, 0x02, b'h', b'i'];
+        assert!(read_frame(&mut input).is_err());
+        let mut oversized = vec![0x82, 0x80 | 127];
+        oversized.extend_from_slice(&(MAX_FRAME + 1).to_be_bytes());
+        let mut input: &[u8] = &oversized;
+        assert!(read_frame(&mut input).is_err());
+    }
+
+    #[test]
+    fn encodes_server_frames() {
+        assert_eq!(encode_frame(OP_TEXT, b"hi"), vec![0x81, 0x02, b'h', b'i']);
+        let frame = encode_frame(OP_BINARY, &[0u8; 300]);
+        assert_eq!(&frame[..4], &[0x82, 126, 0x01, 0x2c]);
+        assert_eq!(frame.len(), 304);
+        let frame = encode_frame(OP_BINARY, &vec![0u8; 70_000]);
+        assert_eq!(&frame[..2], &[0x82, 127]);
+        assert_eq!(u64::from_be_bytes(frame[2..10].try_into().unwrap()), 70_000);
+        assert_eq!(frame.len(), 70_010);
+    }
+
+    #[test]
+    fn token_compare() {
+        assert!(token_matches("abc", "abc"));
+        assert!(!token_matches("abd", "abc"));
+        assert!(!token_matches("ab", "abc"));
+        assert_eq!(generate_token().unwrap().len(), TOKEN_BYTES * 2);
+    }
+}
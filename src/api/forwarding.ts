@@ -2,9 +2,12 @@ import { invoke } from "@tauri-apps/api/core";
 import type { ForwardRule } from "../store/forwardings";
 import type { SshConnection } from "../types/ssh";
 
+// localUnix：本地 Unix socket 转发到远端 socket（仅 macOS/Linux）
+export type ForwardConfigKind = ForwardRule["kind"] | "localUnix";
+
 export type ForwardConfig = {
   id: string;
-  kind: ForwardRule["kind"];
+  kind: ForwardConfigKind;
   connection: SshConnection;
   localBindHost?: string;
   localBindPort?: number;
@@ -12,6 +15,23 @@ export type ForwardConfig = {
   remoteBindPort?: number;
   targetHost?: string;
   targetPort?: number;
+  localSocketPath?: string;
+  remoteSocketPath?: string;
+  oneShot?: boolean;
+};
+
+export type ForwardStoppedEvent = {
+  id: string;
+  reason: string;
+};
+
+export type ForwardStatus = "listening" | "paused" | "error" | "stopped";
+
+// forward-status 事件负载，也是 forwardStatus 的返回值
+export type ForwardState = {
+  id: string;
+  status: ForwardStatus;
+  error?: string | null;
 };
 
 export async function startForward(config: ForwardConfig): Promise<void> {
@@ -25,3 +45,60 @@ export async function stopForward(id: string): Promise<void> {
 export async function listForwards(): Promise<string[]> {
   return await invoke<string[]>("ssh_forward_list");
 }
+
+export type ForwardInfo = {
+  id: string;
+  kind: ForwardConfigKind;
+  connectionId: string;
+  bindHost?: string;
+  bindPort?: number;
+  targetHost?: string;
+  targetPort?: number;
+  localSocketPath?: string;
+  remoteSocketPath?: string;
+  status: ForwardStatus;
+};
+
+export async function findForwards(targetHost: string, targetPort?: number): Promise<ForwardInfo[]> {
+  return await invoke<ForwardInfo[]>("ssh_forward_find", { targetHost, targetPort });
+}
+
+// keepExisting 默认 true：只拒绝新连接，进行中的连接继续
+export async function pauseForward(id: string, keepExisting?: boolean): Promise<void> {
+  await invoke("ssh_forward_pause", { id, keepExisting });
+}
+
+export async function resumeForward(id: string): Promise<void> {
+  await invoke("ssh_forward_resume", { id });
+}
+
+// 转发连接两端 TCP socket 的保活参数（秒），默认空闲 60 秒后每 15 秒探测一次
+export type ForwardKeepalive = {
+  idleSecs: number;
+  intervalSecs: number;
+};
+
+// 传 null 关闭；只影响之后建立的连接
+export async function setForwardKeepalive(keepalive: ForwardKeepalive | null): Promise<void> {
+  await invoke("ssh_set_forward_keepalive", { keepalive });
+}
+
+export async function forwardStatus(id: string): Promise<ForwardState> {
+  return await invoke<ForwardState>("ssh_forward_status", { id });
+}
+
+export type BindAvailability = "free" | "inUse" | "unavailable" | "unknown";
+
+export type BindCheck = {
+  status: BindAvailability;
+  error?: string | null;
+};
+
+// 远程转发在服务端绑定，无法预检，总是返回 unknown
+export async function checkForwardBind(
+  bindPort: number,
+  bindHost?: string,
+  kind?: ForwardConfigKind,
+): Promise<BindCheck> {
+  return await invoke<BindCheck>("ssh_forward_check_bind", { kind, bindHost, bindPort });
+}
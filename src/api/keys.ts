@@ -2,9 +2,23 @@ import { invoke } from "@tauri-apps/api/core";
 
 export type GenerateKeyAlgorithm = "ed25519" | "rsa4096";
 
+export type KeyFormat = "openssh" | "pem" | "pkcs8" | "rfc4716";
+
+export type PublicKeyExportFormat = "rfc4716" | "pem" | "pkcs8";
+
 export interface GeneratedKeypair {
   key_path: string;
   public_key: string;
+  public_key_export?: string;
+  algorithm: GenerateKeyAlgorithm;
+  format: string;
+  comment?: string;
+}
+
+export interface MemoryKeypair {
+  private_key: string;
+  public_key: string;
+  fingerprint: string;
   algorithm: GenerateKeyAlgorithm;
   comment?: string;
 }
@@ -14,7 +28,18 @@ export async function generateKeypair(input: {
   name: string;
   passphrase?: string;
   comment?: string;
+  format?: KeyFormat;
+  publicExport?: PublicKeyExportFormat;
 }): Promise<GeneratedKeypair> {
   return await invoke("ssh_generate_keypair", input);
 }
 
+
+// 密钥只存在于返回值中，不落盘
+export async function generateKeypairMemory(input: {
+  algorithm: GenerateKeyAlgorithm;
+  passphrase?: string;
+  comment?: string;
+}): Promise<MemoryKeypair> {
+  return await invoke("ssh_generate_keypair_memory", input);
+}
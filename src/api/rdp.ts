@@ -1,8 +1,17 @@
 import { invoke } from "@tauri-apps/api/core";
 import type { RdpConnectionConfig } from "../types/connection";
+import type { ConnectionIssue } from "./ssh";
+
+export interface RdpValidation {
+  warnings: ConnectionIssue[];
+  content: string;
+}
 
 export const rdpApi = {
   open: async (connection: RdpConnectionConfig): Promise<void> => {
     await invoke("rdp_open", { connection });
   },
+  validate: async (connection: RdpConnectionConfig): Promise<RdpValidation> => {
+    return await invoke<RdpValidation>("rdp_validate", { connection });
+  },
 };
@@ -1,5 +1,6 @@
 import { invoke } from '@tauri-apps/api/core';
 import { SshConnection, SftpEntry } from '../types/ssh';
+import type { ForwardConfig } from './forwarding';
 
 export interface EndpointCheck {
   ip: string;
@@ -7,6 +8,32 @@ export interface EndpointCheck {
   latency_ms: number;
 }
 
+// 未经历的阶段为 null；failedPhase 为 dns / tcp / handshake / auth
+export interface ConnectTiming {
+  dnsMs: number | null;
+  tcpMs: number | null;
+  handshakeMs: number | null;
+  authMs: number | null;
+  totalMs: number;
+  address: string | null;
+  failedPhase: string | null;
+  finishedAt: number;
+}
+
+export interface LocalToolStatus {
+  name: string;
+  category: 'keys' | 'clipboard' | 'rdp' | 'vnc';
+  available: boolean;
+}
+
+export interface ClipboardSupport {
+  read: boolean;
+  write: boolean;
+  readTool?: string | null;
+  writeTool?: string | null;
+  hint?: string | null;
+}
+
 export interface ControlledCommandResult {
   exitCode: number;
   stdout: string;
@@ -15,47 +42,756 @@ export interface ControlledCommandResult {
   timedOut: boolean;
 }
 
+export interface MergedExecResult {
+  exitCode: number;
+  ptyMerged: boolean;
+  durationMs: number;
+  cancelled: boolean;
+}
+
+// exec-chunk 事件：按 seq 顺序的合并输出
+export interface ExecChunkEvent {
+  session_id: string;
+  stream_id: string;
+  seq: number;
+  data: string;
+  pty_merged: boolean;
+}
+
+// pendingBytes 为本地尚未读取的数据；paused 表示 SSH 流控窗口耗尽，远端暂停发送
+export interface ShellBufferStats {
+  pendingBytes: number;
+  windowRemaining: number;
+  windowSize: number;
+  paused: boolean;
+  tailBytes: number;
+}
+
+// encodingGuess 为空表示无法确定编码；isBinary 时前端应使用十六进制视图
+export interface SftpPreview {
+  isBinary: boolean;
+  encodingGuess?: string | null;
+  contentB64: string;
+  truncated: boolean;
+}
+
+export interface InterfaceAddress {
+  name: string;
+  family: 'inet' | 'inet6';
+  address: string;
+  prefixLen?: number | null;
+}
+
+// source 为实际使用的工具；missing 列出远端不可用的工具
+export interface InterfaceList {
+  interfaces: InterfaceAddress[];
+  source?: 'ip' | 'ifconfig' | null;
+  missing: string[];
+}
+
+export interface ConnectedSession {
+  sessionId: string;
+  username: string;
+  usernameFallback: boolean;
+}
+
+export interface TransferRecord {
+  path: string;
+  direction: 'upload' | 'download' | 'copy';
+  bytes: number;
+  durationMs: number;
+  success: boolean;
+  error?: string | null;
+  finishedAt: number;
+}
+
+// 进行中的传输；rateBps 不含续传前已存在的部分
+export interface ActiveTransfer {
+  id: string;
+  sessionId: string;
+  direction: 'upload' | 'download' | 'copy';
+  path: string;
+  transferred: number;
+  total: number;
+  rateBps: number;
+  elapsedMs: number;
+}
+
+// 未完成的上传/下载，断开后仍保留；offset 为续传起点
+export interface InterruptedTransfer {
+  id: string;
+  sessionId: string;
+  direction: 'upload' | 'download';
+  localPath: string;
+  remotePath: string;
+  total: number;
+  offset: number;
+  error: string;
+  interruptedAt: number;
+}
+
+// terminal-disconnected 事件中的结构化原因：exited 为远端正常退出（exit / logout），
+// signaled 为被信号结束，closed 为远端未给出退出状态就关闭，networkError 为连接错误
+export type DisconnectReason =
+  | { kind: "exited"; exitStatus: number }
+  | { kind: "signaled"; signal: string; message: string | null }
+  | { kind: "closed" }
+  | { kind: "networkError"; message: string };
+
+export interface TerminalDisconnectedEvent {
+  session_id: string;
+  reason: string;
+  detail: DisconnectReason;
+}
+
+// terminal-throttled 事件：throttled 为 false 表示输出已回到上限以内
+export interface TerminalThrottledEvent {
+  session_id: string;
+  throttled: boolean;
+  limit: number;
+}
+
+export type ScriptInterpreter = "bash" | "sh" | "python";
+
+// viaSftp 为 false 表示脚本经标准输入传给解释器（服务端不支持 SFTP）
+export interface ScriptRunResult {
+  exitCode: number;
+  stdout: string;
+  stderr: string;
+  durationMs: number;
+  viaSftp: boolean;
+}
+
+export interface HostCommandResult {
+  exitCode: number;
+  stdout: string;
+  stderr: string;
+  durationMs: number;
+  error?: string | null;
+}
+
+// existing 为 true 时只附加已存在的目标（tmux 可为 session:window）
+export interface MultiplexerAttach {
+  kind: 'tmux' | 'screen';
+  name: string;
+  existing?: boolean;
+}
+
+// 非 POSIX shell 上 os/release/arch 为空；tools 为各工具是否可用
+export interface HostProbe {
+  os?: string | null;
+  release?: string | null;
+  arch?: string | null;
+  home?: string | null;
+  sftp: boolean;
+  tools: Record<string, boolean>;
+}
+
+// 服务端看到的登录身份；连接未填用户名时可能与配置不同
+export interface RemoteUserInfo {
+  username: string;
+  shell?: string | null;
+  home?: string | null;
+  uid?: number | null;
+  gid?: number | null;
+  groups: string[];
+}
+
+export interface TmuxWindow {
+  index: number;
+  name: string;
+  active: boolean;
+  target: string;
+}
+
+export interface TmuxSession {
+  name: string;
+  attached: boolean;
+  windows: TmuxWindow[];
+}
+
+// waitFor 为正则，匹配上一步之后的输出后再发送 send；否则等待 delayMs
+export interface LoginStep {
+  send: string;
+  waitFor?: string;
+  delayMs?: number;
+  timeoutMs?: number;
+}
+
+export interface ShellOptions {
+  multiplexer?: MultiplexerAttach;
+  bufferSize?: number;
+  loginScript?: LoginStep[];
+  title?: string;
+  logPath?: string;
+  logMaxBytes?: number;
+}
+
+export interface TerminalTitleEvent {
+  session_id: string;
+  title: string;
+}
+
+export interface LoginScriptResultEvent {
+  session_id: string;
+  completed: boolean;
+  steps_sent: number;
+  error?: string | null;
+}
+
+// send 为 base64 编码的原始字节；先发送，再等待 waitFor 匹配或 waitMs
+export interface MacroStep {
+  send: string;
+  waitMs?: number;
+  waitFor?: string;
+  timeoutMs?: number;
+}
+
+export interface MacroResultEvent {
+  session_id: string;
+  macro_id: string;
+  completed: boolean;
+  cancelled: boolean;
+  steps_sent: number;
+  error?: string | null;
+}
+
+export interface WsBridgeOptions {
+  port?: number;
+  bindAddress?: string;
+  allowRemote?: boolean;
+  allowedOrigins?: string[];
+}
+
+export interface WsBridgeInfo {
+  sessionId: string;
+  address: string;
+  token: string;
+  clients: number;
+}
+
+export interface SftpSessionInfo {
+  sessionId: string;
+  authenticated: boolean;
+}
+
+export interface SftpUploadDirSummary {
+  uploaded: number;
+  skipped: number;
+  total: number;
+  cancelled: boolean;
+  verified: number;
+  mismatched: SftpVerifyMismatch[];
+  excluded: number;
+}
+
+export interface PathSample {
+  direction: 'upload' | 'download';
+  bytes: number;
+  transferred: number;
+  elapsedMs: number;
+  bytesPerSec: number;
+  completed: boolean;
+  error?: string | null;
+}
+
+export interface PathDiagnostics {
+  samples: PathSample[];
+  socket?: {
+    address: string;
+    localAddress?: string | null;
+    mss?: number | null;
+    sendBuffer?: number | null;
+    recvBuffer?: number | null;
+    nodelay?: boolean | null;
+  } | null;
+  channelWindow?: number | null;
+  observations: string[];
+}
+
+// skip 跳过链接；preserve 在本地重建链接（仅 Unix）；follow 复制链接目标，已访问的目录不再进入
+export type SymlinkPolicy = 'skip' | 'preserve' | 'follow';
+
+export interface SftpDownloadDirSummary {
+  downloaded: number;
+  total: number;
+  symlinksCreated: number;
+  symlinksSkipped: number;
+  loopsSkipped: number;
+  namesSkipped: number;
+  cancelled: boolean;
+}
+
+export interface SftpVerifyMismatch {
+  path: string;
+  reason: string;
+}
+
+// 传输后校验；size 默认开启，hash 需要远端 sha256sum 或 shasum
+export interface TransferVerify {
+  size?: boolean;
+  hash?: boolean;
+}
+
+export interface SftpSyncSummary {
+  uploaded: number;
+  skipped: number;
+  deleted: number;
+  excluded: number;
+}
+
+// 后台线程按用途计数
+export interface ThreadStats {
+  reader: number;
+  keepalive: number;
+  forward: number;
+  other: number;
+  total: number;
+}
+
+export interface AppResourceStats {
+  connections: ConnectionCount;
+  shells: number;
+  localSessions: number;
+  threads: ThreadStats;
+}
+
+// 上次运行结束时仍在使用的连接与转发；密码、私钥内容与口令已去除，重连时需重新提供
+export interface SessionSnapshot {
+  savedAt: number;
+  connections: SshConnection[];
+  forwards: ForwardConfig[];
+}
+
+export interface DisconnectSummary {
+  shellClosed: boolean;
+  sftpClosed: boolean;
+  subsystemsClosed: number;
+  uploadStreamsDropped: number;
+  forwardsStopped: string[];
+}
+
+// stale_entries 形如 "shell_titles:<sessionId>"
+export interface SessionGcReport {
+  readersReaped: string[];
+  keepalivesRemoved: string[];
+  subsystemsClosed: string[];
+  staleEntries: string[];
+}
+
+export interface ConnectionCount {
+  sessions: number;
+  sftpSessions: number;
+  forwards: number;
+  maxSessions?: number;
+}
+
+export interface ShellSize {
+  cols: number;
+  rows: number;
+}
+
+export interface SshPermsReport {
+  changed: string[];
+  warnings: string[];
+}
+
+export interface ShellSizeReport {
+  requested?: ShellSize;
+  observed?: ShellSize;
+  observedTty?: string;
+}
+
+export interface SftpEditInfo {
+  editId: string;
+  localPath: string;
+  remotePath: string;
+}
+
+export interface SftpEditSyncedEvent {
+  edit_id: string;
+  session_id: string;
+  remote_path: string;
+  error?: string | null;
+}
+
+export interface TerminalLatencyEvent {
+  session_id: string;
+  ms: number;
+}
+
+export interface HostStats {
+  memTotal?: number;
+  memUsed?: number;
+  loadAverage?: [number, number, number];
+  cpuCount?: number;
+  missing: string[];
+}
+
+// sftp-chunk 事件；cancelled 为 true 时流被中止，已收到的数据不完整
+export interface SftpChunkEvent {
+  session_id: string;
+  stream_id: string;
+  seq: number;
+  data_b64: string;
+  done: boolean;
+  cancelled: boolean;
+}
+
+export interface SubsystemDataEvent {
+  channel_id: string;
+  data_b64: string;
+}
+
+export interface SubsystemClosedEvent {
+  channel_id: string;
+  reason: string;
+}
+
+export interface BracketedPasteEvent {
+  session_id: string;
+  enabled: boolean;
+}
+
+export interface HostKeyInfo {
+  keyType: string;
+  keyB64: string;
+  fingerprintSha256: string;
+}
+
+// OpenSSH 配置导入结果；ignored 为未支持而被跳过的指令
+export interface ImportedHost {
+  connection: SshConnection;
+  requestTty?: boolean | null;
+  ignored: string[];
+}
+
+export interface ServerAlgorithms {
+  serverVersion: string;
+  kex: string[];
+  hostKey: string[];
+  ciphers: string[];
+  macs: string[];
+  deprecated: string[];
+}
+
+export interface ConnectResult {
+  connectionId: string;
+  sessionId?: string | null;
+  error?: string | null;
+}
+
+export interface SshConnectionStateEvent {
+  connection_id: string;
+  state: 'connecting' | 'connected' | 'failed';
+  error?: string | null;
+}
+
+export interface LocalSessionInfo {
+  shell: string;
+  pid?: number | null;
+  cwd?: string | null;
+}
+
+export interface ConnectionIssue {
+  field: string;
+  message: string;
+}
+
+export const WRONG_PASSPHRASE_ERROR = 'Wrong passphrase for private key';
+
+export const isWrongPassphraseError = (error: unknown): boolean =>
+  String(error).includes(WRONG_PASSPHRASE_ERROR);
+
+export const TOO_MANY_AUTH_FAILURES_ERROR = 'likely due to too many authentication failures';
+
+export const isTooManyAuthFailuresError = (error: unknown): boolean =>
+  String(error).includes(TOO_MANY_AUTH_FAILURES_ERROR);
+
+export const PROCESS_EXITED_ERROR = 'Local process has exited';
+
+export const isProcessExitedError = (error: unknown): boolean =>
+  String(error).includes(PROCESS_EXITED_ERROR);
+
 export const sshApi = {
-  connect: async (connection: SshConnection): Promise<string> => {
+  // username 为实际登录的用户名；usernameFallback 表示连接未填用户名而使用了本机用户名
+  connect: async (connection: SshConnection): Promise<ConnectedSession> => {
     return await invoke('ssh_connect', { connection });
   },
 
+  // 经已连接的外层会话建立内层会话；返回的会话 id 可用于 shell、SFTP、转发等所有命令
+  connectNested: async (outerSessionId: string, connection: SshConnection): Promise<string> => {
+    return await invoke('ssh_connect_nested', { outerSessionId, connection });
+  },
+
+  // 并发连接多台主机，每台完成时发出 ssh-connection-state 事件
+  connectMany: async (connections: SshConnection[]): Promise<ConnectResult[]> => {
+    return await invoke('ssh_connect_many', { connections });
+  },
+
+  retryWithPassphrase: async (connection: SshConnection, passphrase: string): Promise<string> => {
+    return await invoke('ssh_retry_with_passphrase', { connection, passphrase });
+  },
+
+  validateConnection: async (connection: SshConnection): Promise<ConnectionIssue[]> => {
+    return await invoke('ssh_validate_connection', { connection });
+  },
+
+  getHostKey: async (sessionId: string): Promise<HostKeyInfo> => {
+    return await invoke('ssh_get_hostkey', { sessionId });
+  },
+
+  // path 为空时读取 ~/.ssh/config
+  importConfig: async (path?: string): Promise<ImportedHost[]> => {
+    return await invoke('ssh_import_config', { path });
+  },
+
+  serverAlgorithms: async (host: string, port: number): Promise<ServerAlgorithms> => {
+    return await invoke('ssh_server_algorithms', { host, port });
+  },
+
   checkEndpoint: async (host: string, port: number): Promise<EndpointCheck> => {
     return await invoke('ssh_check_endpoint', { host, port });
   },
 
-  openShell: async (sessionId: string): Promise<void> => {
-    return await invoke('ssh_open_shell', { sessionId });
+  checkLocalTools: async (): Promise<LocalToolStatus[]> => {
+    return await invoke('check_local_tools');
   },
 
-  localOpenShell: async (sessionId: string, shell?: string): Promise<void> => {
-    return await invoke('local_open_shell', { sessionId, shell });
+  clipboardAvailable: async (): Promise<ClipboardSupport> => {
+    return await invoke('clipboard_available');
+  },
+
+  cloneSession: async (sessionId: string, newId: string): Promise<string> => {
+    return await invoke('ssh_clone_session', { sessionId, newId });
+  },
+
+  openShell: async (
+    sessionId: string,
+    multiplexer?: MultiplexerAttach,
+    options?: ShellOptions,
+  ): Promise<void> => {
+    return await invoke('ssh_open_shell', { sessionId, multiplexer, options });
+  },
+
+  // 连接后一次取回主机卡片所需的信息
+  hostProbe: async (sessionId: string): Promise<HostProbe> => {
+    return await invoke('ssh_host_probe', { sessionId });
+  },
+
+  // 按会话缓存，断开后重新读取
+  userInfo: async (sessionId: string): Promise<RemoteUserInfo> => {
+    return await invoke('ssh_user_info', { sessionId });
+  },
+
+  // 没有运行中的 tmux server 时返回空数组
+  tmuxList: async (sessionId: string): Promise<TmuxSession[]> => {
+    return await invoke('ssh_tmux_list', { sessionId });
+  },
+
+  tmuxAttach: async (sessionId: string, target: string, options?: ShellOptions): Promise<void> => {
+    return await invoke('ssh_tmux_attach', { sessionId, target, options });
+  },
+
+  localOpenShell: async (
+    sessionId: string,
+    shell?: string,
+    multiplexer?: MultiplexerAttach,
+    options?: ShellOptions,
+    cols?: number,
+    rows?: number,
+  ): Promise<void> => {
+    return await invoke('local_open_shell', { sessionId, shell, multiplexer, options, cols, rows });
+  },
+
+  localRestartShell: async (sessionId: string, shell?: string): Promise<void> => {
+    return await invoke('local_restart_shell', { sessionId, shell });
   },
 
   writeToShell: async (sessionId: string, data: string): Promise<void> => {
     return await invoke('ssh_write_to_shell', { sessionId, data });
   },
 
+  // 原始字节输入（控制字符、二进制协议），dataB64 为 base64 编码
+  writeBytesToShell: async (sessionId: string, dataB64: string): Promise<void> => {
+    return await invoke('ssh_write_to_shell_bytes', { sessionId, dataB64 });
+  },
+
+  // 打开 netconf 等子系统，返回通道 id；数据通过 subsystem-data / subsystem-closed 事件推送
+  openSubsystem: async (sessionId: string, name: string): Promise<string> => {
+    return await invoke('ssh_open_subsystem', { sessionId, name });
+  },
+
+  subsystemWrite: async (channelId: string, dataB64: string): Promise<void> => {
+    return await invoke('ssh_subsystem_write', { channelId, dataB64 });
+  },
+
+  subsystemClose: async (channelId: string): Promise<void> => {
+    return await invoke('ssh_subsystem_close', { channelId });
+  },
+
   localWriteToShell: async (sessionId: string, data: string): Promise<void> => {
     return await invoke('local_write_to_shell', { sessionId, data });
   },
 
-  resizePty: async (sessionId: string, cols: number, rows: number): Promise<void> => {
-    return await invoke('ssh_resize_pty', { sessionId, cols, rows });
+  localWriteBytesToShell: async (sessionId: string, dataB64: string): Promise<void> => {
+    return await invoke('local_write_to_shell_bytes', { sessionId, dataB64 });
+  },
+
+  signalShell: async (sessionId: string, signal: string): Promise<void> => {
+    return await invoke('ssh_signal_shell', { sessionId, signal });
+  },
+
+  updateKeepalive: async (sessionId: string, secs: number): Promise<void> => {
+    return await invoke('ssh_update_keepalive', { sessionId, secs });
+  },
+
+  setLatencyMonitor: async (sessionId: string, enabled: boolean): Promise<void> => {
+    return await invoke('ssh_set_latency_monitor', { sessionId, enabled });
+  },
+
+  setShellTitle: async (sessionId: string, title: string): Promise<void> => {
+    return await invoke('ssh_set_shell_title', { sessionId, title });
+  },
+
+  // 返回轮转后旧日志文件的路径
+  rotateLog: async (sessionId: string): Promise<string> => {
+    return await invoke('ssh_rotate_log', { sessionId });
+  },
+
+  shellTitle: async (sessionId: string): Promise<string | null> => {
+    return await invoke('ssh_shell_title', { sessionId });
+  },
+
+  // 远端程序是否开启了 bracketed paste；变化时另有 bracketed-paste 事件
+  bracketedPaste: async (sessionId: string): Promise<boolean> => {
+    return await invoke('ssh_bracketed_paste', { sessionId });
+  },
+
+  // 运行中切换远端输出的解码编码（encoding_rs 支持的标签，如 gbk、big5、utf-8），返回规范名
+  setSessionEncoding: async (sessionId: string, encoding: string): Promise<string> => {
+    return await invoke('ssh_set_session_encoding', { sessionId, encoding });
+  },
+
+  // 最近一屏的原始输出（含控制序列），可写入离屏 xterm 生成缩略图
+  getScreen: async (sessionId: string): Promise<string> => {
+    return await invoke('ssh_get_screen', { sessionId });
+  },
+
+  // bytesPerSec 为空或 0 时取消限速
+  setOutputRateLimit: async (sessionId: string, bytesPerSec?: number): Promise<void> => {
+    return await invoke('ssh_set_output_rate_limit', { sessionId, bytesPerSec });
+  },
+
+  // 关闭标签页但保留远端 shell：停止推送 terminal-output，通道不关闭
+  detachShell: async (sessionId: string): Promise<void> => {
+    return await invoke('ssh_detach_shell', { sessionId });
+  },
+
+  // 返回分离期间缓存的输出，应先写入终端再处理后续 terminal-output
+  attachShell: async (sessionId: string): Promise<string> => {
+    return await invoke('ssh_attach_shell', { sessionId });
+  },
+
+  // 开启后 shell 分离期间、PTY 模式的 execMerged 中由后端回复 DA/DSR 查询
+  setTerminalAutoAnswer: async (sessionId: string, enabled: boolean): Promise<void> => {
+    return await invoke('ssh_set_terminal_auto_answer', { sessionId, enabled });
+  },
+
+  // 后台播放，结束时发出 macro-result
+  playMacro: async (sessionId: string, macroId: string, steps: MacroStep[]): Promise<void> => {
+    return await invoke('ssh_play_macro', { sessionId, macroId, steps });
+  },
+
+  abortMacro: async (macroId: string): Promise<boolean> => {
+    return await invoke('ssh_abort_macro', { macroId });
+  },
+
+  setWsBridgeEnabled: async (enabled: boolean): Promise<number> => {
+    return await invoke('ssh_set_ws_bridge_enabled', { enabled });
   },
 
-  localResizePty: async (sessionId: string, cols: number, rows: number): Promise<void> => {
-    return await invoke('local_resize_pty', { sessionId, cols, rows });
+  startWsBridge: async (sessionId: string, options?: WsBridgeOptions): Promise<WsBridgeInfo> => {
+    return await invoke('ssh_start_ws_bridge', { sessionId, options });
   },
 
-  disconnect: async (sessionId: string): Promise<void> => {
-    return await invoke('ssh_disconnect', { sessionId });
+  stopWsBridge: async (sessionId: string): Promise<boolean> => {
+    return await invoke('ssh_stop_ws_bridge', { sessionId });
+  },
+
+  listWsBridges: async (): Promise<WsBridgeInfo[]> => {
+    return await invoke('ssh_list_ws_bridges');
+  },
+
+  shellBufferStats: async (sessionId: string): Promise<ShellBufferStats> => {
+    return await invoke('ssh_shell_buffer_stats', { sessionId });
+  },
+
+  // pixelWidth/pixelHeight 为终端区域的设备像素尺寸，供图像协议使用
+  resizePty: async (
+    sessionId: string,
+    cols: number,
+    rows: number,
+    pixelWidth?: number,
+    pixelHeight?: number,
+  ): Promise<void> => {
+    return await invoke('ssh_resize_pty', { sessionId, cols, rows, pixelWidth, pixelHeight });
+  },
+
+  shellSize: async (sessionId: string): Promise<ShellSizeReport> => {
+    return await invoke('ssh_shell_size', { sessionId });
+  },
+
+  // 修正 ~/.ssh（0700）、authorized_keys（0600）和家目录写权限，返回改动与无法修复的问题
+  fixSshPerms: async (sessionId: string): Promise<SshPermsReport> => {
+    return await invoke('ssh_fix_ssh_perms', { sessionId });
+  },
+
+  localResizePty: async (
+    sessionId: string,
+    cols: number,
+    rows: number,
+    pixelWidth?: number,
+    pixelHeight?: number,
+  ): Promise<void> => {
+    return await invoke('local_resize_pty', { sessionId, cols, rows, pixelWidth, pixelHeight });
+  },
+
+  localSessionInfo: async (sessionId: string): Promise<LocalSessionInfo> => {
+    return await invoke('local_session_info', { sessionId });
+  },
+
+  localBracketedPaste: async (sessionId: string): Promise<boolean> => {
+    return await invoke('local_bracketed_paste', { sessionId });
+  },
+
+  // stopForwards 默认为 true，同一连接的端口转发一并停止
+  disconnect: async (sessionId: string, stopForwards?: boolean): Promise<DisconnectSummary> => {
+    return await invoke('ssh_disconnect', { sessionId, stopForwards });
   },
 
   localDisconnect: async (sessionId: string): Promise<void> => {
     return await invoke('local_disconnect', { sessionId });
   },
 
+  // 启动时调用，有上次未关闭的连接时返回，供提示重新连接
+  recoverSessions: async (): Promise<SessionSnapshot | null> => {
+    return await invoke('ssh_recover_sessions');
+  },
+
+  // 回收已失效会话遗留的读取线程、保活条目与状态
+  gcSessions: async (): Promise<SessionGcReport> => {
+    return await invoke('ssh_gc_sessions');
+  },
+
+  resourceStats: async (): Promise<AppResourceStats> => {
+    return await invoke('app_resource_stats');
+  },
+
+  setDebugLogging: async (enabled: boolean): Promise<void> => {
+    return await invoke('ssh_set_debug_logging', { enabled });
+  },
+
   executeCommand: async (sessionId: string, command: string): Promise<string> => {
     return await invoke('ssh_execute_command', { sessionId, command });
   },
@@ -68,6 +804,33 @@ export const sshApi = {
     return await invoke('ssh_execute_command_controlled', { sessionId, command, timeoutSec });
   },
 
+  // 输出通过 exec-chunk 事件推送；usePty 为 true 时由远端 PTY 合并 stdout/stderr。
+  // 用 cancelTransfer(streamId) 中止
+  execMerged: async (
+    sessionId: string,
+    command: string,
+    streamId: string,
+    usePty?: boolean,
+  ): Promise<MergedExecResult> => {
+    return await invoke('ssh_exec_merged', { sessionId, command, streamId, usePty });
+  },
+
+  runScript: async (
+    sessionId: string,
+    script: string,
+    interpreter?: ScriptInterpreter,
+  ): Promise<ScriptRunResult> => {
+    return await invoke('ssh_run_script', { sessionId, script, interpreter });
+  },
+
+  // 返回 sessionId -> 结果
+  executeMany: async (
+    sessionIds: string[],
+    command: string,
+  ): Promise<Record<string, HostCommandResult>> => {
+    return await invoke('ssh_execute_many', { sessionIds, command });
+  },
+
   localExecuteControlledCommand: async (
     sessionId: string,
     command: string,
@@ -76,16 +839,75 @@ export const sshApi = {
     return await invoke('local_execute_command_controlled', { sessionId, command, timeoutSec });
   },
 
-  isConnected: async (sessionId: string): Promise<boolean> => {
-    return await invoke('ssh_is_connected', { sessionId });
+  isConnected: async (sessionId: string, deep?: boolean): Promise<boolean> => {
+    return await invoke('ssh_is_connected', { sessionId, deep });
   },
 
   listSessions: async (): Promise<string[]> => {
     return await invoke('ssh_list_sessions');
   },
 
-  listSftpDir: async (sessionId: string, path: string): Promise<SftpEntry[]> => {
-    return await invoke('ssh_sftp_list_dir', { sessionId, path });
+  connectionCount: async (): Promise<ConnectionCount> => {
+    return await invoke('ssh_connection_count');
+  },
+
+  // limit 为空或 0 表示不限制
+  setMaxSessions: async (limit?: number): Promise<void> => {
+    return await invoke('ssh_set_max_sessions', { limit });
+  },
+
+  // 最近一次建立会话的各阶段耗时（毫秒），从未连接过时为 null
+  lastConnectTiming: async (connectionId: string): Promise<ConnectTiming | null> => {
+    return await invoke('ssh_last_connect_timing', { connectionId });
+  },
+
+  // 开启后用户名留空的连接直接失败
+  setRequireUsername: async (required: boolean): Promise<void> => {
+    return await invoke('ssh_set_require_username', { required });
+  },
+
+  // withInodes 需要能在远端执行命令（find 或 ls -lai），SFTP 本身不提供 inode
+  listSftpDir: async (sessionId: string, path: string, withInodes?: boolean): Promise<SftpEntry[]> => {
+    return await invoke('ssh_sftp_list_dir', { sessionId, path, withInodes });
+  },
+
+  // SFTP 协议版本与服务端声明的扩展（如 posix-rename@openssh.com、copy-data）
+  sftpFeatures: async (
+    sessionId: string,
+  ): Promise<{ version: number; extensions: { name: string; data: string }[] }> => {
+    return await invoke('ssh_sftp_features', { sessionId });
+  },
+
+  // 校验目标是目录并解析为绝对路径后列出；path 为解析后的路径
+  changeSftpDir: async (
+    sessionId: string,
+    path: string,
+  ): Promise<{ path: string; entries: SftpEntry[] }> => {
+    return await invoke('ssh_sftp_change_dir', { sessionId, path });
+  },
+
+  hasSftp: async (sessionId: string): Promise<boolean> => {
+    return await invoke('ssh_has_sftp', { sessionId });
+  },
+
+  listDirViaExec: async (sessionId: string, path: string): Promise<SftpEntry[]> => {
+    return await invoke('ssh_list_dir_via_exec', { sessionId, path });
+  },
+
+  hostStats: async (sessionId: string): Promise<HostStats> => {
+    return await invoke('ssh_host_stats', { sessionId });
+  },
+
+  listInterfaces: async (sessionId: string): Promise<InterfaceList> => {
+    return await invoke('ssh_list_interfaces', { sessionId });
+  },
+
+  sftpSessionInfo: async (): Promise<SftpSessionInfo[]> => {
+    return await invoke('ssh_sftp_session_info');
+  },
+
+  closeIdleSftp: async (sessionId: string): Promise<boolean> => {
+    return await invoke('ssh_close_idle_sftp', { sessionId });
   },
 
   downloadFile: async (
@@ -106,6 +928,154 @@ export const sshApi = {
     return await invoke('ssh_sftp_upload_file', { sessionId, localPath, remotePath, transferId });
   },
 
+  // 以相同 transferId 重新调用可续传；cancelTransfer(transferId) 中止。
+  // exclude 为 .gitignore 风格的模式（*、**、结尾 / 表示目录），如 ['node_modules/', '.git/', '*.log']
+  uploadDir: async (
+    sessionId: string,
+    localDir: string,
+    remoteDir: string,
+    transferId?: string,
+    verify?: TransferVerify,
+    exclude?: string[],
+  ): Promise<SftpUploadDirSummary> => {
+    return await invoke('ssh_sftp_upload_dir', {
+      sessionId,
+      localDir,
+      remoteDir,
+      transferId,
+      options: { verify, exclude },
+    });
+  },
+
+  // 返回 null 表示一致，否则为不一致的原因
+  verifyFile: async (
+    sessionId: string,
+    localPath: string,
+    remotePath: string,
+    verifyHash?: boolean,
+  ): Promise<string | null> => {
+    return await invoke('ssh_sftp_verify_file', { sessionId, localPath, remotePath, verifyHash });
+  },
+
+  // 两个会话之间直接复制文件，不经过本地磁盘；返回复制的字节数
+  remoteToRemote: async (
+    srcSession: string,
+    srcPath: string,
+    dstSession: string,
+    dstPath: string,
+    transferId?: string,
+  ): Promise<number> => {
+    return await invoke('ssh_sftp_remote_to_remote', { srcSession, srcPath, dstSession, dstPath, transferId });
+  },
+
+  // 最近完成的传输（每个会话最多 200 条，旧的在前）
+  transferHistory: async (sessionId: string): Promise<TransferRecord[]> => {
+    return await invoke('ssh_transfer_history', { sessionId });
+  },
+
+  activeTransfers: async (): Promise<ActiveTransfer[]> => {
+    return await invoke('ssh_active_transfers');
+  },
+
+  listInterruptedTransfers: async (sessionId: string): Promise<InterruptedTransfer[]> => {
+    return await invoke('ssh_list_interrupted_transfers', { sessionId });
+  },
+
+  // 进度通过 sftp-transfer-progress 事件发出，transferId 即中断记录的 id
+  resumeTransfer: async (transferId: string): Promise<void> => {
+    return await invoke('ssh_resume_transfer', { transferId });
+  },
+
+  // 启发式诊断大传输停滞（MTU/窗口问题），最长约 90 秒
+  pathDiagnostics: async (sessionId: string): Promise<PathDiagnostics> => {
+    return await invoke('ssh_path_diagnostics', { sessionId });
+  },
+
+  downloadDir: async (
+    sessionId: string,
+    remoteDir: string,
+    localDir: string,
+    transferId?: string,
+    symlinkPolicy?: SymlinkPolicy,
+  ): Promise<SftpDownloadDirSummary> => {
+    return await invoke('ssh_sftp_download_dir', { sessionId, remoteDir, localDir, transferId, symlinkPolicy });
+  },
+
+  cancelTransfer: async (transferId: string): Promise<boolean> => {
+    return await invoke('ssh_sftp_stream_stop', { streamId: transferId });
+  },
+
+  sync: async (
+    sessionId: string,
+    localDir: string,
+    remoteDir: string,
+    deleteExtraneous?: boolean,
+    exclude?: string[],
+  ): Promise<SftpSyncSummary> => {
+    return await invoke('ssh_sftp_sync', { sessionId, localDir, remoteDir, deleteExtraneous, exclude });
+  },
+
+  editFile: async (
+    sessionId: string,
+    remotePath: string,
+    editor?: string,
+    watch?: boolean,
+  ): Promise<SftpEditInfo> => {
+    return await invoke('ssh_sftp_edit_file', { sessionId, remotePath, editor, watch });
+  },
+
+  editCommit: async (editId: string): Promise<boolean> => {
+    return await invoke('ssh_sftp_edit_commit', { editId });
+  },
+
+  editClose: async (editId: string): Promise<void> => {
+    return await invoke('ssh_sftp_edit_close', { editId });
+  },
+
+  streamRead: async (
+    sessionId: string,
+    path: string,
+    streamId: string,
+    chunkSize?: number,
+  ): Promise<void> => {
+    return await invoke('ssh_sftp_stream_read', { sessionId, path, streamId, chunkSize });
+  },
+
+  stopStreamRead: async (streamId: string): Promise<boolean> => {
+    return await invoke('ssh_sftp_stream_stop', { streamId });
+  },
+
+  // atomic 为 true 时先写临时文件，结束时替换目标；失败或中止时目标保持不变
+  streamWriteBegin: async (
+    sessionId: string,
+    streamId: string,
+    path: string,
+    atomic?: boolean,
+  ): Promise<void> => {
+    return await invoke('ssh_sftp_stream_write_begin', { sessionId, streamId, path, atomic });
+  },
+
+  streamWriteAbort: async (streamId: string): Promise<void> => {
+    return await invoke('ssh_sftp_stream_write_abort', { streamId });
+  },
+
+  streamWriteChunk: async (streamId: string, dataB64: string): Promise<number> => {
+    return await invoke('ssh_sftp_stream_write_chunk', { streamId, dataB64 });
+  },
+
+  streamWriteEnd: async (streamId: string): Promise<number> => {
+    return await invoke('ssh_sftp_stream_write_end', { streamId });
+  },
+
+  tailLines: async (sessionId: string, path: string, n: number): Promise<string> => {
+    return await invoke('ssh_sftp_tail_lines', { sessionId, path, n });
+  },
+
+  // 只读取开头 maxBytes 字节（默认 64 KiB）
+  preview: async (sessionId: string, path: string, maxBytes?: number): Promise<SftpPreview> => {
+    return await invoke('ssh_sftp_preview', { sessionId, path, maxBytes });
+  },
+
   renameSftpEntry: async (sessionId: string, fromPath: string, toPath: string): Promise<void> => {
     return await invoke('ssh_sftp_rename', { sessionId, fromPath, toPath });
   },
@@ -121,4 +1091,14 @@ export const sshApi = {
   mkdirSftpEntry: async (sessionId: string, path: string): Promise<void> => {
     return await invoke('ssh_sftp_mkdir', { sessionId, path });
   },
+
+  // 解析为绝对规范路径；"." 为用户主目录
+  sftpRealpath: async (sessionId: string, path: string): Promise<string> => {
+    return await invoke('ssh_sftp_realpath', { sessionId, path });
+  },
+
+  // 读取远端环境变量；未设置时返回 null。变量名只允许字母、数字和下划线
+  getEnv: async (sessionId: string, name: string): Promise<string | null> => {
+    return await invoke('ssh_get_env', { sessionId, name });
+  },
 };
@@ -16,7 +16,7 @@ import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { open as openDialog, save as saveDialog } from '@tauri-apps/plugin-dialog';
 import { openPath } from "@tauri-apps/plugin-opener";
-import { sshApi } from "../api/ssh";
+import { isProcessExitedError, sshApi, type TerminalDisconnectedEvent } from "../api/ssh";
 import type { SftpEntry } from "../types/ssh";
 import "@xterm/xterm/css/xterm.css";
 import "./XTerminal.css";
@@ -364,6 +364,7 @@ export function XTerminal({
   const sftpDragCounterRef = useRef(0);
   const sftpPanelRef = useRef<HTMLDivElement>(null);
   const sftpPathRef = useRef(sftpPath);
+  const sftpHomeResolvedRef = useRef(false);
   const sftpDraggingRef = useRef(false);
   const writeQueueRef = useRef<string[]>([]);
   const writingRef = useRef(false);
@@ -1058,6 +1059,11 @@ export function XTerminal({
         return true;
       } catch (err) {
         const message = err instanceof Error ? err.message : String(err);
+        // 本地进程已退出：terminal-disconnected 会更新状态，重试无意义
+        if (isLocal && isProcessExitedError(err)) {
+          pushTerminalLog("warn", `write after exit: ${message}`);
+          return false;
+        }
         const detail =
           message === "write_timeout"
             ? t("terminal.write.timeout")
@@ -1141,17 +1147,22 @@ export function XTerminal({
   };
 
   const resizePty = (cols: number, rows: number) => {
+    // 按设备像素上报终端区域大小，缩放比例变化后图像协议仍能算对单元格尺寸
+    const screen = terminalInstance.current?.element?.querySelector<HTMLElement>(".xterm-screen");
+    const ratio = window.devicePixelRatio || 1;
+    const pixelWidth = screen ? Math.round(screen.clientWidth * ratio) : undefined;
+    const pixelHeight = screen ? Math.round(screen.clientHeight * ratio) : undefined;
     if (isLocal) {
-      return sshApi.localResizePty(sessionId, cols, rows);
+      return sshApi.localResizePty(sessionId, cols, rows, pixelWidth, pixelHeight);
     }
-    return sshApi.resizePty(sessionId, cols, rows);
+    return sshApi.resizePty(sessionId, cols, rows, pixelWidth, pixelHeight);
   };
 
-  const disconnectShell = () => {
+  const disconnectShell = async (stopForwards?: boolean) => {
     if (isLocal) {
       return sshApi.localDisconnect(sessionId);
     }
-    return sshApi.disconnect(sessionId);
+    await sshApi.disconnect(sessionId, stopForwards);
   };
 
   const clipboardWrite = async (text: string) => {
@@ -1650,7 +1661,8 @@ export function XTerminal({
       appendConnectionLog(
         locale === "zh-CN" ? "执行连接重置（force reset）" : "Running force reset before reconnect",
       );
-      await disconnectShell().catch(() => {});
+      // 重连前的重置不影响该连接的端口转发
+      await disconnectShell(false).catch(() => {});
     }
 
     try {
@@ -1681,7 +1693,8 @@ export function XTerminal({
     );
     try {
       if (isLocal) {
-        await sshApi.localOpenShell(sessionId);
+        const term = terminalInstance.current;
+        await sshApi.localOpenShell(sessionId, undefined, undefined, undefined, term?.cols, term?.rows);
         if (!mountedRef.current) return;
         setConnStatus("connected");
         appendConnectionLog(
@@ -1991,7 +2004,11 @@ export function XTerminal({
       }
     }
 
-    void loadSftpEntries(newPath);
+    // 解析符号链接，面包屑显示真实路径
+    void sshApi.sftpRealpath(sessionId, newPath).then(
+      (resolved) => loadSftpEntries(resolved),
+      () => loadSftpEntries(newPath),
+    );
   };
 
   const openSftpMenu = (
@@ -4224,10 +4241,7 @@ export function XTerminal({
       );
       unlistenSftpProgress = unlistenTransferProgress;
 
-      const unlistenDisconnectEvent = await listen<{
-        session_id: string;
-        reason: string;
-      }>("terminal-disconnected", (event) => {
+      const unlistenDisconnectEvent = await listen<TerminalDisconnectedEvent>("terminal-disconnected", (event) => {
         if (disposed) return;
         if (event.payload.session_id !== sessionId) return;
         const pendingExec = agentTerminalExecutionRef.current;
@@ -4240,6 +4254,12 @@ export function XTerminal({
           });
         }
         pushTerminalLog("warn", `disconnected: ${event.payload.reason}`);
+        // 远端 shell 正常退出（exit / logout）时不自动重连
+        if (!isLocal && event.payload.detail?.kind === "exited") {
+          setConnStatus("error");
+          setConnError(t("terminal.session.exited"));
+          return;
+        }
         if (!isLocal) {
           startReconnectFlow();
           return;
@@ -4690,7 +4710,16 @@ export function XTerminal({
             onClick={() => {
               const nextOpen = !sftpOpen;
               setSftpOpen(nextOpen);
-              if (nextOpen) {
+              if (nextOpen && !sftpHomeResolvedRef.current) {
+                // 首次打开时从用户主目录开始，而不是显示 "."
+                sftpHomeResolvedRef.current = true;
+                void sshApi
+                  .sftpRealpath(sessionId, ".")
+                  .then(
+                    (home) => loadSftpEntries(home),
+                    () => loadSftpEntries(),
+                  );
+              } else if (nextOpen) {
                 void loadSftpEntries();
               }
             }}
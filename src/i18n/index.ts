@@ -655,6 +655,7 @@ const MESSAGES: Messages = {
   "security.cryptoUnavailable": { "zh-CN": "当前环境不支持加密能力", "en-US": "Cryptography is not supported in this environment" },
 
   "terminal.session.disconnected": { "zh-CN": "会话已断开", "en-US": "Session disconnected" },
+  "terminal.session.exited": { "zh-CN": "远端会话已退出", "en-US": "Remote session exited" },
   "terminal.session.recreated": {
     "zh-CN": "SSH 会话已重建，未发送完成的输入已丢弃",
     "en-US": "SSH session recreated. Unsent input was discarded",
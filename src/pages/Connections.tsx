@@ -242,6 +242,13 @@ const normalizeSshAuthType = (authType: SshConnectionConfig["auth_type"]) => {
       ...authType,
     } as SshConnectionConfig["auth_type"];
   }
+  if (authType.type === "Agent") {
+    return {
+      ...authType,
+      agent_sock: trimValue(authType.agent_sock) || undefined,
+      identity: trimValue(authType.identity) || undefined,
+    } as SshConnectionConfig["auth_type"];
+  }
   return {
     ...authType,
     key_path: trimValue(authType.key_path),
@@ -618,6 +625,14 @@ const cloneAuthType = (authType: AuthProfile["auth_type"]): AuthProfile["auth_ty
     return {
       type: "Password",
       password: authType.password,
+      keychain: authType.keychain ? { ...authType.keychain } : undefined,
+    };
+  }
+  if (authType.type === "Agent") {
+    return {
+      type: "Agent",
+      agent_sock: authType.agent_sock,
+      identity: authType.identity,
     };
   }
   return {
@@ -634,7 +649,11 @@ const authTypeEquals = (
 ) => {
   if (left.type !== right.type) return false;
   if (left.type === "Password" && right.type === "Password") {
-    return left.password === right.password;
+    return (
+      left.password === right.password &&
+      left.keychain?.service === right.keychain?.service &&
+      left.keychain?.account === right.keychain?.account
+    );
   }
   if (left.type === "PrivateKey" && right.type === "PrivateKey") {
     return (
@@ -643,6 +662,9 @@ const authTypeEquals = (
       left.passphrase === right.passphrase
     );
   }
+  if (left.type === "Agent" && right.type === "Agent") {
+    return left.agent_sock === right.agent_sock && left.identity === right.identity;
+  }
   return false;
 };
 
@@ -1129,7 +1151,10 @@ export function ConnectionsPage({
     if (!isSshConnection(editingConnection)) return false;
     if (!editingConnection.username.trim()) return false;
     if (editingConnection.auth_type.type === "Password") {
-      return !!editingConnection.auth_type.password;
+      return !!editingConnection.auth_type.password || !!editingConnection.auth_type.keychain;
+    }
+    if (editingConnection.auth_type.type === "Agent") {
+      return true;
     }
     // 私钥模式：需要有 key_path 或 key_content
     return (
@@ -1355,7 +1380,7 @@ export function ConnectionsPage({
 
     if (editingConnection.auth_type.type === "Password") {
       const password = (editingConnection.auth_type as any).password?.trim();
-      if (!password) {
+      if (!password && !editingConnection.auth_type.keychain) {
         setTestStatus("error");
         setTestMessage(t("connections.test.requirePassword"));
         return;
@@ -1394,7 +1419,7 @@ export function ConnectionsPage({
     };
 
     try {
-      const sessionId = await sshApi.connect(testConnection);
+      const { sessionId } = await sshApi.connect(testConnection);
       await sshApi.disconnect(sessionId);
       setTestStatus("success");
       setTestMessage(t("connections.test.success"));
@@ -2369,7 +2394,7 @@ export function ConnectionsPage({
           await sshApi.localOpenShell(sessionId);
           return;
         }
-        const backendSessionId = await sshApi.connect({
+        const { sessionId: backendSessionId } = await sshApi.connect({
           ...session.connection,
           id: sessionId,
         });
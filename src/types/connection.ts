@@ -28,6 +28,7 @@ export interface RdpConnectionConfig {
   redirectClipboard?: boolean;
   redirectAudio?: boolean;
   redirectDrives?: boolean;
+  ephemeralFile?: boolean;
 }
 
 export type ConnectionConfig = SshConnectionConfig | RdpConnectionConfig;
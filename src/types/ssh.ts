@@ -9,11 +9,21 @@ export interface SshConnection {
   auth_type: AuthType;
   auth_profile_id?: string;
   encoding?: string;
+  env?: Record<string, string>;
+  // 主机密钥算法偏好，逗号分隔，如 "ssh-ed25519,rsa-sha2-512"
+  host_key_algorithms?: string;
+}
+
+// 系统凭据库条目；设置后由后端在连接时读取密码
+export interface KeychainRef {
+  service: string; // "NoTerm" 或 "NoTerm:..."，其他 service 会被后端拒绝
+  account: string;
 }
 
 export type AuthType =
-  | { type: 'Password'; password: string }
-  | { type: 'PrivateKey'; key_path: string; key_content?: string; passphrase?: string };
+  | { type: 'Password'; password: string; keychain?: KeychainRef }
+  | { type: 'PrivateKey'; key_path: string; key_content?: string; passphrase?: string }
+  | { type: 'Agent'; agent_sock?: string; identity?: string };
 
 export interface SftpEntry {
   name: string;
@@ -21,6 +31,8 @@ export interface SftpEntry {
   size?: number;
   modified?: number;
   perm?: number;
+  inode?: number; // 仅在 listSftpDir 传入 withInodes 时通过远端命令获取
+  nlink?: number;
 }
 
 export interface SshSession {